@@ -1,11 +1,32 @@
 use crate::application::editor_service::{EditorService, HandleCommandResult};
+use crate::application::input;
+use crate::application::keys;
 use crate::application::lsp::LspRequest;
+use crate::application::shell::{ShellRequest, ShellTarget};
+use crate::domain::editor_model::EditorMode;
+use crate::domain::ex_range::{self, LineRange};
+use crate::domain::line_ops;
+use crate::domain::register::Registers;
+use crate::domain::search;
+use crate::domain::substitute;
 use crate::infrastructure::file_io::FileIO;
-use std::io;
+use std::io::{self, Error, ErrorKind};
+use std::time::Duration;
 
 pub trait EditorCommand<T: FileIO> {
-    fn execute(&self, editor_service: &mut EditorService<T>) -> io::Result<HandleCommandResult>;
+    /// Run the command over `range`, the lines its range prefix resolved to
+    /// (`None` when it had none; the command picks its own default).
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult>;
     fn names(&self) -> Vec<&'static str>;
+
+    /// Whether a range prefix is allowed at all.
+    fn accepts_range(&self) -> bool {
+        false
+    }
 }
 
 pub struct WriteCommand {
@@ -19,7 +40,11 @@ impl WriteCommand {
 }
 
 impl<T: FileIO> EditorCommand<T> for WriteCommand {
-    fn execute(&self, editor_service: &mut EditorService<T>) -> io::Result<HandleCommandResult> {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
         editor_service.save_file(self.filepath.as_deref())?;
         Ok(HandleCommandResult::Continue)
     }
@@ -32,7 +57,11 @@ impl<T: FileIO> EditorCommand<T> for WriteCommand {
 pub struct QuitCommand;
 
 impl<T: FileIO> EditorCommand<T> for QuitCommand {
-    fn execute(&self, _editor_service: &mut EditorService<T>) -> io::Result<HandleCommandResult> {
+    fn execute(
+        &self,
+        _editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
         Ok(HandleCommandResult::Quit)
     }
 
@@ -52,7 +81,11 @@ impl EditCommand {
 }
 
 impl<T: FileIO> EditorCommand<T> for EditCommand {
-    fn execute(&self, editor_service: &mut EditorService<T>) -> io::Result<HandleCommandResult> {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
         editor_service.open_file(&self.filepath)?;
         Ok(HandleCommandResult::Continue)
     }
@@ -67,7 +100,11 @@ impl<T: FileIO> EditorCommand<T> for EditCommand {
 pub struct FormatCommand;
 
 impl<T: FileIO> EditorCommand<T> for FormatCommand {
-    fn execute(&self, editor_service: &mut EditorService<T>) -> io::Result<HandleCommandResult> {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
         editor_service.request_lsp(LspRequest::Format);
         Ok(HandleCommandResult::Continue)
     }
@@ -91,7 +128,11 @@ impl RenameCommand {
 }
 
 impl<T: FileIO> EditorCommand<T> for RenameCommand {
-    fn execute(&self, editor_service: &mut EditorService<T>) -> io::Result<HandleCommandResult> {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
         let (y, x) = (
             editor_service.editor_model.cursor_y,
             editor_service.editor_model.cursor_x,
@@ -108,3 +149,670 @@ impl<T: FileIO> EditorCommand<T> for RenameCommand {
         vec!["rename"]
     }
 }
+
+/// `:earlier {N}` / `:later {N}` — step N undo states back or forward in time
+/// (like `g-` / `g+`); with an `s`/`m`/`h`/`d` suffix, by that much wall-clock
+/// time instead.
+pub struct EarlierLaterCommand {
+    arg: String,
+    forward: bool,
+}
+
+impl EarlierLaterCommand {
+    pub fn new(arg: String, forward: bool) -> Self {
+        Self { arg, forward }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for EarlierLaterCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let arg = self.arg.trim();
+        let invalid = || Error::new(ErrorKind::InvalidInput, "Invalid count or time");
+        let model = &mut editor_service.editor_model;
+        let unit = match arg.chars().last() {
+            Some('s') => Some(1),
+            Some('m') => Some(60),
+            Some('h') => Some(60 * 60),
+            Some('d') => Some(24 * 60 * 60),
+            _ => None,
+        };
+        match unit {
+            Some(secs) => {
+                let n: u64 = arg[..arg.len() - 1].parse().map_err(|_| invalid())?;
+                model.undo_by_time(Duration::from_secs(n * secs), self.forward);
+            }
+            None => {
+                let n = if arg.is_empty() {
+                    1
+                } else {
+                    arg.parse().map_err(|_| invalid())?
+                };
+                model.undo_chronological(n, self.forward);
+            }
+        }
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        if self.forward {
+            vec!["later", "lat"]
+        } else {
+            vec!["earlier", "ea"]
+        }
+    }
+}
+
+/// `:undolist` — list the tip of every undo-tree branch.
+pub struct UndoListCommand;
+
+impl<T: FileIO> EditorCommand<T> for UndoListCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let lines = editor_service.editor_model.undo_list();
+        editor_service.show_output(lines);
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["undolist", "undol"]
+    }
+}
+
+/// `:nohlsearch` — hide search highlighting until the next search.
+pub struct NoHighlightCommand;
+
+impl<T: FileIO> EditorCommand<T> for NoHighlightCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        editor_service.editor_model.clear_search_highlight();
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["nohlsearch", "noh"]
+    }
+}
+
+/// `:[range]s/pattern/replacement/[gciIe]` — substitute over the range (the
+/// cursor line by default). Without an argument, or with `&` (`:&&` keeps
+/// the flags), repeats the last substitution.
+pub struct SubstituteCommand {
+    arg: Option<String>,
+}
+
+impl SubstituteCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for SubstituteCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let model = &mut editor_service.editor_model;
+        let LineRange {
+            start: first,
+            end: last,
+        } = range.unwrap_or(LineRange::line(model.cursor_y));
+        let result = match self.arg.as_deref() {
+            None => model.repeat_substitute(first, last, false),
+            Some("&") => model.repeat_substitute(first, last, true),
+            Some(arg) => match substitute::parse(arg) {
+                Ok(sub) => model.substitute(first, last, sub),
+                Err(e) => Err(e),
+            },
+        };
+        let message = result.map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        if !message.is_empty() {
+            editor_service.set_message(message);
+        }
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["s", "substitute", "&"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]d[elete] [x]` — delete the lines of the range (the cursor line by
+/// default) into register `x`, or the unnamed register.
+pub struct DeleteCommand {
+    arg: Option<String>,
+}
+
+impl DeleteCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for DeleteCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let register = match self.arg.as_deref().map(|a| a.chars().collect::<Vec<_>>()) {
+            None => None,
+            Some(chars) => match chars[..] {
+                [c] if Registers::is_valid(c) => Some(c),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid register")),
+            },
+        };
+        let model = &mut editor_service.editor_model;
+        let range = range.unwrap_or(LineRange::line(model.cursor_y));
+        model.delete_lines(range, register);
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["d", "delete"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]g/pattern/command` — run an ex command on every line of the
+/// range (the whole file by default) that matches; `:g!` or `:v` on every
+/// line that does not. Lines are marked first, so a line deleted by the
+/// command for an earlier one is skipped. The whole run is one undo step.
+/// Without a command, the matching lines are listed.
+pub struct GlobalCommand {
+    arg: Option<String>,
+    invert: bool,
+}
+
+impl GlobalCommand {
+    pub fn new(arg: Option<String>, invert: bool) -> Self {
+        Self { arg, invert }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for GlobalCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let invalid = |e: String| Error::new(ErrorKind::InvalidInput, e);
+        let arg = self.arg.as_deref().unwrap_or_default();
+        let (invert, arg) = match arg.strip_prefix('!') {
+            Some(rest) => (!self.invert, rest),
+            None => (self.invert, arg),
+        };
+        let mut chars = arg.chars();
+        let delim = match chars.next() {
+            Some(c) if !(c.is_alphanumeric() || matches!(c, '\\' | '"' | '|' | ' ')) => c,
+            _ => return Err(invalid("Invalid :global delimiter".to_string())),
+        };
+        let (pattern, command) = search::split_offset(chars.as_str(), delim);
+        let model = &mut editor_service.editor_model;
+        let last = model.buffer.line_count().saturating_sub(1);
+        let range = range.unwrap_or(LineRange {
+            start: 0,
+            end: last,
+        });
+        if model
+            .start_global(range, &pattern, invert)
+            .map_err(invalid)?
+            == 0
+        {
+            model.finish_global();
+            return Err(invalid(format!("Pattern not found: {}", pattern)));
+        }
+        let mut listed = Vec::new();
+        let mut result = Ok(HandleCommandResult::Continue);
+        while let Some(y) = editor_service.editor_model.next_global_line() {
+            if command.trim().is_empty() {
+                let text = editor_service.editor_model.buffer.line_text(y);
+                listed.push(format!("{:>3} {}", y + 1, text));
+                continue;
+            }
+            result = editor_service.execute_command(command);
+            if editor_service.editor_model.mode == EditorMode::Confirm {
+                editor_service.editor_model.confirm_substitute('q');
+                result = Err(invalid("Cannot confirm inside :global".to_string()));
+            }
+            if !matches!(result, Ok(HandleCommandResult::Continue)) {
+                break;
+            }
+        }
+        editor_service.editor_model.finish_global();
+        if !listed.is_empty() {
+            editor_service.show_output(listed);
+        }
+        result
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        if self.invert {
+            vec!["v", "vglobal"]
+        } else {
+            vec!["g", "global"]
+        }
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]norm[al][!] {keys}` — run `keys` (in the `<Esc>` notation of
+/// macros) as Normal-mode commands at the cursor, or with a range at the
+/// start of each of its lines in turn. The whole run is one undo step.
+pub struct NormalCommand {
+    arg: Option<String>,
+}
+
+impl NormalCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for NormalCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let arg = self.arg.as_deref().unwrap_or_default();
+        // No mappings exist, so `:normal!` is the same command.
+        let keys = keys::parse(arg.strip_prefix('!').unwrap_or(arg).trim_start());
+        if keys.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Argument required"));
+        }
+        let model = &mut editor_service.editor_model;
+        model.set_mode(EditorMode::Normal);
+        model.begin_undo_group();
+        let mut quit = false;
+        match range {
+            None => quit = input::feed_keys(editor_service, &keys),
+            Some(range) => {
+                for y in range.start..=range.end {
+                    let model = &mut editor_service.editor_model;
+                    if y >= model.buffer.line_count() {
+                        break;
+                    }
+                    (model.cursor_y, model.cursor_x) = (y, 0);
+                    quit = input::feed_keys(editor_service, &keys);
+                    if quit {
+                        break;
+                    }
+                }
+            }
+        }
+        editor_service.editor_model.end_undo_group();
+        if quit {
+            Ok(HandleCommandResult::Quit)
+        } else {
+            Ok(HandleCommandResult::Continue)
+        }
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["normal", "norm"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]m[ove] {address}` / `:[range]t {address}` (`:co[py]`) — move or
+/// copy the lines of the range (the cursor line by default) below the line
+/// `address` names; `0` is above the first line.
+pub struct MoveCopyCommand {
+    arg: Option<String>,
+    copy: bool,
+}
+
+impl MoveCopyCommand {
+    pub fn new(arg: Option<String>, copy: bool) -> Self {
+        Self { arg, copy }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for MoveCopyCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let invalid = |e: String| Error::new(ErrorKind::InvalidInput, e);
+        let model = &mut editor_service.editor_model;
+        let range = range.unwrap_or(LineRange::line(model.cursor_y));
+        let address = match ex_range::parse(self.arg.as_deref().unwrap_or_default()) {
+            Ok((Some(address), rest)) if rest.trim().is_empty() => address,
+            Ok(_) => return Err(invalid("Invalid address".to_string())),
+            Err(e) => return Err(invalid(e)),
+        };
+        let dest = model.resolve_destination(&address).map_err(invalid)?;
+        if self.copy {
+            model.copy_lines(range, dest);
+        } else {
+            model.move_lines(range, dest).map_err(invalid)?;
+        }
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        if self.copy {
+            vec!["t", "co", "copy"]
+        } else {
+            vec!["m", "move"]
+        }
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]j[oin][!] [count]` — join the lines of the range, or a single
+/// line and the one below it; with a count, `count` lines from the last line
+/// of the range. `!` keeps the indent and inserts no spaces.
+pub struct JoinCommand {
+    arg: Option<String>,
+}
+
+impl JoinCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for JoinCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let arg = self.arg.as_deref().unwrap_or_default();
+        let (bang, count) = match arg.strip_prefix('!') {
+            Some(rest) => (true, rest.trim()),
+            None => (false, arg),
+        };
+        let model = &mut editor_service.editor_model;
+        let LineRange { mut start, mut end } = range.unwrap_or(LineRange::line(model.cursor_y));
+        if !count.is_empty() {
+            let count: usize = count
+                .parse()
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid count"))?;
+            start = end;
+            end += count.saturating_sub(1);
+        }
+        if start == end {
+            end += 1;
+        }
+        model.join_lines(start, end, !bang);
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["j", "join"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]sor[t][!] [i][n][u][r] [/pattern/]` — sort the lines of the
+/// range (the whole file by default); see [`line_ops::sort_lines`].
+pub struct SortCommand {
+    arg: Option<String>,
+}
+
+impl SortCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for SortCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let invalid = |e: String| Error::new(ErrorKind::InvalidInput, e);
+        let arg = self.arg.as_deref().unwrap_or_default();
+        let opts = match arg.strip_prefix('!') {
+            Some(rest) => line_ops::parse_sort_args(rest, true),
+            None => line_ops::parse_sort_args(arg, false),
+        }
+        .map_err(invalid)?;
+        let model = &mut editor_service.editor_model;
+        let last = model.buffer.line_count().saturating_sub(1);
+        let range = range.unwrap_or(LineRange {
+            start: 0,
+            end: last,
+        });
+        model.sort_lines(range, &opts).map_err(invalid)?;
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["sort", "sor"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[range]ret[ab][!] [tabstop]` — rewrite runs of blanks holding a tab
+/// (with `!`, every run of blanks) for the new tab stop, as spaces when
+/// `expandtab` is set; see [`line_ops::retab_line`].
+pub struct RetabCommand {
+    arg: Option<String>,
+}
+
+impl RetabCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for RetabCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let arg = self.arg.as_deref().unwrap_or_default();
+        let (all, tabstop) = match arg.strip_prefix('!') {
+            Some(rest) => (true, rest.trim()),
+            None => (false, arg),
+        };
+        let tabstop = match tabstop {
+            "" => None,
+            n => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid tabstop")),
+            },
+        };
+        let model = &mut editor_service.editor_model;
+        let last = model.buffer.line_count().saturating_sub(1);
+        let range = range.unwrap_or(LineRange {
+            start: 0,
+            end: last,
+        });
+        model.retab(range, tabstop, all);
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["retab", "ret"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:se[t] {option}…` — change or show options; see [`Options::set`].
+///
+/// [`Options::set`]: crate::domain::options::Options::set
+pub struct SetCommand {
+    arg: Option<String>,
+}
+
+impl SetCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for SetCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        _range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let options = &mut editor_service.editor_model.options;
+        let shown = options
+            .set(self.arg.as_deref().unwrap_or_default())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        if let Some(shown) = shown {
+            editor_service.set_message(shown);
+        }
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["set", "se"]
+    }
+}
+
+/// Put the file name in place of each `%` in a command line (`\%` is a
+/// literal `%`), as `:!` and `:r !` do.
+fn expand_filename(command: &str, filepath: Option<&str>) -> io::Result<String> {
+    let mut out = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with('%') => {
+                out.push('%');
+                chars.next();
+            }
+            '%' => match filepath {
+                Some(path) => out.push_str(path),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "No file name to substitute for '%'",
+                    ))
+                }
+            },
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// `:!{cmd}` — run a shell command and show its output. With a range,
+/// `:[range]!{cmd}` filters the lines through it instead, replacing them with
+/// what it prints. The main loop runs the command (see [`ShellRequest`]).
+pub struct ShellCommand {
+    command: Option<String>,
+}
+
+impl ShellCommand {
+    pub fn new(command: Option<String>) -> Self {
+        Self { command }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for ShellCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let Some(command) = &self.command else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Argument required"));
+        };
+        let command = expand_filename(command, editor_service.editor_model.filepath.as_deref())?;
+        let target = match range {
+            Some(range) => ShellTarget::Filter(range),
+            None => ShellTarget::Show,
+        };
+        editor_service.request_shell(ShellRequest { command, target });
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["!"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
+
+/// `:[line]r[ead] !{cmd}` — put the output of a shell command below the line
+/// (the cursor line by default). `:r {file}` puts the file's lines there.
+pub struct ReadCommand {
+    arg: Option<String>,
+}
+
+impl ReadCommand {
+    pub fn new(arg: Option<String>) -> Self {
+        Self { arg }
+    }
+}
+
+impl<T: FileIO> EditorCommand<T> for ReadCommand {
+    fn execute(
+        &self,
+        editor_service: &mut EditorService<T>,
+        range: Option<LineRange>,
+    ) -> io::Result<HandleCommandResult> {
+        let Some(arg) = &self.arg else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Argument required"));
+        };
+        let y = range.map_or(editor_service.editor_model.cursor_y, |r| r.end);
+        match arg.strip_prefix('!') {
+            Some(command) => {
+                let model = &editor_service.editor_model;
+                let command = expand_filename(command.trim_start(), model.filepath.as_deref())?;
+                let target = ShellTarget::Read(y);
+                editor_service.request_shell(ShellRequest { command, target });
+            }
+            None => {
+                let lines = editor_service.read_file_below(arg, y)?;
+                editor_service.set_message(format!("\"{}\" {} lines", arg, lines));
+            }
+        }
+        Ok(HandleCommandResult::Continue)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        vec!["read", "r"]
+    }
+
+    fn accepts_range(&self) -> bool {
+        true
+    }
+}
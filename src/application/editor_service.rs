@@ -1,12 +1,26 @@
+use crate::domain::comment::CommentSyntax;
 use crate::domain::editor_model::{EditorMode, EditorModel};
+use crate::domain::ex_range;
+use crate::domain::indent::Indenter;
+use crate::domain::pairs::Literals;
+use crate::domain::register::Register;
 use crate::infrastructure::file_io::FileIO;
-use crossterm::event::KeyCode;
+use crate::infrastructure::snippet_file::{self, SnippetStore};
+use crate::infrastructure::syntax_worker::{self, SyntaxComments, SyntaxIndenter, SyntaxLiterals};
+use crate::infrastructure::undo_file::UndoStore;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::VecDeque;
 use std::io::{self, Error, ErrorKind};
 
 use crate::application::commands::{
-    EditCommand, EditorCommand, FormatCommand, QuitCommand, RenameCommand, WriteCommand,
+    DeleteCommand, EarlierLaterCommand, EditCommand, EditorCommand, FormatCommand, GlobalCommand,
+    JoinCommand, MoveCopyCommand, NoHighlightCommand, NormalCommand, QuitCommand, ReadCommand,
+    RenameCommand, RetabCommand, SetCommand, ShellCommand, SortCommand, SubstituteCommand,
+    UndoListCommand, WriteCommand,
 };
+use crate::application::keys;
 use crate::application::lsp::LspRequest;
+use crate::application::shell::ShellRequest;
 
 #[derive(Debug)]
 pub enum HandleCommandResult {
@@ -20,6 +34,26 @@ pub struct EditorService<T: FileIO> {
     /// An LSP feature request recorded by a synchronous input handler, to be
     /// picked up and dispatched by the async main loop after the keypress.
     pending_lsp: Option<LspRequest>,
+    /// An external command recorded by `:!` or `:r !`, likewise run by the
+    /// main loop.
+    pending_shell: Option<ShellRequest>,
+    /// Keys typed since `q{reg}` started recording (`editor_model.recording`).
+    recorded_keys: Vec<KeyEvent>,
+    /// Keys of a macro being played back, fed to the input handler one by one
+    /// after the key that started it.
+    macro_queue: VecDeque<KeyEvent>,
+    /// Register of the last macro played, for `@@`.
+    last_macro: Option<char>,
+    /// Where undo history is persisted across sessions, if anywhere.
+    undo_store: Option<Box<dyn UndoStore>>,
+    /// Where user snippets are defined, if anywhere.
+    snippet_store: Option<Box<dyn SnippetStore>>,
+    /// Multi-line output of the last command (`:undolist`), shown until the
+    /// next keypress.
+    output: Vec<String>,
+    /// One-line result of the last command (`:s` counts), shown in the status
+    /// line instead of "Command executed".
+    message: Option<String>,
 }
 
 impl<T: FileIO> EditorService<T> {
@@ -28,9 +62,155 @@ impl<T: FileIO> EditorService<T> {
             editor_model: EditorModel::new(),
             file_io,
             pending_lsp: None,
+            pending_shell: None,
+            recorded_keys: Vec::new(),
+            macro_queue: VecDeque::new(),
+            last_macro: None,
+            output: Vec::new(),
+            message: None,
+            undo_store: None,
+            snippet_store: None,
         }
     }
 
+    /// Persist undo history through `store`: it is saved on every write and
+    /// restored when the same file, unchanged, is opened again.
+    pub fn set_undo_store(&mut self, store: Box<dyn UndoStore>) {
+        self.undo_store = Some(store);
+    }
+
+    /// Expand user snippets defined in `store` (see [`Self::expand_snippet`]).
+    pub fn set_snippet_store(&mut self, store: Box<dyn SnippetStore>) {
+        self.snippet_store = Some(store);
+    }
+
+    /// Edit the file at `filepath`, in its language: in a language the
+    /// editor has a parser for, lines are indented by its syntax, auto-pairs
+    /// stay out of its strings and comments, and `gc` writes its comments.
+    /// Other files get `autoindent` only, and no `gc`.
+    pub fn set_filepath(&mut self, filepath: &str) {
+        self.editor_model.set_filepath(filepath.to_string());
+        let def = syntax_worker::language_for_path(filepath);
+        self.editor_model.set_indenter(
+            def.and_then(|def| SyntaxIndenter::new(def).ok())
+                .map(|indenter| Box::new(indenter) as Box<dyn Indenter>),
+        );
+        self.editor_model
+            .set_literals(def.map(|def| Box::new(SyntaxLiterals::new(def)) as Box<dyn Literals>));
+        self.editor_model.set_comment_syntax(
+            def.and_then(|def| SyntaxComments::new(def).ok())
+                .map(|comments| Box::new(comments) as Box<dyn CommentSyntax>),
+        );
+    }
+
+    /// Show command output (cleared by the next keypress).
+    pub fn show_output(&mut self, lines: Vec<String>) {
+        self.output = lines;
+    }
+
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    pub fn clear_output(&mut self) {
+        self.output.clear();
+    }
+
+    /// Report a one-line command result in the status line.
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    pub fn take_message(&mut self) -> Option<String> {
+        self.message.take()
+    }
+
+    /// `q{reg}`: start recording typed keys into register `reg`.
+    pub fn start_recording(&mut self, reg: char) {
+        self.editor_model.recording = Some(reg);
+        self.recorded_keys.clear();
+    }
+
+    /// `q` while recording: store the keys typed so far, minus the `q` that
+    /// stopped the recording, in the register.
+    pub fn stop_recording(&mut self) {
+        let Some(reg) = self.editor_model.recording.take() else {
+            return;
+        };
+        let mut keys = std::mem::take(&mut self.recorded_keys);
+        keys.pop();
+        let text = keys::encode(&keys);
+        self.editor_model
+            .registers
+            .set(reg, Register::charwise(text));
+    }
+
+    /// Note a key typed by the user (not one played back) while recording.
+    pub fn record_key(&mut self, key: &KeyEvent) {
+        if self.editor_model.recording.is_some() {
+            self.recorded_keys.push(*key);
+        }
+    }
+
+    /// `[count]@{reg}`: queue the keys in register `reg` `count` times, ahead
+    /// of any keys still queued (so a macro may call another). `@` replays
+    /// the last macro and `:` the last command line. Returns `false` if the
+    /// register is empty.
+    pub fn play_macro(&mut self, reg: char, count: usize) -> bool {
+        let reg = if reg == '@' {
+            match self.last_macro {
+                Some(r) => r,
+                None => return false,
+            }
+        } else {
+            reg
+        };
+        let keys = match reg {
+            ':' => match self.editor_model.registers.last_command() {
+                Some(cmd) => {
+                    let mut keys: Vec<KeyEvent> = std::iter::once(':')
+                        .chain(cmd.chars())
+                        .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+                        .collect();
+                    keys.push(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+                    keys
+                }
+                None => return false,
+            },
+            _ => match self.editor_model.register(reg) {
+                Some(r) => keys::parse(&r.text),
+                None => return false,
+            },
+        };
+        self.last_macro = Some(reg);
+        for _ in 0..count.max(1) {
+            for key in keys.iter().rev() {
+                self.macro_queue.push_front(*key);
+            }
+        }
+        true
+    }
+
+    /// The next key of the macro being played back, if any.
+    pub fn next_macro_key(&mut self) -> Option<KeyEvent> {
+        self.macro_queue.pop_front()
+    }
+
+    /// Stop macro playback (a motion failed), dropping the remaining keys.
+    pub fn abort_macro(&mut self) {
+        self.macro_queue.clear();
+    }
+
+    /// Set aside the keys of the macro being played, so keys run by a command
+    /// (`:normal`) do not play them; [`Self::resume_macro`] puts them back.
+    pub fn suspend_macro(&mut self) -> VecDeque<KeyEvent> {
+        std::mem::take(&mut self.macro_queue)
+    }
+
+    pub fn resume_macro(&mut self, keys: VecDeque<KeyEvent>) {
+        self.macro_queue = keys;
+    }
+
     /// Record an LSP feature request for the async loop to dispatch.
     #[allow(dead_code)] // wired up in the LSP feature sprints
     pub fn request_lsp(&mut self, req: LspRequest) {
@@ -44,16 +224,41 @@ impl<T: FileIO> EditorService<T> {
         self.pending_lsp.take()
     }
 
+    /// Record an external command for the async loop to run.
+    pub fn request_shell(&mut self, req: ShellRequest) {
+        self.pending_shell = Some(req);
+    }
+
+    /// Take the pending external command, if any.
+    pub fn take_pending_shell(&mut self) -> Option<ShellRequest> {
+        self.pending_shell.take()
+    }
+
+    /// `:r {file}`: put the lines of `filepath` below line `y`. Returns how
+    /// many lines were read.
+    pub fn read_file_below(&mut self, filepath: &str, y: usize) -> io::Result<usize> {
+        let content = self.file_io.read_file(filepath)?;
+        self.editor_model.read_lines(y, &content);
+        Ok(content.lines().count())
+    }
+
     pub fn open_file(&mut self, filepath: &str) -> io::Result<()> {
         let content = self.file_io.read_file(filepath)?;
         self.editor_model.set_content(&content);
-        self.editor_model.set_filepath(filepath.to_string());
+        self.set_filepath(filepath);
+        if let Some(tree) = self
+            .undo_store
+            .as_ref()
+            .and_then(|store| store.load(filepath, &content))
+        {
+            self.editor_model.restore_undo_tree(tree);
+        }
         Ok(())
     }
 
     pub fn save_file(&mut self, new_filepath: Option<&str>) -> io::Result<()> {
         let path_to_save = if let Some(new_path) = new_filepath {
-            self.editor_model.set_filepath(new_path.to_string());
+            self.set_filepath(new_path);
             new_path
         } else if let Some(existing_path) = self.editor_model.get_filepath() {
             existing_path
@@ -62,19 +267,77 @@ impl<T: FileIO> EditorService<T> {
         };
 
         let content = self.editor_model.get_content();
-        self.file_io.write_file(path_to_save, &content)
+        self.file_io.write_file(path_to_save, &content)?;
+        if let Some(store) = &self.undo_store {
+            // Losing the history is not worth failing the write over.
+            let _ = store.save(path_to_save, &content, self.editor_model.undo_tree());
+        }
+        Ok(())
     }
 
+    // Insert-mode keys act at every cursor.
     pub fn move_cursor(&mut self, key: KeyCode) {
-        self.editor_model.move_cursor(key);
+        self.editor_model.at_each_cursor(|m| m.move_cursor(key));
     }
 
+    // Typing over a snippet placeholder replaces it, and the text typed at a
+    // tabstop is copied to its mirrors.
     pub fn insert_char(&mut self, c: char) {
-        self.editor_model.insert_char(c);
+        self.editor_model.take_placeholder();
+        self.editor_model.at_each_cursor(|m| m.insert_char(c));
+        self.editor_model.sync_snippet();
+    }
+
+    pub fn insert_newline(&mut self) {
+        self.editor_model.take_placeholder();
+        self.editor_model.at_each_cursor(|m| m.insert_newline());
+        self.editor_model.sync_snippet();
     }
 
     pub fn delete_char(&mut self) {
-        self.editor_model.delete_char();
+        if !self.editor_model.take_placeholder() {
+            self.editor_model.at_each_cursor(|m| m.delete_char());
+        }
+        self.editor_model.sync_snippet();
+    }
+
+    /// Expand the user snippet whose prefix ends at the cursor (not right
+    /// after a word character), the longest if several do, for the
+    /// type of the file being edited. Returns whether one was expanded.
+    pub fn expand_snippet(&mut self) -> bool {
+        let model = &self.editor_model;
+        let (Some(store), Some(filetype)) = (
+            &mut self.snippet_store,
+            model
+                .get_filepath()
+                .and_then(|path| snippet_file::filetype(path)),
+        ) else {
+            return false;
+        };
+        let before: String = model
+            .buffer
+            .line_text(model.cursor_y)
+            .chars()
+            .take(model.cursor_x)
+            .collect();
+        let word = |c: char| c.is_alphanumeric() || c == '_';
+        let Some(snippet) = store
+            .snippets(filetype)
+            .into_iter()
+            .filter(|s| {
+                !s.prefix.is_empty()
+                    && before
+                        .strip_suffix(&s.prefix)
+                        .is_some_and(|rest| !(rest.ends_with(word) && s.prefix.starts_with(word)))
+            })
+            .max_by_key(|s| s.prefix.chars().count())
+        else {
+            return false;
+        };
+        let end = model.buffer.cursor_to_char(model.cursor_y, model.cursor_x);
+        let start = end - snippet.prefix.chars().count();
+        self.editor_model.insert_snippet(start, end, &snippet.body);
+        true
     }
 
     pub fn set_mode(&mut self, mode: EditorMode) {
@@ -128,34 +391,89 @@ impl<T: FileIO> EditorService<T> {
         self.editor_model.repeat_last_change();
     }
 
-    pub fn search(&mut self, query: &str) {
-        self.editor_model.search(query);
+    /// `n`: repeat the last search. Returns a message for the status line.
+    pub fn find_next(&mut self) -> Option<String> {
+        self.editor_model.find_next()
     }
 
-    pub fn find_next(&mut self) {
-        self.editor_model.find_next();
-    }
-
-    pub fn find_previous(&mut self) {
-        self.editor_model.find_previous();
+    /// `N`: repeat the last search in the opposite direction.
+    pub fn find_previous(&mut self) -> Option<String> {
+        self.editor_model.find_previous()
     }
 
     pub fn handle_command(&mut self, command_str: &str) -> io::Result<HandleCommandResult> {
-        let parts: Vec<&str> = command_str.splitn(2, ' ').collect();
-        let command_name = parts[0];
-        let arg = parts.get(1).map(|s| s.to_string());
+        if !command_str.is_empty() {
+            self.editor_model.registers.set_last_command(command_str);
+        }
+        self.execute_command(command_str)
+    }
+
+    /// Run an ex command line without recording it as the last command (for
+    /// commands run by other commands, as `:g` does).
+    pub fn execute_command(&mut self, command_str: &str) -> io::Result<HandleCommandResult> {
+        let invalid = |e: String| Error::new(ErrorKind::InvalidInput, e);
+        let (range, rest) = ex_range::parse(command_str).map_err(invalid)?;
+        let range = range
+            .map(|r| self.editor_model.resolve_range(&r))
+            .transpose()
+            .map_err(invalid)?;
+        let rest = rest.trim_start();
+        // The name is a run of letters (`s`, `write`) or a single symbol (`&`);
+        // the argument may follow it directly, as in `s/a/b/`.
+        let name_len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+            Some(n) => n,
+            None => rest.len(),
+        };
+        let command_name = &rest[..name_len];
+        let arg = Some(rest[name_len..].trim())
+            .filter(|a| !a.is_empty())
+            .map(str::to_string);
+        // A range alone (`:42`, `:'a`, `:/fn/`) goes to its last line.
+        if command_name.is_empty() {
+            if let Some(range) = range {
+                self.editor_model.goto_first_non_blank(range.end);
+            }
+            return Ok(HandleCommandResult::Continue);
+        }
 
         let commands: Vec<Box<dyn EditorCommand<T>>> = vec![
             Box::new(WriteCommand::new(arg.clone())),
             Box::new(QuitCommand),
             Box::new(EditCommand::new(arg.clone().unwrap_or_default())),
             Box::new(FormatCommand),
-            Box::new(RenameCommand::new(arg.unwrap_or_default())),
+            Box::new(RenameCommand::new(arg.clone().unwrap_or_default())),
+            Box::new(EarlierLaterCommand::new(
+                arg.clone().unwrap_or_default(),
+                false,
+            )),
+            Box::new(EarlierLaterCommand::new(
+                arg.clone().unwrap_or_default(),
+                true,
+            )),
+            Box::new(UndoListCommand),
+            Box::new(NoHighlightCommand),
+            Box::new(SubstituteCommand::new(arg.clone())),
+            Box::new(DeleteCommand::new(arg.clone())),
+            Box::new(GlobalCommand::new(arg.clone(), false)),
+            Box::new(GlobalCommand::new(arg.clone(), true)),
+            Box::new(NormalCommand::new(arg.clone())),
+            Box::new(MoveCopyCommand::new(arg.clone(), false)),
+            Box::new(MoveCopyCommand::new(arg.clone(), true)),
+            Box::new(JoinCommand::new(arg.clone())),
+            Box::new(SortCommand::new(arg.clone())),
+            Box::new(RetabCommand::new(arg.clone())),
+            Box::new(SetCommand::new(arg.clone())),
+            Box::new(ShellCommand::new(arg.clone())),
+            Box::new(ReadCommand::new(arg)),
         ];
 
         for cmd in commands {
             if cmd.names().contains(&command_name) {
-                return cmd.execute(self);
+                if range.is_some() && !cmd.accepts_range() {
+                    return Err(Error::new(ErrorKind::InvalidInput, "No range allowed"));
+                }
+                return cmd.execute(self, range);
             }
         }
 
@@ -447,4 +765,54 @@ mod tests {
         editor_service.delete_char();
         assert_eq!(editor_service.editor_model.buffer.line_text(0), "ab");
     }
+
+    #[test]
+    fn test_earlier_later_and_undolist_commands() {
+        let mut editor_service = EditorService::new(MockFileIO::new());
+        editor_service.editor_model.set_content("");
+        for c in "abc".chars() {
+            editor_service.insert_char(c);
+            editor_service.set_mode(EditorMode::Normal);
+        }
+        editor_service.handle_command("earlier 2").unwrap();
+        assert_eq!(editor_service.editor_model.buffer.line_text(0), "a");
+        editor_service.handle_command("later 1").unwrap();
+        assert_eq!(editor_service.editor_model.buffer.line_text(0), "ab");
+        // Everything happened within the last minute.
+        editor_service.handle_command("earlier 1m").unwrap();
+        assert_eq!(editor_service.editor_model.buffer.line_text(0), "");
+        assert!(editor_service.handle_command("later 1x").is_err());
+
+        editor_service.handle_command("undolist").unwrap();
+        assert_eq!(editor_service.output().len(), 2);
+    }
+
+    #[test]
+    fn test_undo_history_survives_reopening_unchanged_file() {
+        use crate::infrastructure::undo_file::LocalUndoStore;
+        let dir = std::env::temp_dir().join(format!("rim-undo-svc-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut mock_file_io = MockFileIO::new();
+        mock_file_io.set_read_content("abc");
+        let mut editor_service = EditorService::new(mock_file_io);
+        editor_service.set_undo_store(Box::new(LocalUndoStore::new(dir.clone())));
+        editor_service.open_file("undo_test.txt").unwrap();
+        editor_service.insert_char('x');
+        editor_service.handle_command("w").unwrap();
+        let saved = editor_service.editor_model.get_content();
+
+        // Reopening the written text restores the history...
+        editor_service.file_io.set_read_content(&saved);
+        editor_service.open_file("undo_test.txt").unwrap();
+        editor_service.undo();
+        assert_eq!(editor_service.editor_model.buffer.line_text(0), "abc");
+
+        // ...but not once the file changed on disk.
+        editor_service.file_io.set_read_content("changed");
+        editor_service.open_file("undo_test.txt").unwrap();
+        editor_service.undo();
+        assert_eq!(editor_service.editor_model.buffer.line_text(0), "changed");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
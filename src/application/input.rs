@@ -0,0 +1,969 @@
+use crate::application::editor_service::{EditorService, HandleCommandResult};
+use crate::application::lsp::{Lsp, LspRequest};
+use crate::application::normal_mode::{NormalMode, NormalResult};
+use crate::domain::editor_model::EditorMode;
+use crate::infrastructure::file_io::FileIO;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Most keys one keypress may play back through macros.
+const MACRO_KEY_LIMIT: usize = 1_000_000;
+
+/// Plain insert-mode key handling (no completion popup active).
+fn insert_default_key<T: FileIO>(
+    event: KeyEvent,
+    editor_service: &mut EditorService<T>,
+    status_message: &mut String,
+) {
+    match event.code {
+        KeyCode::Esc => {
+            editor_service.set_mode(EditorMode::Normal);
+            status_message.clear();
+        }
+        KeyCode::Char(c) => {
+            editor_service.insert_char(c);
+            status_message.clear();
+        }
+        KeyCode::Enter => {
+            editor_service.insert_newline();
+            status_message.clear();
+        }
+        KeyCode::Backspace => {
+            editor_service.delete_char();
+            status_message.clear();
+        }
+        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => {
+            editor_service.move_cursor(event.code);
+            status_message.clear();
+        }
+        _ => {}
+    }
+}
+
+/// Dispatch one key event to the editor. Returns `true` if the editor should
+/// quit. Pure synchronous CPU work — it never blocks the async runtime.
+///
+/// The key is recorded if a `q` macro is being recorded, and any macro it
+/// starts (`@a`) is played back through the same dispatch before returning.
+pub fn handle_key<T: FileIO>(
+    event: KeyEvent,
+    editor_service: &mut EditorService<T>,
+    normal_mode: &mut NormalMode,
+    status_message: &mut String,
+    lsp: &mut Lsp,
+) -> bool {
+    editor_service.record_key(&event);
+    run_key(event, editor_service, normal_mode, status_message, lsp)
+}
+
+/// Run keys as if typed, outside the terminal event loop (`:normal`): with
+/// fresh Normal-mode state, no completion popup, and without recording them
+/// into a macro or consuming the rest of a macro being played. A command
+/// left incomplete is ended with `Esc`, as in Vim. Returns `true` if the keys
+/// quit the editor.
+pub fn feed_keys<T: FileIO>(editor_service: &mut EditorService<T>, keys: &[KeyEvent]) -> bool {
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut lsp = Lsp::new(tx);
+    let mut normal_mode = NormalMode::new();
+    let mut status = String::new();
+    let outer_macro = editor_service.suspend_macro();
+    let mut quit = false;
+    for &key in keys {
+        quit = run_key(key, editor_service, &mut normal_mode, &mut status, &mut lsp);
+        if quit {
+            break;
+        }
+    }
+    // Insert mode with a completion popup open takes two.
+    let esc = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+    for _ in 0..2 {
+        if quit || editor_service.editor_model.mode == EditorMode::Normal {
+            break;
+        }
+        quit = run_key(esc, editor_service, &mut normal_mode, &mut status, &mut lsp);
+    }
+    editor_service.resume_macro(outer_macro);
+    quit
+}
+
+/// Dispatch one key, then play back any macro it started.
+fn run_key<T: FileIO>(
+    event: KeyEvent,
+    editor_service: &mut EditorService<T>,
+    normal_mode: &mut NormalMode,
+    status_message: &mut String,
+    lsp: &mut Lsp,
+) -> bool {
+    if dispatch_key(event, editor_service, normal_mode, status_message, lsp) {
+        return true;
+    }
+    let mut played = 0;
+    while let Some(key) = editor_service.next_macro_key() {
+        played += 1;
+        if played > MACRO_KEY_LIMIT {
+            // A macro that calls itself without ever failing would otherwise
+            // never return to the event loop.
+            editor_service.abort_macro();
+            *status_message = "Macro aborted: too many keys".to_string();
+            break;
+        }
+        if dispatch_key(key, editor_service, normal_mode, status_message, lsp) {
+            return true;
+        }
+    }
+    false
+}
+
+fn dispatch_key<T: FileIO>(
+    event: KeyEvent,
+    editor_service: &mut EditorService<T>,
+    normal_mode: &mut NormalMode,
+    status_message: &mut String,
+    lsp: &mut Lsp,
+) -> bool {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let ctrl_x = lsp.take_ctrl_x();
+    match editor_service.editor_model.mode {
+        EditorMode::Normal | EditorMode::Visual(_) => {
+            match normal_mode.feed(editor_service, &event, status_message) {
+                NormalResult::Quit => return true,
+                NormalResult::Continue => {}
+            }
+        }
+        EditorMode::Insert if ctrl_x => match event.code {
+            KeyCode::Char('n') if ctrl => {
+                if !lsp.complete_words(&editor_service.editor_model) {
+                    *status_message = "Pattern not found".to_string();
+                }
+            }
+            KeyCode::Char('f') if ctrl => {
+                if !lsp.complete_paths(&editor_service.editor_model) {
+                    *status_message = "Pattern not found".to_string();
+                }
+            }
+            // Any other key leaves `Ctrl-x` mode and is handled as usual.
+            _ => {
+                status_message.clear();
+                return dispatch_key(event, editor_service, normal_mode, status_message, lsp);
+            }
+        },
+        EditorMode::Insert if lsp.completion_active() => match event.code {
+            KeyCode::Esc => lsp.close_completion(),
+            KeyCode::Char('n') if ctrl => lsp.completion_move(1),
+            KeyCode::Char('p') if ctrl => lsp.completion_move(-1),
+            KeyCode::Char('x') if ctrl => {
+                lsp.close_completion();
+                lsp.begin_ctrl_x();
+                *status_message = "-- ^X mode (^N^F)".to_string();
+            }
+            KeyCode::Down => lsp.completion_move(1),
+            KeyCode::Up => lsp.completion_move(-1),
+            KeyCode::Enter | KeyCode::Tab => {
+                lsp.completion_accept(editor_service);
+            }
+            KeyCode::Char(c) if !ctrl && (c.is_alphanumeric() || c == '_') => {
+                editor_service.insert_char(c);
+                lsp.completion_refilter(&editor_service.editor_model);
+            }
+            KeyCode::Backspace => {
+                editor_service.delete_char();
+                lsp.completion_refilter(&editor_service.editor_model);
+            }
+            // Any other key dismisses the popup and is handled normally.
+            _ => {
+                lsp.close_completion();
+                insert_default_key(event, editor_service, status_message);
+            }
+        },
+        EditorMode::Insert => match event.code {
+            // Trigger completion: Ctrl-n or Ctrl-Space.
+            KeyCode::Char('n') if ctrl => {
+                let (y, x) = (
+                    editor_service.editor_model.cursor_y,
+                    editor_service.editor_model.cursor_x,
+                );
+                editor_service.request_lsp(LspRequest::Completion { y, x });
+            }
+            KeyCode::Char(' ') if ctrl => {
+                let (y, x) = (
+                    editor_service.editor_model.cursor_y,
+                    editor_service.editor_model.cursor_x,
+                );
+                editor_service.request_lsp(LspRequest::Completion { y, x });
+            }
+            KeyCode::Char('x') if ctrl => {
+                lsp.begin_ctrl_x();
+                *status_message = "-- ^X mode (^N^F)".to_string();
+            }
+            // Expand a user snippet, or go to the next (previous) tabstop of
+            // the one being filled in.
+            KeyCode::Tab => {
+                if editor_service.expand_snippet() || editor_service.editor_model.snippet_jump(true)
+                {
+                    lsp.open_snippet_choices(&editor_service.editor_model);
+                }
+            }
+            KeyCode::BackTab => {
+                if editor_service.editor_model.snippet_jump(false) {
+                    lsp.open_snippet_choices(&editor_service.editor_model);
+                }
+            }
+            _ => insert_default_key(event, editor_service, status_message),
+        },
+        EditorMode::Command => match event.code {
+            KeyCode::Esc => {
+                editor_service.set_mode(EditorMode::Normal);
+                editor_service.clear_command_buffer();
+                status_message.clear();
+            }
+            KeyCode::Char(c) => {
+                editor_service.push_command_char(c);
+                *status_message = format!(":{}", editor_service.editor_model.command_buffer);
+            }
+            KeyCode::Backspace => {
+                editor_service.pop_command_char();
+                *status_message = format!(":{}", editor_service.editor_model.command_buffer);
+            }
+            KeyCode::Enter => {
+                let command = editor_service.editor_model.command_buffer.clone();
+                editor_service.clear_command_buffer();
+                match editor_service.handle_command(&command) {
+                    Ok(HandleCommandResult::Quit) => return true,
+                    Ok(HandleCommandResult::Continue) => {
+                        *status_message = editor_service
+                            .take_message()
+                            .unwrap_or_else(|| format!("Command executed: {}", command));
+                    }
+                    Err(e) => {
+                        *status_message = format!("Error: {}", e);
+                    }
+                }
+                // A command may have moved on to another mode (`:s///c`).
+                if editor_service.editor_model.mode == EditorMode::Command {
+                    editor_service.set_mode(EditorMode::Normal);
+                }
+            }
+            _ => {}
+        },
+        EditorMode::Replace => {
+            let model = &mut editor_service.editor_model;
+            match event.code {
+                KeyCode::Esc => {
+                    model.set_mode(EditorMode::Normal);
+                    status_message.clear();
+                }
+                KeyCode::Char(c) if !ctrl => model.replace_mode_char(c),
+                KeyCode::Enter => model.replace_mode_newline(),
+                KeyCode::Backspace => model.replace_mode_backspace(),
+                _ => {}
+            }
+        }
+        EditorMode::Confirm => {
+            let answer = match event.code {
+                KeyCode::Esc => '\u{1b}',
+                KeyCode::Char(c) => c,
+                _ => return false,
+            };
+            *status_message = editor_service.editor_model.confirm_substitute(answer);
+        }
+        EditorMode::Search => {
+            let model = &mut editor_service.editor_model;
+            match event.code {
+                KeyCode::Esc => {
+                    model.cancel_search();
+                    status_message.clear();
+                    return false;
+                }
+                KeyCode::Char(c) => model.command_buffer.push(c),
+                KeyCode::Backspace => {
+                    model.command_buffer.pop();
+                }
+                KeyCode::Enter => {
+                    let input = model.command_buffer.clone();
+                    *status_message = model.finish_search(&input).unwrap_or_default();
+                    return false;
+                }
+                _ => return false,
+            }
+            // Incremental search: jump and highlight as the pattern is typed.
+            let input = model.command_buffer.clone();
+            model.preview_search(&input);
+            *status_message = format!("{}{}", model.search_prompt_char(), input);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::keys;
+    use crate::application::shell::{ShellRequest, ShellTarget};
+    use crate::domain::ex_range::LineRange;
+    use crate::infrastructure::file_io::LocalFileIO;
+    use crate::infrastructure::snippet_file::{SnippetStore, UserSnippet};
+
+    /// Type `keys` (in `<Esc>` notation) into a fresh editor holding `content`.
+    fn run(content: &str, keys: &str) -> EditorService<LocalFileIO> {
+        type_keys(EditorService::new(LocalFileIO), content, keys)
+    }
+
+    /// Type `keys` into `svc` once it holds `content`.
+    fn type_keys(
+        mut svc: EditorService<LocalFileIO>,
+        content: &str,
+        keys: &str,
+    ) -> EditorService<LocalFileIO> {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut lsp = Lsp::new(tx);
+        svc.editor_model.set_content(content);
+        let mut nm = NormalMode::new();
+        let mut status = String::new();
+        for key in keys::parse(keys) {
+            handle_key(key, &mut svc, &mut nm, &mut status, &mut lsp);
+        }
+        svc
+    }
+
+    /// The lines `svc` holds.
+    fn lines(svc: &EditorService<LocalFileIO>) -> Vec<String> {
+        svc.editor_model.buffer.to_lines()
+    }
+
+    #[test]
+    fn recorded_macro_lands_in_register_and_replays() {
+        let svc = run("abc\nabc\nabc", "qaA!<Esc>jq@a");
+        assert_eq!(svc.editor_model.register('a').unwrap().text, "A!<Esc>j");
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["abc!", "abc!", "abc"]
+        );
+        assert_eq!(svc.editor_model.recording, None);
+    }
+
+    #[test]
+    fn counted_playback_stops_when_a_motion_fails() {
+        // The third `j` fails on the last line, so its `x` never runs.
+        let svc = run("abc\nabc\nabc", "qaxjq5@a");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["bc", "bc", "bc"]);
+    }
+
+    #[test]
+    fn at_at_repeats_last_macro() {
+        let svc = run("abcd", "qqxq@q@@");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "d");
+    }
+
+    #[test]
+    fn edited_register_plays_as_macro() {
+        // Yank "iX<Esc>" into "b and run it.
+        let svc = run("iX<Esc>", "\"byy@b");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "XiX<Esc>");
+    }
+
+    #[test]
+    fn incremental_search_moves_while_typing_and_esc_returns() {
+        let svc = run("one two\nthree two", "/thr");
+        assert_eq!(svc.editor_model.mode, EditorMode::Search);
+        assert_eq!(
+            (svc.editor_model.cursor_y, svc.editor_model.cursor_x),
+            (1, 0)
+        );
+        let svc = run("one two\nthree two", "/thr<Esc>");
+        assert_eq!(
+            (svc.editor_model.cursor_y, svc.editor_model.cursor_x),
+            (0, 0)
+        );
+        // `?` finds the match above; `n` keeps going backward and wraps.
+        let svc = run("one two\nthree two", "G?two<CR>");
+        assert_eq!(
+            (svc.editor_model.cursor_y, svc.editor_model.cursor_x),
+            (0, 4)
+        );
+        let svc = run("one two\nthree two", "G?two<CR>n");
+        assert_eq!(
+            (svc.editor_model.cursor_y, svc.editor_model.cursor_x),
+            (1, 6)
+        );
+    }
+
+    #[test]
+    fn substitute_command_ranges_and_normal_mode_repeat() {
+        let svc = run("a a\na a\na a", ":2,$s/a/b/g<CR>");
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["a a", "b b", "b b"]
+        );
+        let svc = run("a a\na a", ":%s/a/b/<CR>gg&");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["b b", "b a"]);
+        let svc = run("a a\na a", ":s/a/b/g<CR>ug&");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["b b", "b b"]);
+        let svc = run("a a\na a", ":%s/a/b/gc<CR>ynyq");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["b a", "b a"]);
+        assert_eq!(svc.editor_model.mode, EditorMode::Normal);
+    }
+
+    #[test]
+    fn ex_ranges_from_visual_marks_and_addresses() {
+        let svc = run("a\na\na\na", "jVj:s/a/b/<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a", "b", "b", "a"]);
+        let svc = run("a\na\na\na", "jVj<Esc>gg:'<,'>+1s/a/c/<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a", "c", "c", "c"]);
+        let svc = run("one\ntwo\nthree", ":/thr/<CR>");
+        assert_eq!(svc.editor_model.cursor_y, 2);
+        let svc = run("one\ntwo\nthree", ":2<CR>:-1,+1 s/$/!/<CR>");
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["one!", "two!", "three!"]
+        );
+    }
+
+    #[test]
+    fn global_runs_a_command_on_matching_lines_as_one_undo_step() {
+        let text = "dbg!(a);\nlet a = 1;\ndbg!(b);\nlet b = 2;";
+        let svc = run(text, ":g/dbg!/d<CR>");
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["let a = 1;", "let b = 2;"]
+        );
+        let svc = run(text, ":g/dbg!/d<CR>u");
+        assert_eq!(svc.editor_model.buffer.to_lines().len(), 4);
+        let svc = run(text, ":v/dbg!/s/let/const/<CR>");
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["dbg!(a);", "const a = 1;", "dbg!(b);", "const b = 2;"]
+        );
+        let svc = run(text, ":g!/let/d<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines().len(), 2);
+    }
+
+    #[test]
+    fn global_skips_lines_deleted_by_earlier_commands() {
+        // Line 2 is marked but gone after line 1's `.,+1d`; line 4 survives.
+        let svc = run("x1\nx2\ny\nx3\nz", ":g/x/.,+1d<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["y"]);
+        // A marked line joined onto the one above is gone too.
+        let svc = run("a\nb\nb\nc", r":g/b/s/\n/ /<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a", "b b", "c"]);
+        let svc = run("a\nb", ":2,$g/a/d<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn normal_command_runs_keys_on_each_line_as_one_undo_step() {
+        let svc = run("a\nb\nc", ":%norm A;<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a;", "b;", "c;"]);
+        assert_eq!(svc.editor_model.mode, EditorMode::Normal);
+        let svc = run("a\nb\nc", ":%norm A;<CR>u");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a", "b", "c"]);
+        // `<Esc>` notation ends the insert; the keys start at column 0.
+        let svc = run("one two\nthree", ":1,2normal! ix<lt>Esc>$x<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["xone tw", "xthre"]);
+        let svc = run("let a\nfoo\nlet b", ":g/let/norm dw<CR>");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a", "foo", "b"]);
+    }
+
+    #[test]
+    fn undo_and_redo_inside_normal_keep_the_undo_tree_in_step() {
+        assert_eq!(lines(&run("abc", "x:normal u<CR>u")), vec!["abc"]);
+        assert_eq!(lines(&run("abc", "x:normal u<CR><C-r>")), vec!["bc"]);
+        // Undoing, then editing, is one step back to where the group began.
+        assert_eq!(lines(&run("abc", "x:normal uA!<CR>")), vec!["abc!"]);
+        assert_eq!(lines(&run("abc", "x:normal uA!<CR>u")), vec!["bc"]);
+    }
+
+    #[test]
+    fn normal_command_inside_a_macro_leaves_the_macro_keys_alone() {
+        let svc = run("abc\nabc\nabc", "qa:norm x<CR>jq@a");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["bc", "bc", "abc"]);
+        assert_eq!(svc.editor_model.cursor_y, 2);
+        assert_eq!(svc.editor_model.register('a').unwrap().text, ":norm x<CR>j");
+    }
+
+    #[test]
+    fn move_and_copy_lines() {
+        let text = "a\nb\nc\nd";
+        assert_eq!(lines(&run(text, ":1m$<CR>")), vec!["b", "c", "d", "a"]);
+        assert_eq!(lines(&run(text, "G:m0<CR>")), vec!["d", "a", "b", "c"]);
+        assert_eq!(lines(&run(text, ":2,3m0<CR>")), vec!["b", "c", "a", "d"]);
+        assert_eq!(lines(&run(text, "jj:m-2<CR>")), vec!["a", "c", "b", "d"]);
+        assert_eq!(lines(&run(text, ":2,3m0<CR>u")), vec!["a", "b", "c", "d"]);
+        assert_eq!(lines(&run(text, ":1,3m2<CR>")), vec!["a", "b", "c", "d"]);
+        let svc = run(text, ":1,2t$<CR>");
+        assert_eq!(lines(&svc), vec!["a", "b", "c", "d", "a", "b"]);
+        assert_eq!(svc.editor_model.cursor_y, 5);
+        assert_eq!(lines(&run(text, ":co0<CR>")), vec!["a", "a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn join_sort_and_retab_lines() {
+        assert_eq!(
+            lines(&run("a\n  b\n)c\nd", ":1,3j<CR>")),
+            vec!["a b)c", "d"]
+        );
+        assert_eq!(lines(&run("a\n  b\nc", ":j!<CR>")), vec!["a  b", "c"]);
+        assert_eq!(lines(&run("a\nb\nc\nd", ":2j 3<CR>")), vec!["a", "b c d"]);
+        let text = "x10\nb\nx9\nA";
+        assert_eq!(lines(&run(text, ":sort<CR>")), vec!["A", "b", "x10", "x9"]);
+        assert_eq!(
+            lines(&run(text, ":sort! n<CR>")),
+            vec!["x10", "x9", "A", "b"]
+        );
+        assert_eq!(
+            lines(&run(text, ":sort /x/<CR>")),
+            vec!["b", "A", "x10", "x9"]
+        );
+        assert_eq!(
+            lines(&run(text, ":sort i<CR>u")),
+            vec!["x10", "b", "x9", "A"]
+        );
+        let svc = run("\tx\n  \ty", ":retab 4<CR>");
+        assert_eq!(lines(&svc), vec!["        x", "        y"]);
+        assert_eq!(svc.editor_model.options.tabstop, 4);
+    }
+
+    #[test]
+    fn new_lines_keep_the_indent_and_shift_operators_move_it() {
+        assert_eq!(lines(&run("  a", "ob<Esc>")), vec!["  a", "  b"]);
+        assert_eq!(lines(&run("  a", "Ob<Esc>")), vec!["  b", "  a"]);
+        assert_eq!(lines(&run("  a b", "fbi<CR>c<Esc>")), vec!["  a ", "  cb"]);
+        assert_eq!(lines(&run("  a\n  b", "jccx<Esc>")), vec!["  a", "  x"]);
+        // An indent nothing was typed after is dropped again.
+        assert_eq!(lines(&run("  a", "o<Esc>")), vec!["  a", ""]);
+        assert_eq!(
+            lines(&run("  a", "A<CR><CR>b<Esc>")),
+            vec!["  a", "", "  b"]
+        );
+        assert_eq!(lines(&run("  a", ":set noai<CR>ob<Esc>")), vec!["  a", "b"]);
+
+        assert_eq!(lines(&run("a\n\nb", ">2j")), vec!["    a", "", "    b"]);
+        assert_eq!(lines(&run("      a", "<lt><lt>")), vec!["  a"]);
+        assert_eq!(
+            lines(&run("a\nb\nc", "2>>j.")),
+            vec!["    a", "        b", "    c"]
+        );
+        assert_eq!(lines(&run("a\nb", "Vj>u")), vec!["a", "b"]);
+        assert_eq!(lines(&run("a", ":set sw=2<CR>>>")), vec!["  a"]);
+        assert_eq!(lines(&run("a", ":set noet<CR>>>>>")), vec!["\ta"]);
+        // Without a syntax, = takes the indent of the line above.
+        assert_eq!(
+            lines(&run("  a\nb\n\t c", "j=j")),
+            vec!["  a", "  b", "  c"]
+        );
+    }
+
+    #[test]
+    fn smart_indent_follows_the_syntax() {
+        let smart_in = |path: &str, content: &str, keys: &str| {
+            let mut svc = EditorService::new(LocalFileIO);
+            svc.set_filepath(path);
+            lines(&type_keys(svc, content, keys))
+        };
+        let smart = |content: &str, keys: &str| smart_in("a.rs", content, keys);
+        assert_eq!(
+            smart("fn f() {", "ox;<CR>}<Esc>"),
+            vec!["fn f() {", "    x;", "}"]
+        );
+        assert_eq!(
+            smart("fn f() {\n  if a {\nx;\n      }\n}", "=G"),
+            vec!["fn f() {", "    if a {", "        x;", "    }", "}"]
+        );
+        assert_eq!(
+            smart(
+                "fn f() {\n}",
+                "oif a {<CR>x<Esc>ggOfn g() {<CR>}<Esc>Gkoy<CR>}<Esc>"
+            ),
+            vec![
+                "fn g() {",
+                "}",
+                "fn f() {",
+                "    if a {",
+                "        x",
+                "        y",
+                "    }",
+                "}"
+            ]
+        );
+        // A language the editor does not parse keeps the indent above.
+        assert_eq!(
+            smart_in("a.py", "def f():\n    if x:\n        y", "Goz<Esc>"),
+            vec!["def f():", "    if x:", "        y", "        z"]
+        );
+    }
+
+    #[test]
+    fn surround_adds_deletes_and_changes_delimiters() {
+        assert_eq!(lines(&run("foo bar", "ysiw)")), vec!["(foo) bar"]);
+        assert_eq!(lines(&run("foo bar", "ysiw(")), vec!["( foo ) bar"]);
+        assert_eq!(lines(&run("foo bar", "ys2w\"")), vec!["\"foo bar\""]);
+        assert_eq!(lines(&run("foo bar", "ysw]")), vec!["[foo] bar"]);
+        assert_eq!(lines(&run("  a b", "yssB")), vec!["  {a b}"]);
+        assert_eq!(lines(&run("a b c", "ysiw*W.W.")), vec!["*a* *b* *c*"]);
+        assert_eq!(lines(&run("f(a, b)", "fads(")), vec!["fa, b"]);
+        assert_eq!(lines(&run("[ x ]", "lds[")), vec!["x"]);
+        assert_eq!(lines(&run("[ x ]", "lds]")), vec![" x "]);
+        assert_eq!(lines(&run("'hi' 'yo'", "lcs'\"")), vec!["\"hi\" 'yo'"]);
+        assert_eq!(lines(&run("((a) b)", "2lcs)]")), vec!["([a] b)"]);
+        assert_eq!(lines(&run("((a) b)", "2l2cs)]")), vec!["[(a) b]"]);
+        assert_eq!(lines(&run("{\n  a\n}", "jcsB(")), vec!["( ", "  a", " )"]);
+        // Each is one undo step; nothing happens without a pair.
+        assert_eq!(lines(&run("(a)", "lcs)]u")), vec!["(a)"]);
+        assert_eq!(lines(&run("a b", "ds(ysiw1")), vec!["a b"]);
+    }
+
+    #[test]
+    fn auto_pairs_close_brackets_and_quotes() {
+        let pairs_in = |path: &str, content: &str, keys: &str| {
+            let mut svc = EditorService::new(LocalFileIO);
+            svc.set_filepath(path);
+            lines(&type_keys(svc, content, &format!(":set ap<CR>{}", keys)))
+        };
+        let pairs = |content: &str, keys: &str| pairs_in("a.rs", content, keys);
+        assert_eq!(pairs("", "iif(a<Esc>"), vec!["if(a)"]);
+        assert_eq!(pairs("", "iif(a)b<Esc>"), vec!["if(a)b"]);
+        assert_eq!(pairs("", "ix = \"a\";<Esc>"), vec!["x = \"a\";"]);
+        assert_eq!(pairs("", "i[<BS>x<Esc>"), vec!["x"]);
+        // Not before a word, nor a quote after one, nor inside a string.
+        assert_eq!(pairs("x", "i(<Esc>"), vec!["(x"]);
+        assert_eq!(pairs("", "idon't<Esc>"), vec!["don't"]);
+        assert_eq!(pairs("s = \"\";", "f\"a(<Esc>"), vec!["s = \"(\";"]);
+        assert_eq!(pairs("// a", "A (<Esc>"), vec!["// a ("]);
+        assert_eq!(
+            pairs("fn f() ", "A{<CR>x<Esc>"),
+            vec!["fn f() {", "    x", "}"]
+        );
+        assert_eq!(lines(&run("", "iif(a<Esc>")), vec!["if(a"]);
+        assert_eq!(pairs("", "if(x<Esc>u"), vec!["f()"]);
+        // Brackets typed after edits elsewhere still see the strings.
+        assert_eq!(
+            pairs("s = \"\";", "Ox<Esc>jf\"a(<Esc>"),
+            vec!["x", "s = \"(\";"]
+        );
+        // A file in no language the editor parses has no strings to skip.
+        assert_eq!(pairs_in("a.txt", "// a", "A (<Esc>"), vec!["// a ()"]);
+    }
+
+    #[test]
+    fn gc_toggles_line_and_block_comments() {
+        let gc_in = |path: &str, content: &str, keys: &str| {
+            let mut svc = EditorService::new(LocalFileIO);
+            svc.set_filepath(path);
+            lines(&type_keys(svc, content, keys))
+        };
+        let gc = |content: &str, keys: &str| gc_in("a.rs", content, keys);
+        assert_eq!(gc("  a\n\n    b", "gcG"), vec!["  // a", "", "  //   b"]);
+        assert_eq!(gc("  // a\nb", "gcc"), vec!["  a", "b"]);
+        assert_eq!(gc("a\nb\nc", "gccjj."), vec!["// a", "b", "// c"]);
+        assert_eq!(gc("a\nb\nc", "2gcc"), vec!["// a", "// b", "c"]);
+        assert_eq!(gc("a\nb\nc", "gcipu"), vec!["a", "b", "c"]);
+        assert_eq!(gc("a\nb", "Vjgc"), vec!["// a", "// b"]);
+        assert_eq!(gc("f(a + b)", "2lvf)hgc"), vec!["f(/* a + b */)"]);
+        assert_eq!(gc("f(/* a */)", "2lvf)hgc"), vec!["f(a)"]);
+        assert_eq!(
+            gc("fn f() {\n    x();\n}", "jgcc"),
+            vec!["fn f() {", "    // x();", "}"]
+        );
+        // Text in no language the editor knows is left alone.
+        assert_eq!(gc_in("a.py", "x = 1", "gcc"), vec!["x = 1"]);
+        assert_eq!(lines(&run("f(a + b)", "2lvf)hgc")), vec!["f(a + b)"]);
+    }
+
+    #[test]
+    fn gq_reflows_and_textwidth_wraps_while_typing() {
+        let text = "// one two\n// three\n\nfour five six";
+        assert_eq!(
+            lines(&run(text, ":set tw=12<CR>gqG")),
+            vec!["// one two", "// three", "", "four five", "six"]
+        );
+        let svc = run(text, ":set tw=12<CR>gqip");
+        assert_eq!(lines(&svc)[..2], ["// one two", "// three"]);
+        assert_eq!(svc.editor_model.cursor_y, 1);
+        let svc = run("a b c d", ":set tw=3<CR>wwgww");
+        assert_eq!(lines(&svc), vec!["a b", "c d"]);
+        assert_eq!(
+            (svc.editor_model.cursor_y, svc.editor_model.cursor_x),
+            (1, 0)
+        );
+        assert_eq!(lines(&run("a\nb\nc", "Vjgqu")), vec!["a", "b", "c"]);
+        assert_eq!(lines(&run("a\nb\nc", "Vjgq")), vec!["a b", "c"]);
+
+        assert_eq!(
+            lines(&run("", ":set tw=10<CR>i/// one two three<Esc>")),
+            vec!["/// one", "/// two", "/// three"]
+        );
+        assert_eq!(
+            lines(&run("", ":set tw=8<CR>i- ab cd ef<Esc>")),
+            vec!["- ab cd", "  ef"]
+        );
+        assert_eq!(lines(&run("", "iab cd ef gh<Esc>")), vec!["ab cd ef gh"]);
+    }
+
+    struct Snippets;
+
+    impl SnippetStore for Snippets {
+        fn snippets(&mut self, _filetype: &str) -> Vec<UserSnippet> {
+            [
+                ("pl", "let ${1:x} = ${2:0};\nprintln!(\"{}\", $1);$0"),
+                ("if", "if $1 {\n\t$0\n}"),
+                ("ch", "${1|a,b|} ${2:x}"),
+            ]
+            .into_iter()
+            .map(|(prefix, body)| UserSnippet {
+                prefix: prefix.to_string(),
+                body: body.to_string(),
+            })
+            .collect()
+        }
+    }
+
+    #[test]
+    fn snippets_expand_on_tab_and_step_through_their_tabstops() {
+        let snippets = |content, keys| {
+            let mut svc = EditorService::new(LocalFileIO);
+            svc.set_snippet_store(Box::new(Snippets));
+            svc.editor_model.set_filepath("a.rs".to_string());
+            type_keys(svc, content, keys)
+        };
+        // Typing replaces a placeholder and goes to its mirrors.
+        let svc = snippets("", "ipl<Tab>value<Tab><Tab>!<Esc>");
+        assert_eq!(
+            lines(&svc),
+            vec!["let value = 0;", "println!(\"{}\", value);!"]
+        );
+        // The snippet and what is typed in it undo as one step.
+        let svc = snippets("", "ipl<Tab>v<Tab>1<Esc>u");
+        assert_eq!(lines(&svc), vec!["pl"]);
+        assert_eq!(
+            lines(&snippets("", "ipl<Tab><Tab><BS><Tab>;<Esc>")),
+            vec!["let x = ;", "println!(\"{}\", x);;"]
+        );
+        assert_eq!(
+            lines(&snippets("    if", "A<Tab>x<Tab>y<Esc>")),
+            vec!["    if x {", "        y", "    }"]
+        );
+        // Choices come up in the completion menu.
+        assert_eq!(
+            lines(&snippets("", "ich<Tab><C-n><Tab><Tab>z<S-Tab>y<Esc>")),
+            vec!["by z"]
+        );
+        // Without a snippet, Tab leaves the text alone.
+        assert_eq!(lines(&snippets("", "ixy<Tab>z<Esc>")), vec!["xyz"]);
+        assert_eq!(lines(&run("", "ipl<Tab><Esc>")), vec!["pl"]);
+    }
+
+    #[test]
+    fn ctrl_x_completes_words_and_file_paths() {
+        let text = "alpha also\nbeta al";
+        assert_eq!(lines(&run(text, "GA<C-x><C-n><CR><Esc>"))[1], "beta alpha");
+        assert_eq!(
+            lines(&run(text, "GA<C-x><C-n><C-n><CR><Esc>"))[1],
+            "beta also"
+        );
+        // The nearest word after the cursor comes first.
+        assert_eq!(
+            lines(&run(text, "ggA a<C-x><C-n><CR>")),
+            vec!["alpha also al", "beta al"]
+        );
+        assert_eq!(lines(&run("x\nzz", "A z<C-x><C-n>!")), vec!["x z!", "zz"]);
+        assert_eq!(lines(&run("x", "A <C-x>y")), vec!["x y"]);
+
+        let dir = std::env::temp_dir().join(format!("rim-path-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("notes.md"), "").unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+        let paths = |content, keys| {
+            let mut svc = EditorService::new(LocalFileIO);
+            let path = dir.join("x.md").to_string_lossy().into_owned();
+            svc.editor_model.set_filepath(path);
+            lines(&type_keys(svc, content, keys))
+        };
+        let see_no = paths("see ./no", "A<C-x><C-f><CR>");
+        let nested = paths("cd", "A <C-x><C-f><CR>");
+        let hidden = paths("cd", "A .<C-x><C-f><CR>");
+        let absolute = paths(&format!("{}/ne", dir.display()), "A<C-x><C-f><CR>");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(see_no, vec!["see ./notes.md"]);
+        assert_eq!(nested, vec!["cd nested/"]);
+        assert_eq!(hidden, vec!["cd .hidden"]);
+        assert_eq!(absolute, vec![format!("{}/nested/", dir.display())]);
+    }
+
+    #[test]
+    fn shell_commands_record_requests_and_bang_opens_a_filter() {
+        let request = |command: &str, target| {
+            Some(ShellRequest {
+                command: command.to_string(),
+                target,
+            })
+        };
+        let text = "a\nb\nc\nd";
+        let mut svc = run(text, ":2,3!sort -r<CR>");
+        let range = LineRange { start: 1, end: 2 };
+        assert_eq!(
+            svc.take_pending_shell(),
+            request("sort -r", ShellTarget::Filter(range))
+        );
+        let mut svc = run(text, "j:r !echo %<CR>");
+        assert_eq!(svc.take_pending_shell(), None);
+        svc.editor_model.set_filepath("f.txt".to_string());
+        svc.handle_command(r"r !echo % \%").unwrap();
+        assert_eq!(
+            svc.take_pending_shell(),
+            request(r"echo f.txt %", ShellTarget::Read(1))
+        );
+        let mut svc = run(text, ":!ls<CR>");
+        assert_eq!(svc.take_pending_shell(), request("ls", ShellTarget::Show));
+
+        // `!{motion}` and Visual `!` open the command line with the lines.
+        let svc = run(text, "j!j");
+        assert_eq!(svc.editor_model.mode, EditorMode::Command);
+        assert_eq!(svc.editor_model.command_buffer, ".,.+1!");
+        assert_eq!(run(text, "G!k").editor_model.command_buffer, ".,.+1!");
+        assert_eq!(run(text, "!!").editor_model.command_buffer, ".!");
+        assert_eq!(run(text, "!ip").editor_model.command_buffer, ".,.+3!");
+        assert_eq!(run(text, "Vj!").editor_model.command_buffer, "'<,'>!");
+        let mut svc = run(text, "j2!!tac<CR>");
+        assert_eq!(
+            svc.take_pending_shell(),
+            request("tac", ShellTarget::Filter(range))
+        );
+    }
+
+    #[test]
+    fn case_operators_join_and_replace_char() {
+        let svc = run("abC", "~");
+        assert_eq!(lines(&svc), vec!["AbC"]);
+        assert_eq!(svc.editor_model.cursor_x, 1);
+        // A count stops at the end of the line, on its last char.
+        let svc = run("ab-c\nd", "l10~");
+        assert_eq!(lines(&svc), vec!["aB-C", "d"]);
+        assert_eq!(svc.editor_model.cursor_x, 3);
+        assert_eq!(lines(&run("\nab", "~")), vec!["", "ab"]);
+        assert_eq!(lines(&run("abcd", "~~.")), vec!["ABCd"]);
+        assert_eq!(lines(&run("aBcd", "2~")), vec!["Abcd"]);
+        let svc = run("aBcd", "3~.u");
+        assert_eq!(lines(&svc), vec!["AbCd"]);
+        assert_eq!(svc.editor_model.cursor_x, 3);
+
+        let svc = run("abc def", "wgUiw");
+        assert_eq!(lines(&svc), vec!["abc DEF"]);
+        assert_eq!(svc.editor_model.cursor_x, 4);
+        assert_eq!(lines(&run("Abc Def", "g~$")), vec!["aBC dEF"]);
+        assert_eq!(lines(&run("ABC\nDEF\nG", "guj")), vec!["abc", "def", "G"]);
+        assert_eq!(lines(&run("ab\ncd", "gUUj.")), vec!["AB", "CD"]);
+        assert_eq!(lines(&run("ab\ncd", "guugUgU")), vec!["AB", "cd"]);
+        assert_eq!(lines(&run("aB cD", "g~~")), vec!["Ab Cd"]);
+        assert_eq!(lines(&run("A\nB\nC", "2guu")), vec!["a", "b", "C"]);
+        assert_eq!(lines(&run("A\nB\nC", "gugujj.")), vec!["a", "B", "c"]);
+        assert_eq!(lines(&run("ab cd\nx", "wvjU")), vec!["ab CD", "X"]);
+        assert_eq!(lines(&run("abc\nabc", "<C-v>jlU")), vec!["ABc", "ABc"]);
+        // Letters without a case are left alone.
+        assert_eq!(lines(&run("straße 1", "gUU")), vec!["STRASSE 1"]);
+
+        // J drops the next line's indent and puts one space at the join,
+        // where the cursor goes; none after trailing whitespace, before
+        // `)`, or around an empty line.
+        let svc = run("abc\n    def", "J");
+        assert_eq!(lines(&svc), vec!["abc def"]);
+        assert_eq!(svc.editor_model.cursor_x, 3);
+        assert_eq!(lines(&run("a  \n b", "J")), vec!["a  b"]);
+        assert_eq!(lines(&run("a\n  b\n)c\nd", "3J")), vec!["a b)c", "d"]);
+        assert_eq!(lines(&run("a\n\nb", "J")), vec!["a", "b"]);
+        assert_eq!(lines(&run("\n  b", "J")), vec!["b"]);
+        // A count of two or less joins two lines; nothing on the last line.
+        assert_eq!(lines(&run("a\nb\nc", "2J")), vec!["a b", "c"]);
+        assert_eq!(lines(&run("a\nb\nc", "GJ")), vec!["a", "b", "c"]);
+        assert_eq!(lines(&run("a\nb\nc", "4J")), vec!["a b c"]);
+        assert_eq!(lines(&run("a\nb\nc\nd", "J.")), vec!["a b c", "d"]);
+        // gJ keeps the indent and adds nothing.
+        let svc = run("a\n  b\nc", "gJ");
+        assert_eq!(lines(&svc), vec!["a  b", "c"]);
+        assert_eq!(svc.editor_model.cursor_x, 1);
+        assert_eq!(lines(&run("a \n)", "gJ")), vec!["a )"]);
+        assert_eq!(lines(&run("a\nb\nc\nd", "3gJ")), vec!["abc", "d"]);
+        assert_eq!(lines(&run("a\n  b\nc\nd", "gJj.")), vec!["a  b", "cd"]);
+        assert_eq!(
+            lines(&run("a\nb\nc\nd", "Vjj<Esc>ggVjJ")),
+            vec!["a b", "c", "d"]
+        );
+        assert_eq!(lines(&run("a\nb\nc", "vjgJu")), vec!["a", "b", "c"]);
+
+        let svc = run("abcd", "r-");
+        assert_eq!(lines(&svc), vec!["-bcd"]);
+        assert_eq!(svc.editor_model.cursor_x, 0);
+        // A count up to the end of the line; past it, nothing changes.
+        let svc = run("abcd", "3rx");
+        assert_eq!(lines(&svc), vec!["xxxd"]);
+        assert_eq!(svc.editor_model.cursor_x, 2);
+        let svc = run("abcd", "l4rx");
+        assert_eq!(lines(&svc), vec!["abcd"]);
+        assert_eq!(svc.editor_model.cursor_x, 1);
+        // `r<CR>` replaces the chars with a single line break.
+        assert_eq!(lines(&run("ab cd", "ll2r<CR>")), vec!["ab", "d"]);
+        // `.` keeps the count unless given a new one.
+        assert_eq!(lines(&run("abcdef", "2rxl.")), vec!["xxxxef"]);
+        assert_eq!(lines(&run("abcdef", "2rxl3.")), vec!["xxxxxf"]);
+        assert_eq!(lines(&run("abcd", "2rxl.u")), vec!["xxcd"]);
+        assert_eq!(lines(&run("abcd", "2rxu")), vec!["abcd"]);
+        // <Esc> cancels.
+        assert_eq!(lines(&run("abcd", "r<Esc>x")), vec!["bcd"]);
+    }
+
+    #[test]
+    fn ctrl_a_and_ctrl_x_change_numbers() {
+        let svc = run("x = 9;", "<C-a>");
+        assert_eq!(lines(&svc), vec!["x = 10;"]);
+        assert_eq!(svc.editor_model.cursor_x, 5);
+        assert_eq!(lines(&run("a 0x0f", "5<C-x>")), vec!["a 0x0a"]);
+        assert_eq!(lines(&run("1", "3<C-x>.")), vec!["-5"]);
+        assert_eq!(lines(&run("1", "3<C-a>2.")), vec!["6"]);
+        assert_eq!(lines(&run("1", "<C-a><C-a>u")), vec!["2"]);
+        assert_eq!(lines(&run("abc", "<C-a>")), vec!["abc"]);
+
+        assert_eq!(
+            lines(&run("1 1\n1 1\nx\n1 1", "wVG<C-a>")),
+            vec!["2 1", "2 1", "x", "2 1"]
+        );
+        assert_eq!(
+            lines(&run("0 0\n0 0\nx x\n0 0", "w<C-v>3jg<C-a>")),
+            vec!["0 1", "0 2", "x x", "0 3"]
+        );
+        let svc = run("v0\nv0\nv0", "VG2g<C-x>u");
+        assert_eq!(lines(&svc), vec!["v0", "v0", "v0"]);
+        assert_eq!(
+            lines(&run("v0\nv0\nv0", "VG2g<C-x>")),
+            vec!["v-2", "v-4", "v-6"]
+        );
+    }
+
+    #[test]
+    fn replace_mode_overwrites_and_backspace_restores() {
+        let svc = run("abc", "lRxyz");
+        assert_eq!(svc.editor_model.mode, EditorMode::Replace);
+        assert_eq!(lines(&svc), vec!["axyz"]);
+        // Backspace brings back what was typed over, and takes away what
+        // was added past the end of the line or as a line break.
+        assert_eq!(lines(&run("abc", "Rxyzw<BS><BS><Esc>")), vec!["xyc"]);
+        let svc = run("abc", "lRxy<BS><BS>");
+        assert_eq!(lines(&svc), vec!["abc"]);
+        assert_eq!(svc.editor_model.cursor_x, 1);
+        assert_eq!(lines(&run("abcd", "Rx<CR>y<BS><BS><Esc>")), vec!["xbcd"]);
+        assert_eq!(lines(&run("abcd", "lRx<CR>y<Esc>")), vec!["ax", "yd"]);
+        // Before where Replace began it only moves left.
+        let svc = run("abc", "llRx<BS><BS><BS>");
+        assert_eq!(lines(&svc), vec!["abc"]);
+        assert_eq!(svc.editor_model.cursor_x, 0);
+        assert_eq!(lines(&run("ab", "Rxyz<BS><BS><BS><BS>q<Esc>")), vec!["qb"]);
+        // One undo step, and `.` types the same over the text at the
+        // cursor or past the end of the line; each repeat is one undo step.
+        assert_eq!(lines(&run("abcd", "Rxy<Esc>u")), vec!["abcd"]);
+        assert_eq!(lines(&run("abcdef", "Rxy<Esc>ll.")), vec!["xycdxy"]);
+        assert_eq!(lines(&run("abcdef", "Rxy<Esc>2.")), vec!["xyxyxy"]);
+        assert_eq!(lines(&run("abcdef", "Rxy<Esc>ll.u")), vec!["xycdef"]);
+        assert_eq!(lines(&run("ab\ncd", "Rxyz<Esc>j0.")), vec!["xyz", "xyz"]);
+        assert_eq!(lines(&run("abcdef", "Rx<BS>y<Esc>.")), vec!["yycdef"]);
+        assert_eq!(
+            lines(&run("abcd\nefgh", "Rx<CR>y<Esc>.")),
+            vec!["x", "yx", "y", "efgh"]
+        );
+    }
+}
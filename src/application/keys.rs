@@ -0,0 +1,121 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Named keys in Vim's `<...>` notation. `<lt>` stands for a literal `<`.
+const NAMED: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("BS", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("S-Tab", KeyCode::BackTab),
+    ("Del", KeyCode::Delete),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("lt", KeyCode::Char('<')),
+];
+
+/// Render keys as text in Vim notation (`dw`, `ifoo<Esc>`, `<C-v>`), so a
+/// recorded macro can live in a register and be edited like yanked text.
+pub fn encode(keys: &[KeyEvent]) -> String {
+    let mut out = String::new();
+    for key in keys {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char(c) if ctrl => out.push_str(&format!("<C-{}>", c)),
+            KeyCode::Char('<') => out.push_str("<lt>"),
+            KeyCode::Char(c) => out.push(c),
+            // Keys without a notation (function keys, …) are not recorded.
+            code => {
+                if let Some((name, _)) = NAMED.iter().find(|(_, k)| *k == code) {
+                    out.push_str(&format!("<{}>", name));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Parse text in the notation of [`encode`] back into keys. A raw newline (as in
+/// a linewise register) is Enter; a `<` that starts no known name is literal.
+pub fn parse(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some((key, len)) = parse_named(rest) {
+                keys.push(key);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        let code = if c == '\n' {
+            KeyCode::Enter
+        } else {
+            KeyCode::Char(c)
+        };
+        keys.push(KeyEvent::new(code, KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+/// Parse a `<...>` key at the start of `text`, returning it and its byte length.
+fn parse_named(text: &str) -> Option<(KeyEvent, usize)> {
+    let end = text.find('>')?;
+    let name = &text[1..end];
+    if let Some(c) = name.strip_prefix("C-") {
+        let mut chars = c.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+        return Some((
+            KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL),
+            end + 1,
+        ));
+    }
+    NAMED
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, code)| (KeyEvent::new(*code, KeyModifiers::NONE), end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn round_trips_special_keys() {
+        let keys = vec![
+            key(KeyCode::Char('i')),
+            key(KeyCode::Char('<')),
+            key(KeyCode::Esc),
+            KeyEvent::new(KeyCode::Char('v'), KeyModifiers::CONTROL),
+            key(KeyCode::Enter),
+        ];
+        let text = encode(&keys);
+        assert_eq!(text, "i<lt><Esc><C-v><CR>");
+        assert_eq!(parse(&text), keys);
+    }
+
+    #[test]
+    fn unknown_names_and_newlines() {
+        assert_eq!(
+            parse("<x>\n"),
+            vec![
+                key(KeyCode::Char('<')),
+                key(KeyCode::Char('x')),
+                key(KeyCode::Char('>')),
+                key(KeyCode::Enter),
+            ]
+        );
+    }
+}
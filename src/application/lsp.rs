@@ -15,14 +15,15 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use async_lsp::lsp_types::{
-    CompletionItem, CompletionParams, CompletionResponse, Diagnostic, DiagnosticSeverity,
-    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-    DocumentChangeOperation, DocumentChanges, DocumentFormattingParams, FormattingOptions,
-    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
-    InitializedParams, MarkedString, OneOf, PartialResultParams, Position, PositionEncodingKind,
-    ProgressParamsValue, RenameParams, TextDocumentContentChangeEvent, TextDocumentIdentifier,
-    TextDocumentItem, TextDocumentPositionParams, TextEdit, Url, VersionedTextDocumentIdentifier,
-    WorkDoneProgress, WorkDoneProgressParams, WorkspaceEdit,
+    CompletionItem, CompletionParams, CompletionResponse, CompletionTextEdit, Diagnostic,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentChangeOperation, DocumentChanges, DocumentFormattingParams,
+    FormattingOptions, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, InitializedParams, InsertTextFormat, MarkedString, OneOf, PartialResultParams,
+    Position, PositionEncodingKind, ProgressParamsValue, RenameParams,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, TextEdit, Url, VersionedTextDocumentIdentifier, WorkDoneProgress,
+    WorkDoneProgressParams, WorkspaceEdit,
 };
 use async_lsp::{LanguageServer, ServerSocket};
 use tokio::sync::mpsc::UnboundedSender;
@@ -71,7 +72,8 @@ pub enum ApplyOutcome {
     Edited,
 }
 
-/// Active completion popup state.
+/// Active completion popup state: server results, snippet choices, or the
+/// buffer words and file paths of `Ctrl-x` completion.
 struct CompletionState {
     /// All items returned by the server.
     items: Vec<CompletionItem>,
@@ -118,6 +120,8 @@ pub struct Lsp {
     completion: Option<CompletionState>,
     /// Monotonic id tagging completion requests so stale results are dropped.
     completion_gen: u64,
+    /// Set by `Ctrl-x` in Insert mode, for the completion key after it.
+    ctrl_x: bool,
     /// Title of an in-progress server work item (e.g. indexing), for the status
     /// line; `None` when idle.
     progress: Option<String>,
@@ -139,6 +143,7 @@ impl Lsp {
             hover: None,
             completion: None,
             completion_gen: 0,
+            ctrl_x: false,
             progress: None,
         }
     }
@@ -402,19 +407,114 @@ impl Lsp {
             return;
         }
         let (prefix, anchor_x) = word_prefix(model, model.cursor_y, model.cursor_x);
+        self.show_completion(items, model.cursor_y, anchor_x, &prefix);
+    }
+
+    /// Open the popup on the `items` matching `prefix`, which starts at
+    /// `(anchor_y, anchor_x)`. Returns false (and closes it) when none match.
+    fn show_completion(
+        &mut self,
+        items: Vec<CompletionItem>,
+        anchor_y: usize,
+        anchor_x: usize,
+        prefix: &str,
+    ) -> bool {
         let mut state = CompletionState {
             items,
             filtered: Vec::new(),
             selected: 0,
-            anchor_y: model.cursor_y,
+            anchor_y,
             anchor_x,
         };
-        filter_completion(&mut state, &prefix);
-        self.completion = if state.filtered.is_empty() {
-            None
-        } else {
-            Some(state)
+        filter_completion(&mut state, prefix);
+        self.completion = (!state.filtered.is_empty()).then_some(state);
+        self.completion.is_some()
+    }
+
+    /// `Ctrl-x` in Insert mode: the next key picks a completion source.
+    pub fn begin_ctrl_x(&mut self) {
+        self.ctrl_x = true;
+    }
+
+    /// Whether the key being handled follows `Ctrl-x`, clearing it.
+    pub fn take_ctrl_x(&mut self) -> bool {
+        std::mem::take(&mut self.ctrl_x)
+    }
+
+    /// `Ctrl-x Ctrl-n`: complete the word before the cursor from the words
+    /// in the buffer (the only one open), nearest after it first.
+    /// A server result still on its way is dropped. Returns false when no
+    /// word matches.
+    pub fn complete_words(&mut self, model: &EditorModel) -> bool {
+        self.completion_gen += 1;
+        let (prefix, anchor_x) = word_prefix(model, model.cursor_y, model.cursor_x);
+        let at = model.buffer.cursor_to_char(model.cursor_y, anchor_x);
+        let items = buffer_words(&model.buffer.raw_content(), at)
+            .into_iter()
+            .filter(|word| *word != prefix)
+            .map(|label| CompletionItem {
+                label,
+                ..CompletionItem::default()
+            })
+            .collect();
+        self.show_completion(items, model.cursor_y, anchor_x, &prefix)
+    }
+
+    /// `Ctrl-x Ctrl-f`: complete the file path before the cursor with the
+    /// entries of its directory, taken relative to the buffer's own directory
+    /// (or from `/` or `~/`). Directories end in `/`; dotfiles are offered
+    /// only for a name starting with `.`. Returns false when none match.
+    pub fn complete_paths(&mut self, model: &EditorModel) -> bool {
+        self.completion_gen += 1;
+        let line: Vec<char> = model.buffer.line_text(model.cursor_y).chars().collect();
+        let x = model.cursor_x.min(line.len());
+        let mut start = x;
+        while start > 0 && is_path_char(line[start - 1]) {
+            start -= 1;
+        }
+        let typed: String = line[start..x].iter().collect();
+        let (dir, name) = match typed.rfind('/') {
+            Some(i) => typed.split_at(i + 1),
+            None => ("", typed.as_str()),
         };
+        let base = match dir.strip_prefix("~/") {
+            Some(rest) => std::env::var_os("HOME")
+                .map_or_else(cwd, PathBuf::from)
+                .join(rest),
+            None => model
+                .get_filepath()
+                .and_then(|p| Path::new(p).parent())
+                .filter(|p| !p.as_os_str().is_empty())
+                .map_or_else(cwd, Path::to_path_buf)
+                .join(dir),
+        };
+        let Ok(entries) = std::fs::read_dir(base) else {
+            self.completion = None;
+            return false;
+        };
+        let mut labels: Vec<String> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let mut label = entry.file_name().to_str()?.to_string();
+                if label.starts_with('.') && !name.starts_with('.') {
+                    return None;
+                }
+                if entry.path().is_dir() {
+                    label.push('/');
+                }
+                Some(label)
+            })
+            .collect();
+        labels.sort();
+        let items = labels
+            .into_iter()
+            .map(|label| CompletionItem {
+                label,
+                ..CompletionItem::default()
+            })
+            .collect();
+        let anchor_x = x - name.chars().count();
+        self.show_completion(items, model.cursor_y, anchor_x, name)
     }
 
     /// Re-filter the open completion popup against the word now under the cursor
@@ -440,9 +540,34 @@ impl Lsp {
         }
     }
 
+    /// Offer the choices of the snippet tabstop the cursor is at, if it has
+    /// any, in the completion popup; accepting one replaces the tabstop's
+    /// text.
+    pub fn open_snippet_choices(&mut self, model: &EditorModel) {
+        let Some((choices, anchor_y, anchor_x)) = model.snippet_choices() else {
+            return;
+        };
+        self.completion = Some(CompletionState {
+            filtered: (0..choices.len()).collect(),
+            items: choices
+                .into_iter()
+                .map(|label| CompletionItem {
+                    label,
+                    ..CompletionItem::default()
+                })
+                .collect(),
+            selected: 0,
+            anchor_y,
+            anchor_x,
+        });
+    }
+
     /// Accept the selected completion: replace the word under the cursor with
-    /// the item's insert text (one undo step) and place the cursor after it.
-    /// Returns `true` if something was inserted.
+    /// the item's text (its text edit, else its insert text or label) as one
+    /// undo step, and place the cursor after it.
+    /// A snippet is expanded, with the cursor at its first tabstop (see
+    /// [`EditorModel::insert_snippet`]). Returns `true` if something was
+    /// inserted.
     pub fn completion_accept<T: FileIO>(&mut self, svc: &mut EditorService<T>) -> bool {
         let Some(c) = self.completion.take() else {
             return false;
@@ -451,23 +576,47 @@ impl Lsp {
             return false;
         };
         let item = &c.items[item_idx];
-        let text = item
-            .insert_text
-            .clone()
-            .unwrap_or_else(|| item.label.clone());
-        let start = svc
-            .editor_model
-            .buffer
-            .cursor_to_char(c.anchor_y, c.anchor_x);
-        let end = svc
-            .editor_model
-            .buffer
-            .cursor_to_char(svc.editor_model.cursor_y, svc.editor_model.cursor_x);
+        let buffer = &svc.editor_model.buffer;
+        let cursor = buffer.cursor_to_char(svc.editor_model.cursor_y, svc.editor_model.cursor_x);
+        let to_char = |pos| {
+            let (y, x) = position::from_lsp(buffer, self.encoding, pos);
+            buffer.cursor_to_char(y, x)
+        };
+        // A server may send the text as an edit over the word (rust-analyzer
+        // does); it was computed before any keys typed since, so the edit
+        // runs on to the cursor.
+        let edit = match &item.text_edit {
+            Some(CompletionTextEdit::Edit(e)) => Some((e.range, &e.new_text)),
+            Some(CompletionTextEdit::InsertAndReplace(e)) => Some((e.replace, &e.new_text)),
+            None => None,
+        };
+        let (start, end, text) = match edit {
+            Some((range, text)) => (
+                to_char(range.start),
+                to_char(range.end).max(cursor),
+                text.clone(),
+            ),
+            None => (
+                buffer.cursor_to_char(c.anchor_y, c.anchor_x),
+                cursor,
+                item.insert_text
+                    .clone()
+                    .unwrap_or_else(|| item.label.clone()),
+            ),
+        };
+        if item.insert_text_format == Some(InsertTextFormat::SNIPPET) {
+            svc.editor_model.insert_snippet(start, end, &text);
+            self.open_snippet_choices(&svc.editor_model);
+            return true;
+        }
+        let start_y = buffer.char_to_line(start);
+        let start_x = start - buffer.line_to_char(start_y);
         let inserted_chars = text.chars().count();
         if svc.editor_model.apply_lsp_edits(vec![(start, end, text)]) {
             // Place the cursor just after the inserted text (single-line items).
-            svc.editor_model
-                .goto(c.anchor_y, c.anchor_x + inserted_chars);
+            svc.editor_model.goto(start_y, start_x + inserted_chars);
+            // A snippet choice goes to the tabstop's mirrors too.
+            svc.editor_model.sync_snippet();
             true
         } else {
             false
@@ -800,6 +949,37 @@ fn is_ident_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_'
 }
 
+/// Chars `Ctrl-x Ctrl-f` takes as part of a file path.
+fn is_path_char(c: char) -> bool {
+    is_ident_char(c) || matches!(c, '/' | '.' | '-' | '+' | '~')
+}
+
+/// The distinct words of `text`, nearest at or after char `at` first and
+/// wrapping around to the start.
+fn buffer_words(text: &str, at: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut words: Vec<(usize, String)> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_ident_char(chars[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && is_ident_char(chars[i]) {
+            i += 1;
+        }
+        let distance = (start + chars.len() - at) % chars.len();
+        words.push((distance, chars[start..i].iter().collect()));
+    }
+    words.sort_by_key(|(distance, _)| *distance);
+    let mut seen = std::collections::HashSet::new();
+    words
+        .into_iter()
+        .filter_map(|(_, word)| seen.insert(word.clone()).then_some(word))
+        .collect()
+}
+
 /// Recompute `state.filtered` as the items whose label starts with `prefix`
 /// (case-insensitive), resetting the selection to the top.
 fn filter_completion(state: &mut CompletionState, prefix: &str) {
@@ -907,6 +1087,7 @@ fn severity_label(s: DiagSeverity) -> &'static str {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::editor_model::EditorMode;
     use crate::infrastructure::file_io::LocalFileIO;
     use async_lsp::lsp_types::{
         Diagnostic, DiagnosticSeverity, Position, PublishDiagnosticsParams, Range,
@@ -969,6 +1150,146 @@ mod tests {
         assert_eq!(lsp.diagnostic_summary(), "1 error(s)");
     }
 
+    #[test]
+    fn snippet_completions_expand_to_their_tabstops() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut lsp = Lsp::new(tx);
+        let mut svc = EditorService::new(LocalFileIO);
+        let mut status = String::new();
+        svc.editor_model.set_content("let v = ve");
+        svc.editor_model.set_mode(EditorMode::Insert);
+        svc.editor_model.goto(0, 10);
+        let item = CompletionItem {
+            label: "vec!".to_string(),
+            insert_text: Some("vec![${1:x}; ${2:n}]$0".to_string()),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..CompletionItem::default()
+        };
+        lsp.apply(
+            LspEvent::Completion(0, Some(CompletionResponse::Array(vec![item]))),
+            &mut svc,
+            &mut status,
+        );
+        assert!(lsp.completion_accept(&mut svc));
+        assert_eq!(svc.editor_model.buffer.line_text(0), "let v = vec![x; n]");
+        assert_eq!(svc.editor_model.cursor_x, 13);
+        svc.insert_char('0');
+        assert!(svc.editor_model.snippet_jump(true));
+        svc.insert_char('3');
+        assert!(svc.editor_model.snippet_jump(true));
+        assert_eq!(svc.editor_model.buffer.line_text(0), "let v = vec![0; 3]");
+        assert_eq!(svc.editor_model.cursor_x, 18);
+    }
+
+    #[test]
+    fn snippet_completions_take_their_text_edit() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut lsp = Lsp::new(tx);
+        let mut svc = EditorService::new(LocalFileIO);
+        let mut status = String::new();
+        svc.editor_model.set_content("let v = ve");
+        svc.editor_model.set_mode(EditorMode::Insert);
+        svc.editor_model.goto(0, 10);
+        let range = Range::new(Position::new(0, 8), Position::new(0, 10));
+        let item = CompletionItem {
+            label: "vec!".to_string(),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                range,
+                "vec![${1:x}]$0".to_string(),
+            ))),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..CompletionItem::default()
+        };
+        lsp.apply(
+            LspEvent::Completion(0, Some(CompletionResponse::Array(vec![item]))),
+            &mut svc,
+            &mut status,
+        );
+        assert!(lsp.completion_accept(&mut svc));
+        assert_eq!(svc.editor_model.buffer.line_text(0), "let v = vec![x]");
+        assert_eq!(svc.editor_model.cursor_x, 13);
+    }
+
+    /// The labels the completion popup shows, in order.
+    fn shown(lsp: &Lsp) -> Vec<String> {
+        lsp.completion.as_ref().map_or(Vec::new(), |c| {
+            c.filtered
+                .iter()
+                .map(|&i| c.items[i].label.clone())
+                .collect()
+        })
+    }
+
+    /// A model holding `content`, in Insert mode at `(y, x)`.
+    fn typing(content: &str, y: usize, x: usize) -> EditorModel {
+        let mut model = EditorModel::new();
+        model.set_content(content);
+        model.set_mode(EditorMode::Insert);
+        model.goto(y, x);
+        model
+    }
+
+    #[test]
+    fn word_completion_offers_the_nearest_words_first() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut lsp = Lsp::new(tx);
+        // From the word being typed on: forward, then wrapping to the top.
+        let model = typing("alpha al\nbeta also al\nalways", 1, 12);
+        assert!(lsp.complete_words(&model));
+        assert_eq!(shown(&lsp), vec!["always", "alpha", "also"]);
+        // The word being typed is not offered, wherever else it appears.
+        assert!(!shown(&lsp).contains(&"al".to_string()));
+        // Nothing to offer: no popup.
+        let model = typing("one two\nq", 1, 1);
+        assert!(!lsp.complete_words(&model));
+        assert!(lsp.completion.is_none());
+    }
+
+    #[test]
+    fn path_completion_lists_the_typed_directory() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut lsp = Lsp::new(tx);
+        let dir = std::env::temp_dir().join(format!("rim-paths-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/inner.rs"), "").unwrap();
+        std::fs::write(dir.join("notes.md"), "").unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+        let mut complete = |line: &str| {
+            let mut model = typing(line, 0, line.chars().count());
+            model.set_filepath(dir.join("x.md").to_string_lossy().into_owned());
+            let found = lsp.complete_paths(&model);
+            (found, shown(&lsp))
+        };
+        // Relative to the buffer's directory, directories ending in `/`, and
+        // dotfiles only for a name starting with `.`.
+        let all = complete("see ");
+        let hidden = complete("see .");
+        let nested = complete("see nested/in");
+        let missing = complete("see nested/zz");
+        let no_dir = complete("see nowhere/");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(
+            all,
+            (true, vec!["nested/".to_string(), "notes.md".to_string()])
+        );
+        assert_eq!(hidden, (true, vec![".hidden".to_string()]));
+        assert_eq!(nested, (true, vec!["inner.rs".to_string()]));
+        assert_eq!(missing, (false, Vec::new()));
+        assert_eq!(no_dir, (false, Vec::new()));
+
+        // `~/` is the home directory.
+        if let Some(home) = std::env::var_os("HOME").map(PathBuf::from) {
+            let model = typing("~/", 0, 2);
+            let found = lsp.complete_paths(&model);
+            let labels = shown(&lsp);
+            assert_eq!(found, !labels.is_empty());
+            for label in labels {
+                assert!(!label.starts_with('.'));
+                assert!(home.join(label.trim_end_matches('/')).exists());
+            }
+        }
+    }
+
     /// Full orchestrator lifecycle against a real `rust-analyzer`, over a tiny
     /// temporary crate so indexing is fast: open a broken file, drive the
     /// handshake to Active, and confirm diagnostics land in the store. Ignored
@@ -1,6 +1,9 @@
 pub mod commands;
 pub mod editor_service;
+pub mod input;
+pub mod keys;
 pub mod lsp;
 pub mod normal_mode;
 pub mod position;
+pub mod shell;
 pub mod syntax;
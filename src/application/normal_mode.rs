@@ -1,7 +1,8 @@
 use crate::application::editor_service::EditorService;
 use crate::application::lsp::LspRequest;
-use crate::domain::editor_model::{EditorMode, Operator};
+use crate::domain::editor_model::{EditorMode, InsertEntry, Operator, OperatorRange, VisualKind};
 use crate::domain::motion::Motion;
+use crate::domain::register::Registers;
 use crate::domain::text_object::TextObject;
 use crate::infrastructure::file_io::FileIO;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
@@ -12,20 +13,53 @@ pub enum NormalResult {
     Quit,
 }
 
+/// How far a surround command has got.
+#[derive(Clone, Copy)]
+enum Surround {
+    /// `ys`, awaiting a motion or text object.
+    Add,
+    /// `ys{motion}`, awaiting the character to surround `range` with.
+    AddOver(OperatorRange, usize),
+    /// `ds` (`false`) or `cs` (`true`), awaiting the delimiter to change.
+    Change(bool),
+    /// `cs{old}`, awaiting the new delimiter.
+    ChangeTo(char),
+}
+
 /// Normal-mode input interpreter implementing Vim's compositional grammar:
-/// an optional count, an optional operator (`d`/`c`/`y`) with its own optional
-/// count, and a motion or text object. State accumulates across keystrokes
-/// until a complete command is recognised, then resets.
+/// an optional `"x` register and count, an optional operator (`d`/`c`/`y`)
+/// with its own optional count, and a motion or text object. State
+/// accumulates across keystrokes until a complete command is recognised,
+/// then resets.
 #[derive(Default)]
 pub struct NormalMode {
     count: Option<usize>,
+    /// Register selected with a `"x` prefix; `pending_register` is set after
+    /// `"` while awaiting the name.
+    register: Option<char>,
+    pending_register: bool,
     operator: Option<Operator>,
     op_count: Option<usize>,
     pending_g: bool,
-    /// Set to `Some(inner)` after an operator + `i`/`a`, awaiting the object key.
+    /// Set to `Some(inner)` after an operator (or in Visual mode) + `i`/`a`,
+    /// awaiting the object key.
     pending_object: Option<bool>,
     /// Set to `Some((till, forward))` after `f`/`t`/`F`/`T`, awaiting the target.
     pending_find: Option<(bool, bool)>,
+    /// Set after `q` (`Some(true)`, record) or `@` (`Some(false)`, play),
+    /// awaiting the register name.
+    pending_macro: Option<bool>,
+    /// Set after `Z`, awaiting `Q`.
+    pending_z: bool,
+    /// Set after `m`, awaiting the mark name.
+    pending_set_mark: bool,
+    /// Set after `r`, awaiting the replacement character.
+    pending_replace: bool,
+    /// Set to `Some(linewise)` after `'` (linewise) or `` ` ``, awaiting the
+    /// mark to jump to.
+    pending_mark: Option<bool>,
+    /// A surround command (`ys`, `ds`, `cs`) in progress.
+    surround: Option<Surround>,
     /// The last completed `f`/`t`/`F`/`T` as `(target, till, forward)`, for
     /// `;` (repeat) and `,` (repeat reversed). Persists across commands.
     last_find: Option<(char, bool, bool)>,
@@ -38,11 +72,19 @@ impl NormalMode {
 
     fn reset(&mut self) {
         self.count = None;
+        self.register = None;
+        self.pending_register = false;
         self.operator = None;
         self.op_count = None;
         self.pending_g = false;
         self.pending_object = None;
         self.pending_find = None;
+        self.pending_macro = None;
+        self.pending_z = false;
+        self.pending_set_mark = false;
+        self.pending_replace = false;
+        self.pending_mark = None;
+        self.surround = None;
     }
 
     /// Combined repeat count: a count before the operator multiplies a count
@@ -74,16 +116,33 @@ impl NormalMode {
         status: &mut String,
     ) {
         let count = self.effective_count();
+        let m = &svc.editor_model;
+        let failed = match m.motion_target(motion, count) {
+            Some(t) => (t.y, t.x) == (m.cursor_y, m.cursor_x) && motion.fails_in_place(),
+            None => true,
+        };
+        if failed {
+            // A failed motion ends macro playback, as in Vim.
+            svc.abort_macro();
+        }
+        if let Some(Surround::Add) = self.surround {
+            self.surround = Some(Surround::AddOver(OperatorRange::Motion(motion), count));
+            return;
+        }
         if let Some(op) = self.operator {
-            let enter_insert = svc.editor_model.apply_operator(op, motion, count);
+            let enter_insert = svc
+                .editor_model
+                .at_each_cursor(|m| m.apply_operator(op, motion, count));
             if enter_insert {
                 svc.set_mode(EditorMode::Insert);
                 *status = "-- INSERT --".to_string();
             } else {
                 status.clear();
             }
+            open_filter_command(svc, status);
         } else {
-            svc.editor_model.move_by_motion(motion, count);
+            svc.editor_model
+                .at_each_cursor(|m| m.move_by_motion(motion, count));
             status.clear();
         }
         self.reset();
@@ -98,13 +157,16 @@ impl NormalMode {
         if self.operator == Some(op) {
             // Doubled operator: dd / cc / yy operate on whole lines.
             let count = self.effective_count();
-            let enter_insert = svc.editor_model.operate_current_lines(op, count);
+            let enter_insert = svc
+                .editor_model
+                .at_each_cursor(|m| m.operate_current_lines(op, count));
             if enter_insert {
                 svc.set_mode(EditorMode::Insert);
                 *status = "-- INSERT --".to_string();
             } else {
                 status.clear();
             }
+            open_filter_command(svc, status);
             self.reset();
         } else if self.operator.is_some() {
             // A different operator after one already pending cancels.
@@ -120,12 +182,285 @@ impl NormalMode {
         *status = "-- INSERT --".to_string();
     }
 
+    /// `[count]J` / `gJ`, or in Visual mode the selected lines.
+    fn join<T: FileIO>(&mut self, svc: &mut EditorService<T>, spaces: bool, status: &mut String) {
+        let joined = if matches!(svc.editor_model.mode, EditorMode::Visual(_)) {
+            svc.editor_model.join_visual(spaces)
+        } else {
+            let count = self.effective_count();
+            svc.editor_model.at_each_cursor(|m| m.join(count, spaces))
+        };
+        if !joined {
+            svc.abort_macro();
+        }
+        self.reset();
+        status.clear();
+    }
+
+    /// A `g`-prefixed operator that also acts on the Visual selection (`gc`,
+    /// `gq`, `gw`).
+    fn g_operator<T: FileIO>(
+        &mut self,
+        svc: &mut EditorService<T>,
+        op: Operator,
+        status: &mut String,
+    ) {
+        if matches!(svc.editor_model.mode, EditorMode::Visual(_)) {
+            svc.editor_model.apply_operator_visual(op);
+            self.reset();
+            status.clear();
+        } else {
+            self.handle_operator(svc, op, status);
+        }
+    }
+
+    /// `Ctrl-a` / `Ctrl-x` (`sign` 1 / -1) adding the count; in Visual mode
+    /// to each selected line, as a sequence with `progressive` (`g Ctrl-a`).
+    fn increment<T: FileIO>(
+        &mut self,
+        svc: &mut EditorService<T>,
+        sign: i64,
+        progressive: bool,
+        status: &mut String,
+    ) {
+        let delta = sign * self.effective_count() as i64;
+        let changed = if matches!(svc.editor_model.mode, EditorMode::Visual(_)) {
+            svc.editor_model.increment_visual(delta, progressive)
+        } else {
+            svc.editor_model.at_each_cursor(|m| m.increment(delta))
+        };
+        if !changed {
+            svc.abort_macro();
+        }
+        self.reset();
+        status.clear();
+    }
+
+    fn start_visual<T: FileIO>(
+        &mut self,
+        svc: &mut EditorService<T>,
+        kind: VisualKind,
+        status: &mut String,
+    ) {
+        svc.editor_model.start_visual(kind);
+        *status = visual_status(kind).to_string();
+        self.reset();
+    }
+
+    /// Visual-mode commands acting on the selection. Returns false for keys
+    /// that fall through to the shared grammar (counts and motions, which move
+    /// the cursor end of the selection).
+    fn feed_visual<T: FileIO>(
+        &mut self,
+        svc: &mut EditorService<T>,
+        kind: VisualKind,
+        ev: &KeyEvent,
+        status: &mut String,
+    ) -> bool {
+        let ctrl = ev.modifiers.contains(KeyModifiers::CONTROL);
+        match ev.code {
+            // `i`/`a` await a text object to extend the selection over.
+            KeyCode::Char('i') => self.pending_object = Some(true),
+            KeyCode::Char('a') if !ctrl => self.pending_object = Some(false),
+            _ => {
+                if !self.visual_command(svc, kind, ev, status) {
+                    return false;
+                }
+                self.reset();
+            }
+        }
+        true
+    }
+
+    /// The selection commands themselves; true when `ev` was one.
+    fn visual_command<T: FileIO>(
+        &mut self,
+        svc: &mut EditorService<T>,
+        kind: VisualKind,
+        ev: &KeyEvent,
+        status: &mut String,
+    ) -> bool {
+        let ctrl = ev.modifiers.contains(KeyModifiers::CONTROL);
+        let m = &mut svc.editor_model;
+        let op = match ev.code {
+            KeyCode::Esc => {
+                svc.set_mode(EditorMode::Normal);
+                status.clear();
+                return true;
+            }
+            // Pressing the key of the current kind leaves Visual mode; another
+            // kind switches to it.
+            KeyCode::Char('v') | KeyCode::Char('V') => {
+                let new = match ev.code {
+                    KeyCode::Char('v') if ctrl => VisualKind::Block,
+                    KeyCode::Char('v') => VisualKind::Char,
+                    _ => VisualKind::Line,
+                };
+                if new == kind {
+                    svc.set_mode(EditorMode::Normal);
+                    status.clear();
+                } else {
+                    m.set_visual_kind(new);
+                    *status = visual_status(new).to_string();
+                }
+                return true;
+            }
+            KeyCode::Char('o') => {
+                m.visual_swap_ends();
+                return true;
+            }
+            // The command line then applies to the selected lines; `!` starts
+            // a filter command for them.
+            KeyCode::Char(c @ (':' | '!')) => {
+                svc.set_mode(EditorMode::Command);
+                let range = if c == '!' { "'<,'>!" } else { "'<,'>" };
+                range.chars().for_each(|c| svc.push_command_char(c));
+                *status = format!(":{}", svc.editor_model.command_buffer);
+                return true;
+            }
+            // One cursor per selected line (every line of a block).
+            KeyCode::Char('n') if ctrl => {
+                m.split_selection_into_cursors();
+                status.clear();
+                return true;
+            }
+            KeyCode::Char('p') | KeyCode::Char('P') => {
+                m.paste_visual(self.register, ev.code == KeyCode::Char('p'));
+                status.clear();
+                return true;
+            }
+            KeyCode::Char('I') | KeyCode::Char('A') if kind == VisualKind::Block => {
+                if m.start_block_insert(ev.code == KeyCode::Char('A')) {
+                    self.enter_insert(svc, status);
+                }
+                return true;
+            }
+            KeyCode::Char(c @ ('a' | 'x')) if ctrl => {
+                self.increment(svc, if c == 'a' { 1 } else { -1 }, false, status);
+                return true;
+            }
+            KeyCode::Char('d') | KeyCode::Char('x') | KeyCode::Delete => Operator::Delete,
+            KeyCode::Char('c') | KeyCode::Char('s') => Operator::Change,
+            KeyCode::Char('y') => Operator::Yank,
+            KeyCode::Char('~') => Operator::SwitchCase,
+            KeyCode::Char('u') => Operator::Lowercase,
+            KeyCode::Char('U') => Operator::Uppercase,
+            KeyCode::Char('>') => Operator::ShiftRight,
+            KeyCode::Char('<') => Operator::ShiftLeft,
+            KeyCode::Char('=') => Operator::Reindent,
+            KeyCode::Char('J') => {
+                self.join(svc, true, status);
+                return true;
+            }
+            // Uppercase variants act on whole lines whatever the kind.
+            KeyCode::Char('D') | KeyCode::Char('X') => {
+                m.set_visual_kind(VisualKind::Line);
+                Operator::Delete
+            }
+            KeyCode::Char('C') | KeyCode::Char('S') | KeyCode::Char('R') => {
+                m.set_visual_kind(VisualKind::Line);
+                Operator::Change
+            }
+            KeyCode::Char('Y') => {
+                m.set_visual_kind(VisualKind::Line);
+                Operator::Yank
+            }
+            _ => return false,
+        };
+        if svc.editor_model.apply_operator_visual(op) {
+            self.enter_insert(svc, status);
+        } else {
+            status.clear();
+        }
+        true
+    }
+
     pub fn feed<T: FileIO>(
         &mut self,
         svc: &mut EditorService<T>,
         ev: &KeyEvent,
         status: &mut String,
     ) -> NormalResult {
+        // Name of a `"x` register prefix.
+        if self.pending_register {
+            self.pending_register = false;
+            match ev.code {
+                KeyCode::Char(c) if Registers::is_valid(c) => self.register = Some(c),
+                _ => {
+                    self.reset();
+                    status.clear();
+                }
+            }
+            return NormalResult::Continue;
+        }
+        // Whatever this key completes writes to the selected register.
+        svc.editor_model.register_name = self.register;
+
+        // The characters of a surround command.
+        if let Some(
+            surround @ (Surround::AddOver(..) | Surround::Change(_) | Surround::ChangeTo(_)),
+        ) = self.surround
+        {
+            let KeyCode::Char(c) = ev.code else {
+                self.reset();
+                status.clear();
+                return NormalResult::Continue;
+            };
+            let count = self.effective_count();
+            let m = &mut svc.editor_model;
+            match surround {
+                Surround::Change(true) => {
+                    self.surround = Some(Surround::ChangeTo(c));
+                    return NormalResult::Continue;
+                }
+                Surround::AddOver(range, count) => {
+                    m.at_each_cursor(|m| {
+                        m.apply_operator_range(Operator::Surround(c), range, count)
+                    });
+                }
+                Surround::Change(false) => {
+                    m.at_each_cursor(|m| m.change_surround(c, None, count));
+                }
+                Surround::ChangeTo(old) => {
+                    m.at_each_cursor(|m| m.change_surround(old, Some(c), count));
+                }
+                Surround::Add => {}
+            }
+            self.reset();
+            status.clear();
+            return NormalResult::Continue;
+        }
+
+        // `ZQ`: quit without writing.
+        if self.pending_z {
+            self.reset();
+            if ev.code == KeyCode::Char('Q') {
+                return NormalResult::Quit;
+            }
+            status.clear();
+            return NormalResult::Continue;
+        }
+
+        // Register of `q{reg}` (record) or `[count]@{reg}` (play).
+        if let Some(record) = self.pending_macro.take() {
+            match ev.code {
+                KeyCode::Char(c) if record && (c.is_ascii_alphanumeric() || c == '"') => {
+                    svc.start_recording(c);
+                    *status = format!("recording @{}", c);
+                }
+                KeyCode::Char(c) if !record => {
+                    if !svc.play_macro(c, self.effective_count()) {
+                        *status = format!("Register {} is empty", c);
+                    } else {
+                        status.clear();
+                    }
+                }
+                _ => status.clear(),
+            }
+            self.reset();
+            return NormalResult::Continue;
+        }
+
         // Second key of a `g`-prefixed command.
         if self.pending_g {
             self.pending_g = false;
@@ -151,6 +486,55 @@ impl NormalMode {
                     svc.request_lsp(LspRequest::Definition { y, x });
                     status.clear();
                 }
+                // `g-` / `g+`: step through undo states in time order, across
+                // branches of the undo tree.
+                KeyCode::Char(c @ ('-' | '+')) => {
+                    let m = &mut svc.editor_model;
+                    m.undo_chronological(self.effective_count(), c == '+');
+                    let (state, last) = m.undo_state();
+                    *status = format!("Undo state {} of {}", state, last);
+                    self.reset();
+                }
+                // `g~`, `gu`, `gU`: case operators.
+                KeyCode::Char('~') => self.handle_operator(svc, Operator::SwitchCase, status),
+                KeyCode::Char('u') => self.handle_operator(svc, Operator::Lowercase, status),
+                KeyCode::Char('U') => self.handle_operator(svc, Operator::Uppercase, status),
+                // Visual `g Ctrl-a` / `g Ctrl-x`: number the selected lines
+                // in sequence.
+                KeyCode::Char(c @ ('a' | 'x'))
+                    if ev.modifiers.contains(KeyModifiers::CONTROL)
+                        && matches!(svc.editor_model.mode, EditorMode::Visual(_)) =>
+                {
+                    self.increment(svc, if c == 'a' { 1 } else { -1 }, true, status);
+                }
+                // `gc`: the comment operator; in Visual mode, a block comment
+                // around a charwise selection, else line comments.
+                KeyCode::Char('c') => match svc.editor_model.mode {
+                    EditorMode::Visual(VisualKind::Char) => {
+                        self.g_operator(svc, Operator::BlockComment, status)
+                    }
+                    _ => self.g_operator(svc, Operator::Comment, status),
+                },
+                // `gq` / `gw`: reflow lines.
+                KeyCode::Char(c @ ('q' | 'w')) => {
+                    let op = if c == 'q' {
+                        Operator::Format
+                    } else {
+                        Operator::FormatKeep
+                    };
+                    self.g_operator(svc, op, status);
+                }
+                // `gJ`: join lines as they are.
+                KeyCode::Char('J') => {
+                    self.join(svc, false, status);
+                }
+                // `g&`: repeat the last `:s`, with its flags, on every line.
+                KeyCode::Char('&') => {
+                    let last = svc.editor_model.buffer.line_count().saturating_sub(1);
+                    let result = svc.editor_model.repeat_substitute(0, last, true);
+                    *status = result.unwrap_or_else(|e| e);
+                    self.reset();
+                }
                 _ => {
                     self.reset();
                     status.clear();
@@ -177,16 +561,24 @@ impl NormalMode {
                 KeyCode::Char('p') => Some(TextObject::Paragraph),
                 _ => None,
             };
-            if let (Some(obj), Some(op)) = (obj, self.operator) {
+            if let (Some(obj), EditorMode::Visual(_)) = (obj, svc.editor_model.mode) {
+                let count = self.effective_count();
+                svc.editor_model.select_textobject(obj, inner, count);
+            } else if let (Some(obj), Some(Surround::Add)) = (obj, self.surround) {
+                let range = OperatorRange::TextObject { obj, inner };
+                self.surround = Some(Surround::AddOver(range, self.effective_count()));
+                return NormalResult::Continue;
+            } else if let (Some(obj), Some(op)) = (obj, self.operator) {
                 let count = self.effective_count();
                 let enter = svc
                     .editor_model
-                    .apply_operator_textobject(op, obj, inner, count);
+                    .at_each_cursor(|m| m.apply_operator_textobject(op, obj, inner, count));
                 if enter {
                     self.enter_insert(svc, status);
                 } else {
                     status.clear();
                 }
+                open_filter_command(svc, status);
             }
             self.reset();
             return NormalResult::Continue;
@@ -212,15 +604,90 @@ impl NormalMode {
             return NormalResult::Continue;
         }
 
+        // `r{char}`: replace characters with `char`; `r<CR>` breaks the line.
+        if self.pending_replace {
+            let c = match ev.code {
+                KeyCode::Char(c) => Some(c),
+                KeyCode::Enter => Some('\n'),
+                _ => None,
+            };
+            if let Some(c) = c {
+                let count = self.effective_count();
+                if !svc
+                    .editor_model
+                    .at_each_cursor(|m| m.replace_chars(c, count))
+                {
+                    svc.abort_macro();
+                }
+            }
+            self.reset();
+            status.clear();
+            return NormalResult::Continue;
+        }
+
+        // `m{a-zA-Z}`: set a mark.
+        if self.pending_set_mark {
+            match ev.code {
+                KeyCode::Char(c) if svc.editor_model.set_mark(c) => status.clear(),
+                _ => *status = "Invalid mark name".to_string(),
+            }
+            self.reset();
+            return NormalResult::Continue;
+        }
+
+        // `'x` / `` `x ``: jump to a mark, or use it as an operator's range. A
+        // file mark in another file opens that file first.
+        if let Some(linewise) = self.pending_mark.take() {
+            let KeyCode::Char(name) = ev.code else {
+                self.reset();
+                status.clear();
+                return NormalResult::Continue;
+            };
+            if let Some(path) = svc.editor_model.mark_file(name) {
+                if self.operator.is_some() {
+                    self.reset();
+                    *status = "Mark is in another file".to_string();
+                    return NormalResult::Continue;
+                }
+                if let Err(e) = svc.open_file(&path) {
+                    self.reset();
+                    *status = format!("Error: {}", e);
+                    return NormalResult::Continue;
+                }
+            }
+            let set = svc.editor_model.mark(name).is_some();
+            self.run_motion(svc, Motion::Mark { name, linewise }, status);
+            if !set {
+                *status = "Mark not set".to_string();
+            }
+            return NormalResult::Continue;
+        }
+
+        if let EditorMode::Visual(kind) = svc.editor_model.mode {
+            if self.feed_visual(svc, kind, ev, status) {
+                return NormalResult::Continue;
+            }
+        }
+
         match ev.code {
             KeyCode::Esc => {
+                if self.operator.is_none() && self.count.is_none() {
+                    svc.editor_model.clear_cursors();
+                }
                 self.reset();
                 status.clear();
             }
 
             // `i`/`a` begin a text object only when an operator is pending.
             KeyCode::Char('i') if self.operator.is_some() => self.pending_object = Some(true),
-            KeyCode::Char('a') if self.operator.is_some() => self.pending_object = Some(false),
+            KeyCode::Char('a')
+                if self.operator.is_some() && !ev.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.pending_object = Some(false)
+            }
+
+            // Register prefix, before the operator (`"ayy`, `"_dw`, `"0p` …).
+            KeyCode::Char('"') if self.operator.is_none() => self.pending_register = true,
 
             // Counts. '0' is a digit only while a count is being built, else it
             // is the line-start motion.
@@ -229,8 +696,48 @@ impl NormalMode {
 
             // Operators.
             KeyCode::Char('d') => self.handle_operator(svc, Operator::Delete, status),
+            // The second key of `gqq` / `gww`.
+            KeyCode::Char('q') if self.operator == Some(Operator::Format) => {
+                self.handle_operator(svc, Operator::Format, status)
+            }
+            KeyCode::Char('w') if self.operator == Some(Operator::FormatKeep) => {
+                self.handle_operator(svc, Operator::FormatKeep, status)
+            }
+            // The second key of `gcc`.
+            KeyCode::Char('c') if self.operator == Some(Operator::Comment) => {
+                self.handle_operator(svc, Operator::Comment, status)
+            }
             KeyCode::Char('c') => self.handle_operator(svc, Operator::Change, status),
             KeyCode::Char('y') => self.handle_operator(svc, Operator::Yank, status),
+            KeyCode::Char('!') => self.handle_operator(svc, Operator::Filter, status),
+            KeyCode::Char('>') => self.handle_operator(svc, Operator::ShiftRight, status),
+            KeyCode::Char('<') => self.handle_operator(svc, Operator::ShiftLeft, status),
+            KeyCode::Char('=') => self.handle_operator(svc, Operator::Reindent, status),
+            // Surround: `ys{motion}{char}`, `yss{char}` (the line), `ds{char}`
+            // and `cs{old}{new}`.
+            KeyCode::Char('s') if matches!(self.surround, Some(Surround::Add)) => {
+                let count = self.effective_count();
+                self.surround = Some(Surround::AddOver(OperatorRange::Lines, count));
+            }
+            KeyCode::Char('s') if self.operator == Some(Operator::Yank) => {
+                self.surround = Some(Surround::Add)
+            }
+            KeyCode::Char('s') if self.operator == Some(Operator::Delete) => {
+                self.surround = Some(Surround::Change(false))
+            }
+            KeyCode::Char('s') if self.operator == Some(Operator::Change) => {
+                self.surround = Some(Surround::Change(true))
+            }
+            // The second key of a doubled case operator (`guu`, `gUU`, `g~~`).
+            KeyCode::Char('u') if self.operator == Some(Operator::Lowercase) => {
+                self.handle_operator(svc, Operator::Lowercase, status)
+            }
+            KeyCode::Char('U') if self.operator == Some(Operator::Uppercase) => {
+                self.handle_operator(svc, Operator::Uppercase, status)
+            }
+            KeyCode::Char('~') if self.operator == Some(Operator::SwitchCase) => {
+                self.handle_operator(svc, Operator::SwitchCase, status)
+            }
 
             // Motions (work bare or as an operator's range).
             KeyCode::Char('h') | KeyCode::Left => self.run_motion(svc, Motion::Left, status),
@@ -262,6 +769,8 @@ impl NormalMode {
             KeyCode::Char('F') => self.pending_find = Some((false, false)),
             KeyCode::Char('T') => self.pending_find = Some((true, false)),
             KeyCode::Char('%') => self.run_motion(svc, Motion::MatchPair, status),
+            KeyCode::Char('\'') => self.pending_mark = Some(true),
+            KeyCode::Char('`') => self.pending_mark = Some(false),
             KeyCode::Char(';') => match self.last_find {
                 Some((target, till, forward)) => self.run_motion(
                     svc,
@@ -299,6 +808,20 @@ impl NormalMode {
                 status.clear();
             }
 
+            // Visual mode only takes motions past this point.
+            _ if matches!(svc.editor_model.mode, EditorMode::Visual(_)) => self.reset(),
+
+            KeyCode::Char('v') if ev.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.start_visual(svc, VisualKind::Block, status)
+            }
+            KeyCode::Char('v') => self.start_visual(svc, VisualKind::Char, status),
+            KeyCode::Char('V') => self.start_visual(svc, VisualKind::Line, status),
+
+            // Ctrl-a / Ctrl-x: add or subtract the count from a number.
+            KeyCode::Char(c @ ('a' | 'x')) if ev.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.increment(svc, if c == 'a' { 1 } else { -1 }, false, status)
+            }
+
             // Jump list: Ctrl-o back, Ctrl-i forward (guarded before the bare
             // `o`/`i` insert-entry arms below).
             KeyCode::Char('o') if ev.modifiers.contains(KeyModifiers::CONTROL) => {
@@ -319,14 +842,14 @@ impl NormalMode {
             // Operator shortcuts (no pending operator here).
             KeyCode::Char('D') => {
                 svc.editor_model
-                    .apply_operator(Operator::Delete, Motion::LineEnd, 1);
+                    .at_each_cursor(|m| m.apply_operator(Operator::Delete, Motion::LineEnd, 1));
                 self.reset();
                 status.clear();
             }
             KeyCode::Char('C') => {
                 let enter = svc
                     .editor_model
-                    .apply_operator(Operator::Change, Motion::LineEnd, 1);
+                    .at_each_cursor(|m| m.apply_operator(Operator::Change, Motion::LineEnd, 1));
                 if enter {
                     self.enter_insert(svc, status);
                 }
@@ -335,64 +858,61 @@ impl NormalMode {
             KeyCode::Char('Y') => {
                 let count = self.effective_count();
                 svc.editor_model
-                    .operate_current_lines(Operator::Yank, count);
+                    .at_each_cursor(|m| m.operate_current_lines(Operator::Yank, count));
                 self.reset();
                 status.clear();
             }
 
             // Insert-entry actions.
-            KeyCode::Char('i') => {
-                self.enter_insert(svc, status);
-                self.reset();
-            }
-            KeyCode::Char('a') => {
-                let m = &mut svc.editor_model;
-                if m.buffer.line_count() > 0 {
-                    let len = m.buffer.line_char_len(m.cursor_y);
-                    if m.cursor_x < len {
-                        m.cursor_x += 1;
-                    }
-                }
-                self.enter_insert(svc, status);
-                self.reset();
-            }
-            KeyCode::Char('A') => {
-                let m = &mut svc.editor_model;
-                if m.buffer.line_count() > 0 {
-                    m.cursor_x = m.buffer.line_char_len(m.cursor_y);
-                }
+            KeyCode::Char(c @ ('i' | 'a' | 'I' | 'A' | 'o' | 'O')) => {
+                let entry = match c {
+                    'i' => InsertEntry::Before,
+                    'a' => InsertEntry::After,
+                    'I' => InsertEntry::FirstNonBlank,
+                    'A' => InsertEntry::LineEnd,
+                    'o' => InsertEntry::LineBelow,
+                    _ => InsertEntry::LineAbove,
+                };
+                svc.editor_model.at_each_cursor(|m| m.begin_insert(entry));
                 self.enter_insert(svc, status);
                 self.reset();
             }
-            KeyCode::Char('I') => {
-                svc.editor_model.move_by_motion(Motion::FirstNonBlank, 1);
-                self.enter_insert(svc, status);
+
+            // Editing actions.
+            KeyCode::Char('~') => {
+                let count = self.effective_count();
+                svc.editor_model
+                    .at_each_cursor(|m| m.switch_case_chars(count));
                 self.reset();
+                status.clear();
             }
-            KeyCode::Char('o') => {
-                svc.editor_model.insert_line_below();
-                self.enter_insert(svc, status);
-                self.reset();
+            KeyCode::Char('J') => self.join(svc, true, status),
+            KeyCode::Char('r') if !ev.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.pending_replace = true;
             }
-            KeyCode::Char('O') => {
-                svc.editor_model.insert_line_above();
-                self.enter_insert(svc, status);
+            KeyCode::Char('R') => {
+                svc.set_mode(EditorMode::Replace);
                 self.reset();
+                *status = "-- REPLACE --".to_string();
             }
-
-            // Editing actions.
             KeyCode::Char('x') => {
-                svc.editor_model.delete_under_cursor(self.effective_count());
+                let count = self.effective_count();
+                svc.editor_model
+                    .at_each_cursor(|m| m.delete_under_cursor(count));
                 self.reset();
                 status.clear();
             }
             KeyCode::Char('p') => {
-                svc.editor_model.paste(true, self.effective_count());
+                let (reg, count) = (self.register, self.effective_count());
+                svc.editor_model
+                    .at_each_cursor(|m| m.paste(reg, true, count));
                 self.reset();
                 status.clear();
             }
             KeyCode::Char('P') => {
-                svc.editor_model.paste(false, self.effective_count());
+                let (reg, count) = (self.register, self.effective_count());
+                svc.editor_model
+                    .at_each_cursor(|m| m.paste(reg, false, count));
                 self.reset();
                 status.clear();
             }
@@ -407,7 +927,9 @@ impl NormalMode {
                 *status = "Redo".to_string();
             }
             KeyCode::Char('.') => {
-                svc.editor_model.repeat_last_change();
+                let count = self.count;
+                svc.editor_model
+                    .at_each_cursor(|m| m.repeat_last_change_with(count));
                 self.reset();
                 status.clear();
             }
@@ -421,18 +943,45 @@ impl NormalMode {
             }
 
             // Mode switches.
-            KeyCode::Char('/') => {
-                svc.set_mode(EditorMode::Search);
-                svc.clear_command_buffer();
+            KeyCode::Char(c @ ('/' | '?')) => {
+                svc.editor_model.begin_search(c == '?');
                 self.reset();
-                *status = "/".to_string();
+                *status = c.to_string();
             }
-            KeyCode::Char('n') => {
-                svc.find_next();
+            // Multiple cursors: Ctrl-n adds one at the next match of the word
+            // under the cursor (guarded before the bare `n` search arm).
+            KeyCode::Char('n') if ev.modifiers.contains(KeyModifiers::CONTROL) => {
+                if !svc.editor_model.add_cursor_at_next_match() {
+                    *status = "No more matches".to_string();
+                }
                 self.reset();
             }
-            KeyCode::Char('N') => {
-                svc.find_previous();
+            KeyCode::Char(c @ ('n' | 'N')) => {
+                let mut message = None;
+                for _ in 0..self.effective_count() {
+                    message = if c == 'n' {
+                        svc.find_next()
+                    } else {
+                        svc.find_previous()
+                    };
+                }
+                *status = message.unwrap_or_default();
+                self.reset();
+            }
+            // `*` / `#`: search for the word under the cursor.
+            KeyCode::Char(c @ ('*' | '#')) => {
+                let mut message = svc.editor_model.search_word_under_cursor(c == '#');
+                for _ in 1..self.effective_count() {
+                    message = svc.find_next();
+                }
+                *status = message.unwrap_or_default();
+                self.reset();
+            }
+            // `&`: repeat the last `:s`, without its flags, on this line.
+            KeyCode::Char('&') => {
+                let y = svc.editor_model.cursor_y;
+                let result = svc.editor_model.repeat_substitute(y, y, false);
+                *status = result.unwrap_or_else(|e| e);
                 self.reset();
             }
             KeyCode::Char(':') => {
@@ -440,10 +989,17 @@ impl NormalMode {
                 self.reset();
                 *status = ":".to_string();
             }
-            KeyCode::Char('q') => {
+            // Macros: `q{reg}` records, `q` stops; `[count]@{reg}` plays.
+            KeyCode::Char('q') if svc.editor_model.recording.is_some() => {
+                svc.stop_recording();
                 self.reset();
-                return NormalResult::Quit;
+                status.clear();
             }
+            KeyCode::Char('q') => self.pending_macro = Some(true),
+            KeyCode::Char('@') => self.pending_macro = Some(false),
+
+            KeyCode::Char('Z') => self.pending_z = true,
+            KeyCode::Char('m') => self.pending_set_mark = true,
 
             _ => {}
         }
@@ -452,6 +1008,29 @@ impl NormalMode {
     }
 }
 
+/// After `!{motion}`: open the command line with the lines moved over as its
+/// range, ready for the filter command (`:.,.+2!`).
+fn open_filter_command<T: FileIO>(svc: &mut EditorService<T>, status: &mut String) {
+    let Some(range) = svc.editor_model.take_filter_lines() else {
+        return;
+    };
+    let prefix = match range.end - range.start {
+        0 => ".!".to_string(),
+        n => format!(".,.+{}!", n),
+    };
+    svc.set_mode(EditorMode::Command);
+    prefix.chars().for_each(|c| svc.push_command_char(c));
+    *status = format!(":{}", svc.editor_model.command_buffer);
+}
+
+fn visual_status(kind: VisualKind) -> &'static str {
+    match kind {
+        VisualKind::Char => "-- VISUAL --",
+        VisualKind::Line => "-- VISUAL LINE --",
+        VisualKind::Block => "-- VISUAL BLOCK --",
+    }
+}
+
 /// Move to a jump-list location, opening its file first if it differs from the
 /// current buffer. (Reloading the same file would clear undo history, so we
 /// only open when the path actually changes; the main loop notices the file
@@ -734,10 +1313,11 @@ mod tests {
         svc.editor_model.cursor_y = 0;
         press(&mut nm, &mut svc, "yap");
         assert_eq!(
-            svc.editor_model.register,
-            Some(crate::domain::editor_model::Register {
+            svc.editor_model.register('"'),
+            Some(crate::domain::register::Register {
                 text: "one\ntwo\n\n".to_string(),
                 linewise: true,
+                blockwise: false,
             })
         );
     }
@@ -751,15 +1331,234 @@ mod tests {
     }
 
     #[test]
-    fn quit_key_returns_quit() {
+    fn zq_returns_quit() {
         let mut nm = NormalMode::new();
         let mut svc = service("abc");
         let mut status = String::new();
-        let r = nm.feed(
-            &mut svc,
-            &KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE),
-            &mut status,
-        );
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        let r = nm.feed(&mut svc, &key('Z'), &mut status);
+        assert!(matches!(r, NormalResult::Continue));
+        let r = nm.feed(&mut svc, &key('Q'), &mut status);
         assert!(matches!(r, NormalResult::Quit));
     }
+
+    #[test]
+    fn visual_word_delete_via_keystrokes() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("foo bar baz");
+        press(&mut nm, &mut svc, "wvey");
+        assert_eq!(svc.editor_model.register('"').unwrap().text, "bar");
+        assert_eq!(svc.editor_model.mode, EditorMode::Normal);
+        press(&mut nm, &mut svc, "vex");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "foo  baz");
+    }
+
+    #[test]
+    fn visual_line_change_enters_insert() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("a\nb\nc");
+        press(&mut nm, &mut svc, "Vjc");
+        assert_eq!(svc.editor_model.mode, EditorMode::Insert);
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["".to_string(), "c".to_string()]
+        );
+    }
+
+    #[test]
+    fn visual_inner_object_and_swap_ends() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("f(a, b) x");
+        svc.editor_model.cursor_x = 3;
+        press(&mut nm, &mut svc, "vi(");
+        assert_eq!(svc.editor_model.selection_span(0), Some((2, 6)));
+        press(&mut nm, &mut svc, "o");
+        assert_eq!(svc.editor_model.cursor_x, 2);
+        press(&mut nm, &mut svc, "d");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "f() x");
+    }
+
+    #[test]
+    fn ctrl_v_block_insert_via_keystrokes() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("ab\ncd");
+        let mut status = String::new();
+        let ctrl_v = KeyEvent::new(KeyCode::Char('v'), KeyModifiers::CONTROL);
+        nm.feed(&mut svc, &ctrl_v, &mut status);
+        assert_eq!(status, "-- VISUAL BLOCK --");
+        press(&mut nm, &mut svc, "jI");
+        assert_eq!(svc.editor_model.mode, EditorMode::Insert);
+        svc.editor_model.insert_char('#');
+        svc.set_mode(EditorMode::Normal);
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["#ab".to_string(), "#cd".to_string()]
+        );
+    }
+
+    #[test]
+    fn visual_same_key_exits_and_other_key_switches_kind() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("abc");
+        press(&mut nm, &mut svc, "vV");
+        assert_eq!(svc.editor_model.mode, EditorMode::Visual(VisualKind::Line));
+        press(&mut nm, &mut svc, "V");
+        assert_eq!(svc.editor_model.mode, EditorMode::Normal);
+    }
+
+    #[test]
+    fn named_register_yank_and_paste() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("foo bar");
+        press(&mut nm, &mut svc, "\"ayiwwdiw");
+        // The delete went to the unnamed register; "a still holds the yank.
+        assert_eq!(svc.editor_model.register('"').unwrap().text, "bar");
+        press(&mut nm, &mut svc, "\"aP");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "foo foo");
+    }
+
+    #[test]
+    fn uppercase_register_appends_and_black_hole_discards() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("one\ntwo\nthree");
+        press(&mut nm, &mut svc, "\"ayyj\"Ayyj\"_dd");
+        assert_eq!(svc.editor_model.register('a').unwrap().text, "one\ntwo\n");
+        // The unnamed register follows the append, not the black-hole delete.
+        assert_eq!(svc.editor_model.register('"').unwrap().text, "one\ntwo\n");
+        press(&mut nm, &mut svc, "gg\"ap");
+        assert_eq!(
+            svc.editor_model.buffer.to_lines(),
+            vec!["one", "one", "two", "two"]
+        );
+    }
+
+    #[test]
+    fn numbered_registers_keep_older_deletes() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("a\nb\nc");
+        press(&mut nm, &mut svc, "dddd\"2p");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn read_only_file_path_register() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("");
+        svc.editor_model.set_filepath("x.rs".to_string());
+        press(&mut nm, &mut svc, "\"%p");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "x.rs");
+    }
+
+    /// Type `text` in insert mode and press Esc.
+    fn type_insert(svc: &mut EditorService<NoFile>, text: &str) {
+        for c in text.chars() {
+            svc.insert_char(c);
+        }
+        svc.set_mode(EditorMode::Normal);
+    }
+
+    #[test]
+    fn dot_repeats_operator_with_count() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("a b c d e f g");
+        press(&mut nm, &mut svc, "d3w.");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "g");
+    }
+
+    #[test]
+    fn dot_repeats_change_text_object_with_typed_text() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("say \"x\" and \"y\"");
+        press(&mut nm, &mut svc, "fxci\"");
+        type_insert(&mut svc, "foo");
+        press(&mut nm, &mut svc, "fy.");
+        assert_eq!(
+            svc.editor_model.buffer.line_text(0),
+            "say \"foo\" and \"foo\""
+        );
+        // The repeat is a single undo step.
+        svc.editor_model.undo();
+        assert_eq!(
+            svc.editor_model.buffer.line_text(0),
+            "say \"foo\" and \"y\""
+        );
+    }
+
+    #[test]
+    fn dot_count_overrides_and_is_remembered() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("aa bb cc dd ee");
+        press(&mut nm, &mut svc, "cw");
+        type_insert(&mut svc, "X");
+        press(&mut nm, &mut svc, "w2.");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "X X dd ee");
+        press(&mut nm, &mut svc, "w.");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "X X X");
+    }
+
+    #[test]
+    fn dot_repeats_x_and_open_line() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("abcdef");
+        press(&mut nm, &mut svc, "2x.");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "ef");
+        press(&mut nm, &mut svc, "o");
+        type_insert(&mut svc, "foo");
+        press(&mut nm, &mut svc, ".");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["ef", "foo", "foo"]);
+    }
+
+    #[test]
+    fn dot_repeats_visual_operator_over_same_extent() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("abcdef\nabcdef");
+        press(&mut nm, &mut svc, "vld");
+        press(&mut nm, &mut svc, "j0.");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["cdef", "cdef"]);
+    }
+
+    #[test]
+    fn quote_mark_is_a_linewise_range() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("a\nb\nc\nd");
+        press(&mut nm, &mut svc, "jmajjd'a");
+        assert_eq!(svc.editor_model.buffer.to_lines(), vec!["a"]);
+    }
+
+    #[test]
+    fn backtick_mark_jumps_exactly() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("foo bar\nbaz");
+        press(&mut nm, &mut svc, "wlmbjgg`b");
+        assert_eq!(
+            (svc.editor_model.cursor_y, svc.editor_model.cursor_x),
+            (0, 5)
+        );
+        press(&mut nm, &mut svc, "0d`b");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "ar");
+    }
+
+    #[test]
+    fn ctrl_n_adds_cursors_and_change_applies_at_each() {
+        let mut nm = NormalMode::new();
+        let mut svc = service("let xs = xs * xs;");
+        press(&mut nm, &mut svc, "w");
+        let mut status = String::new();
+        for _ in 0..2 {
+            let ev = KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL);
+            nm.feed(&mut svc, &ev, &mut status);
+        }
+        assert_eq!(svc.editor_model.cursor_count(), 3);
+        press(&mut nm, &mut svc, "cw");
+        for c in "ys".chars() {
+            svc.insert_char(c);
+        }
+        svc.set_mode(EditorMode::Normal);
+        assert_eq!(svc.editor_model.buffer.line_text(0), "let ys = ys * ys;");
+        // As with one cursor, the typing and the deletion undo separately.
+        press(&mut nm, &mut svc, "u");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "let  =  * ;");
+        press(&mut nm, &mut svc, "u");
+        assert_eq!(svc.editor_model.buffer.line_text(0), "let xs = xs * xs;");
+    }
 }
@@ -0,0 +1,294 @@
+//! External-command orchestration (application layer).
+//!
+//! `Shell` is the main-task handle to commands run by `:!`, `:r !` and the
+//! `!` filter. Ex commands only record a [`ShellRequest`] on the service;
+//! the event loop hands it to [`Shell::dispatch`], which runs it on its own
+//! `tokio::spawn` task ([`crate::infrastructure::shell::run`]). The result
+//! comes back over a tokio channel and is applied with [`Shell::apply`].
+//!
+//! One command runs at a time. Keys typed while it does wait, and the event
+//! loop handles them once it is done, except Ctrl-C, which calls
+//! [`Shell::cancel`]: aborting the task kills the child. A command still
+//! running after [`SHELL_TIMEOUT`] is killed the same way.
+
+use crate::application::editor_service::EditorService;
+use crate::domain::editor_model::EditorModel;
+use crate::domain::ex_range::LineRange;
+use crate::infrastructure::file_io::FileIO;
+use crate::infrastructure::shell::{self, ShellOutput};
+use std::io;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::AbortHandle;
+use tokio::time::Duration;
+
+/// How long a command may run before it is killed.
+pub const SHELL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What to do with a command's output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShellTarget {
+    /// `:!cmd`: show it.
+    Show,
+    /// `:r !cmd`: put it below the line.
+    Read(usize),
+    /// `:[range]!cmd`: pipe the lines through the command and replace them
+    /// with it.
+    Filter(LineRange),
+}
+
+/// A command recorded by an ex command, for the event loop to run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShellRequest {
+    pub command: String,
+    pub target: ShellTarget,
+}
+
+/// A finished command, tagged with the job that ran it so the result of a
+/// cancelled one is dropped.
+#[derive(Debug)]
+pub struct ShellResult {
+    id: u64,
+    output: io::Result<ShellOutput>,
+}
+
+struct Job {
+    id: u64,
+    request: ShellRequest,
+    /// The buffer's edit revision when the command started; output for lines
+    /// that have changed since is not used.
+    revision: u64,
+    handle: AbortHandle,
+}
+
+pub struct Shell {
+    tx: UnboundedSender<ShellResult>,
+    job: Option<Job>,
+    next_id: u64,
+}
+
+impl Shell {
+    /// `tx` is the channel results are reported on; the event loop owns the
+    /// receiver.
+    pub fn new(tx: UnboundedSender<ShellResult>) -> Self {
+        Self {
+            tx,
+            job: None,
+            next_id: 0,
+        }
+    }
+
+    /// Start running `request`, cancelling any command still running. A
+    /// filter gets the text of its lines on stdin.
+    pub fn dispatch(&mut self, request: ShellRequest, model: &EditorModel) {
+        self.cancel();
+        let input = match request.target {
+            ShellTarget::Filter(range) => Some(model.lines_text(range)),
+            _ => None,
+        };
+        self.next_id += 1;
+        let id = self.next_id;
+        let tx = self.tx.clone();
+        let command = request.command.clone();
+        let handle = tokio::spawn(async move {
+            let output = shell::run(&command, input, SHELL_TIMEOUT).await;
+            let _ = tx.send(ShellResult { id, output });
+        })
+        .abort_handle();
+        self.job = Some(Job {
+            id,
+            request,
+            revision: model.edit_revision(),
+            handle,
+        });
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// The status line while a command runs.
+    pub fn status(&self) -> Option<String> {
+        self.job
+            .as_ref()
+            .map(|job| format!(":!{}  (keys wait; Ctrl-C to cancel)", job.request.command))
+    }
+
+    /// Kill the running command, if any. Returns false when none was running.
+    pub fn cancel(&mut self) -> bool {
+        match self.job.take() {
+            Some(job) => {
+                job.handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Use the output of a finished command as its request asked. A command
+    /// that fails leaves the buffer alone and shows what it printed. Returns
+    /// true when the buffer was edited.
+    pub fn apply<T: FileIO>(
+        &mut self,
+        result: ShellResult,
+        svc: &mut EditorService<T>,
+        status: &mut String,
+    ) -> bool {
+        let Some(job) = self.job.take_if(|job| job.id == result.id) else {
+            return false;
+        };
+        let out = match result.output {
+            Ok(out) => out,
+            Err(e) => {
+                *status = format!("Error: {}", e);
+                return false;
+            }
+        };
+        if !out.success() {
+            svc.show_output(output_lines(&out));
+            *status = match out.code {
+                Some(code) => format!("shell returned {}", code),
+                None => "shell killed".to_string(),
+            };
+            return false;
+        }
+        let changed = svc.editor_model.edit_revision() != job.revision;
+        if job.request.target != ShellTarget::Show && changed {
+            *status = "Text changed while the command ran; output not used".to_string();
+            return false;
+        }
+        match job.request.target {
+            ShellTarget::Show => {
+                svc.show_output(output_lines(&out));
+                status.clear();
+                false
+            }
+            ShellTarget::Read(y) => {
+                svc.editor_model.read_lines(y, &out.stdout);
+                *status = format!("{} lines read", out.stdout.lines().count());
+                true
+            }
+            ShellTarget::Filter(range) => {
+                svc.editor_model.replace_lines(range, &out.stdout);
+                *status = format!("{} lines filtered", range.end - range.start + 1);
+                true
+            }
+        }
+    }
+}
+
+/// What a command printed, stdout then stderr, as lines.
+fn output_lines(out: &ShellOutput) -> Vec<String> {
+    out.stdout
+        .lines()
+        .chain(out.stderr.lines())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoFile;
+    impl FileIO for NoFile {
+        fn read_file(&self, _path: &str) -> io::Result<String> {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        }
+        fn write_file(&self, _path: &str, _content: &str) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn service(content: &str) -> EditorService<NoFile> {
+        let mut svc = EditorService::new(NoFile);
+        svc.editor_model.set_content(content);
+        svc
+    }
+
+    /// Run `request` to completion and apply its result.
+    async fn run(svc: &mut EditorService<NoFile>, request: ShellRequest) -> (bool, String) {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut shell = Shell::new(tx);
+        shell.dispatch(request, &svc.editor_model);
+        let result = rx.recv().await.unwrap();
+        let mut status = String::new();
+        let edited = shell.apply(result, svc, &mut status);
+        assert!(!shell.is_running());
+        (edited, status)
+    }
+
+    fn request(command: &str, target: ShellTarget) -> ShellRequest {
+        ShellRequest {
+            command: command.to_string(),
+            target,
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn filters_reads_and_shows_output() {
+        let mut svc = service("x\nc\nb\na\ny\n");
+        let range = LineRange { start: 1, end: 3 };
+        let (edited, status) = run(&mut svc, request("sort", ShellTarget::Filter(range))).await;
+        assert!(edited);
+        assert_eq!(status, "3 lines filtered");
+        assert_eq!(svc.editor_model.get_content(), "x\na\nb\nc\ny");
+        assert_eq!(svc.editor_model.cursor_y, 1);
+        // The filter is one undo step.
+        svc.editor_model.undo();
+        assert_eq!(svc.editor_model.get_content(), "x\nc\nb\na\ny");
+
+        let (edited, _) = run(&mut svc, request("printf 'r1\\nr2'", ShellTarget::Read(0))).await;
+        assert!(edited);
+        assert_eq!(svc.editor_model.get_content(), "x\nr1\nr2\nc\nb\na\ny");
+
+        let (edited, _) = run(&mut svc, request("echo hi", ShellTarget::Show)).await;
+        assert!(!edited);
+        assert_eq!(svc.output(), ["hi"]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn failures_and_stale_results_leave_the_buffer_alone() {
+        let mut svc = service("a\nb\n");
+        let range = LineRange { start: 0, end: 1 };
+        let (edited, status) = run(
+            &mut svc,
+            request("echo bad >&2; exit 2", ShellTarget::Filter(range)),
+        )
+        .await;
+        assert!(!edited);
+        assert_eq!(status, "shell returned 2");
+        assert_eq!(svc.output(), ["bad"]);
+        assert_eq!(svc.editor_model.get_content(), "a\nb");
+
+        // The text changed while the filter ran.
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut shell = Shell::new(tx);
+        shell.dispatch(
+            request("sort", ShellTarget::Filter(range)),
+            &svc.editor_model,
+        );
+        svc.editor_model.insert_char('z');
+        let result = rx.recv().await.unwrap();
+        let mut status = String::new();
+        assert!(!shell.apply(result, &mut svc, &mut status));
+        assert_eq!(svc.editor_model.get_content(), "za\nb");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn cancel_kills_the_command_and_drops_its_result() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut shell = Shell::new(tx);
+        let svc = service("a\n");
+        shell.dispatch(request("sleep 5", ShellTarget::Show), &svc.editor_model);
+        assert!(shell.status().unwrap().starts_with(":!sleep 5"));
+        assert!(shell.cancel());
+        assert!(!shell.is_running());
+        // The aborted task never reports; the channel closes once it is gone.
+        let got = tokio::time::timeout(Duration::from_secs(2), async {
+            drop(shell);
+            rx.recv().await
+        })
+        .await;
+        assert!(matches!(got, Ok(None)));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::domain::transaction::ByteEdit;
+use ropey::Rope;
+
+/// How a language writes comments: the prefix of a line comment and the
+/// delimiters of a block comment, where it has them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommentStyle {
+    pub line: Option<&'static str>,
+    pub block: Option<(&'static str, &'static str)>,
+}
+
+/// Works out which comments to write at a place in the text: those of the
+/// language there, which may be embedded in the file's own. The editor model
+/// holds one when the file's language has a parser; see
+/// `infrastructure::syntax_worker::SyntaxComments`.
+pub trait CommentSyntax {
+    /// The comment style at byte `at` of `text`. `revision` is the model's
+    /// edit revision of `text`.
+    fn comment_style(&mut self, text: &Rope, revision: u64, at: usize) -> CommentStyle;
+
+    /// `edit` took the text to `revision`.
+    fn edit(&mut self, edit: &ByteEdit, revision: u64);
+}
+
+impl CommentStyle {
+    /// How to comment out a single line: a line comment's prefix, else block
+    /// delimiters around it.
+    pub fn line_delimiters(&self) -> Option<(&'static str, Option<&'static str>)> {
+        match (self.line, self.block) {
+            (Some(prefix), _) => Some((prefix, None)),
+            (None, Some((open, close))) => Some((open, Some(close))),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Toggle comments on `lines`, as `gc` does: when every line that is not
+/// blank is commented with `open` (and `close`, for block delimiters), they
+/// are uncommented; else they are all commented, the delimiters going at the
+/// smallest indent among them. Blank lines stay as they are. Returns the new
+/// text of each line that changes.
+pub fn toggle_lines(lines: &[String], open: &str, close: Option<&str>) -> Vec<Option<String>> {
+    let is_commented = |line: &str| {
+        let text = line.trim();
+        text.starts_with(open) && close.is_none_or(|close| text.ends_with(close))
+    };
+    let mut used = lines.iter().filter(|line| !line.trim().is_empty());
+    let uncomment = used.clone().next().is_some() && used.all(|line| is_commented(line));
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                return None;
+            }
+            if uncomment {
+                Some(uncomment_span(line, open, close))
+            } else {
+                let blanks: String = line.chars().take(indent).collect();
+                let rest: String = line.chars().skip(indent).collect();
+                let end = close.map(|close| format!(" {}", close)).unwrap_or_default();
+                Some(format!("{}{} {}{}", blanks, open, rest, end))
+            }
+        })
+        .collect()
+}
+
+/// Toggle a block comment over `text`, as Visual `gc` does: remove the
+/// delimiters if it is wrapped in them (blanks around it stay), else wrap it.
+pub fn toggle_block(text: &str, open: &str, close: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.len() >= open.len() + close.len()
+        && trimmed.starts_with(open)
+        && trimmed.ends_with(close)
+    {
+        uncomment_span(text, open, Some(close))
+    } else {
+        format!("{} {} {}", open, text, close)
+    }
+}
+
+/// `text` with the comment delimiters just inside its blanks removed, each
+/// with a space next to it on the inside.
+fn uncomment_span(text: &str, open: &str, close: Option<&str>) -> String {
+    let start = text.len() - text.trim_start().len();
+    let end = text.trim_end().len();
+    let mut inner = &text[start + open.len()..end];
+    if let Some(close) = close {
+        inner = &inner[..inner.len() - close.len()];
+        inner = inner.strip_suffix(' ').unwrap_or(inner);
+    }
+    inner = inner.strip_prefix(' ').unwrap_or(inner);
+    format!("{}{}{}", &text[..start], inner, &text[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toggle(lines: &[&str], open: &str, close: Option<&str>) -> Vec<Option<String>> {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        toggle_lines(&lines, open, close)
+    }
+
+    #[test]
+    fn line_comments_go_at_the_smallest_indent_and_come_back_out() {
+        assert_eq!(
+            toggle(&["  a", "", "    b"], "//", None),
+            vec![Some("  // a".into()), None, Some("  //   b".into())]
+        );
+        assert_eq!(
+            toggle(&["  // a", "", "  //   b", "//c"], "//", None),
+            vec![
+                Some("  a".into()),
+                None,
+                Some("    b".into()),
+                Some("c".into())
+            ]
+        );
+        // One uncommented line comments them all.
+        assert_eq!(
+            toggle(&["// a", "b"], "//", None),
+            vec![Some("// // a".into()), Some("// b".into())]
+        );
+        assert_eq!(
+            toggle(&["a {", "/* b */"], "/*", Some("*/")),
+            vec![Some("/* a { */".into()), Some("/* /* b */ */".into())]
+        );
+        assert_eq!(toggle(&["", " "], "//", None), vec![None, None]);
+    }
+
+    #[test]
+    fn block_comments_wrap_and_unwrap() {
+        assert_eq!(toggle_block("a + b", "/*", "*/"), "/* a + b */");
+        assert_eq!(toggle_block("/* a + b */", "/*", "*/"), "a + b");
+        assert_eq!(toggle_block(" /*x*/ ", "/*", "*/"), " x ");
+        assert_eq!(toggle_block("/*/", "/*", "*/"), "/* /*/ */");
+    }
+}
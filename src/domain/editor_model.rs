@@ -1,18 +1,67 @@
+use crate::domain::comment::{self, CommentStyle, CommentSyntax};
+use crate::domain::ex_range::{self, Address, LineRange};
 use crate::domain::grapheme;
+use crate::domain::increment;
+use crate::domain::indent::{self, Indenter};
+use crate::domain::line_ops::{self, SortOptions};
 use crate::domain::motion::{self, Motion, MotionKind, Target};
+use crate::domain::options::Options;
+use crate::domain::pairs::{self, Literals};
+use crate::domain::reflow;
+use crate::domain::register::{Register, Registers};
+use crate::domain::search::{self, Search, SearchOffset};
+use crate::domain::snippet::{self, Session};
+use crate::domain::substitute::{self, Substitution};
 use crate::domain::text_buffer::TextBuffer;
 use crate::domain::text_object::{self, TextObject};
-use crate::domain::transaction::{Change, Transaction};
+use crate::domain::transaction::{self, Change, Transaction};
+use crate::domain::undo_tree::{UndoStep, UndoTree};
 use crossterm::event::KeyCode;
-use unicode_width::UnicodeWidthStr;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum EditorMode {
     Normal,
     Insert,
     Command,
     Search,
+    Visual(VisualKind),
+    /// Answering the "replace with …?" prompt of `:s///c`.
+    Confirm,
+    /// `R`: typed characters replace the ones under the cursor.
+    Replace,
+}
+
+/// The flavour of Visual mode: `v` (charwise), `V` (linewise) or `Ctrl-v`
+/// (blockwise).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VisualKind {
+    Char,
+    Line,
+    Block,
 }
 
+/// A resolved Visual-mode selection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Selection {
+    /// Half-open whole-buffer char range.
+    Chars(usize, usize),
+    /// Inclusive line range.
+    Lines(usize, usize),
+    /// Inclusive line range and inclusive display-column range. Columns rather
+    /// than char offsets keep the block rectangular across wide characters.
+    Block {
+        top: usize,
+        bottom: usize,
+        left: usize,
+        right: usize,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum LastChange {
     InsertChar(char),
     DeleteChar,
@@ -21,7 +70,106 @@ pub enum LastChange {
     InsertLineBelow,
     InsertLineAbove,
     DeleteCurrentLine,
-    PutLineBelow,
+    /// `p` (`after`) or `P` from `register` (the unnamed one when `None`).
+    Paste {
+        register: Option<char>,
+        after: bool,
+        count: usize,
+    },
+    /// Visual `p` (`swap`) or `P` from `register` over a selection of the
+    /// recorded extent (see [`OperatorRange::Selection`]).
+    PasteVisual {
+        register: Option<char>,
+        swap: bool,
+        kind: VisualKind,
+        lines: usize,
+        width: usize,
+    },
+    /// An operator and its range (`d3w`, `ci"`, `2dd`). For the change
+    /// operator, `inserted` is the text typed before leaving insert mode.
+    Operator {
+        op: Operator,
+        range: OperatorRange,
+        count: usize,
+        inserted: String,
+    },
+    /// An insert session entered with `i`/`a`/`I`/`A`/`o`/`O`, with its text.
+    Insert {
+        entry: InsertEntry,
+        text: String,
+    },
+    /// `~` over `count` characters.
+    SwitchCase(usize),
+    /// `r{char}` over `count` characters.
+    ReplaceChars {
+        c: char,
+        count: usize,
+    },
+    /// `J` (`spaces`) or `gJ` over `count` lines.
+    Join {
+        count: usize,
+        spaces: bool,
+    },
+    /// A Replace-mode session (`R`), with its text in the form of
+    /// `insert_record`.
+    Replace {
+        text: String,
+    },
+    /// `Ctrl-a` (positive) or `Ctrl-x` (negative) adding `delta`.
+    Increment(i64),
+    /// `ds{old}` (`new` is `None`) or `cs{old}{new}` on the `count`th pair.
+    Surround {
+        old: char,
+        new: Option<char>,
+        count: usize,
+    },
+}
+
+/// An `R` session: its text so far, and for each character typed what it
+/// took the place of (`None` where it was added: past the end of the line,
+/// or a line break), so Backspace can put it back.
+#[derive(Default)]
+struct ReplaceSession {
+    text: String,
+    replaced: Vec<Option<String>>,
+}
+
+/// The range an operator acted on, recorded so `.` can re-apply it from the
+/// cursor.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OperatorRange {
+    Motion(Motion),
+    TextObject {
+        obj: TextObject,
+        inner: bool,
+    },
+    /// `dd`/`cc`: whole lines from the cursor.
+    Lines,
+    /// A Visual selection, replayed with the same extent from the cursor:
+    /// `lines` further lines and, on the last one, `width` chars (charwise) or
+    /// display columns (blockwise).
+    Selection {
+        kind: VisualKind,
+        lines: usize,
+        width: usize,
+    },
+}
+
+/// How an insert session was entered, so `.` can enter it the same way.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertEntry {
+    /// `i`
+    Before,
+    /// `a`
+    After,
+    /// `I`
+    FirstNonBlank,
+    /// `A`
+    LineEnd,
+    /// `o`
+    LineBelow,
+    /// `O`
+    LineAbove,
 }
 
 /// A normal-mode operator applied over a motion or text-object range.
@@ -30,14 +178,95 @@ pub enum Operator {
     Delete,
     Change,
     Yank,
+    /// `!`: leaves the buffer alone and sets the lines moved over aside for
+    /// a filter command (see [`EditorModel::take_filter_lines`]).
+    Filter,
+    /// `g~`: switch the case of each letter.
+    SwitchCase,
+    /// `gu`
+    Lowercase,
+    /// `gU`
+    Uppercase,
+    /// `>`: indent whole lines one `shiftwidth` more.
+    ShiftRight,
+    /// `<`
+    ShiftLeft,
+    /// `=`: re-indent whole lines (see [`Options::smartindent`]).
+    Reindent,
+    /// `ys`: put the delimiters for the char around the text (see
+    /// [`pairs::surround_delimiters`]).
+    Surround(char),
+    /// `gc`: toggle line comments on whole lines.
+    Comment,
+    /// Visual `gc` on a charwise selection: toggle a block comment around it.
+    BlockComment,
+    /// `gq`: reflow whole lines to `textwidth`, leaving the cursor on the
+    /// last of them.
+    Format,
+    /// `gw`: `gq`, keeping the cursor where it is in the text.
+    FormatKeep,
+}
+
+impl Operator {
+    /// The text a case operator turns `text` into; `None` for the others.
+    fn recase(self, text: &str) -> Option<String> {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            match self {
+                Operator::SwitchCase if c.is_lowercase() => out.extend(c.to_uppercase()),
+                Operator::SwitchCase => out.extend(c.to_lowercase()),
+                Operator::Lowercase => out.extend(c.to_lowercase()),
+                Operator::Uppercase => out.extend(c.to_uppercase()),
+                _ => return None,
+            }
+        }
+        Some(out)
+    }
+}
+
+/// An in-progress blockwise insert (`I`, `A` or `c` in Visual-block mode). The
+/// text typed on `top` is replicated onto the other lines when insert mode ends.
+struct BlockInsert {
+    top: usize,
+    bottom: usize,
+    /// Display column the text is inserted at on every line.
+    col: usize,
+    /// `A`: pad lines shorter than `col` instead of skipping them.
+    append: bool,
+    /// Char column on `top` where typing started, and that line's length then.
+    start_x: usize,
+    start_len: usize,
+}
+
+/// State saved by [`EditorModel::begin_undo_group`].
+struct UndoGroup {
+    /// Undo-tree size and current state when the group began.
+    len: usize,
+    state: usize,
+    cursor_before: (usize, usize),
 }
 
-/// The unnamed register: text captured by the last delete/change/yank, plus
-/// whether it was linewise (pasted onto new lines) or charwise (pasted inline).
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Register {
-    pub text: String,
-    pub linewise: bool,
+/// The lines a `:g` run has still to visit.
+struct GlobalRun {
+    /// Char offset of the start of each marked line, in line order; `None`
+    /// once the line has been deleted or joined onto the one above.
+    lines: Vec<Option<usize>>,
+    next: usize,
+}
+
+/// A `:s///c` waiting for each replacement to be confirmed.
+struct PendingSubstitute {
+    /// Replacements still to ask about, as `(line, start, end, text)` in the
+    /// text as it was before the first one was made.
+    edits: VecDeque<(usize, usize, usize, String)>,
+    /// How far the replacements made so far have moved later offsets.
+    shift: isize,
+    count: usize,
+    lines: usize,
+    last_line: Option<usize>,
+    /// Where the last replacement made starts, in the current text.
+    last_start: Option<usize>,
+    cursor_before: (usize, usize),
 }
 
 pub struct EditorModel {
@@ -49,14 +278,60 @@ pub struct EditorModel {
     pub filepath: Option<String>,
     pub mode: EditorMode,
     pub command_buffer: String,
-    pub register: Option<Register>,
+    pub registers: Registers,
+    /// Register named by a `"x` prefix for the command being executed; yanks,
+    /// deletes and changes write to it (the unnamed register when `None`).
+    pub register_name: Option<char>,
+    /// Register a `q` macro is being recorded into, shown in the status bar.
+    pub recording: Option<char>,
+    /// Pattern of the last search, and where its matches started when it
+    /// last ran (`current_search_match` indexes the one jumped to).
     pub search_query: Option<String>,
     pub search_matches: Vec<(usize, usize)>,
     pub current_search_match: Option<usize>,
-    undo_stack: Vec<Transaction>,
-    redo_stack: Vec<Transaction>,
+    search: Option<Search>,
+    /// Whether the last search's matches are highlighted (`:nohlsearch`
+    /// hides them until the next search).
+    highlight_search: bool,
+    /// The open `/` or `?` prompt: the cursor it started from and whether it
+    /// searches backward; and the pattern typed so far, for highlighting.
+    search_prompt: Option<((usize, usize), bool)>,
+    search_preview: Option<Regex>,
+    /// The last `:s`, for `&` and `g&`, and the one being confirmed.
+    last_substitute: Option<Substitution>,
+    pending_substitute: Option<PendingSubstitute>,
+    global: Option<GlobalRun>,
+    /// The lines a `!{motion}` went over, for the command line it opens.
+    filter_lines: Option<LineRange>,
+    replace_session: Option<ReplaceSession>,
+    /// The display column `j`/`k` aim for (`usize::MAX` after `$`: the end of
+    /// each line), with the `(y, x)` it was set at: once the cursor moves
+    /// some other way, its own column counts again.
+    sticky_col: Option<(usize, usize, usize)>,
+    /// Settings changed with `:set`.
+    pub options: Options,
+    /// Smart indent, when there is a parser for the language.
+    indenter: Option<Box<dyn Indenter>>,
+    /// The comment syntax at a place, when there is a parser for the
+    /// language; `gc` does nothing without one.
+    comments: Option<Box<dyn CommentSyntax>>,
+    /// Where auto-pairs stays out, when there is a parser for the language.
+    literals: Option<Box<dyn Literals>>,
+    /// Where the cursor was left after indenting a new line. If it is still
+    /// there, with nothing typed, when the line is left, the indent is
+    /// removed again.
+    auto_indent: Option<(usize, usize)>,
+    /// The snippet being filled in, which holds an undo group open until it
+    /// ends, so it undoes as one step with the typing in it.
+    snippet: Option<Session>,
+    undo_tree: UndoTree,
     coalescing: bool,
     last_change: Option<LastChange>,
+    /// The change the current insert session completes (a `c` operator or an
+    /// insert entry), and the keys typed in it so far: the text, with `\n` for
+    /// Enter and `\u{8}` for a Backspace past the start of the session.
+    insert_repeat: Option<LastChange>,
+    insert_record: Option<String>,
     /// Monotonic counter bumped once per buffer edit (including undo/redo and
     /// whole-file loads). The syntax layer compares it before/after handling
     /// input to detect that the text changed and a re-highlight is due.
@@ -67,6 +342,26 @@ pub struct EditorModel {
     /// not currently navigating history".
     jumps: Vec<(Option<String>, usize, usize)>,
     jump_index: usize,
+    /// Buffer-local marks `a`–`z` and the automatic marks `.` (last change),
+    /// `^` (last insert exit) and `[`/`]` (last yanked or changed text), as
+    /// whole-buffer char offsets kept in place by `apply_change`.
+    marks: HashMap<char, usize>,
+    /// File marks `A`–`Z`: the file they were set in and a char offset into it,
+    /// adjusted only while that file is the one being edited.
+    global_marks: HashMap<char, (Option<String>, usize)>,
+    /// Secondary cursors (the primary one is `cursor_y`/`cursor_x`), as sorted
+    /// whole-buffer char offsets kept in place by `apply_change`.
+    cursors: Vec<usize>,
+    /// Set while an insert session with secondary cursors holds an undo group
+    /// open, so the typing at every cursor undoes as one step.
+    cursor_insert_group: bool,
+    /// The fixed end of a Visual selection; the cursor is the moving end.
+    visual_anchor: (usize, usize),
+    block_insert: Option<BlockInsert>,
+    undo_group: Option<UndoGroup>,
+    /// Nesting depth of `begin_undo_group` calls; only the outermost pair
+    /// snapshots and collapses.
+    undo_group_depth: usize,
 }
 
 impl EditorModel {
@@ -80,17 +375,44 @@ impl EditorModel {
             row_offset: 0,
             col_offset: 0,
             filepath: None,
-            register: None,
+            registers: Registers::default(),
+            register_name: None,
+            recording: None,
             search_query: None,
             search_matches: Vec::new(),
             current_search_match: None,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            search: None,
+            highlight_search: false,
+            search_prompt: None,
+            search_preview: None,
+            last_substitute: None,
+            pending_substitute: None,
+            global: None,
+            filter_lines: None,
+            replace_session: None,
+            sticky_col: None,
+            options: Options::default(),
+            indenter: None,
+            comments: None,
+            literals: None,
+            auto_indent: None,
+            snippet: None,
+            undo_tree: UndoTree::default(),
             coalescing: false,
             last_change: None,
+            insert_repeat: None,
+            insert_record: None,
             edit_revision: 0,
             jumps: Vec::new(),
+            marks: HashMap::new(),
+            global_marks: HashMap::new(),
+            cursors: Vec::new(),
+            cursor_insert_group: false,
             jump_index: 0,
+            visual_anchor: (0, 0),
+            block_insert: None,
+            undo_group: None,
+            undo_group_depth: 0,
         }
     }
 
@@ -107,19 +429,182 @@ impl EditorModel {
     /// `edit_revision` bumps exactly once per edit. Any new edit path MUST call
     /// this instead of `change.apply(&mut self.buffer)` directly.
     fn apply_change(&mut self, change: &Change) {
+        let at_line_start =
+            change.pos == 0 || self.buffer.slice_text(change.pos - 1..change.pos) == "\n";
+        // The parses the syntax services keep are edited along with the text.
+        let edit = (self.indenter.is_some() || self.literals.is_some() || self.comments.is_some())
+            .then(|| change.byte_edit(&self.buffer));
         Change::apply(change, &mut self.buffer);
         self.edit_revision = self.edit_revision.wrapping_add(1);
+        if let Some(edit) = edit {
+            if let Some(indenter) = self.indenter.as_mut() {
+                indenter.edit(&edit, self.edit_revision);
+            }
+            if let Some(literals) = self.literals.as_mut() {
+                literals.edit(&edit, self.edit_revision);
+            }
+            if let Some(comments) = self.comments.as_mut() {
+                comments.edit(&edit, self.edit_revision);
+            }
+        }
+        self.sticky_col = None;
+
+        if let Some(run) = &mut self.global {
+            // A `:g` line mark goes with its first char, or with the line
+            // break before it when that alone joins it onto the line above.
+            let removed = change.removed.chars().count();
+            let end = change.pos + removed;
+            for line in run.lines.iter_mut() {
+                *line = line.filter(|&m| {
+                    removed == 0
+                        || !((change.pos..end).contains(&m) || (m == end && !at_line_start))
+                });
+                if let Some(m) = line {
+                    *m = change.map_pos(*m);
+                }
+            }
+        }
+
+        let map = |offset: &mut usize| *offset = change.map_pos(*offset);
+        self.marks.values_mut().for_each(map);
+        self.cursors.iter_mut().for_each(map);
+        if let Some(session) = &mut self.snippet {
+            session.map(change);
+        }
+        let path = &self.filepath;
+        self.global_marks
+            .values_mut()
+            .filter(|(p, _)| p == path)
+            .for_each(|(_, offset)| map(offset));
+        let end = change.pos + change.inserted.chars().count();
+        self.set_change_marks(change.pos, end);
+        self.marks.insert('.', change.pos);
+    }
+
+    /// Point `` `[ `` and `` `] `` at the first and last char of `[s, e)`.
+    fn set_change_marks(&mut self, s: usize, e: usize) {
+        self.marks.insert('[', s);
+        self.marks.insert(']', e.saturating_sub(1).max(s));
+    }
+
+    /// `m{a-zA-Z}`: set a mark at the cursor. Returns `false` for other names.
+    pub fn set_mark(&mut self, name: char) -> bool {
+        let offset = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        match name {
+            'a'..='z' => self.marks.insert(name, offset),
+            'A'..='Z' => self
+                .global_marks
+                .insert(name, (self.filepath.clone(), offset))
+                .map(|(_, o)| o),
+            _ => return false,
+        };
+        true
+    }
+
+    /// The file a global mark was set in, when that is not the current file
+    /// (the caller opens it before jumping).
+    pub fn mark_file(&self, name: char) -> Option<String> {
+        match self.global_marks.get(&name) {
+            Some((Some(path), _)) if self.filepath.as_ref() != Some(path) => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    /// Cursor position of mark `name` in the current buffer, if it is set.
+    pub fn mark(&self, name: char) -> Option<(usize, usize)> {
+        let offset = match name {
+            'A'..='Z' => match self.global_marks.get(&name)? {
+                (path, offset) if *path == self.filepath => *offset,
+                _ => return None,
+            },
+            _ => *self.marks.get(&name)?,
+        };
+        let last = self.buffer.len_chars().saturating_sub(1);
+        Some(self.char_to_cursor(offset.min(last)))
+    }
+
+    /// Where `motion` leads from the cursor, or `None` if it cannot move (a
+    /// mark that is not set). Marks are resolved here since they live in the
+    /// model rather than the buffer.
+    pub fn motion_target(&self, motion: Motion, count: usize) -> Option<Target> {
+        match motion {
+            Motion::Mark { name, linewise } => {
+                let (y, x) = self.mark(name)?;
+                Some(if linewise {
+                    let line = self.buffer.line_text(y);
+                    let x = line.chars().take_while(|c| c.is_whitespace()).count();
+                    Target {
+                        y,
+                        x,
+                        kind: MotionKind::Linewise,
+                        inclusive: false,
+                    }
+                } else {
+                    Target {
+                        y,
+                        x,
+                        kind: MotionKind::Charwise,
+                        inclusive: false,
+                    }
+                })
+            }
+            _ => Some(motion::compute(
+                &self.buffer,
+                self.cursor_y,
+                self.cursor_x,
+                motion,
+                count,
+            )),
+        }
     }
 
     /// Set the editor mode. Any mode transition ends the current insert-coalescing
     /// run so a new insert session becomes its own undo step.
     pub fn set_mode(&mut self, mode: EditorMode) {
+        if matches!(self.mode, EditorMode::Visual(_)) && !matches!(mode, EditorMode::Visual(_)) {
+            self.set_visual_marks();
+        }
+        if self.mode == EditorMode::Insert && mode != EditorMode::Insert {
+            self.end_snippet();
+            self.drop_auto_indent();
+            self.finish_block_insert();
+            self.finish_insert_record();
+            let offset = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+            self.marks.insert('^', offset);
+            if std::mem::take(&mut self.cursor_insert_group) {
+                self.end_undo_group();
+            }
+        } else if self.mode != EditorMode::Insert && mode == EditorMode::Insert {
+            self.insert_record = Some(String::new());
+            if !self.cursors.is_empty() {
+                self.begin_undo_group();
+                self.cursor_insert_group = true;
+            }
+        }
+        // A Replace-mode session is one undo step and one change for `.`.
+        if self.mode == EditorMode::Replace && mode != EditorMode::Replace {
+            if let Some(session) = self.replace_session.take() {
+                self.end_undo_group();
+                if !session.text.is_empty() {
+                    self.last_change = Some(LastChange::Replace { text: session.text });
+                }
+            }
+        } else if self.mode != EditorMode::Replace && mode == EditorMode::Replace {
+            self.begin_undo_group();
+            self.replace_session = Some(ReplaceSession::default());
+        }
         self.mode = mode;
         self.coalescing = false;
     }
 
     pub fn set_content(&mut self, content: &str) {
         self.buffer.set_content(content);
+        self.marks.clear();
+        self.cursors.clear();
+        // The old history does not apply to the new text; a persisted one may
+        // be restored with `restore_undo_tree`.
+        self.undo_tree = UndoTree::default();
+        self.coalescing = false;
         // Whole-file load bypasses `Change`, so bump the revision here too — the
         // syntax layer re-parses from scratch on the next cycle.
         self.edit_revision = self.edit_revision.wrapping_add(1);
@@ -140,12 +625,10 @@ impl EditorModel {
     pub fn move_cursor(&mut self, key: KeyCode) {
         match key {
             KeyCode::Up => {
-                self.cursor_y = self.cursor_y.saturating_sub(1);
+                self.move_to_col(self.cursor_y.saturating_sub(1), true);
             }
-            KeyCode::Down => {
-                if self.cursor_y < self.buffer.line_count().saturating_sub(1) {
-                    self.cursor_y += 1;
-                }
+            KeyCode::Down if self.cursor_y < self.buffer.line_count().saturating_sub(1) => {
+                self.move_to_col(self.cursor_y + 1, true);
             }
             KeyCode::Left => {
                 if self.cursor_x > 0 {
@@ -175,16 +658,49 @@ impl EditorModel {
                 self.cursor_x = line_len;
             }
         }
-        // Moving the cursor ends the current insert-coalescing run.
+        // Moving the cursor ends the current insert-coalescing run, and `.`
+        // will only repeat what is typed from here on (as in Vim).
         self.coalescing = false;
+        if let Some(record) = &mut self.insert_record {
+            record.clear();
+            self.insert_repeat = Some(LastChange::Insert {
+                entry: InsertEntry::Before,
+                text: String::new(),
+            });
+        }
     }
 
     /// Terminal display column of the cursor: the sum of display widths of the
     /// characters left of the cursor on its line (wide CJK = 2, combining = 0).
     pub fn display_col(&self) -> usize {
-        let line = self.buffer.line_text(self.cursor_y);
-        let prefix: String = line.chars().take(self.cursor_x).collect();
-        UnicodeWidthStr::width(prefix.as_str())
+        self.display_col_at(self.cursor_y, self.cursor_x)
+    }
+
+    /// Display column at which char `x` of line `y` starts.
+    pub fn display_col_at(&self, y: usize, x: usize) -> usize {
+        grapheme::display_col(&self.buffer.line_text(y), x)
+    }
+
+    /// Move to line `y`, onto the character covering the display column of the
+    /// last vertical move (or `$`) if the cursor has stayed where that left
+    /// it, else of the cursor. On a shorter line the cursor goes to its last
+    /// character, or past it with `past_end` (Insert mode). The column is
+    /// remembered for the next vertical move.
+    fn move_to_col(&mut self, y: usize, past_end: bool) {
+        let col = match self.sticky_col {
+            Some((sy, sx, col)) if (sy, sx) == (self.cursor_y, self.cursor_x) => col,
+            _ => self.display_col(),
+        };
+        self.cursor_y = y;
+        let line = self.buffer.line_text(y);
+        let len = self.buffer.line_char_len(y);
+        let last = if past_end {
+            len
+        } else {
+            grapheme::prev_boundary(&line, len)
+        };
+        self.cursor_x = grapheme::col_span(&line, col, col).map_or(last, |(s, _)| s.min(last));
+        self.sticky_col = Some((self.cursor_y, self.cursor_x, col));
     }
 
     /// Adjust the viewport offsets so the cursor is visible within a text area
@@ -207,6 +723,9 @@ impl EditorModel {
     }
 
     pub fn insert_char(&mut self, c: char) {
+        if self.options.autopairs && self.insert_pair(c) {
+            return;
+        }
         let before = (self.cursor_y, self.cursor_x);
         let change = if self.buffer.line_count() == 0 {
             // Empty document: materialize the first line (keeping the invariant).
@@ -228,20 +747,149 @@ impl EditorModel {
         // one char to the right of the inserted character.
         self.cursor_x += 1;
         self.last_change = Some(LastChange::InsertChar(c));
+        if let Some(record) = &mut self.insert_record {
+            record.push(c);
+        }
+        if let Some(extra) = self.electric_indent(c).or_else(|| self.auto_wrap(c)) {
+            self.commit_changes(vec![change, extra], before, (self.cursor_y, self.cursor_x));
+            return;
+        }
         let after = (self.cursor_y, self.cursor_x);
         self.commit_insert(change, before, after);
     }
 
+    /// With `autopairs`: type over the closing bracket or quote `c` when it is
+    /// next, or type an opening bracket or quote with its closing one after
+    /// it. A quote is only paired away from word characters; neither pairs in
+    /// a string or comment, or before a word character. Returns false when
+    /// `c` is to be typed as usual.
+    fn insert_pair(&mut self, c: char) -> bool {
+        if self.cursor_y >= self.buffer.line_count() {
+            return false;
+        }
+        let line: Vec<char> = self.buffer.line_text(self.cursor_y).chars().collect();
+        let x = self.cursor_x;
+        let word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric() || *c == '_');
+        let idx = self.buffer.cursor_to_char(self.cursor_y, x);
+        let change = if line.get(x) == Some(&c) && pairs::is_closer(c) {
+            None
+        } else {
+            let Some(close) = pairs::closer(c) else {
+                return false;
+            };
+            let quote_after_word = pairs::is_quote(c) && x > 0 && word(line.get(x - 1));
+            if quote_after_word || word(line.get(x)) || self.in_literal(idx) {
+                return false;
+            }
+            Some(Change {
+                pos: idx,
+                removed: String::new(),
+                inserted: format!("{}{}", c, close),
+            })
+        };
+        let before = (self.cursor_y, x);
+        self.cursor_x += 1;
+        self.last_change = Some(LastChange::InsertChar(c));
+        if let Some(record) = &mut self.insert_record {
+            record.push(c);
+        }
+        match change {
+            Some(change) => {
+                self.apply_change(&change);
+                self.commit(change, before, (self.cursor_y, self.cursor_x));
+            }
+            None => self.coalescing = false,
+        }
+        true
+    }
+
+    /// Whether char `idx` is inside a string or comment, as far as the
+    /// syntax tells.
+    fn in_literal(&mut self, idx: usize) -> bool {
+        let at = self.buffer.char_to_byte(idx);
+        match self.literals.as_mut() {
+            Some(literals) => literals.in_literal(&self.buffer.snapshot(), self.edit_revision, at),
+            None => false,
+        }
+    }
+
+    /// With a `textwidth`, a non-blank typed past it breaks the line at the
+    /// last blank that leaves the text before it within the width, else at
+    /// the first (see [`reflow::wrap_point`]); the new line keeps the comment
+    /// leader and list indent. Returns the change made, not yet committed.
+    fn auto_wrap(&mut self, c: char) -> Option<Change> {
+        let width = self.options.textwidth;
+        if width == 0 || c.is_whitespace() {
+            return None;
+        }
+        let y = self.cursor_y;
+        let line = self.buffer.line_text(y);
+        let (s, e) = reflow::wrap_point(&line, self.cursor_x, width, self.options.tabstop)?;
+        let head = reflow::continuation(&line);
+        let base = self.buffer.line_to_char(y);
+        let change = Change {
+            pos: base + s,
+            removed: self.buffer.slice_text(base + s..base + e),
+            inserted: format!("\n{}", head),
+        };
+        self.apply_change(&change);
+        self.cursor_y = y + 1;
+        self.cursor_x = head.chars().count() + self.cursor_x - e;
+        Some(change)
+    }
+
+    /// A closing bracket typed first on its line takes the line to the indent
+    /// the syntax calls for. Returns the change made, not yet committed.
+    fn electric_indent(&mut self, c: char) -> Option<Change> {
+        if !matches!(c, '}' | ')' | ']') {
+            return None;
+        }
+        let y = self.cursor_y;
+        let line = self.buffer.line_text(y);
+        let blanks = indent::leading_blanks(&line);
+        if blanks + 1 != self.cursor_x {
+            return None;
+        }
+        let width = self.smart_indents(y, y)[0]?;
+        let text = indent::indent_text(width, self.options.tabstop, self.options.expandtab);
+        if line.chars().take(blanks).collect::<String>() == text {
+            return None;
+        }
+        let pos = self.buffer.line_to_char(y);
+        let change = Change {
+            pos,
+            removed: self.buffer.slice_text(pos..pos + blanks),
+            inserted: text,
+        };
+        self.apply_change(&change);
+        self.cursor_x = change.inserted.chars().count() + 1;
+        Some(change)
+    }
+
     pub fn delete_char(&mut self) {
         if self.cursor_y >= self.buffer.line_count() {
             return;
         }
+        if let Some(record) = &mut self.insert_record {
+            if record.is_empty() || record.ends_with('\u{8}') {
+                record.push('\u{8}');
+            } else {
+                record.pop();
+            }
+        }
         let before = (self.cursor_y, self.cursor_x);
         if self.cursor_x > 0 {
             let idx = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+            // With `autopairs`, deleting an opening bracket or quote deletes
+            // the closing one right after it too.
+            let line_end =
+                self.buffer.line_to_char(self.cursor_y) + self.buffer.line_char_len(self.cursor_y);
+            let paired = self.options.autopairs
+                && idx < line_end
+                && pairs::closer(self.buffer.char_at(idx - 1)) == Some(self.buffer.char_at(idx));
             let change = Change {
                 pos: idx - 1,
-                removed: self.buffer.slice_text(idx - 1..idx),
+                removed: self.buffer.slice_text(idx - 1..idx + paired as usize),
                 inserted: String::new(),
             };
             self.apply_change(&change);
@@ -283,8 +931,24 @@ impl EditorModel {
         }
     }
 
+    /// Split the line at the cursor. The new line is indented (see
+    /// [`Options`]) in place of the blanks it started with, and an auto
+    /// indent left unused on the line split goes. With `autopairs`, splitting
+    /// an empty bracket pair puts the closing bracket on a line of its own
+    /// below the new one.
     pub fn insert_newline(&mut self) {
         let before = (self.cursor_y, self.cursor_x);
+        let unused = self.unused_auto_indent();
+        let in_pair = self.options.autopairs
+            && self.cursor_x > 0
+            && self.cursor_y < self.buffer.line_count()
+            && {
+                let line: Vec<char> = self.buffer.line_text(self.cursor_y).chars().collect();
+                let open = line[self.cursor_x - 1];
+                !pairs::is_quote(open)
+                    && pairs::closer(open).is_some()
+                    && line.get(self.cursor_x).copied() == pairs::closer(open)
+            };
         let change = if self.buffer.line_count() == 0 {
             // Empty document: create the current empty line and the split line.
             Change {
@@ -294,17 +958,43 @@ impl EditorModel {
             }
         } else {
             let idx = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+            let start = unused.map_or(idx, |(pos, _)| pos);
+            let line = self.buffer.line_text(self.cursor_y);
+            let tail: String = line.chars().skip(self.cursor_x).collect();
+            let end = idx + indent::leading_blanks(&tail);
             Change {
-                pos: idx,
-                removed: String::new(),
+                pos: start,
+                removed: self.buffer.slice_text(start..end),
                 inserted: "\n".to_string(),
             }
         };
         self.apply_change(&change);
         self.cursor_y += 1;
         self.cursor_x = 0;
+        let fallback = self.indent_above(self.cursor_y);
+        let width = self.wanted_indent(self.cursor_y, fallback);
+        let mut changes = vec![change];
+        changes.extend(self.indent_new_line(width));
+        if in_pair {
+            let change = Change {
+                pos: self.buffer.line_to_char(self.cursor_y),
+                removed: String::new(),
+                inserted: "\n".to_string(),
+            };
+            self.apply_change(&change);
+            self.cursor_x = 0;
+            changes.push(change);
+            let closing = self.buffer.line_text(self.cursor_y + 1);
+            let fallback =
+                indent::indent_width(&closing, self.options.tabstop) + self.options.shift_width();
+            let width = self.wanted_indent(self.cursor_y, fallback);
+            changes.extend(self.indent_new_line(width));
+        }
         self.last_change = Some(LastChange::InsertNewline);
-        self.commit(change, before, (self.cursor_y, self.cursor_x));
+        if let Some(record) = &mut self.insert_record {
+            record.push('\n');
+        }
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
     }
 
     pub fn insert_line_below(&mut self) {
@@ -327,8 +1017,10 @@ impl EditorModel {
         };
         self.apply_change(&change);
         self.cursor_x = 0;
+        let from = self.cursor_y.saturating_sub(1);
+        let changes = self.indent_opened_line(change, from);
         self.last_change = Some(LastChange::InsertLineBelow);
-        self.commit(change, before, (self.cursor_y, self.cursor_x));
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
     }
 
     pub fn insert_line_above(&mut self) {
@@ -341,8 +1033,27 @@ impl EditorModel {
         };
         self.apply_change(&change);
         self.cursor_x = 0;
+        let from = self.cursor_y + 1;
+        let changes = self.indent_opened_line(change, from);
         self.last_change = Some(LastChange::InsertLineAbove);
-        self.commit(change, before, (self.cursor_y, self.cursor_x));
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// Indent the line `o` / `O` just opened with `change`: as the syntax
+    /// calls for, else like line `from`, the one it was opened from (or the
+    /// line above when that is blank).
+    fn indent_opened_line(&mut self, change: Change, from: usize) -> Vec<Change> {
+        let y = self.cursor_y;
+        let line = self.buffer.line_text(from);
+        let fallback = if line.chars().any(|c| !indent::is_blank(c)) {
+            indent::indent_width(&line, self.options.tabstop)
+        } else {
+            self.indent_above(y)
+        };
+        let width = self.wanted_indent(y, fallback);
+        let mut changes = vec![change];
+        changes.extend(self.indent_new_line(width));
+        changes
     }
 
     pub fn delete_current_line(&mut self) {
@@ -376,10 +1087,6 @@ impl EditorModel {
         self.commit(change, before, (self.cursor_y, self.cursor_x));
     }
 
-    pub fn put_line_below(&mut self) {
-        self.paste(true, 1);
-    }
-
     /// Delete `count` characters from under the cursor (the `x` command),
     /// filling the unnamed register (charwise) like Vim.
     pub fn delete_under_cursor(&mut self, count: usize) {
@@ -394,16 +1101,60 @@ impl EditorModel {
         let end_x = (self.cursor_x + count.max(1)).min(line_len);
         let e = self.buffer.cursor_to_char(self.cursor_y, end_x);
         self.operate_charwise_range(Operator::Delete, s, e);
+        // `x` is `dl`.
+        let range = OperatorRange::Motion(Motion::Right);
+        self.record_operator(Operator::Delete, range, count.max(1), false);
+    }
+
+    /// Contents of register `name`, including the read-only `"%` (file path)
+    /// and `":` (last command line).
+    pub fn register(&self, name: char) -> Option<Register> {
+        match name {
+            '%' => self.filepath.as_deref().map(Register::charwise),
+            ':' => self.registers.last_command().map(Register::charwise),
+            _ => self.registers.get(name).cloned(),
+        }
+    }
+
+    /// Store the text an operator captured in the selected register, following
+    /// the yank/delete rules of [`Registers`].
+    fn store_register(&mut self, op: Operator, reg: Register) {
+        match op {
+            Operator::Yank => self.registers.yank(self.register_name, reg),
+            Operator::Delete | Operator::Change => self.registers.delete(self.register_name, reg),
+            Operator::Filter
+            | Operator::SwitchCase
+            | Operator::Lowercase
+            | Operator::Uppercase
+            | Operator::ShiftRight
+            | Operator::ShiftLeft
+            | Operator::Reindent
+            | Operator::Surround(_)
+            | Operator::Comment
+            | Operator::BlockComment
+            | Operator::Format
+            | Operator::FormatKeep => {}
+        }
     }
 
-    /// Paste the unnamed register `count` times. `after` selects `p`
-    /// (below/after) vs `P` (above/before). Linewise registers paste onto new
-    /// lines; charwise registers paste inline.
-    pub fn paste(&mut self, after: bool, count: usize) {
-        let Some(reg) = self.register.clone() else {
+    /// Paste register `name` (the unnamed register when `None`) `count` times.
+    /// `after` selects `p` (below/after) vs `P` (above/before). Linewise
+    /// registers paste onto new lines; charwise registers paste inline.
+    pub fn paste(&mut self, name: Option<char>, after: bool, count: usize) {
+        let Some(reg) = self.register(name.unwrap_or('"')) else {
             return;
         };
         let count = count.max(1);
+        if reg.blockwise {
+            if self.paste_block(&reg.text, after, count) {
+                self.last_change = Some(LastChange::Paste {
+                    register: name,
+                    after,
+                    count,
+                });
+            }
+            return;
+        }
         let text = reg.text.repeat(count);
         let before = (self.cursor_y, self.cursor_x);
         if reg.linewise {
@@ -425,7 +1176,11 @@ impl EditorModel {
             self.apply_change(&change);
             self.cursor_y = new_y;
             self.cursor_x = 0;
-            self.last_change = Some(LastChange::PutLineBelow);
+            self.last_change = Some(LastChange::Paste {
+                register: name,
+                after,
+                count,
+            });
             self.commit(change, before, (self.cursor_y, self.cursor_x));
         } else {
             // Charwise paste.
@@ -455,11 +1210,60 @@ impl EditorModel {
             let (cy, cx) = self.char_to_cursor(pos + text_len.saturating_sub(1));
             self.cursor_y = cy;
             self.cursor_x = cx;
-            self.last_change = Some(LastChange::PutLineBelow);
+            self.last_change = Some(LastChange::Paste {
+                register: name,
+                after,
+                count,
+            });
             self.commit(change, before, (self.cursor_y, self.cursor_x));
         }
     }
 
+    /// Paste a blockwise register as a rectangle: row `i` goes into line
+    /// `cursor_y + i` at the cursor's display column, padding short lines with
+    /// spaces and appending lines past the end of the buffer. Returns false
+    /// when nothing was pasted.
+    fn paste_block(&mut self, text: &str, after: bool, count: usize) -> bool {
+        let before = (self.cursor_y, self.cursor_x);
+        let base_x = if after && self.buffer.line_char_len(self.cursor_y) > 0 {
+            grapheme::next_boundary(&self.buffer.line_text(self.cursor_y), self.cursor_x)
+        } else {
+            self.cursor_x
+        };
+        let col = self.display_col_at(self.cursor_y, base_x);
+        let mut edits = Vec::new();
+        let mut tail = String::new();
+        for (i, row) in text.split('\n').enumerate() {
+            let row = row.repeat(count);
+            let y = self.cursor_y + i;
+            if y < self.buffer.line_count() {
+                let line = self.buffer.line_text(y);
+                let base = self.buffer.line_to_char(y);
+                let width = grapheme::width(&line);
+                if width < col {
+                    let end = base + line.chars().count();
+                    edits.push((end, end, format!("{}{}", " ".repeat(col - width), row)));
+                } else {
+                    let x = base + grapheme::col_to_char(&line, col);
+                    edits.push((x, x, row));
+                }
+            } else {
+                tail.push_str(&format!("{}{}\n", " ".repeat(col), row));
+            }
+        }
+        if !tail.is_empty() {
+            let end = self.buffer.len_chars();
+            edits.push((end, end, tail));
+        }
+        let changes = self.apply_edits(edits);
+        if changes.is_empty() {
+            return false;
+        }
+        self.goto(before.0, base_x);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        true
+    }
+
     /// Map a whole-buffer char index to a clamped `(y, x)` cursor position.
     fn char_to_cursor(&self, idx: usize) -> (usize, usize) {
         if self.buffer.line_count() == 0 {
@@ -477,9 +1281,19 @@ impl EditorModel {
 
     /// Move the cursor by a bare motion (no operator pending).
     pub fn move_by_motion(&mut self, motion: Motion, count: usize) {
-        let t = motion::compute(&self.buffer, self.cursor_y, self.cursor_x, motion, count);
-        self.cursor_y = t.y.min(self.buffer.line_count().saturating_sub(1));
-        self.cursor_x = t.x.min(self.buffer.line_char_len(self.cursor_y));
+        let Some(t) = self.motion_target(motion, count) else {
+            return;
+        };
+        let y = t.y.min(self.buffer.line_count().saturating_sub(1));
+        if matches!(motion, Motion::Up | Motion::Down) {
+            self.move_to_col(y, false);
+        } else {
+            self.cursor_y = y;
+            self.cursor_x = t.x.min(self.buffer.line_char_len(self.cursor_y));
+            if motion == Motion::LineEnd {
+                self.sticky_col = Some((self.cursor_y, self.cursor_x, usize::MAX));
+            }
+        }
         self.coalescing = false;
     }
 
@@ -496,58 +1310,204 @@ impl EditorModel {
 
     /// Apply a batch of non-overlapping char-range edits (from an LSP
     /// `formatting`/`rename` response) as a **single undo step**. Each edit is
-    /// `(start_char, end_char, new_text)` with whole-buffer char offsets. The
-    /// edits are folded into one spanning [`Change`] over `[min_start, max_end)`
-    /// so `u` reverts the whole batch at once. Returns `false` if there was
-    /// nothing to apply.
-    #[allow(dead_code)] // wired up in the format/rename milestone
-    pub fn apply_lsp_edits(&mut self, mut edits: Vec<(usize, usize, String)>) -> bool {
-        edits.retain(|(s, e, t)| !(s == e && t.is_empty()));
-        if edits.is_empty() {
+    /// `(start_char, end_char, new_text)` with whole-buffer char offsets. Each
+    /// edit becomes its own [`Change`] in one transaction, so `u` reverts the
+    /// whole batch at once while text between the edits is left untouched.
+    /// Returns `false` if there was nothing to apply.
+    pub fn apply_lsp_edits(&mut self, edits: Vec<(usize, usize, String)>) -> bool {
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(edits);
+        if changes.is_empty() {
             return false;
         }
-        edits.sort_by_key(|(s, _, _)| *s);
-        let len = self.buffer.len_chars();
-        let min = edits.first().unwrap().0.min(len);
-        let max = edits
-            .iter()
-            .map(|(_, e, _)| *e)
-            .max()
-            .unwrap()
-            .min(len)
-            .max(min);
-        let removed = self.buffer.slice_text(min..max);
-        // Rebuild the [min, max) region with the edits applied in order.
-        let mut inserted = String::new();
-        let mut cur = min;
-        for (s, e, t) in &edits {
-            let s = (*s).clamp(min, max);
-            let e = (*e).clamp(s, max);
-            if cur < s {
-                inserted.push_str(&self.buffer.slice_text(cur..s));
-            }
-            inserted.push_str(t);
-            cur = e;
-        }
-        if cur < max {
-            inserted.push_str(&self.buffer.slice_text(cur..max));
-        }
-        let change = Change {
-            pos: min,
-            removed,
-            inserted,
-        };
-        let before = (self.cursor_y, self.cursor_x);
-        self.apply_change(&change);
         // Best-effort cursor: clamp to the edited buffer.
         let last = self.buffer.line_count().saturating_sub(1);
         self.cursor_y = self.cursor_y.min(last);
         self.cursor_x = self.cursor_x.min(self.buffer.line_char_len(self.cursor_y));
         let after = (self.cursor_y, self.cursor_x);
-        self.commit(change, before, after);
+        self.commit_changes(changes, before, after);
+        true
+    }
+
+    /// Apply non-overlapping char-range edits `(start, end, text)` (offsets into
+    /// the current text) one region at a time, so marks outside the edited
+    /// text keep their place. Returns the changes for [`Self::commit_changes`];
+    /// empty when there was nothing to do.
+    fn apply_edits(&mut self, edits: Vec<(usize, usize, String)>) -> Vec<Change> {
+        let changes = transaction::sequential_changes(&self.buffer, edits);
+        for change in &changes {
+            self.apply_change(change);
+        }
+        changes
+    }
+
+    /// Replace chars `[s, e)` with the snippet `body`, in the LSP snippet
+    /// syntax (see [`snippet::parse`]). Its lines after the first take the
+    /// indent of the line it goes on, and its tabs are indented as
+    /// `expandtab` says. The cursor goes to its first tabstop; in Insert
+    /// mode, Tab and Shift-Tab then step through the others (see
+    /// [`Self::snippet_jump`]) until the final one or the end of the insert.
+    pub fn insert_snippet(&mut self, s: usize, e: usize, body: &str) {
+        self.end_snippet();
+        let y = self.buffer.char_to_line(s);
+        let line = self.buffer.line_text(y);
+        let blanks: String = line.chars().take_while(|c| indent::is_blank(*c)).collect();
+        let tab = if self.options.expandtab {
+            " ".repeat(self.options.shift_width())
+        } else {
+            "\t".to_string()
+        };
+        let body = body
+            .replace('\t', &tab)
+            .replace('\n', &format!("\n{}", blanks));
+        let mut snippet = snippet::parse(&body, &|name| self.snippet_variable(name, y));
+        let text = std::mem::take(&mut snippet.text);
+        let session = Session::new(snippet, s);
+        let grouped = self.mode == EditorMode::Insert && !session.at_final();
+        if grouped {
+            self.begin_undo_group();
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(vec![(s, e, text)]);
+        if grouped {
+            self.snippet = Some(session);
+            self.enter_stop();
+        } else {
+            (self.cursor_y, self.cursor_x) = self.char_to_cursor(session.current().ranges[0].0);
+        }
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// The value of snippet variable `name` for a snippet going on line `y`.
+    fn snippet_variable(&self, name: &str, y: usize) -> Option<String> {
+        let path = self.filepath.as_deref().map(Path::new);
+        let lossy = |s: &std::ffi::OsStr| s.to_string_lossy().into_owned();
+        match name {
+            "TM_FILENAME" => path.and_then(Path::file_name).map(lossy),
+            "TM_FILENAME_BASE" => path.and_then(Path::file_stem).map(lossy),
+            "TM_DIRECTORY" => path
+                .and_then(Path::parent)
+                .map(|dir| dir.to_string_lossy().into_owned()),
+            "TM_FILEPATH" => self.filepath.clone(),
+            "TM_LINE_INDEX" => Some(y.to_string()),
+            "TM_LINE_NUMBER" => Some((y + 1).to_string()),
+            "TM_CURRENT_LINE" => Some(self.buffer.line_text(y)),
+            "TM_SELECTED_TEXT" => Some(String::new()),
+            "CLIPBOARD" => Some(self.register('"').map(|r| r.text).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    /// Put the cursor on the current tabstop of the snippet: at the start of
+    /// its placeholder, which typing replaces, or after a choice, which the
+    /// completion menu replaces. The final tabstop ends the snippet.
+    fn enter_stop(&mut self) {
+        let Some(session) = &mut self.snippet else {
+            return;
+        };
+        let (s, e) = session.current().ranges[0];
+        let choice = !session.current().choices.is_empty();
+        session.fresh = !choice && s < e;
+        let at_final = session.at_final();
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(if choice { e } else { s });
+        if at_final {
+            self.end_snippet();
+        }
+    }
+
+    /// `Tab` / `Shift-Tab` in a snippet: move to its next (or previous)
+    /// tabstop. Returns false when there is no snippet or no such tabstop.
+    pub fn snippet_jump(&mut self, forward: bool) -> bool {
+        if !self
+            .snippet
+            .as_mut()
+            .is_some_and(|session| session.step(forward))
+        {
+            return false;
+        }
+        self.enter_stop();
+        true
+    }
+
+    /// End the snippet being filled in, closing its undo group.
+    fn end_snippet(&mut self) {
+        if self.snippet.take().is_some() {
+            self.end_undo_group();
+        }
+    }
+
+    /// With the cursor at the start of a snippet placeholder nothing has been
+    /// typed in yet, delete it (and its mirrors) for what is typed instead.
+    /// Returns whether it did.
+    pub fn take_placeholder(&mut self) -> bool {
+        let cursor = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        let Some(session) = &mut self.snippet else {
+            return false;
+        };
+        let ranges = session.current().ranges.clone();
+        if !std::mem::take(&mut session.fresh) || ranges[0].0 != cursor {
+            return false;
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(
+            ranges
+                .into_iter()
+                .map(|(s, e)| (s, e, String::new()))
+                .collect(),
+        );
+        if let Some(session) = &self.snippet {
+            (self.cursor_y, self.cursor_x) = self.char_to_cursor(session.current().ranges[0].0);
+        }
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
         true
     }
 
+    /// Copy the text typed at the current snippet tabstop to its mirrors.
+    pub fn sync_snippet(&mut self) {
+        let Some(session) = &self.snippet else {
+            return;
+        };
+        let ranges = &session.current().ranges;
+        let text = self.buffer.slice_text(ranges[0].0..ranges[0].1);
+        let edits: Vec<_> = ranges[1..]
+            .iter()
+            .filter(|&&(s, e)| self.buffer.slice_text(s..e) != text)
+            .map(|&(s, e)| (s, e, text.clone()))
+            .collect();
+        if edits.is_empty() {
+            return;
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let cursor = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(edits);
+        let cursor = changes.iter().fold(cursor, |at, change| change.map_pos(at));
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(cursor);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// The choices offered at the current snippet tabstop, with where its
+    /// text starts, for the completion menu.
+    pub fn snippet_choices(&self) -> Option<(Vec<String>, usize, usize)> {
+        let stop = self.snippet.as_ref()?.current();
+        if stop.choices.is_empty() {
+            return None;
+        }
+        let (y, x) = self.char_to_cursor(stop.ranges[0].0);
+        Some((stop.choices.clone(), y, x))
+    }
+
+    /// Char columns of the snippet placeholder waiting to be typed over, if
+    /// it starts on line `y`. Used by the renderer.
+    pub fn placeholder_span(&self, y: usize) -> Option<(usize, usize)> {
+        let session = self.snippet.as_ref().filter(|session| session.fresh)?;
+        let (s, e) = session.current().ranges[0];
+        let start = self.buffer.line_to_char(y);
+        let end = start + self.buffer.line_char_len(y);
+        (start..end)
+            .contains(&s)
+            .then(|| (s - start, e.min(end) - start))
+    }
+
     /// Record the current location on the jump list (call right before an
     /// absolute jump such as go-to-definition). Drops any forward history.
     #[allow(dead_code)] // wired up in the go-to-definition milestone
@@ -585,20 +1545,168 @@ impl EditorModel {
         Some(self.jumps[self.jump_index].clone())
     }
 
-    /// Apply an operator over the range described by `motion`. Returns true when
-    /// the caller should switch to insert mode (the change operator).
-    pub fn apply_operator(&mut self, op: Operator, motion: Motion, count: usize) -> bool {
-        // `cw`/`cW` behaves like `ce`/`cE`: change up to the end of the word.
-        let motion = if op == Operator::Change {
-            match motion {
-                Motion::WordForward { big } => Motion::WordEnd { big },
-                other => other,
-            }
-        } else {
-            motion
-        };
-        let t = motion::compute(&self.buffer, self.cursor_y, self.cursor_x, motion, count);
-        self.operate(op, t)
+    /// Number of cursors, counting the primary one.
+    pub fn cursor_count(&self) -> usize {
+        self.cursors.len() + 1
+    }
+
+    /// Char columns of the secondary cursors on line `y`. Used by the renderer.
+    pub fn cursors_on_line(&self, y: usize) -> Vec<usize> {
+        self.cursors
+            .iter()
+            .map(|&c| self.char_to_cursor(c))
+            .filter(|&(cy, _)| cy == y)
+            .map(|(_, x)| x)
+            .collect()
+    }
+
+    /// Drop every cursor but the primary one.
+    pub fn clear_cursors(&mut self) {
+        self.cursors.clear();
+    }
+
+    /// Add a cursor at the next whole-word occurrence of the word under the
+    /// cursor, wrapping around the buffer, and make it the primary cursor (at
+    /// the same column within the word). Returns `false` when the cursor is
+    /// not on a word or every occurrence already has a cursor.
+    pub fn add_cursor_at_next_match(&mut self) -> bool {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let word = TextObject::Word { big: false };
+        let Some((s, e, _)) =
+            text_object::range(&self.buffer, self.cursor_y, self.cursor_x, word, true, 1)
+        else {
+            return false;
+        };
+        let text: Vec<char> = self.buffer.raw_content().chars().collect();
+        let pattern = &text[s..e];
+        if !pattern.iter().all(|&c| is_word(c)) {
+            return false;
+        }
+        let cursor = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        let taken = |start: usize| {
+            (start..start + pattern.len()).any(|c| c == cursor || self.cursors.contains(&c))
+        };
+        let found = (1..text.len())
+            .map(|k| (s + k) % text.len())
+            .filter(|&start| start + pattern.len() <= text.len())
+            .find(|&start| {
+                let end = start + pattern.len();
+                &text[start..end] == pattern
+                    && (start == 0 || !is_word(text[start - 1]))
+                    && (end == text.len() || !is_word(text[end]))
+                    && !taken(start)
+            });
+        let Some(start) = found else {
+            return false;
+        };
+        self.cursors.push(cursor);
+        self.cursors.sort_unstable();
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(start + (cursor - s));
+        self.coalescing = false;
+        true
+    }
+
+    /// Leave Visual mode with one cursor per selected line, at the start of
+    /// the line's part of the selection: every line of a block (skipping lines
+    /// too short to reach it), or the lines of a charwise or linewise
+    /// selection. The cursor's own line keeps the primary cursor.
+    pub fn split_selection_into_cursors(&mut self) -> bool {
+        if !matches!(self.mode, EditorMode::Visual(_)) {
+            return false;
+        }
+        let top = self.visual_anchor.0.min(self.cursor_y);
+        let bottom = self.visual_anchor.0.max(self.cursor_y);
+        let mut primary = None;
+        let mut cursors = Vec::new();
+        for y in top..=bottom {
+            let Some((sx, _)) = self.selection_span(y) else {
+                continue;
+            };
+            let x = sx.min(self.buffer.line_char_len(y).saturating_sub(1));
+            if y == self.cursor_y {
+                primary = Some((y, x));
+            } else {
+                cursors.push(self.buffer.cursor_to_char(y, x));
+            }
+        }
+        self.set_mode(EditorMode::Normal);
+        let (y, x) = match primary {
+            Some(p) => p,
+            None if !cursors.is_empty() => self.char_to_cursor(cursors.remove(0)),
+            None => return false,
+        };
+        (self.cursor_y, self.cursor_x) = (y, x);
+        self.cursors = cursors;
+        self.cursors.sort_unstable();
+        true
+    }
+
+    /// Run `f` once at every cursor, secondary ones first and the primary one
+    /// last, as a single undo step. Registers and the insert record for `.`
+    /// only see the primary cursor's run. Cursors that end up in the same
+    /// place merge. Returns the primary run's result.
+    pub fn at_each_cursor<R>(&mut self, mut f: impl FnMut(&mut Self) -> R) -> R {
+        if self.cursors.is_empty() {
+            return f(self);
+        }
+        self.begin_undo_group();
+        let primary = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        self.cursors.push(primary);
+        let last = self.cursors.len() - 1;
+        for i in 0..last {
+            (self.cursor_y, self.cursor_x) = self.char_to_cursor(self.cursors[i]);
+            let registers = self.registers.clone();
+            let record = self.insert_record.take();
+            f(self);
+            self.registers = registers;
+            self.insert_record = record;
+            self.cursors[i] = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        }
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(self.cursors[last]);
+        let result = f(self);
+        self.cursors.pop();
+        let primary = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        self.cursors.retain(|&c| c != primary);
+        self.cursors.sort_unstable();
+        self.cursors.dedup();
+        self.end_undo_group();
+        result
+    }
+
+    /// Apply an operator over the range described by `motion`. Returns true when
+    /// the caller should switch to insert mode (the change operator).
+    pub fn apply_operator(&mut self, op: Operator, motion: Motion, count: usize) -> bool {
+        // `cw`/`cW` behaves like `ce`/`cE`: change up to the end of the word.
+        let effective = if op == Operator::Change {
+            match motion {
+                Motion::WordForward { big } => Motion::WordEnd { big },
+                other => other,
+            }
+        } else {
+            motion
+        };
+        let Some(t) = self.motion_target(effective, count) else {
+            return false;
+        };
+        let enter = self.operate(op, t);
+        self.record_operator(op, OperatorRange::Motion(motion), count, enter);
+        enter
+    }
+
+    /// Remember an operator for `.`. Yanks are not repeatable; a change that
+    /// entered insert mode is completed by the text typed in that session.
+    fn record_operator(&mut self, op: Operator, range: OperatorRange, count: usize, insert: bool) {
+        let change = LastChange::Operator {
+            op,
+            range,
+            count,
+            inserted: String::new(),
+        };
+        match op {
+            Operator::Yank | Operator::Filter => {}
+            Operator::Change if insert => self.insert_repeat = Some(change),
+            _ => self.last_change = Some(change),
+        }
     }
 
     /// Apply an operator to `count` whole lines from the cursor (dd/cc/yy).
@@ -611,7 +1719,9 @@ impl EditorModel {
             kind: MotionKind::Linewise,
             inclusive: false,
         };
-        self.operate(op, t)
+        let enter = self.operate(op, t);
+        self.record_operator(op, OperatorRange::Lines, count, enter);
+        enter
     }
 
     fn operate(&mut self, op: Operator, t: Target) -> bool {
@@ -652,12 +1762,10 @@ impl EditorModel {
         }
         let before = (self.cursor_y, self.cursor_x);
         let reg_text = self.buffer.slice_text(s..e);
-        self.register = Some(Register {
-            text: reg_text.clone(),
-            linewise: false,
-        });
+        self.store_register(op, Register::charwise(reg_text.clone()));
         match op {
             Operator::Yank => {
+                self.set_change_marks(s, e);
                 let (cy, cx) = self.char_to_cursor(s);
                 self.cursor_y = cy;
                 self.cursor_x = cx;
@@ -673,10 +1781,35 @@ impl EditorModel {
                 let (cy, cx) = self.char_to_cursor(s);
                 self.cursor_y = cy;
                 self.cursor_x = cx;
-                self.last_change = Some(LastChange::DeleteChar);
                 self.commit(change, before, (self.cursor_y, self.cursor_x));
                 op == Operator::Change
             }
+            // A filter and the indent and comment operators always take whole
+            // lines.
+            Operator::Filter
+            | Operator::ShiftRight
+            | Operator::ShiftLeft
+            | Operator::Reindent
+            | Operator::Comment
+            | Operator::Format
+            | Operator::FormatKeep => {
+                let lo = self.buffer.char_to_line(s);
+                let hi = self.buffer.char_to_line(e - 1);
+                self.operate_linewise_range(op, lo, hi)
+            }
+            Operator::SwitchCase | Operator::Lowercase | Operator::Uppercase => {
+                self.recase_range(op, s, e);
+                (self.cursor_y, self.cursor_x) = self.char_to_cursor(s);
+                false
+            }
+            Operator::Surround(c) => {
+                self.surround_range(c, s, e);
+                false
+            }
+            Operator::BlockComment => {
+                self.toggle_block_comment(s, e);
+                false
+            }
         }
     }
 
@@ -689,12 +1822,15 @@ impl EditorModel {
         let hi = hi.min(last);
         let start = self.buffer.line_to_char(lo);
         let end = self.buffer.line_to_char(hi + 1);
-        self.register = Some(Register {
+        let reg = Register {
             text: self.buffer.slice_text(start..end),
             linewise: true,
-        });
+            blockwise: false,
+        };
+        self.store_register(op, reg);
         match op {
             Operator::Yank => {
+                self.set_change_marks(start, end.saturating_sub(1));
                 self.cursor_y = lo;
                 self.cursor_x = 0;
                 false
@@ -717,11 +1853,11 @@ impl EditorModel {
                 self.apply_change(&change);
                 self.cursor_y = lo.min(self.buffer.line_count().saturating_sub(1));
                 self.cursor_x = 0;
-                self.last_change = Some(LastChange::DeleteCurrentLine);
                 self.commit(change, before, (self.cursor_y, self.cursor_x));
                 false
             }
             Operator::Change => {
+                let old = indent::indent_width(&self.buffer.line_text(lo), self.options.tabstop);
                 let change = Change {
                     pos: start,
                     removed: self.buffer.slice_text(start..end),
@@ -730,11 +1866,356 @@ impl EditorModel {
                 self.apply_change(&change);
                 self.cursor_y = lo;
                 self.cursor_x = 0;
-                self.last_change = Some(LastChange::DeleteCurrentLine);
-                self.commit(change, before, (lo, 0));
+                // `cc` keeps the indent of the first line.
+                let width = self.wanted_indent(lo, old);
+                let mut changes = vec![change];
+                changes.extend(self.indent_new_line(width));
+                self.commit_changes(changes, before, (lo, self.cursor_x));
                 true
             }
+            Operator::Filter => {
+                self.filter_lines = Some(LineRange { start: lo, end: hi });
+                self.cursor_y = lo;
+                self.cursor_x = 0;
+                false
+            }
+            Operator::SwitchCase | Operator::Lowercase | Operator::Uppercase => {
+                self.recase_range(op, start, end);
+                (self.cursor_y, self.cursor_x) = (lo, 0);
+                false
+            }
+            Operator::ShiftRight | Operator::ShiftLeft | Operator::Reindent => {
+                self.indent_lines(op, lo, hi);
+                false
+            }
+            // Lines are surrounded from the first non-blank to the end.
+            Operator::Surround(c) => {
+                let s = start + indent::leading_blanks(&self.buffer.line_text(lo));
+                let e = self.buffer.line_to_char(hi) + self.buffer.line_char_len(hi);
+                self.surround_range(c, s, e);
+                false
+            }
+            Operator::Comment | Operator::BlockComment => {
+                self.toggle_comments(lo, hi);
+                false
+            }
+            Operator::Format | Operator::FormatKeep => {
+                self.format_lines(lo, hi, op == Operator::FormatKeep);
+                false
+            }
+        }
+    }
+
+    /// `gq` / `gw` over the lines `lo..=hi` (see [`reflow::reflow`]), as one
+    /// undo step. The cursor goes to the first non-blank of the last line,
+    /// or with `keep` stays on the same text.
+    fn format_lines(&mut self, lo: usize, hi: usize, keep: bool) {
+        let lines: Vec<String> = (lo..=hi).map(|y| self.buffer.line_text(y)).collect();
+        let width = self.options.format_width();
+        let formatted = reflow::reflow(&lines, width, self.options.tabstop);
+        let s = self.buffer.line_to_char(lo);
+        let e = self.buffer.line_to_char(hi) + self.buffer.line_char_len(hi);
+        // The cursor's place in the text, counted in non-blank chars.
+        let cursor = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x);
+        let nth = self
+            .buffer
+            .slice_text(s..cursor.clamp(s, e))
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .count();
+        let before = (self.cursor_y, self.cursor_x);
+        let text = formatted.join("\n");
+        let changes = self.apply_edits(vec![(s, e, text.clone())]);
+        let last = lo + formatted.len().saturating_sub(1);
+        self.set_change_marks(s, s + text.chars().count());
+        if keep {
+            let offset = text
+                .char_indices()
+                .filter(|(_, c)| !c.is_whitespace())
+                .nth(nth)
+                .map_or(text.len(), |(i, _)| i);
+            let idx = s + text[..offset].chars().count();
+            (self.cursor_y, self.cursor_x) = self.char_to_cursor(idx);
+        } else {
+            self.cursor_y = last;
+            self.cursor_x = indent::leading_blanks(&self.buffer.line_text(last));
+        }
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// `>`, `<` and `=` over the lines `lo..=hi`, as one undo step, leaving
+    /// the cursor on the first non-blank of `lo`. Shifting skips empty lines;
+    /// `=` empties blank ones.
+    fn indent_lines(&mut self, op: Operator, lo: usize, hi: usize) {
+        let (ts, sw) = (self.options.tabstop, self.options.shift_width());
+        let widths: Vec<Option<usize>> = match op {
+            Operator::Reindent => {
+                let fallback = self.indent_above(lo);
+                self.smart_indents(lo, hi)
+                    .into_iter()
+                    .map(|w| w.or(self.options.autoindent.then_some(fallback)))
+                    .collect()
+            }
+            _ => (lo..=hi)
+                .map(|y| {
+                    let width = indent::indent_width(&self.buffer.line_text(y), ts);
+                    Some(match op {
+                        Operator::ShiftRight => width + sw,
+                        _ => width.saturating_sub(sw),
+                    })
+                })
+                .collect(),
+        };
+        let mut edits = Vec::new();
+        for (y, width) in (lo..=hi).zip(widths) {
+            let line = self.buffer.line_text(y);
+            let Some(width) = width.filter(|_| !line.is_empty()) else {
+                continue;
+            };
+            let blanks = indent::leading_blanks(&line);
+            let text = if blanks == line.chars().count() && op == Operator::Reindent {
+                String::new()
+            } else {
+                indent::indent_text(width, ts, self.options.expandtab)
+            };
+            if line.chars().take(blanks).collect::<String>() != text {
+                let base = self.buffer.line_to_char(y);
+                edits.push((base, base + blanks, text));
+            }
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(edits);
+        let s = self.buffer.line_to_char(lo);
+        let e = self.buffer.line_to_char(hi) + self.buffer.line_char_len(hi);
+        self.set_change_marks(s, e);
+        self.cursor_y = lo;
+        self.cursor_x = indent::leading_blanks(&self.buffer.line_text(lo));
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// `ys`: put the delimiters for `c` around the chars `[s, e)`, less any
+    /// blanks they end with, as one undo step. The cursor goes to the opening
+    /// delimiter.
+    fn surround_range(&mut self, c: char, s: usize, e: usize) {
+        let Some((open, close)) = pairs::surround_delimiters(c) else {
+            return;
+        };
+        let text = self.buffer.slice_text(s..e);
+        let e = s + text.trim_end().chars().count();
+        if s >= e {
+            return;
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let close_len = close.chars().count();
+        let changes = self.apply_edits(vec![(s, s, open.clone()), (e, e, close)]);
+        let end = e + open.chars().count() + close_len;
+        self.set_change_marks(s, end);
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(s);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// `ds{old}` (`new` is `None`) / `cs{old}{new}`: find the delimiters of
+    /// the `count`th pair `old` names around the cursor (see
+    /// [`pairs::surround_object`]), and delete them or put those for `new` in
+    /// their place, as one undo step. Inside an opening bracket the blanks
+    /// next to the delimiters go too. False when there is no such pair.
+    pub fn change_surround(&mut self, old: char, new: Option<char>, count: usize) -> bool {
+        let Some(obj) = pairs::surround_object(old) else {
+            return false;
+        };
+        let (open, close) = match new.map(pairs::surround_delimiters) {
+            Some(None) => return false,
+            Some(Some(delimiters)) => delimiters,
+            None => (String::new(), String::new()),
+        };
+        let Some((s, e, _)) =
+            text_object::range(&self.buffer, self.cursor_y, self.cursor_x, obj, true, count)
+        else {
+            return false;
+        };
+        // The delimiters are the chars just outside the inner range.
+        let (mut inner_s, mut inner_e) = (s, e);
+        if pairs::pads(old) {
+            while inner_s < inner_e && indent::is_blank(self.buffer.char_at(inner_s)) {
+                inner_s += 1;
+            }
+            while inner_e > inner_s && indent::is_blank(self.buffer.char_at(inner_e - 1)) {
+                inner_e -= 1;
+            }
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(vec![(s - 1, inner_s, open), (inner_e, e + 1, close)]);
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(s - 1);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        self.last_change = Some(LastChange::Surround { old, new, count });
+        true
+    }
+
+    /// Comment the way `comments` says for the language at the cursor.
+    pub fn set_comment_syntax(&mut self, comments: Option<Box<dyn CommentSyntax>>) {
+        self.comments = comments;
+    }
+
+    /// The comment style at char `idx`; `None` in text with no language.
+    fn comment_style(&mut self, idx: usize) -> Option<CommentStyle> {
+        let at = self.buffer.char_to_byte(idx);
+        let comments = self.comments.as_mut()?;
+        Some(comments.comment_style(&self.buffer.snapshot(), self.edit_revision, at))
+    }
+
+    /// `gc` over the lines `lo..=hi`: comment them all out, or back in when
+    /// they all are (see [`comment::toggle_lines`]), in the comments of the
+    /// language at the first non-blank of `lo`. One undo step; the cursor
+    /// goes to that non-blank.
+    fn toggle_comments(&mut self, lo: usize, hi: usize) {
+        let first = self.buffer.line_to_char(lo);
+        let x = indent::leading_blanks(&self.buffer.line_text(lo));
+        let Some((open, close)) = self
+            .comment_style(first + x)
+            .and_then(|style| style.line_delimiters())
+        else {
+            return;
+        };
+        let lines: Vec<String> = (lo..=hi).map(|y| self.buffer.line_text(y)).collect();
+        let mut edits = Vec::new();
+        for (y, text) in (lo..=hi).zip(comment::toggle_lines(&lines, open, close)) {
+            if let Some(text) = text {
+                let s = self.buffer.line_to_char(y);
+                edits.push((s, s + self.buffer.line_char_len(y), text));
+            }
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(edits);
+        let e = self.buffer.line_to_char(hi) + self.buffer.line_char_len(hi);
+        self.set_change_marks(first, e);
+        self.cursor_y = lo;
+        self.cursor_x = indent::leading_blanks(&self.buffer.line_text(lo));
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// Visual `gc` over the chars `[s, e)`: toggle a block comment around
+    /// them (see [`comment::toggle_block`]), or line comments on their lines
+    /// for a language without block comments. The cursor goes to `s`.
+    fn toggle_block_comment(&mut self, s: usize, e: usize) {
+        let Some(style) = self.comment_style(s) else {
+            return;
+        };
+        let Some((open, close)) = style.block else {
+            let (lo, hi) = (self.buffer.char_to_line(s), self.buffer.char_to_line(e - 1));
+            return self.toggle_comments(lo, hi);
+        };
+        let text = self.buffer.slice_text(s..e);
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(vec![(s, e, comment::toggle_block(&text, open, close))]);
+        (self.cursor_y, self.cursor_x) = self.char_to_cursor(s);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// Keep auto-pairs out of the strings and comments `literals` finds.
+    pub fn set_literals(&mut self, literals: Option<Box<dyn Literals>>) {
+        self.literals = literals;
+    }
+
+    /// Use `indenter` for smart indent.
+    pub fn set_indenter(&mut self, indenter: Option<Box<dyn Indenter>>) {
+        self.indenter = indenter;
+    }
+
+    /// The indent widths the syntax calls for on the lines `lo..=hi`, with
+    /// `smartindent` and an indenter; `None` where it does not say.
+    fn smart_indents(&mut self, lo: usize, hi: usize) -> Vec<Option<usize>> {
+        let sw = self.options.shift_width();
+        match self.indenter.as_mut() {
+            Some(indenter) if self.options.smartindent => indenter
+                .indent_levels(&self.buffer.snapshot(), self.edit_revision, lo..hi + 1)
+                .into_iter()
+                .map(|level| level.map(|l| l * sw))
+                .collect(),
+            _ => vec![None; hi + 1 - lo],
+        }
+    }
+
+    /// The indent line `y` should get when it is started: what the syntax
+    /// calls for, else `fallback` with `autoindent`, else none.
+    fn wanted_indent(&mut self, y: usize, fallback: usize) -> usize {
+        let smart = self.smart_indents(y, y)[0];
+        smart.unwrap_or(if self.options.autoindent { fallback } else { 0 })
+    }
+
+    /// The indent width of the nearest line above `y` that is not blank.
+    fn indent_above(&self, y: usize) -> usize {
+        (0..y)
+            .rev()
+            .map(|y| self.buffer.line_text(y))
+            .find(|line| line.chars().any(|c| !indent::is_blank(c)))
+            .map_or(0, |line| indent::indent_width(&line, self.options.tabstop))
+    }
+
+    /// Indent the cursor's line, just started, to `width`, putting the cursor
+    /// after the indent. Returns the change made, not yet committed.
+    fn indent_new_line(&mut self, width: usize) -> Option<Change> {
+        if width == 0 {
+            return None;
+        }
+        let change = Change {
+            pos: self.buffer.line_to_char(self.cursor_y),
+            removed: String::new(),
+            inserted: indent::indent_text(width, self.options.tabstop, self.options.expandtab),
+        };
+        self.apply_change(&change);
+        self.cursor_x = change.inserted.chars().count();
+        self.auto_indent = Some((self.cursor_y, self.cursor_x));
+        Some(change)
+    }
+
+    /// The auto indent left on the cursor's line if nothing was typed after
+    /// it: where its blanks start and how many there are.
+    fn unused_auto_indent(&mut self) -> Option<(usize, usize)> {
+        let (y, x) = self.auto_indent.take()?;
+        if (y, x) != (self.cursor_y, self.cursor_x) || y >= self.buffer.line_count() {
+            return None;
+        }
+        let line = self.buffer.line_text(y);
+        let len = line.chars().count();
+        (len > 0 && indent::leading_blanks(&line) == len)
+            .then(|| (self.buffer.line_to_char(y), len))
+    }
+
+    /// Leaving Insert mode on a line that has only its auto indent empties
+    /// it, in the undo step that made it.
+    fn drop_auto_indent(&mut self) {
+        let Some((pos, len)) = self.unused_auto_indent() else {
+            return;
+        };
+        let change = Change {
+            pos,
+            removed: self.buffer.slice_text(pos..pos + len),
+            inserted: String::new(),
+        };
+        self.apply_change(&change);
+        self.cursor_x = 0;
+        match self.undo_tree.last_mut() {
+            Some(last) => {
+                last.changes.push(change);
+                last.cursor_after = (self.cursor_y, 0);
+            }
+            None => self.commit(change, (self.cursor_y, len), (self.cursor_y, 0)),
+        }
+    }
+
+    /// Apply a case operator to the chars `[s, e)` as one undo step; nothing
+    /// is recorded when no letter changes.
+    fn recase_range(&mut self, op: Operator, s: usize, e: usize) {
+        let text = self.buffer.slice_text(s..e);
+        let recased = op.recase(&text).unwrap_or_else(|| text.clone());
+        self.set_change_marks(s, e);
+        if recased == text {
+            return;
         }
+        let before = (self.cursor_y, self.cursor_x);
+        let (y, x) = self.char_to_cursor(s);
+        let changes = self.apply_edits(vec![(s, e, recased)]);
+        self.commit_changes(changes, before, (y, x));
     }
 
     /// Apply an operator over a text object (`diw`, `ci"`, `ya(`, `dip` …).
@@ -745,7 +2226,7 @@ impl EditorModel {
         inner: bool,
         count: usize,
     ) -> bool {
-        match text_object::range(
+        let enter = match text_object::range(
             &self.buffer,
             self.cursor_y,
             self.cursor_x,
@@ -762,143 +2243,2044 @@ impl EditorModel {
             }
             Some((s, e, false)) => self.operate_charwise_range(op, s, e),
             None => false,
+        };
+        let range = OperatorRange::TextObject { obj, inner };
+        self.record_operator(op, range, count, enter);
+        enter
+    }
+
+    /// Enter Visual mode of `kind`, anchoring the selection at the cursor.
+    pub fn start_visual(&mut self, kind: VisualKind) {
+        // The selection belongs to the primary cursor; the others are dropped.
+        self.cursors.clear();
+        self.visual_anchor = (self.cursor_y, self.cursor_x);
+        self.set_mode(EditorMode::Visual(kind));
+    }
+
+    /// Set the `'<` and `'>` marks to the start and end of the selection,
+    /// as it is left.
+    fn set_visual_marks(&mut self) {
+        let (start, end) = match self.selection() {
+            Some(Selection::Chars(s, e)) => (s, e.saturating_sub(1).max(s)),
+            Some(Selection::Lines(top, bottom)) => (
+                self.buffer.line_to_char(top),
+                self.buffer.line_to_char(bottom) + self.buffer.line_char_len(bottom),
+            ),
+            Some(Selection::Block { top, bottom, .. }) => (
+                self.buffer.line_to_char(top),
+                self.buffer.line_to_char(bottom),
+            ),
+            None => return,
+        };
+        self.marks.insert('<', start);
+        self.marks.insert('>', end);
+    }
+
+    /// Swap the cursor and the anchor of the selection (`o` in Visual mode).
+    pub fn visual_swap_ends(&mut self) {
+        let (ay, ax) = self.visual_anchor;
+        self.visual_anchor = (self.cursor_y, self.cursor_x);
+        self.goto(ay, ax);
+    }
+
+    /// The current Visual selection, or `None` outside Visual mode.
+    pub fn selection(&self) -> Option<Selection> {
+        let EditorMode::Visual(kind) = self.mode else {
+            return None;
+        };
+        if self.buffer.line_count() == 0 {
+            return None;
         }
+        let last = self.buffer.line_count() - 1;
+        let ay = self.visual_anchor.0.min(last);
+        let anchor = (ay, self.visual_anchor.1.min(self.buffer.line_char_len(ay)));
+        let cursor = (self.cursor_y, self.cursor_x);
+        let (start, end) = if anchor <= cursor {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        };
+        Some(match kind {
+            VisualKind::Char => {
+                let s = self.buffer.cursor_to_char(start.0, start.1);
+                let line = self.buffer.line_text(end.0);
+                let e = if end.1 < line.chars().count() {
+                    self.buffer
+                        .cursor_to_char(end.0, grapheme::next_boundary(&line, end.1))
+                } else {
+                    // At (or past) the end of the line the selection takes the
+                    // line break, but never the buffer's final newline.
+                    (self.buffer.line_to_char(end.0) + line.chars().count() + 1)
+                        .min(self.buffer.len_chars() - 1)
+                };
+                Selection::Chars(s, e.max(s))
+            }
+            VisualKind::Line => Selection::Lines(start.0, end.0),
+            VisualKind::Block => {
+                let (acol, aw) = self.cell_at(anchor.0, anchor.1);
+                let (ccol, cw) = self.cell_at(cursor.0, cursor.1);
+                Selection::Block {
+                    top: start.0,
+                    bottom: end.0,
+                    left: acol.min(ccol),
+                    right: (acol + aw - 1).max(ccol + cw - 1),
+                }
+            }
+        })
     }
 
-    pub fn repeat_last_change(&mut self) {
-        if let Some(last_change) = &self.last_change {
-            match last_change {
-                LastChange::InsertChar(c) => self.insert_char(*c),
-                LastChange::DeleteChar => self.delete_char(),
-                LastChange::DeleteCharUnderCursor => self.delete_char_under_cursor(),
-                LastChange::InsertNewline => self.insert_newline(),
-                LastChange::InsertLineBelow => self.insert_line_below(),
-                LastChange::InsertLineAbove => self.insert_line_above(),
-                LastChange::DeleteCurrentLine => self.delete_current_line(),
-                LastChange::PutLineBelow => self.put_line_below(),
+    /// Display column and width (at least 1) of the grapheme at `(y, x)`.
+    fn cell_at(&self, y: usize, x: usize) -> (usize, usize) {
+        let line = self.buffer.line_text(y);
+        let next = grapheme::next_boundary(&line, x);
+        let g: String = line.chars().skip(x).take(next.saturating_sub(x)).collect();
+        (grapheme::display_col(&line, x), grapheme::width(&g).max(1))
+    }
+
+    /// Char columns of line `y` covered by the Visual selection, half-open. An
+    /// end past the line length means the line break is selected too. Used by
+    /// the renderer.
+    pub fn selection_span(&self, y: usize) -> Option<(usize, usize)> {
+        let len = self.buffer.line_char_len(y);
+        match self.selection()? {
+            Selection::Chars(s, e) => {
+                let base = self.buffer.line_to_char(y);
+                let lo = s.max(base);
+                let hi = e.min(base + len + 1);
+                (lo < hi).then(|| (lo - base, hi - base))
+            }
+            Selection::Lines(top, bottom) => (top..=bottom).contains(&y).then_some((0, len + 1)),
+            Selection::Block {
+                top,
+                bottom,
+                left,
+                right,
+            } if (top..=bottom).contains(&y) => {
+                grapheme::col_span(&self.buffer.line_text(y), left, right)
+            }
+            Selection::Block { .. } => None,
+        }
+    }
+
+    /// Switch between charwise, linewise and blockwise Visual mode while keeping
+    /// the selection's anchor.
+    pub fn set_visual_kind(&mut self, kind: VisualKind) {
+        self.mode = EditorMode::Visual(kind);
+    }
+
+    /// Apply an operator to the Visual selection and return to Normal mode.
+    /// Returns true when the caller should enter insert mode (the change
+    /// operator).
+    pub fn apply_operator_visual(&mut self, op: Operator) -> bool {
+        let Some(sel) = self.selection() else {
+            return false;
+        };
+        let range = self.selection_extent();
+        self.set_mode(EditorMode::Normal);
+        let enter = match sel {
+            Selection::Chars(s, e) => {
+                let (y, x) = self.char_to_cursor(s);
+                self.goto(y, x);
+                self.operate_charwise_range(op, s, e)
+            }
+            Selection::Lines(lo, hi) => {
+                self.goto(lo, self.cursor_x);
+                self.operate_linewise_range(op, lo, hi)
+            }
+            Selection::Block { top, bottom, .. }
+                if matches!(
+                    op,
+                    Operator::ShiftRight
+                        | Operator::ShiftLeft
+                        | Operator::Reindent
+                        | Operator::Comment
+                        | Operator::Format
+                        | Operator::FormatKeep
+                ) =>
+            {
+                self.goto(top, self.cursor_x);
+                self.operate_linewise_range(op, top, bottom)
+            }
+            Selection::Block {
+                top,
+                bottom,
+                left,
+                right,
+            } => self.operate_block(op, top, bottom, left, right),
+        };
+        if let Some(range) = range {
+            self.record_operator(op, range, 1, enter);
+        }
+        enter
+    }
+
+    /// The size of the current Visual selection, for repeating a Visual
+    /// operator with `.` from wherever the cursor is then.
+    fn selection_extent(&self) -> Option<OperatorRange> {
+        let EditorMode::Visual(kind) = self.mode else {
+            return None;
+        };
+        let anchor = self.visual_anchor;
+        let cursor = (self.cursor_y, self.cursor_x);
+        let (start, end) = if anchor <= cursor {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        };
+        let lines = end.0 - start.0;
+        let width = match (self.selection()?, kind) {
+            (Selection::Block { left, right, .. }, _) => right - left + 1,
+            (_, VisualKind::Char) if lines == 0 => end.1 - start.1 + 1,
+            (_, VisualKind::Char) => end.1 + 1,
+            _ => 0,
+        };
+        Some(OperatorRange::Selection { kind, lines, width })
+    }
+
+    /// Apply `op` over `range` from the cursor, as recorded for `.`.
+    pub fn apply_operator_range(
+        &mut self,
+        op: Operator,
+        range: OperatorRange,
+        count: usize,
+    ) -> bool {
+        match range {
+            OperatorRange::Motion(motion) => self.apply_operator(op, motion, count),
+            OperatorRange::TextObject { obj, inner } => {
+                self.apply_operator_textobject(op, obj, inner, count)
+            }
+            OperatorRange::Lines => self.operate_current_lines(op, count),
+            OperatorRange::Selection { kind, lines, width } => {
+                self.replay_selection(op, kind, lines, width)
+            }
+        }
+    }
+
+    /// Re-select a recorded Visual extent from the cursor and apply `op` to it.
+    fn replay_selection(
+        &mut self,
+        op: Operator,
+        kind: VisualKind,
+        lines: usize,
+        width: usize,
+    ) -> bool {
+        self.reselect(kind, lines, width) && self.apply_operator_visual(op)
+    }
+
+    /// Enter Visual mode over a recorded extent from the cursor (see
+    /// [`Self::selection_extent`]). Returns false in an empty buffer.
+    fn reselect(&mut self, kind: VisualKind, lines: usize, width: usize) -> bool {
+        if self.buffer.line_count() == 0 {
+            return false;
+        }
+        self.start_visual(kind);
+        let y = (self.cursor_y + lines).min(self.buffer.line_count() - 1);
+        let x = match kind {
+            VisualKind::Char if lines == 0 => self.cursor_x + width.saturating_sub(1),
+            VisualKind::Char => width.saturating_sub(1),
+            VisualKind::Line => self.cursor_x,
+            VisualKind::Block => {
+                let col = self.display_col() + width.saturating_sub(1);
+                grapheme::col_to_char(&self.buffer.line_text(y), col)
+            }
+        };
+        self.goto(y, x);
+        true
+    }
+
+    /// Apply an operator to the display columns `left..=right` of lines
+    /// `top..=bottom`. Lines ending before `left` contribute an empty row.
+    fn operate_block(
+        &mut self,
+        op: Operator,
+        top: usize,
+        bottom: usize,
+        left: usize,
+        right: usize,
+    ) -> bool {
+        let before = (self.cursor_y, self.cursor_x);
+        let mut rows = Vec::new();
+        let mut edits = Vec::new();
+        for y in top..=bottom {
+            let line = self.buffer.line_text(y);
+            match grapheme::col_span(&line, left, right) {
+                Some((sx, ex)) => {
+                    let base = self.buffer.line_to_char(y);
+                    let row = line.chars().skip(sx).take(ex - sx).collect::<String>();
+                    // A case operator rewrites the block; the others remove it.
+                    let text = op.recase(&row).unwrap_or_default();
+                    rows.push(row);
+                    edits.push((base + sx, base + ex, text));
+                }
+                None => rows.push(String::new()),
             }
         }
+        let reg = Register {
+            text: rows.join("\n"),
+            linewise: false,
+            blockwise: true,
+        };
+        self.store_register(op, reg);
+        let first_x = grapheme::col_to_char(&self.buffer.line_text(top), left);
+        if op == Operator::Yank {
+            let s = self.buffer.cursor_to_char(top, first_x);
+            let last = grapheme::col_to_char(&self.buffer.line_text(bottom), right);
+            let e = self.buffer.cursor_to_char(bottom, last) + 1;
+            self.set_change_marks(s, e);
+            self.goto(top, first_x);
+            return false;
+        }
+        if op == Operator::Change {
+            self.begin_undo_group();
+        }
+        let changes = self.apply_edits(edits);
+        self.goto(top, first_x);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        if op == Operator::Change {
+            self.block_insert = Some(BlockInsert {
+                top,
+                bottom,
+                col: left,
+                append: false,
+                start_x: first_x,
+                start_len: self.buffer.line_char_len(top),
+            });
+            return true;
+        }
+        false
+    }
+
+    /// Start a blockwise insert from Visual-block mode: `I` inserts before the
+    /// block, `A` appends after it. Returns false outside Visual-block mode;
+    /// otherwise the caller enters insert mode and the typed text is copied to
+    /// every line of the block when it ends.
+    pub fn start_block_insert(&mut self, append: bool) -> bool {
+        let Some(Selection::Block {
+            top,
+            bottom,
+            left,
+            right,
+        }) = self.selection()
+        else {
+            return false;
+        };
+        self.set_mode(EditorMode::Normal);
+        self.begin_undo_group();
+        let col = if append { right + 1 } else { left };
+        let line = self.buffer.line_text(top);
+        let width = grapheme::width(&line);
+        if append && width < col {
+            let end = self.buffer.line_to_char(top) + line.chars().count();
+            let change = Change {
+                pos: end,
+                removed: String::new(),
+                inserted: " ".repeat(col - width),
+            };
+            let before = (self.cursor_y, self.cursor_x);
+            self.apply_change(&change);
+            self.commit(change, before, before);
+        }
+        let x = grapheme::col_to_char(&self.buffer.line_text(top), col);
+        self.goto(top, x);
+        self.block_insert = Some(BlockInsert {
+            top,
+            bottom,
+            col,
+            append,
+            start_x: x,
+            start_len: self.buffer.line_char_len(top),
+        });
+        true
+    }
+
+    /// Replicate the text typed during a blockwise insert onto the block's
+    /// other lines and close the undo group, so the whole command undoes at
+    /// once. Typing that left the first line (a newline, cursor motion) is not
+    /// replicated, as in Vim.
+    fn finish_block_insert(&mut self) {
+        let Some(bi) = self.block_insert.take() else {
+            return;
+        };
+        let len = self.buffer.line_char_len(bi.top);
+        if self.cursor_y == bi.top && len > bi.start_len {
+            let line = self.buffer.line_text(bi.top);
+            let text: String = line
+                .chars()
+                .skip(bi.start_x)
+                .take(len - bi.start_len)
+                .collect();
+            let mut edits = Vec::new();
+            for y in bi.top + 1..=bi.bottom.min(self.buffer.line_count().saturating_sub(1)) {
+                let line = self.buffer.line_text(y);
+                let base = self.buffer.line_to_char(y);
+                let width = grapheme::width(&line);
+                if bi.append && width <= bi.col {
+                    let end = base + line.chars().count();
+                    let pad = " ".repeat(bi.col - width);
+                    edits.push((end, end, format!("{pad}{text}")));
+                } else if width > bi.col || bi.col == 0 {
+                    let x = base + grapheme::col_to_char(&line, bi.col);
+                    edits.push((x, x, text.clone()));
+                }
+            }
+            let before = (self.cursor_y, self.cursor_x);
+            let changes = self.apply_edits(edits);
+            self.commit_changes(changes, before, before);
+        }
+        self.end_undo_group();
+    }
+
+    /// Begin collapsing every following edit into one undo step, closed by
+    /// [`Self::end_undo_group`]. Groups nest; the outermost one wins.
+    pub fn begin_undo_group(&mut self) {
+        self.undo_group_depth += 1;
+        if self.undo_group_depth > 1 {
+            return;
+        }
+        self.coalescing = false;
+        self.undo_group = Some(UndoGroup {
+            len: self.undo_tree.last() + 1,
+            state: self.undo_tree.current(),
+            cursor_before: (self.cursor_y, self.cursor_x),
+        });
+    }
+
+    /// Replace the transactions recorded since [`Self::begin_undo_group`] with
+    /// a single one holding all of their changes: the way from the state the
+    /// group began at to the one it ends at, so an undo or redo inside the
+    /// group counts too. A group that ends at a state that already existed
+    /// (it only undid or redid) records nothing.
+    pub fn end_undo_group(&mut self) {
+        self.undo_group_depth = self.undo_group_depth.saturating_sub(1);
+        if self.undo_group_depth > 0 {
+            return;
+        }
+        let Some(group) = self.undo_group.take() else {
+            return;
+        };
+        let end = self.undo_tree.current();
+        self.undo_tree.walk_to(group.state);
+        let steps = self.undo_tree.walk_to(end);
+        if end < group.len {
+            self.undo_tree.truncate(group.len, end);
+            return;
+        }
+        let mut changes: Vec<Change> = Vec::new();
+        for step in steps {
+            let step_changes = match step {
+                UndoStep::Undo(n) => self.undo_tree.node(n).transaction.inverted(),
+                UndoStep::Redo(n) => self.undo_tree.node(n).transaction.changes.clone(),
+            };
+            for change in step_changes {
+                // Typing arrives one insert at a time; keep a run as one change.
+                match changes.last_mut() {
+                    Some(last)
+                        if last.removed.is_empty()
+                            && change.removed.is_empty()
+                            && change.pos == last.pos + last.inserted.chars().count() =>
+                    {
+                        last.inserted.push_str(&change.inserted)
+                    }
+                    _ => changes.push(change),
+                }
+            }
+        }
+        self.undo_tree.truncate(group.len, group.state);
+        let after = (self.cursor_y, self.cursor_x);
+        self.commit_changes(changes, group.cursor_before, after);
+    }
+
+    /// Extend the Visual selection over a text object (`viw`, `va(`, `vip` …).
+    /// A linewise object switches to linewise Visual mode.
+    pub fn select_textobject(&mut self, obj: TextObject, inner: bool, count: usize) {
+        let Some((s, e, linewise)) = text_object::range(
+            &self.buffer,
+            self.cursor_y,
+            self.cursor_x,
+            obj,
+            inner,
+            count,
+        ) else {
+            return;
+        };
+        if e <= s {
+            return;
+        }
+        self.visual_anchor = self.char_to_cursor(s);
+        let (y, x) = self.char_to_cursor(e - 1);
+        self.goto(y, x);
+        if linewise {
+            self.set_visual_kind(VisualKind::Line);
+        }
+    }
+
+    /// Replace the Visual selection with register `name` (`p`/`P` in Visual
+    /// mode) as one undo step. With `swap` (`p`) the replaced text is stored
+    /// like a delete into the unnamed register; `P` leaves the registers
+    /// untouched so the same text can be put repeatedly.
+    pub fn paste_visual(&mut self, name: Option<char>, swap: bool) {
+        let Some(reg) = self.register(name.unwrap_or('"')) else {
+            return;
+        };
+        let Some(sel) = self.selection() else {
+            return;
+        };
+        let Some(OperatorRange::Selection { kind, lines, width }) = self.selection_extent() else {
+            return;
+        };
+        self.set_mode(EditorMode::Normal);
+        let before = (self.cursor_y, self.cursor_x);
+        let (changes, replaced, cursor) = match sel {
+            Selection::Chars(s, e) => {
+                // A linewise register goes onto lines of its own, splitting the
+                // line around the selection.
+                let inserted = if reg.linewise {
+                    format!("\n{}", reg.text)
+                } else {
+                    reg.text.clone()
+                };
+                let change = Change {
+                    pos: s,
+                    removed: self.buffer.slice_text(s..e),
+                    inserted,
+                };
+                let cursor = if reg.linewise {
+                    (self.char_to_cursor(s).0 + 1, 0)
+                } else {
+                    self.char_to_cursor(s)
+                };
+                let replaced = Register {
+                    text: change.removed.clone(),
+                    linewise: false,
+                    blockwise: false,
+                };
+                (vec![change], replaced, cursor)
+            }
+            Selection::Lines(lo, hi) => {
+                let start = self.buffer.line_to_char(lo);
+                let end = self.buffer.line_to_char(hi + 1);
+                let mut inserted = reg.text.clone();
+                if !inserted.ends_with('\n') {
+                    inserted.push('\n');
+                }
+                let change = Change {
+                    pos: start,
+                    removed: self.buffer.slice_text(start..end),
+                    inserted,
+                };
+                let replaced = Register {
+                    text: change.removed.clone(),
+                    linewise: true,
+                    blockwise: false,
+                };
+                (vec![change], replaced, (lo, 0))
+            }
+            Selection::Block {
+                top,
+                bottom,
+                left,
+                right,
+            } => {
+                // Row `i` of a blockwise register replaces line `i` of the block;
+                // a single-line register fills every line.
+                let src: Vec<&str> = reg.text.trim_end_matches('\n').split('\n').collect();
+                let mut rows = Vec::new();
+                let mut edits = Vec::new();
+                for (i, y) in (top..=bottom).enumerate() {
+                    let line = self.buffer.line_text(y);
+                    let Some((sx, ex)) = grapheme::col_span(&line, left, right) else {
+                        rows.push(String::new());
+                        continue;
+                    };
+                    let base = self.buffer.line_to_char(y);
+                    rows.push(line.chars().skip(sx).take(ex - sx).collect::<String>());
+                    let text = if src.len() == 1 {
+                        src[0]
+                    } else {
+                        src.get(i).copied().unwrap_or("")
+                    };
+                    edits.push((base + sx, base + ex, text.to_string()));
+                }
+                let changes = transaction::sequential_changes(&self.buffer, edits);
+                if changes.is_empty() {
+                    return;
+                }
+                let x = grapheme::col_to_char(&self.buffer.line_text(top), left);
+                let replaced = Register {
+                    text: rows.join("\n"),
+                    linewise: false,
+                    blockwise: true,
+                };
+                (changes, replaced, (top, x))
+            }
+        };
+        for change in &changes {
+            self.apply_change(change);
+        }
+        self.goto(cursor.0, cursor.1);
+        self.last_change = Some(LastChange::PasteVisual {
+            register: name,
+            swap,
+            kind,
+            lines,
+            width,
+        });
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        if swap {
+            self.registers.delete(None, replaced);
+        }
+    }
+
+    /// `~`: switch the case of `count` characters from the cursor (as many as
+    /// the line has) and move past them, staying on the line. Returns false on
+    /// an empty line.
+    pub fn switch_case_chars(&mut self, count: usize) -> bool {
+        let y = self.cursor_y;
+        if y >= self.buffer.line_count() || self.buffer.line_char_len(y) == 0 {
+            return false;
+        }
+        let line = self.buffer.line_text(y);
+        let len = self.buffer.line_char_len(y);
+        let mut end = self.cursor_x;
+        for _ in 0..count.max(1) {
+            if end >= len {
+                break;
+            }
+            end = grapheme::next_boundary(&line, end);
+        }
+        let base = self.buffer.line_to_char(y);
+        self.recase_range(Operator::SwitchCase, base + self.cursor_x, base + end);
+        let line = self.buffer.line_text(y);
+        let last = grapheme::prev_boundary(&line, self.buffer.line_char_len(y));
+        self.cursor_x = end.min(last);
+        self.last_change = Some(LastChange::SwitchCase(count));
+        true
+    }
+
+    /// `r{char}`: replace `count` characters from the cursor with `c`, leaving
+    /// the cursor on the last; `r<CR>` (`c` is `'\n'`) replaces them with one
+    /// line break. Fails, changing nothing, if the line has fewer characters
+    /// left.
+    pub fn replace_chars(&mut self, c: char, count: usize) -> bool {
+        let (y, x) = (self.cursor_y, self.cursor_x);
+        if y >= self.buffer.line_count() {
+            return false;
+        }
+        let count = count.max(1);
+        let line = self.buffer.line_text(y);
+        let len = self.buffer.line_char_len(y);
+        let mut end = x;
+        for _ in 0..count {
+            if end >= len {
+                return false;
+            }
+            end = grapheme::next_boundary(&line, end);
+        }
+        let before = (y, x);
+        let base = self.buffer.line_to_char(y);
+        let text = if c == '\n' {
+            "\n".to_string()
+        } else {
+            c.to_string().repeat(count)
+        };
+        let changes = self.apply_edits(vec![(base + x, base + end, text)]);
+        (self.cursor_y, self.cursor_x) = if c == '\n' {
+            (y + 1, 0)
+        } else {
+            (y, x + count - 1)
+        };
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        self.last_change = Some(LastChange::ReplaceChars { c, count });
+        true
+    }
+
+    /// `[count]J` / `gJ`: join `count` lines (at least two) from the cursor's,
+    /// as [`Self::join_lines`] does. Returns false on the last line.
+    pub fn join(&mut self, count: usize, spaces: bool) -> bool {
+        let y = self.cursor_y;
+        if !self.join_lines(y, y + count.max(2) - 1, spaces) {
+            return false;
+        }
+        self.last_change = Some(LastChange::Join { count, spaces });
+        true
+    }
+
+    /// `J` / `gJ` in Visual mode: join the selected lines (at least two).
+    pub fn join_visual(&mut self, spaces: bool) -> bool {
+        let Some(sel) = self.selection() else {
+            return false;
+        };
+        let (top, bottom) = match sel {
+            Selection::Chars(s, e) => (
+                self.buffer.char_to_line(s),
+                self.buffer.char_to_line(e.saturating_sub(1).max(s)),
+            ),
+            Selection::Lines(top, bottom) | Selection::Block { top, bottom, .. } => (top, bottom),
+        };
+        self.set_mode(EditorMode::Normal);
+        self.cursor_y = top;
+        self.join((bottom - top + 1).max(2), spaces)
+    }
+
+    /// `Ctrl-a` / `Ctrl-x`: add `delta` to the number under or after the
+    /// cursor on its line (see [`increment::increment`]), leaving the cursor on
+    /// its last character. Returns false when the line has none.
+    pub fn increment(&mut self, delta: i64) -> bool {
+        let y = self.cursor_y;
+        if y >= self.buffer.line_count() {
+            return false;
+        }
+        let line = self.buffer.line_text(y);
+        let Some((s, e, text)) = increment::increment(&line, self.cursor_x, delta as i128) else {
+            return false;
+        };
+        let before = (y, self.cursor_x);
+        let base = self.buffer.line_to_char(y);
+        let len = text.chars().count();
+        let changes = self.apply_edits(vec![(base + s, base + e, text)]);
+        self.cursor_x = s + len - 1;
+        self.commit_changes(changes, before, (y, self.cursor_x));
+        self.last_change = Some(LastChange::Increment(delta));
+        true
+    }
+
+    /// Visual `Ctrl-a` / `Ctrl-x`: add `delta` to the first number selected on
+    /// each line, as one undo step. With `progressive` (`g Ctrl-a`), the n-th
+    /// number changed gets n times `delta`, making a sequence. The cursor goes
+    /// to the start of the selection. Returns false when no line had a number.
+    pub fn increment_visual(&mut self, delta: i64, progressive: bool) -> bool {
+        let Some(sel) = self.selection() else {
+            return false;
+        };
+        // The selected chars `[from, to)` of each line.
+        let spans: Vec<(usize, usize, usize)> = match sel {
+            Selection::Chars(s, e) => {
+                let (top, left) = self.char_to_cursor(s);
+                let (bottom, right) = self.char_to_cursor(e);
+                (top..=bottom)
+                    .map(|y| {
+                        let from = if y == top { left } else { 0 };
+                        let to = if y == bottom {
+                            right
+                        } else {
+                            self.buffer.line_char_len(y)
+                        };
+                        (y, from, to)
+                    })
+                    .collect()
+            }
+            Selection::Lines(top, bottom) => (top..=bottom)
+                .map(|y| (y, 0, self.buffer.line_char_len(y)))
+                .collect(),
+            Selection::Block {
+                top,
+                bottom,
+                left,
+                right,
+            } => (top..=bottom)
+                .map(|y| {
+                    let line = self.buffer.line_text(y);
+                    let from = grapheme::col_to_char(&line, left);
+                    (y, from, grapheme::col_to_char(&line, right + 1))
+                })
+                .collect(),
+        };
+        let (top, left) = match sel {
+            Selection::Chars(s, _) => self.char_to_cursor(s),
+            Selection::Lines(top, _) => (top, 0),
+            Selection::Block { top, left, .. } => (
+                top,
+                grapheme::col_to_char(&self.buffer.line_text(top), left),
+            ),
+        };
+        let mut edits = Vec::new();
+        let mut step = delta as i128;
+        for (y, from, to) in spans {
+            let line = self.buffer.line_text(y);
+            let selected: String = line
+                .chars()
+                .skip(from)
+                .take(to.saturating_sub(from))
+                .collect();
+            if let Some((s, e, text)) = increment::increment(&selected, 0, step) {
+                let base = self.buffer.line_to_char(y) + from;
+                edits.push((base + s, base + e, text));
+                if progressive {
+                    step += delta as i128;
+                }
+            }
+        }
+        self.set_mode(EditorMode::Normal);
+        let before = (self.cursor_y, self.cursor_x);
+        (self.cursor_y, self.cursor_x) = (top, left);
+        if edits.is_empty() {
+            return false;
+        }
+        let changes = self.apply_edits(edits);
+        self.commit_changes(changes, before, (top, left));
+        true
+    }
+
+    /// Replace mode: type `c` over the character under the cursor, or add it
+    /// past the end of the line.
+    pub fn replace_mode_char(&mut self, c: char) {
+        let Some(session) = self.replace_session.as_mut() else {
+            return;
+        };
+        session.text.push(c);
+        let (y, x) = (self.cursor_y, self.cursor_x);
+        if y >= self.buffer.line_count() {
+            session.replaced.push(None);
+            self.insert_char(c);
+            return;
+        }
+        let line = self.buffer.line_text(y);
+        let end = grapheme::next_boundary(&line, x);
+        let old: String = line.chars().skip(x).take(end - x).collect();
+        session
+            .replaced
+            .push(Some(old.clone()).filter(|o| !o.is_empty()));
+        let pos = self.buffer.line_to_char(y) + x;
+        let change = Change {
+            pos,
+            removed: old,
+            inserted: c.to_string(),
+        };
+        self.apply_change(&change);
+        self.cursor_x += 1;
+        self.commit(change, (y, x), (self.cursor_y, self.cursor_x));
+    }
+
+    /// Replace mode: Enter breaks the line without replacing anything.
+    pub fn replace_mode_newline(&mut self) {
+        let Some(session) = self.replace_session.as_mut() else {
+            return;
+        };
+        session.text.push('\n');
+        session.replaced.push(None);
+        self.insert_newline();
+    }
+
+    /// Replace mode: Backspace puts back what the last character typed took
+    /// the place of. Before the first one it only moves left.
+    pub fn replace_mode_backspace(&mut self) {
+        let Some(session) = self.replace_session.as_mut() else {
+            return;
+        };
+        session.text.push('\u{8}');
+        match session.replaced.pop() {
+            None => {
+                let line = self.buffer.line_text(self.cursor_y);
+                self.cursor_x = grapheme::prev_boundary(&line, self.cursor_x);
+            }
+            Some(None) => self.delete_char(),
+            Some(Some(old)) => {
+                let (y, x) = (self.cursor_y, self.cursor_x);
+                let line = self.buffer.line_text(y);
+                let start = grapheme::prev_boundary(&line, x);
+                let pos = self.buffer.line_to_char(y) + start;
+                let change = Change {
+                    pos,
+                    removed: self.buffer.slice_text(pos..pos + x - start),
+                    inserted: old,
+                };
+                self.apply_change(&change);
+                self.cursor_x = start;
+                self.commit(change, (y, x), (y, start));
+            }
+        }
+    }
+
+    /// Type recorded Replace-mode keys (see [`LastChange::Replace`]).
+    fn replay_replace(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => self.replace_mode_newline(),
+                '\u{8}' => self.replace_mode_backspace(),
+                c => self.replace_mode_char(c),
+            }
+        }
+    }
+
+    /// Position the cursor (opening a line for `o`/`O`) for an insert session
+    /// and remember how it was entered for `.`. The caller switches to insert
+    /// mode.
+    pub fn begin_insert(&mut self, entry: InsertEntry) {
+        let empty = self.buffer.line_count() == 0;
+        match entry {
+            InsertEntry::Before => {}
+            InsertEntry::After | InsertEntry::FirstNonBlank | InsertEntry::LineEnd if empty => {}
+            InsertEntry::After => {
+                let line = self.buffer.line_text(self.cursor_y);
+                let len = self.buffer.line_char_len(self.cursor_y);
+                self.cursor_x = grapheme::next_boundary(&line, self.cursor_x).min(len);
+            }
+            InsertEntry::FirstNonBlank => self.move_by_motion(Motion::FirstNonBlank, 1),
+            InsertEntry::LineEnd => self.cursor_x = self.buffer.line_char_len(self.cursor_y),
+            InsertEntry::LineBelow => self.insert_line_below(),
+            InsertEntry::LineAbove => self.insert_line_above(),
+        }
+        self.coalescing = false;
+        self.insert_repeat = Some(LastChange::Insert {
+            entry,
+            text: String::new(),
+        });
+    }
+
+    /// Attach the text typed in the insert session that just ended to the
+    /// change that started it, making that the change `.` repeats.
+    fn finish_insert_record(&mut self) {
+        let text = self.insert_record.take().unwrap_or_default();
+        match self.insert_repeat.take() {
+            Some(LastChange::Operator {
+                op, range, count, ..
+            }) => {
+                self.last_change = Some(LastChange::Operator {
+                    op,
+                    range,
+                    count,
+                    inserted: text,
+                });
+            }
+            // An empty `i<Esc>` changes nothing and keeps the previous change.
+            Some(LastChange::Insert { entry, .. })
+                if !text.is_empty()
+                    || matches!(entry, InsertEntry::LineBelow | InsertEntry::LineAbove) =>
+            {
+                self.last_change = Some(LastChange::Insert { entry, text });
+            }
+            _ => {}
+        }
+    }
+
+    /// Type recorded insert-session keys (see `insert_record`).
+    fn replay_insert(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => self.insert_newline(),
+                '\u{8}' => self.delete_char(),
+                c => self.insert_char(c),
+            }
+        }
+    }
+
+    pub fn repeat_last_change(&mut self) {
+        self.repeat_last_change_with(None);
+    }
+
+    /// `.`: repeat the last change. A `count` replaces the recorded one (and is
+    /// remembered for the next `.`); simple edits are repeated `count` times.
+    /// An operator or insert session and its typed text undo as one step.
+    pub fn repeat_last_change_with(&mut self, count: Option<usize>) {
+        let Some(last) = self.last_change.clone() else {
+            return;
+        };
+        match last {
+            LastChange::Operator {
+                op,
+                range,
+                count: recorded,
+                inserted,
+            } => {
+                let n = count.unwrap_or(recorded);
+                self.begin_undo_group();
+                let enter = self.apply_operator_range(op, range, n);
+                if enter {
+                    self.replay_insert(&inserted);
+                    self.finish_block_insert();
+                }
+                self.insert_repeat = None;
+                self.end_undo_group();
+                self.last_change = Some(LastChange::Operator {
+                    op,
+                    range,
+                    count: n,
+                    inserted,
+                });
+            }
+            LastChange::Insert { entry, text } => {
+                let n = count.unwrap_or(1).max(1);
+                self.begin_undo_group();
+                if matches!(entry, InsertEntry::LineBelow | InsertEntry::LineAbove) {
+                    // `3.` after `ofoo<Esc>` opens three lines.
+                    for _ in 0..n {
+                        self.begin_insert(entry);
+                        self.replay_insert(&text);
+                    }
+                } else {
+                    self.begin_insert(entry);
+                    self.replay_insert(&text.repeat(n));
+                }
+                self.insert_repeat = None;
+                self.end_undo_group();
+                self.last_change = Some(LastChange::Insert { entry, text });
+            }
+            LastChange::SwitchCase(recorded) => {
+                self.switch_case_chars(count.unwrap_or(recorded));
+            }
+            LastChange::ReplaceChars { c, count: recorded } => {
+                self.replace_chars(c, count.unwrap_or(recorded));
+            }
+            LastChange::Join {
+                count: recorded,
+                spaces,
+            } => {
+                self.join(count.unwrap_or(recorded), spaces);
+            }
+            LastChange::Increment(delta) => {
+                let n = count.map_or(delta, |n| n as i64 * delta.signum());
+                self.increment(n);
+            }
+            LastChange::Surround {
+                old,
+                new,
+                count: recorded,
+            } => {
+                self.change_surround(old, new, count.unwrap_or(recorded));
+            }
+            LastChange::Paste {
+                register,
+                after,
+                count: recorded,
+            } => {
+                self.paste(register, after, count.unwrap_or(recorded));
+            }
+            LastChange::PasteVisual {
+                register,
+                swap,
+                kind,
+                lines,
+                width,
+            } => {
+                if self.reselect(kind, lines, width) {
+                    self.paste_visual(register, swap);
+                }
+            }
+            LastChange::Replace { text } => {
+                self.set_mode(EditorMode::Replace);
+                self.replay_replace(&text.repeat(count.unwrap_or(1).max(1)));
+                self.set_mode(EditorMode::Normal);
+                self.last_change = Some(LastChange::Replace { text });
+            }
+            simple => {
+                for _ in 0..count.unwrap_or(1).max(1) {
+                    match simple {
+                        LastChange::InsertChar(c) => self.insert_char(c),
+                        LastChange::DeleteChar => self.delete_char(),
+                        LastChange::DeleteCharUnderCursor => self.delete_char_under_cursor(),
+                        LastChange::InsertNewline => self.insert_newline(),
+                        LastChange::InsertLineBelow => self.insert_line_below(),
+                        LastChange::InsertLineAbove => self.insert_line_above(),
+                        LastChange::DeleteCurrentLine => self.delete_current_line(),
+                        LastChange::Operator { .. }
+                        | LastChange::Insert { .. }
+                        | LastChange::SwitchCase(_)
+                        | LastChange::ReplaceChars { .. }
+                        | LastChange::Join { .. }
+                        | LastChange::Paste { .. }
+                        | LastChange::PasteVisual { .. }
+                        | LastChange::Replace { .. }
+                        | LastChange::Increment(_)
+                        | LastChange::Surround { .. } => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record a non-insert edit as its own undo step, ending any coalescing run.
+    fn commit(&mut self, change: Change, before: (usize, usize), after: (usize, usize)) {
+        self.commit_changes(vec![change], before, after);
+    }
+
+    /// Record already-applied sequential changes as one undo step. Nothing is
+    /// recorded when `changes` is empty.
+    fn commit_changes(
+        &mut self,
+        changes: Vec<Change>,
+        before: (usize, usize),
+        after: (usize, usize),
+    ) {
+        if !changes.is_empty() {
+            self.undo_tree.push(Transaction {
+                changes,
+                cursor_before: before,
+                cursor_after: after,
+            });
+        }
+        self.coalescing = false;
+    }
+
+    /// Record an inserted character, coalescing it onto the previous transaction
+    /// when it is a contiguous continuation of an ongoing insert run so that a
+    /// word of typing collapses into a single undo step.
+    fn commit_insert(&mut self, change: Change, before: (usize, usize), after: (usize, usize)) {
+        if self.coalescing {
+            if let Some(Transaction {
+                changes,
+                cursor_after,
+                ..
+            }) = self.undo_tree.last_mut()
+            {
+                if let [top] = changes.as_mut_slice() {
+                    if top.removed.is_empty()
+                        && change.removed.is_empty()
+                        && change.pos == top.pos + top.inserted.chars().count()
+                    {
+                        top.inserted.push_str(&change.inserted);
+                        *cursor_after = after;
+                        return;
+                    }
+                }
+            }
+        }
+        self.undo_tree.push(Transaction {
+            changes: vec![change],
+            cursor_before: before,
+            cursor_after: after,
+        });
+        self.coalescing = true;
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(t) = self.undo_tree.undo() {
+            let (changes, cursor) = (t.inverted(), t.cursor_before);
+            for change in &changes {
+                self.apply_change(change);
+            }
+            (self.cursor_y, self.cursor_x) = cursor;
+        }
+        self.coalescing = false;
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(t) = self.undo_tree.redo() {
+            let (changes, cursor) = (t.changes.clone(), t.cursor_after);
+            for change in &changes {
+                self.apply_change(change);
+            }
+            (self.cursor_y, self.cursor_x) = cursor;
+        }
+        self.coalescing = false;
+    }
+
+    /// Move to undo state `target` along the tree, undoing and redoing the
+    /// transactions in between. Returns whether the state changed.
+    pub fn undo_to_state(&mut self, target: usize) -> bool {
+        let steps = self.undo_tree.walk_to(target);
+        for step in &steps {
+            let (changes, cursor) = match *step {
+                UndoStep::Undo(n) => {
+                    let t = &self.undo_tree.node(n).transaction;
+                    (t.inverted(), t.cursor_before)
+                }
+                UndoStep::Redo(n) => {
+                    let t = &self.undo_tree.node(n).transaction;
+                    (t.changes.clone(), t.cursor_after)
+                }
+            };
+            for change in &changes {
+                self.apply_change(change);
+            }
+            (self.cursor_y, self.cursor_x) = cursor;
+        }
+        self.coalescing = false;
+        !steps.is_empty()
+    }
+
+    /// `g-` / `g+` (and `:earlier N` / `:later N`): step `count` states back or
+    /// forward in time, across branches.
+    pub fn undo_chronological(&mut self, count: usize, forward: bool) -> bool {
+        let current = self.undo_tree.current();
+        let target = if forward {
+            (current + count).min(self.undo_tree.last())
+        } else {
+            current.saturating_sub(count)
+        };
+        self.undo_to_state(target)
+    }
+
+    /// `:earlier {N}s` / `:later {N}m` …: go to the newest state made no later
+    /// than `by` before (or after) the current state was made.
+    pub fn undo_by_time(&mut self, by: Duration, forward: bool) -> bool {
+        let now = self.undo_tree.node(self.undo_tree.current()).time;
+        let target = if forward {
+            self.undo_tree
+                .state_at(now + by)
+                .max(self.undo_tree.current())
+        } else {
+            match now.checked_sub(by) {
+                Some(t) => self.undo_tree.state_at(t).min(self.undo_tree.current()),
+                None => 0,
+            }
+        };
+        self.undo_to_state(target)
+    }
+
+    pub fn undo_tree(&self) -> &UndoTree {
+        &self.undo_tree
+    }
+
+    /// Adopt an undo history loaded for the text now in the buffer.
+    pub fn restore_undo_tree(&mut self, tree: UndoTree) {
+        self.undo_tree = tree;
+        self.coalescing = false;
+    }
+
+    /// The current and newest undo state numbers.
+    pub fn undo_state(&self) -> (usize, usize) {
+        (self.undo_tree.current(), self.undo_tree.last())
+    }
+
+    /// The `:undolist` table.
+    pub fn undo_list(&self) -> Vec<String> {
+        self.undo_tree.list(SystemTime::now())
+    }
+
+    /// Open a `/` (or `?` when `backward`) prompt. Until it is closed, typing
+    /// previews the search from the current cursor position.
+    pub fn begin_search(&mut self, backward: bool) {
+        self.search_prompt = Some(((self.cursor_y, self.cursor_x), backward));
+        self.command_buffer.clear();
+        self.set_mode(EditorMode::Search);
+    }
+
+    /// The prompt character of the search being typed: `/` or `?`.
+    pub fn search_prompt_char(&self) -> char {
+        match self.search_prompt {
+            Some((_, true)) => '?',
+            _ => '/',
+        }
+    }
+
+    /// Incremental search: move to where the typed `input` would jump from the
+    /// prompt's start and highlight its matches, without remembering it.
+    pub fn preview_search(&mut self, input: &str) {
+        let Some((origin, backward)) = self.search_prompt else {
+            return;
+        };
+        (self.cursor_y, self.cursor_x) = origin;
+        let delim = self.search_prompt_char();
+        let (pattern, offset) = search::split_offset(input, delim);
+        let offset = search::parse_offset(offset).unwrap_or(SearchOffset::Start(0));
+        self.search_preview = None;
+        if pattern.is_empty() {
+            return;
+        }
+        if let Ok(regex) = search::compile(&pattern) {
+            if let Some((m, _)) = self.next_match(&regex, offset, backward) {
+                self.goto_match(m, offset);
+            }
+            self.search_preview = Some(regex);
+        }
+    }
+
+    /// Close the prompt without searching, back where it was opened.
+    pub fn cancel_search(&mut self) {
+        if let Some((origin, _)) = self.search_prompt.take() {
+            (self.cursor_y, self.cursor_x) = origin;
+        }
+        self.search_preview = None;
+        self.set_mode(EditorMode::Normal);
+    }
+
+    /// Close the prompt and run the search typed into it. Returns a message
+    /// for the status line (an error, or a note that the search wrapped).
+    pub fn finish_search(&mut self, input: &str) -> Option<String> {
+        let (origin, backward) = self.search_prompt.take()?;
+        (self.cursor_y, self.cursor_x) = origin;
+        self.search_preview = None;
+        self.set_mode(EditorMode::Normal);
+        self.search_for(input, backward)
+    }
+
+    /// `/{input}` (or `?{input}` when `backward`) from the cursor: a pattern
+    /// (see [`search::compile`]) optionally followed by the delimiter and an
+    /// offset, as in `/foo/e+1`. An empty pattern reuses the last one.
+    /// Remembers the search for `n` / `N` and highlighting, then jumps to the
+    /// first match. Returns a status message, as [`Self::finish_search`].
+    pub fn search_for(&mut self, input: &str, backward: bool) -> Option<String> {
+        let delim = if backward { '?' } else { '/' };
+        let (pattern, offset) = search::split_offset(input, delim);
+        let Some(offset) = search::parse_offset(offset) else {
+            return Some(format!("Invalid search offset: {}", offset));
+        };
+        let (pattern, regex) = if pattern.is_empty() {
+            match &self.search {
+                Some(last) => (last.pattern.clone(), last.regex.clone()),
+                None => return Some("No previous search pattern".to_string()),
+            }
+        } else {
+            match search::compile(&pattern) {
+                Ok(regex) => (pattern, regex),
+                Err(e) => return Some(format!("Invalid pattern: {}", e)),
+            }
+        };
+        self.search_query = Some(pattern.clone());
+        self.search = Some(Search {
+            pattern,
+            regex,
+            offset,
+            backward,
+        });
+        self.highlight_search = true;
+        self.search_next(false)
+    }
+
+    /// `*` / `#`: search forward (backward) for the whole word under the
+    /// cursor, or the next word on the line. Case always matters.
+    pub fn search_word_under_cursor(&mut self, backward: bool) -> Option<String> {
+        let line: Vec<char> = self.buffer.line_text(self.cursor_y).chars().collect();
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let mut start = self.cursor_x.min(line.len());
+        if !line.get(start).is_some_and(is_word) {
+            let Some(next) = line[start..].iter().position(is_word) else {
+                return Some("No string under cursor".to_string());
+            };
+            start += next;
+        }
+        while start > 0 && is_word(&line[start - 1]) {
+            start -= 1;
+        }
+        let len = line[start..].iter().take_while(|c| is_word(c)).count();
+        let word: String = line[start..start + len].iter().collect();
+        // Search from the word's start, so the word itself is skipped.
+        self.cursor_x = start;
+        let pattern = format!(r"\<{}\>\C", regex::escape(&word));
+        self.search_for(&pattern, backward)
+    }
+
+    /// `n` (or `N` when `reverse`): repeat the last search, in its direction
+    /// or the opposite one.
+    pub fn search_next(&mut self, reverse: bool) -> Option<String> {
+        let Some(last) = &self.search else {
+            return Some("No previous search pattern".to_string());
+        };
+        let (regex, offset) = (last.regex.clone(), last.offset);
+        let backward = last.backward != reverse;
+        let pattern = last.pattern.clone();
+        self.highlight_search = true;
+        match self.next_match(&regex, offset, backward) {
+            Some((m, wrapped)) => {
+                self.goto_match(m, offset);
+                wrapped.then(|| {
+                    if backward {
+                        "search hit TOP, continuing at BOTTOM".to_string()
+                    } else {
+                        "search hit BOTTOM, continuing at TOP".to_string()
+                    }
+                })
+            }
+            None => Some(format!("Pattern not found: {}", pattern)),
+        }
+    }
+
+    pub fn find_next(&mut self) -> Option<String> {
+        self.search_next(false)
+    }
+
+    pub fn find_previous(&mut self) -> Option<String> {
+        self.search_next(true)
+    }
+
+    /// `:nohlsearch`: hide the search highlighting until the next search.
+    pub fn clear_search_highlight(&mut self) {
+        self.highlight_search = false;
+    }
+
+    /// The match of `regex` after (`backward`: before) the cursor, wrapping
+    /// around the buffer, and whether it wrapped. The cursor is first moved
+    /// back by `offset`, so `n` after `/foo/e` finds the next match rather
+    /// than the one it is on. Refreshes `search_matches` along the way.
+    fn next_match(
+        &mut self,
+        regex: &Regex,
+        offset: SearchOffset,
+        backward: bool,
+    ) -> Option<((usize, usize), bool)> {
+        let matches = search::find_all(regex, &self.buffer.raw_content(), 0);
+        self.search_matches = matches
+            .iter()
+            .map(|&(s, _)| self.char_to_cursor(s))
+            .collect();
+        self.current_search_match = None;
+        let cursor = self.buffer.cursor_to_char(self.cursor_y, self.cursor_x) as isize;
+        let from = match offset {
+            SearchOffset::Start(n) | SearchOffset::End(n) => (cursor - n).max(0) as usize,
+            SearchOffset::Line(n) => {
+                let last = self.buffer.line_count().saturating_sub(1) as isize;
+                let y = (self.cursor_y as isize - n).clamp(0, last) as usize;
+                if backward {
+                    self.buffer.line_to_char(y)
+                } else {
+                    self.buffer.line_to_char(y) + self.buffer.line_char_len(y)
+                }
+            }
+        };
+        let from_end = |&(s, e): &(usize, usize)| match offset {
+            // With an end offset the cursor sat on the match's last char.
+            SearchOffset::End(_) => e.saturating_sub(1).max(s),
+            _ => s,
+        };
+        let (index, wrapped) = if backward {
+            match matches.iter().rposition(|m| from_end(m) < from) {
+                Some(i) => (i, false),
+                None => (matches.len().checked_sub(1)?, true),
+            }
+        } else {
+            match matches.iter().position(|m| from_end(m) > from) {
+                Some(i) => (i, false),
+                None if matches.is_empty() => return None,
+                None => (0, true),
+            }
+        };
+        self.current_search_match = Some(index);
+        Some((matches[index], wrapped))
+    }
+
+    /// Put the cursor on match `m` (a char range), adjusted by `offset`.
+    fn goto_match(&mut self, (s, e): (usize, usize), offset: SearchOffset) {
+        let len = self.buffer.len_chars() as isize;
+        let (y, x) = match offset {
+            SearchOffset::Start(n) => self.char_to_cursor((s as isize + n).clamp(0, len) as usize),
+            SearchOffset::End(n) => {
+                let end = e.saturating_sub(1).max(s) as isize;
+                self.char_to_cursor((end + n).clamp(0, len) as usize)
+            }
+            SearchOffset::Line(n) => {
+                let last = self.buffer.line_count().saturating_sub(1) as isize;
+                let y = self.char_to_cursor(s).0 as isize + n;
+                (y.clamp(0, last) as usize, 0)
+            }
+        };
+        self.cursor_y = y;
+        self.cursor_x = x.min(self.buffer.line_char_len(y).saturating_sub(1));
+        self.coalescing = false;
+    }
+
+    /// Char columns of search matches on lines `top..bottom`, as `(y, start,
+    /// end)`: the prompt's preview while one is being typed, else the last
+    /// search unless hidden by `:nohlsearch`. A match running over a line
+    /// break is split per line. Used by the renderer.
+    pub fn search_highlights(&self, top: usize, bottom: usize) -> Vec<(usize, usize, usize)> {
+        let regex = match (&self.search_preview, &self.search) {
+            (Some(preview), _) => preview,
+            (None, Some(last)) if self.highlight_search => &last.regex,
+            _ => return Vec::new(),
+        };
+        let bottom = bottom.min(self.buffer.line_count());
+        if top >= bottom {
+            return Vec::new();
+        }
+        let base = self.buffer.line_to_char(top);
+        let text = self
+            .buffer
+            .slice_text(base..self.buffer.line_to_char(bottom));
+        let mut out = Vec::new();
+        for (s, e) in search::find_all(regex, &text, base) {
+            let mut y = self.buffer.char_to_line(s);
+            let (mut s, stop) = (s, e.max(s + 1));
+            while s < stop && y < bottom {
+                let line_start = self.buffer.line_to_char(y);
+                let line_end = line_start + self.buffer.line_char_len(y);
+                out.push((y, s - line_start, e.min(line_end + 1) - line_start));
+                s = line_end + 1;
+                y += 1;
+            }
+        }
+        out
+    }
+
+    /// `:[range]d [x]`: delete whole lines into register `x` (or the
+    /// unnamed one).
+    pub fn delete_lines(&mut self, range: LineRange, register: Option<char>) {
+        self.register_name = register;
+        (self.cursor_y, self.cursor_x) = (range.start, 0);
+        self.operate_linewise_range(Operator::Delete, range.start, range.end);
+        self.register_name = None;
+    }
+
+    /// `:[range]m {address}`: move the lines below line `dest` (counted as in
+    /// [`Self::resolve_destination`]), leaving the cursor on the last of them.
+    pub fn move_lines(&mut self, range: LineRange, dest: usize) -> Result<(), String> {
+        let LineRange { start, end } = range;
+        if dest > start && dest <= end {
+            return Err("Cannot move a range of lines into itself".to_string());
+        }
+        let count = end - start + 1;
+        if dest == start || dest == end + 1 {
+            self.goto_first_non_blank(end);
+            return Ok(());
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let (s, e) = (
+            self.buffer.line_to_char(start),
+            self.buffer.line_to_char(end + 1),
+        );
+        let at = self.buffer.line_to_char(dest);
+        let text = self.buffer.slice_text(s..e);
+        let changes = self.apply_edits(vec![(at, at, text), (s, e, String::new())]);
+        let last = if dest > end {
+            dest - 1
+        } else {
+            dest + count - 1
+        };
+        self.goto_first_non_blank(last);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        Ok(())
+    }
+
+    /// `:[range]t {address}` / `:co`: copy the lines below line `dest`,
+    /// leaving the cursor on the last copy.
+    pub fn copy_lines(&mut self, range: LineRange, dest: usize) {
+        let before = (self.cursor_y, self.cursor_x);
+        let text = self.buffer.slice_text(
+            self.buffer.line_to_char(range.start)..self.buffer.line_to_char(range.end + 1),
+        );
+        let at = self.buffer.line_to_char(dest);
+        let changes = self.apply_edits(vec![(at, at, text)]);
+        self.goto_first_non_blank(dest + range.end - range.start);
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// Join lines `start..=end` into one. With `spaces` (`J`, `:j`) each
+    /// line's indent is replaced by a space as [`line_ops::join_separator`]
+    /// decides; without (`gJ`, `:j!`) the lines are put together as they are.
+    /// The cursor goes where the last two lines met. Returns false when there
+    /// is no line to join.
+    pub fn join_lines(&mut self, start: usize, end: usize, spaces: bool) -> bool {
+        let end = end.min(self.buffer.line_count().saturating_sub(1));
+        if start >= end {
+            return false;
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let mut edits = Vec::new();
+        let mut joined = self.buffer.line_text(start);
+        let mut join_col = 0;
+        for y in start + 1..=end {
+            let line = self.buffer.line_text(y);
+            let break_at = self.buffer.line_to_char(y) - 1;
+            let (indent, separator) = if spaces {
+                let rest = line.trim_start();
+                let indent = line.chars().count() - rest.chars().count();
+                (
+                    indent,
+                    line_ops::join_separator(joined.chars().last(), rest),
+                )
+            } else {
+                (0, "")
+            };
+            edits.push((break_at, break_at + 1 + indent, separator.to_string()));
+            join_col = joined.chars().count();
+            joined.push_str(separator);
+            joined.extend(line.chars().skip(indent));
+        }
+        let changes = self.apply_edits(edits);
+        self.cursor_y = start;
+        self.cursor_x = join_col.min(self.buffer.line_char_len(start).saturating_sub(1));
+        self.coalescing = false;
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        true
+    }
+
+    /// `:[range]sort`: sort the lines as [`line_ops::sort_lines`] does. The
+    /// pattern, if any, is a search pattern; an empty one is the last search.
+    pub fn sort_lines(&mut self, range: LineRange, opts: &SortOptions) -> Result<(), String> {
+        let pattern = match opts.pattern.as_deref() {
+            None => None,
+            Some("") => match &self.search {
+                Some(last) => Some(last.regex.clone()),
+                None => return Err("No previous regular expression".to_string()),
+            },
+            Some(p) => Some(search::compile(p).map_err(|e| format!("Invalid pattern: {}", e))?),
+        };
+        let lines: Vec<String> = (range.start..=range.end)
+            .map(|y| self.buffer.line_text(y))
+            .collect();
+        let sorted = line_ops::sort_lines(&lines, opts, pattern.as_ref());
+        let before = (self.cursor_y, self.cursor_x);
+        if sorted != lines {
+            let s = self.buffer.line_to_char(range.start);
+            let e = self.buffer.line_to_char(range.end + 1);
+            let text: String = sorted.iter().map(|l| format!("{}\n", l)).collect();
+            let changes = self.apply_edits(vec![(s, e, text)]);
+            (self.cursor_y, self.cursor_x) = (range.start, 0);
+            self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        } else {
+            (self.cursor_y, self.cursor_x) = (range.start, 0);
+        }
+        Ok(())
+    }
+
+    /// `:[range]retab[!] [tabstop]`: rewrite the blanks of the lines for a
+    /// new tab stop (the current one when `None`), which then becomes
+    /// `tabstop`. See [`line_ops::retab_line`].
+    pub fn retab(&mut self, range: LineRange, tabstop: Option<usize>, all: bool) {
+        let new_ts = tabstop.unwrap_or(self.options.tabstop);
+        let mut edits = Vec::new();
+        for y in range.start..=range.end {
+            let line = self.buffer.line_text(y);
+            let retabbed = line_ops::retab_line(
+                &line,
+                self.options.tabstop,
+                new_ts,
+                self.options.expandtab,
+                all,
+            );
+            if retabbed != line {
+                let s = self.buffer.line_to_char(y);
+                edits.push((s, s + line.chars().count(), retabbed));
+            }
+        }
+        self.options.tabstop = new_ts;
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(edits);
+        let x = self
+            .cursor_x
+            .min(self.buffer.line_char_len(self.cursor_y).saturating_sub(1));
+        self.cursor_x = x;
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// The lines the last `!{motion}` picked, if they have not been taken.
+    pub fn take_filter_lines(&mut self) -> Option<LineRange> {
+        self.filter_lines.take()
+    }
+
+    /// The text of the lines of `range`, each with its line break (the input
+    /// of a filter).
+    pub fn lines_text(&self, range: LineRange) -> String {
+        self.buffer.slice_text(
+            self.buffer.line_to_char(range.start)..self.buffer.line_to_char(range.end + 1),
+        )
+    }
+
+    /// `:[range]!cmd`: replace the lines with `text`, a filter's output, as
+    /// one undo step, leaving the cursor on the first new line.
+    pub fn replace_lines(&mut self, range: LineRange, text: &str) {
+        self.splice_lines(range.start, range.end + 1, text);
+    }
+
+    /// `:r`: put `text` as new lines below line `y`, leaving the cursor on the
+    /// first of them.
+    pub fn read_lines(&mut self, y: usize, text: &str) {
+        self.splice_lines(y + 1, y + 1, text);
+    }
+
+    /// Replace lines `start..end` with the lines of `text`; a last line
+    /// without a line break gets one.
+    fn splice_lines(&mut self, start: usize, end: usize, text: &str) {
+        let count = self.buffer.line_count();
+        let (start, end) = (start.min(count), end.min(count));
+        let mut text = text.to_string();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        if start == end && text.is_empty() {
+            return;
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let (s, e) = (
+            self.buffer.line_to_char(start),
+            self.buffer.line_to_char(end),
+        );
+        let changes = self.apply_edits(vec![(s, e, text)]);
+        let last = self.buffer.line_count().saturating_sub(1);
+        self.goto_first_non_blank(start.min(last));
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+    }
+
+    /// Start a `:g` (or, `invert`, `:v`) run: mark the lines of `range` that
+    /// `pattern` matches (does not match). Everything done until
+    /// [`Self::finish_global`] is one undo step. Returns how many lines were
+    /// marked.
+    pub fn start_global(
+        &mut self,
+        range: LineRange,
+        pattern: &str,
+        invert: bool,
+    ) -> Result<usize, String> {
+        if self.global.is_some() {
+            return Err("Cannot do :global recursive".to_string());
+        }
+        let regex = if pattern.is_empty() {
+            match &self.search {
+                Some(last) => last.regex.clone(),
+                None => return Err("No previous regular expression".to_string()),
+            }
+        } else {
+            search::compile(pattern).map_err(|e| format!("Invalid pattern: {}", e))?
+        };
+        let last = self.buffer.line_count().saturating_sub(1);
+        let lines: Vec<Option<usize>> = (range.start..=range.end.min(last))
+            .filter(|&y| regex.is_match(&self.buffer.line_text(y)) != invert)
+            .map(|y| Some(self.buffer.line_to_char(y)))
+            .collect();
+        let count = lines.len();
+        self.begin_undo_group();
+        self.global = Some(GlobalRun { lines, next: 0 });
+        Ok(count)
+    }
+
+    /// Move to the start of the next marked line that still exists and
+    /// return it, or `None` when the run is done.
+    pub fn next_global_line(&mut self) -> Option<usize> {
+        let run = self.global.as_mut()?;
+        while let Some(&line) = run.lines.get(run.next) {
+            run.next += 1;
+            if let Some(offset) = line {
+                (self.cursor_y, self.cursor_x) = (self.buffer.char_to_line(offset), 0);
+                return Some(self.cursor_y);
+            }
+        }
+        None
+    }
+
+    /// End a `:g` run, closing its undo step.
+    pub fn finish_global(&mut self) {
+        if self.global.take().is_some() {
+            self.end_undo_group();
+        }
+    }
+
+    /// Resolve a parsed ex range against the buffer: marks, searches (from
+    /// the cursor line, wrapping around) and offsets, with `;` making the
+    /// first address the current line for the second.
+    pub fn resolve_range(&self, range: &ex_range::Range) -> Result<LineRange, String> {
+        let start = self.resolve_address(&range.start, self.cursor_y)?;
+        let end = match &range.end {
+            Some(end) => {
+                let current = if range.relative_to_start {
+                    start
+                } else {
+                    self.cursor_y
+                };
+                self.resolve_address(end, current)?
+            }
+            None => start,
+        };
+        if start > end {
+            return Err("Backwards range".to_string());
+        }
+        Ok(LineRange { start, end })
+    }
+
+    /// The line an address names, counting `.` as `current`. Line `0` is
+    /// taken as the first line.
+    fn resolve_address(&self, pos: &ex_range::Position, current: usize) -> Result<usize, String> {
+        if pos.base == Address::Line(0) && pos.offset == 0 {
+            return Ok(0);
+        }
+        let last = self.buffer.line_count().saturating_sub(1);
+        match self.address_line(pos, current)? {
+            line if line < 0 || line > last as isize => Err("Invalid range".to_string()),
+            line => Ok(line as usize),
+        }
+    }
+
+    /// The line an address names, unchecked: `-1` is the (1-based) line 0.
+    fn address_line(&self, pos: &ex_range::Position, current: usize) -> Result<isize, String> {
+        let last = self.buffer.line_count().saturating_sub(1);
+        let base = match &pos.base {
+            Address::Current => current as isize,
+            Address::Last => last as isize,
+            Address::Line(n) => (*n as isize - 1).min(last as isize),
+            Address::Mark(name) => match self.mark(*name) {
+                Some((y, _)) => y as isize,
+                None => return Err("Mark not set".to_string()),
+            },
+            Address::Search { pattern, backward } => {
+                self.search_line(pattern, *backward, current)? as isize
+            }
+        };
+        Ok(base + pos.offset)
+    }
+
+    /// Where `:m` / `:t` put lines: below the line the (last) address names,
+    /// as the number of lines above them; `0` puts them at the top.
+    pub fn resolve_destination(&self, range: &ex_range::Range) -> Result<usize, String> {
+        let (pos, current) = match &range.end {
+            Some(end) if range.relative_to_start => {
+                (end, self.resolve_address(&range.start, self.cursor_y)?)
+            }
+            Some(end) => (end, self.cursor_y),
+            None => (&range.start, self.cursor_y),
+        };
+        let last = self.buffer.line_count().saturating_sub(1) as isize;
+        match self.address_line(pos, current)? {
+            line if line < -1 || line > last => Err("Invalid range".to_string()),
+            line => Ok((line + 1) as usize),
+        }
+    }
+
+    /// The first line after (`backward`: before) `from` that `pattern`
+    /// matches, wrapping around. An empty pattern is the last search.
+    fn search_line(&self, pattern: &str, backward: bool, from: usize) -> Result<usize, String> {
+        let regex = if pattern.is_empty() {
+            match &self.search {
+                Some(last) => last.regex.clone(),
+                None => return Err("No previous regular expression".to_string()),
+            }
+        } else {
+            search::compile(pattern).map_err(|e| format!("Invalid pattern: {}", e))?
+        };
+        let count = self.buffer.line_count();
+        (1..=count)
+            .map(|i| {
+                if backward {
+                    (from + count - i % count) % count
+                } else {
+                    (from + i) % count
+                }
+            })
+            .find(|&y| regex.is_match(&self.buffer.line_text(y)))
+            .ok_or_else(|| format!("Pattern not found: {}", pattern))
+    }
+
+    /// `:[first,last]s/pattern/replacement/flags` over lines `first..=last`.
+    /// An empty pattern is the last search pattern; the pattern used becomes
+    /// the search pattern for `n` and highlighting. All the replacements are
+    /// one undo step. With the `c` flag each one is asked about in
+    /// [`EditorMode::Confirm`] (see [`Self::confirm_substitute`]) and the
+    /// prompt is returned; otherwise the cursor ends on the last line changed
+    /// and the returned message counts the substitutions.
+    pub fn substitute(
+        &mut self,
+        first: usize,
+        last: usize,
+        sub: Substitution,
+    ) -> Result<String, String> {
+        let pattern = if sub.pattern.is_empty() {
+            match &self.search {
+                Some(last) => last.pattern.clone(),
+                None => return Err("No previous regular expression".to_string()),
+            }
+        } else {
+            sub.pattern.clone()
+        };
+        let regex = search::compile(&sub.search_pattern(&pattern))
+            .map_err(|e| format!("Invalid pattern: {}", e))?;
+        let sub = Substitution {
+            pattern: pattern.clone(),
+            ..sub
+        };
+        self.search_query = Some(pattern.clone());
+        self.search = Some(Search {
+            pattern: pattern.clone(),
+            regex: regex.clone(),
+            offset: SearchOffset::Start(0),
+            backward: false,
+        });
+        self.highlight_search = true;
+        let edits = self.substitute_edits(&regex, first, last, &sub);
+        let flags = sub.flags;
+        self.last_substitute = Some(sub);
+        if edits.is_empty() {
+            // Under `:g`, lines without a match are simply left alone.
+            return if flags.quiet || self.global.is_some() {
+                Ok(String::new())
+            } else {
+                Err(format!("Pattern not found: {}", pattern))
+            };
+        }
+        if flags.confirm {
+            self.begin_undo_group();
+            self.pending_substitute = Some(PendingSubstitute {
+                edits: edits.into(),
+                shift: 0,
+                count: 0,
+                lines: 0,
+                last_line: None,
+                last_start: None,
+                cursor_before: (self.cursor_y, self.cursor_x),
+            });
+            self.set_mode(EditorMode::Confirm);
+            return Ok(self.confirm_next());
+        }
+        let before = (self.cursor_y, self.cursor_x);
+        let count = edits.len();
+        let mut lines: Vec<usize> = edits.iter().map(|&(y, ..)| y).collect();
+        lines.dedup();
+        // Where the last replacement starts once the earlier ones are made.
+        let shift: isize = edits[..count - 1]
+            .iter()
+            .map(|(_, s, e, text)| text.chars().count() as isize - (e - s) as isize)
+            .sum();
+        let last_start = (edits[count - 1].1 as isize + shift) as usize;
+        let changes = self.apply_edits(edits.into_iter().map(|(_, s, e, t)| (s, e, t)).collect());
+        self.goto_first_non_blank(self.buffer.char_to_line(last_start));
+        self.commit_changes(changes, before, (self.cursor_y, self.cursor_x));
+        Ok(substitute_report(count, lines.len()))
     }
 
-    /// Record a non-insert edit as its own undo step, ending any coalescing run.
-    fn commit(&mut self, change: Change, before: (usize, usize), after: (usize, usize)) {
-        self.redo_stack.clear();
-        self.undo_stack.push(Transaction {
-            change,
-            cursor_before: before,
-            cursor_after: after,
-        });
-        self.coalescing = false;
+    /// `&`, `g&` and `:s` without a pattern: run the last substitution again
+    /// over lines `first..=last`, with its flags or (`keep_flags` false)
+    /// without them.
+    pub fn repeat_substitute(
+        &mut self,
+        first: usize,
+        last: usize,
+        keep_flags: bool,
+    ) -> Result<String, String> {
+        let Some(mut sub) = self.last_substitute.clone() else {
+            return Err("No previous substitute regular expression".to_string());
+        };
+        if !keep_flags {
+            sub.flags = Default::default();
+        }
+        self.substitute(first, last, sub)
     }
 
-    /// Record an inserted character, coalescing it onto the previous transaction
-    /// when it is a contiguous continuation of an ongoing insert run so that a
-    /// word of typing collapses into a single undo step.
-    fn commit_insert(&mut self, change: Change, before: (usize, usize), after: (usize, usize)) {
-        self.redo_stack.clear();
-        if self.coalescing {
-            if let Some(top) = self.undo_stack.last_mut() {
-                if top.change.removed.is_empty()
-                    && change.removed.is_empty()
-                    && change.pos == top.change.pos + top.change.inserted.chars().count()
-                {
-                    top.change.inserted.push_str(&change.inserted);
-                    top.cursor_after = after;
-                    return;
+    /// Answer the `:s///c` prompt: `y` replaces this match, `l` replaces it
+    /// and stops, `n` skips it, `a` replaces it and all the rest, and `q` or
+    /// Esc stops. Returns the next prompt, or once done the report for the
+    /// status line.
+    pub fn confirm_substitute(&mut self, answer: char) -> String {
+        let Some(mut pending) = self.pending_substitute.take() else {
+            return String::new();
+        };
+        match answer {
+            'y' => self.apply_pending(&mut pending),
+            'l' => {
+                self.apply_pending(&mut pending);
+                pending.edits.clear();
+            }
+            'n' => {
+                pending.edits.pop_front();
+            }
+            'a' => {
+                while !pending.edits.is_empty() {
+                    self.apply_pending(&mut pending);
                 }
             }
+            'q' | '\u{1b}' => pending.edits.clear(),
+            _ => {}
         }
-        self.undo_stack.push(Transaction {
-            change,
-            cursor_before: before,
-            cursor_after: after,
-        });
-        self.coalescing = true;
-    }
-
-    pub fn undo(&mut self) {
-        if let Some(t) = self.undo_stack.pop() {
-            self.apply_change(&t.change.invert());
-            self.cursor_y = t.cursor_before.0;
-            self.cursor_x = t.cursor_before.1;
-            self.redo_stack.push(t);
+        let done = pending.edits.is_empty();
+        let (count, lines, last_start, origin) = (
+            pending.count,
+            pending.lines,
+            pending.last_start,
+            pending.cursor_before,
+        );
+        self.pending_substitute = Some(pending);
+        if !done {
+            return self.confirm_next();
         }
-        self.coalescing = false;
-    }
-
-    pub fn redo(&mut self) {
-        if let Some(t) = self.redo_stack.pop() {
-            self.apply_change(&t.change);
-            self.cursor_y = t.cursor_after.0;
-            self.cursor_x = t.cursor_after.1;
-            self.undo_stack.push(t);
+        self.pending_substitute = None;
+        match last_start {
+            Some(start) => self.goto_first_non_blank(self.buffer.char_to_line(start)),
+            None => (self.cursor_y, self.cursor_x) = origin,
+        }
+        self.end_undo_group();
+        self.set_mode(EditorMode::Normal);
+        if count == 0 {
+            String::new()
+        } else {
+            substitute_report(count, lines)
         }
-        self.coalescing = false;
     }
 
-    pub fn search(&mut self, query: &str) {
-        self.search_query = Some(query.to_string());
-        self.search_matches.clear();
-        self.current_search_match = None;
+    /// Put the cursor on the match being confirmed and return the prompt.
+    fn confirm_next(&mut self) -> String {
+        let Some(pending) = &self.pending_substitute else {
+            return String::new();
+        };
+        let Some((_, s, _, text)) = pending.edits.front() else {
+            return String::new();
+        };
+        let prompt = format!("replace with {} (y/n/a/q/l)?", text);
+        let (y, x) = self.char_to_cursor((*s as isize + pending.shift) as usize);
+        (self.cursor_y, self.cursor_x) = (y, x);
+        prompt
+    }
 
-        if query.is_empty() {
+    /// Make the next confirmed replacement, as its own transaction inside the
+    /// undo group opened by [`Self::substitute`].
+    fn apply_pending(&mut self, pending: &mut PendingSubstitute) {
+        let Some((y, s, e, text)) = pending.edits.pop_front() else {
             return;
+        };
+        let start = (s as isize + pending.shift) as usize;
+        let end = (e as isize + pending.shift) as usize;
+        pending.shift += text.chars().count() as isize - (e - s) as isize;
+        pending.count += 1;
+        if pending.last_line != Some(y) {
+            pending.lines += 1;
+            pending.last_line = Some(y);
         }
-
-        for y in 0..self.buffer.line_count() {
-            let line = self.buffer.line_text(y);
-            for (byte_idx, _) in line.match_indices(query) {
-                // Convert the byte offset from match_indices into a char offset
-                // so match positions share the cursor's char coordinate.
-                let char_x = line[..byte_idx].chars().count();
-                self.search_matches.push((y, char_x));
-            }
-        }
-
-        if !self.search_matches.is_empty() {
-            let initial_match_index = self
-                .search_matches
-                .iter()
-                .position(|&(y, x)| y >= self.cursor_y && x >= self.cursor_x)
-                .unwrap_or(0);
-            self.current_search_match = Some(initial_match_index);
-            let (y, x) = self.search_matches[initial_match_index];
-            self.cursor_y = y;
-            self.cursor_x = x;
-        }
+        pending.last_start = Some(start);
+        let before = (self.cursor_y, self.cursor_x);
+        let changes = self.apply_edits(vec![(start, end, text)]);
+        self.commit_changes(changes, before, before);
     }
 
-    pub fn find_next(&mut self) {
-        if let Some(current_match) = self.current_search_match {
-            if self.search_matches.is_empty() {
-                return;
+    /// The replacements `sub` makes on lines `first..=last`, as `(line,
+    /// start, end, text)`: the first match starting on each line, or with the
+    /// `g` flag every match. A match may run on past `last` but never takes
+    /// the buffer's final line break.
+    fn substitute_edits(
+        &self,
+        regex: &Regex,
+        first: usize,
+        last: usize,
+        sub: &Substitution,
+    ) -> Vec<(usize, usize, usize, String)> {
+        let line_count = self.buffer.line_count();
+        if line_count == 0 || first >= line_count {
+            return Vec::new();
+        }
+        let last = last.min(line_count - 1);
+        let base = self.buffer.line_to_char(first);
+        let text = self
+            .buffer
+            .slice_text(base..self.buffer.len_chars().saturating_sub(1));
+        let mut edits = Vec::new();
+        let (mut byte, mut pos) = (0, base);
+        let mut prev_line = None;
+        for caps in regex.captures_iter(&text) {
+            let m = caps.get(0).expect("group 0 is the whole match");
+            pos += text[byte..m.start()].chars().count();
+            byte = m.start();
+            let y = self.buffer.char_to_line(pos);
+            if y > last {
+                break;
             }
-            let next_match_index = (current_match + 1) % self.search_matches.len();
-            self.current_search_match = Some(next_match_index);
-            let (y, x) = self.search_matches[next_match_index];
-            self.cursor_y = y;
-            self.cursor_x = x;
+            if sub.flags.global || prev_line != Some(y) {
+                let end = pos + m.as_str().chars().count();
+                edits.push((y, pos, end, substitute::expand(&sub.replacement, &caps)));
+            }
+            prev_line = Some(y);
         }
+        edits
     }
 
-    pub fn find_previous(&mut self) {
-        if let Some(current_match) = self.current_search_match {
-            if self.search_matches.is_empty() {
-                return;
-            }
-            let prev_match_index = if current_match == 0 {
-                self.search_matches.len() - 1
-            } else {
-                current_match - 1
-            };
-            self.current_search_match = Some(prev_match_index);
-            let (y, x) = self.search_matches[prev_match_index];
-            self.cursor_y = y;
-            self.cursor_x = x;
-        }
+    /// Move to the first non-blank character of line `y`.
+    pub fn goto_first_non_blank(&mut self, y: usize) {
+        let y = y.min(self.buffer.line_count().saturating_sub(1));
+        let line = self.buffer.line_text(y);
+        self.cursor_y = y;
+        self.cursor_x = line.chars().position(|c| !c.is_whitespace()).unwrap_or(0);
+        self.coalescing = false;
     }
 }
 
+/// The "N substitutions on M lines" message.
+fn substitute_report(count: usize, lines: usize) -> String {
+    let plural = |n: usize, word: &str| {
+        if n == 1 {
+            format!("1 {}", word)
+        } else {
+            format!("{} {}s", n, word)
+        }
+    };
+    format!(
+        "{} on {}",
+        plural(count, "substitution"),
+        plural(lines, "line")
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1044,7 +4426,7 @@ mod tests {
     fn test_search_multibyte_char_offsets() {
         let mut editor = EditorModel::new();
         editor.buffer.set_content("あいうabc");
-        editor.search("abc");
+        editor.search_for("abc", false);
         // match is at char offset 3, not byte offset 9
         assert_eq!(editor.search_matches, vec![(0, 3)]);
         assert_eq!(editor.cursor_x, 3);
@@ -1059,6 +4441,33 @@ mod tests {
         assert_eq!(editor.cursor_x, 1);
     }
 
+    #[test]
+    fn vertical_moves_keep_the_display_column() {
+        let mut editor = EditorModel::new();
+        editor.set_content("abcdef\nab\n日本語x\nabcdefgh\n");
+        editor.cursor_x = 4;
+        editor.move_by_motion(Motion::Down, 1);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 1));
+        // Column 4 falls on the third wide character.
+        editor.move_by_motion(Motion::Down, 1);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (2, 2));
+        editor.move_by_motion(Motion::Down, 1);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (3, 4));
+        // Moving otherwise forgets the column.
+        editor.move_by_motion(Motion::Left, 1);
+        editor.move_by_motion(Motion::Up, 3);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 3));
+
+        // `$` sticks to the end of each line.
+        editor.move_by_motion(Motion::LineEnd, 1);
+        editor.move_by_motion(Motion::Down, 1);
+        assert_eq!(editor.cursor_x, 1);
+        editor.move_by_motion(Motion::Down, 2);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (3, 7));
+        editor.move_cursor(KeyCode::Up);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (2, 4));
+    }
+
     #[test]
     fn test_insert_char() {
         let mut editor = EditorModel::new();
@@ -1101,7 +4510,8 @@ mod tests {
         editor.insert_newline();
         assert_eq!(editor.buffer.line_count(), 2);
         assert_eq!(editor.buffer.line_text(0), "Hello");
-        assert_eq!(editor.buffer.line_text(1), " World");
+        // autoindent drops the blanks the new line would start with.
+        assert_eq!(editor.buffer.line_text(1), "World");
         assert_eq!(editor.cursor_y, 1);
         assert_eq!(editor.cursor_x, 0);
     }
@@ -1249,11 +4659,15 @@ mod tests {
         editor.buffer.push_line("line1");
         editor.buffer.push_line("line2");
         editor.cursor_y = 0;
-        editor.register = Some(Register {
-            text: "yanked_line\n".to_string(),
-            linewise: true,
-        });
-        editor.put_line_below();
+        editor.registers.set(
+            '"',
+            Register {
+                text: "yanked_line\n".to_string(),
+                linewise: true,
+                blockwise: false,
+            },
+        );
+        editor.paste(None, true, 1);
         assert_eq!(editor.buffer.line_count(), 3);
         assert_eq!(editor.buffer.line_text(1), "yanked_line");
         assert_eq!(editor.cursor_y, 1);
@@ -1265,11 +4679,15 @@ mod tests {
         let mut editor = EditorModel::new();
         editor.buffer.push_line("line1");
         editor.cursor_y = 0;
-        editor.register = Some(Register {
-            text: "\n".to_string(),
-            linewise: true,
-        });
-        editor.put_line_below();
+        editor.registers.set(
+            '"',
+            Register {
+                text: "\n".to_string(),
+                linewise: true,
+                blockwise: false,
+            },
+        );
+        editor.paste(None, true, 1);
         assert_eq!(editor.buffer.line_count(), 2);
         assert_eq!(editor.buffer.line_text(1), "");
         assert_eq!(editor.cursor_y, 1);
@@ -1445,17 +4863,45 @@ mod tests {
     fn test_repeat_last_change_put_line_below() {
         let mut editor = EditorModel::new();
         editor.buffer.push_line("line1");
-        editor.register = Some(Register {
-            text: "yanked\n".to_string(),
-            linewise: true,
-        });
-        editor.put_line_below();
+        editor.registers.set(
+            '"',
+            Register {
+                text: "yanked\n".to_string(),
+                linewise: true,
+                blockwise: false,
+            },
+        );
+        editor.paste(None, true, 1);
         editor.repeat_last_change();
         assert_eq!(editor.buffer.line_count(), 3);
         assert_eq!(editor.buffer.line_text(1), "yanked");
         assert_eq!(editor.buffer.line_text(2), "yanked");
     }
 
+    #[test]
+    fn repeat_paste_keeps_register_direction_and_count() {
+        let mut editor = EditorModel::new();
+        editor.set_content("a\nb\n");
+        let line = |text: &str| Register {
+            text: text.to_string(),
+            linewise: true,
+            blockwise: false,
+        };
+        editor.registers.set('a', line("a\n"));
+        editor.registers.set('b', line("b\n"));
+        editor.goto(1, 0);
+        editor.paste(Some('a'), true, 1);
+        editor.repeat_last_change();
+        assert_eq!(editor.buffer.to_lines(), ["a", "b", "a", "a"]);
+        editor.set_content("x\n");
+        editor.goto(0, 0);
+        editor.registers.set('"', line("y\n"));
+        editor.paste(None, false, 2);
+        assert_eq!(editor.buffer.to_lines(), ["y", "y", "x"]);
+        editor.repeat_last_change();
+        assert_eq!(editor.buffer.to_lines(), ["y", "y", "y", "y", "x"]);
+    }
+
     #[test]
     fn test_insert_line_below_empty_document() {
         let mut editor = EditorModel::new();
@@ -1470,7 +4916,7 @@ mod tests {
     fn test_search() {
         let mut editor = EditorModel::new();
         editor.buffer.set_content("hello world\nworld hello");
-        editor.search("world");
+        editor.search_for("world", false);
         assert_eq!(editor.search_matches, vec![(0, 6), (1, 0)]);
         assert_eq!(editor.cursor_y, 0);
         assert_eq!(editor.cursor_x, 6);
@@ -1480,7 +4926,7 @@ mod tests {
     fn test_find_next() {
         let mut editor = EditorModel::new();
         editor.buffer.set_content("a b c\nd e f");
-        editor.search(" ");
+        editor.search_for(" ", false);
         editor.find_next();
         assert_eq!(editor.cursor_y, 0);
         assert_eq!(editor.cursor_x, 3);
@@ -1493,7 +4939,7 @@ mod tests {
     fn test_find_previous() {
         let mut editor = EditorModel::new();
         editor.buffer.set_content("a b c\nd e f");
-        editor.search(" ");
+        editor.search_for(" ", false);
         editor.find_previous();
         assert_eq!(editor.cursor_y, 1);
         assert_eq!(editor.cursor_x, 3);
@@ -1502,6 +4948,90 @@ mod tests {
         assert_eq!(editor.cursor_x, 1);
     }
 
+    #[test]
+    fn test_regex_search_backward_and_offsets() {
+        let mut editor = EditorModel::new();
+        editor
+            .buffer
+            .set_content("fn foo() {}\nlet FOO_2 = foo(1);\n");
+        assert_eq!(editor.search_for(r"foo\(\d", false), None);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 12));
+        // Smartcase: lowercase matches FOO too; `?` searches backward.
+        editor.search_for("foo", true);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 4));
+        assert_eq!(editor.search_matches.len(), 3);
+        // `n` keeps the backward direction, `N` reverses it.
+        editor.find_next();
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 3));
+        assert!(editor.find_next().unwrap().contains("hit TOP"));
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 12));
+        editor.find_previous();
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 3));
+        // An end offset lands past the end of the match under the cursor, then
+        // `n` moves on to the next match.
+        editor.search_for("foo/e+1", false);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 6));
+        editor.find_next();
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 7));
+        editor.find_next();
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 15));
+        // A multi-line pattern and a line offset.
+        editor.goto(0, 0);
+        editor.search_for(r"\{\}\nlet/-1", false);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 0));
+        assert!(editor
+            .search_for("(", false)
+            .unwrap()
+            .starts_with("Invalid pattern"));
+        assert!(editor
+            .search_for("zzz", false)
+            .unwrap()
+            .starts_with("Pattern not found"));
+    }
+
+    #[test]
+    fn test_star_and_hash_search_whole_words() {
+        let mut editor = EditorModel::new();
+        editor.buffer.set_content("ab abc Ab ab\n");
+        editor.search_word_under_cursor(false);
+        assert_eq!(editor.cursor_x, 10);
+        editor.search_word_under_cursor(true);
+        assert_eq!(editor.cursor_x, 0);
+        // Off a word, `*` takes the next one on the line.
+        editor.goto(0, 2);
+        assert!(editor.search_word_under_cursor(false).is_some());
+        assert_eq!(editor.cursor_x, 3);
+        assert_eq!(editor.search_query.as_deref(), Some(r"\<abc\>\C"));
+    }
+
+    #[test]
+    fn test_incremental_search_preview_and_cancel() {
+        let mut editor = EditorModel::new();
+        editor.buffer.set_content("one two\nthree two\n");
+        editor.begin_search(false);
+        assert_eq!(editor.mode, EditorMode::Search);
+        editor.preview_search("tw");
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 4));
+        assert_eq!(editor.search_highlights(0, 2), vec![(0, 4, 6), (1, 6, 8)]);
+        editor.preview_search("thr");
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 0));
+        editor.cancel_search();
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 0));
+        assert!(editor.search_highlights(0, 2).is_empty());
+        editor.begin_search(true);
+        assert_eq!(editor.search_prompt_char(), '?');
+        assert_eq!(
+            editor.finish_search("two"),
+            Some("search hit TOP, continuing at BOTTOM".to_string())
+        );
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 6));
+        // A match across a line break is highlighted on both lines.
+        editor.search_for(r"two\nthree", false);
+        assert_eq!(editor.search_highlights(0, 2), vec![(0, 4, 8), (1, 0, 5)]);
+        editor.clear_search_highlight();
+        assert!(editor.search_highlights(0, 2).is_empty());
+    }
+
     // ---- MS2: operators × motions ------------------------------------------
 
     fn model(s: &str) -> EditorModel {
@@ -1624,7 +5154,7 @@ mod tests {
         e.apply_operator(Operator::Yank, Motion::WordForward { big: false }, 1);
         // register holds "foo " (charwise); cursor returns to start of yank
         assert_eq!((e.cursor_y, e.cursor_x), (0, 0));
-        e.paste(true, 1); // paste after the char under cursor
+        e.paste(None, true, 1); // paste after the char under cursor
         assert_eq!(e.buffer.line_text(0), "ffoo oo bar");
     }
 
@@ -1633,7 +5163,7 @@ mod tests {
         let mut e = model("line1\nline2");
         e.cursor_y = 0;
         e.operate_current_lines(Operator::Yank, 1);
-        e.paste(true, 1);
+        e.paste(None, true, 1);
         assert_eq!(
             e.buffer.to_lines(),
             vec![
@@ -1652,14 +5182,15 @@ mod tests {
         e.delete_under_cursor(3); // delete "bcd"
         assert_eq!(e.buffer.line_text(0), "aef");
         assert_eq!(
-            e.register,
+            e.register('"'),
             Some(Register {
                 text: "bcd".to_string(),
-                linewise: false
+                linewise: false,
+                blockwise: false,
             })
         );
         // x then p re-inserts the deleted text (Vim behaviour).
-        e.paste(true, 1);
+        e.paste(None, true, 1);
         assert_eq!(e.buffer.line_text(0), "aebcdf");
     }
 
@@ -1674,24 +5205,32 @@ mod tests {
     #[test]
     fn test_charwise_paste_count() {
         let mut e = model("ab");
-        e.register = Some(Register {
-            text: "X".to_string(),
-            linewise: false,
-        });
+        e.registers.set(
+            '"',
+            Register {
+                text: "X".to_string(),
+                linewise: false,
+                blockwise: false,
+            },
+        );
         e.cursor_x = 0;
-        e.paste(true, 3); // paste "XXX" after 'a'
+        e.paste(None, true, 3); // paste "XXX" after 'a'
         assert_eq!(e.buffer.line_text(0), "aXXXb");
     }
 
     #[test]
     fn test_charwise_paste_before() {
         let mut e = model("abc");
-        e.register = Some(Register {
-            text: "X".to_string(),
-            linewise: false,
-        });
+        e.registers.set(
+            '"',
+            Register {
+                text: "X".to_string(),
+                linewise: false,
+                blockwise: false,
+            },
+        );
         e.cursor_x = 1; // on 'b'
-        e.paste(false, 1); // P inserts before cursor
+        e.paste(None, false, 1); // P inserts before cursor
         assert_eq!(e.buffer.line_text(0), "aXbc");
         assert_eq!(e.cursor_x, 1);
     }
@@ -1744,6 +5283,83 @@ mod tests {
         assert_eq!(e.buffer.line_text(0), "foo baz");
     }
 
+    #[test]
+    fn test_apply_lsp_edits_leave_text_between_untouched() {
+        let mut e = model("fn foo() {\n    body\n}\nfoo();");
+        e.goto(1, 4);
+        e.set_mark('a');
+        // A rename touching the first and last lines only.
+        assert!(e.apply_lsp_edits(vec![
+            (22, 25, "renamed".to_string()),
+            (3, 6, "renamed".to_string()),
+        ]));
+        assert_eq!(e.buffer.line_text(0), "fn renamed() {");
+        assert_eq!(e.buffer.line_text(3), "renamed();");
+        assert_eq!(e.mark('a'), Some((1, 4)));
+        let t = &e.undo_tree().node(e.undo_state().0).transaction;
+        assert_eq!(t.changes.len(), 2);
+        assert!(t.changes.iter().all(|c| c.removed == "foo"));
+        e.undo();
+        assert_eq!(e.buffer.raw_content(), "fn foo() {\n    body\n}\nfoo();\n");
+        assert_eq!(e.mark('a'), Some((1, 4)));
+    }
+
+    #[test]
+    fn test_cursor_at_next_match_types_everywhere_as_one_step() {
+        let mut e = model("foo bar\nfoobar foo\nfoo");
+        assert!(e.add_cursor_at_next_match());
+        // `foobar` is not a whole-word match.
+        assert_eq!((e.cursor_y, e.cursor_x), (1, 7));
+        assert!(e.add_cursor_at_next_match());
+        assert_eq!((e.cursor_y, e.cursor_x), (2, 0));
+        assert!(!e.add_cursor_at_next_match());
+        assert_eq!(e.cursor_count(), 3);
+        e.set_mode(EditorMode::Insert);
+        for c in "my_".chars() {
+            e.at_each_cursor(|m| m.insert_char(c));
+        }
+        e.set_mode(EditorMode::Normal);
+        assert_eq!(
+            e.buffer.raw_content(),
+            "my_foo bar\nfoobar my_foo\nmy_foo\n"
+        );
+        assert_eq!(e.cursors_on_line(1), vec![10]);
+        e.undo();
+        assert_eq!(e.buffer.raw_content(), "foo bar\nfoobar foo\nfoo\n");
+    }
+
+    #[test]
+    fn test_operator_at_each_cursor() {
+        let mut e = model("a one\nb two\nc three");
+        e.start_visual(VisualKind::Block);
+        e.goto(2, 0);
+        assert!(e.split_selection_into_cursors());
+        assert_eq!(e.mode, EditorMode::Normal);
+        assert_eq!(e.cursor_count(), 3);
+        e.at_each_cursor(|m| {
+            m.apply_operator(Operator::Delete, Motion::WordForward { big: false }, 1)
+        });
+        assert_eq!(e.buffer.raw_content(), "one\ntwo\nthree\n");
+        // The register holds the primary cursor's text only.
+        assert_eq!(e.register('"').unwrap().text, "c ");
+        e.undo();
+        assert_eq!(e.buffer.raw_content(), "a one\nb two\nc three\n");
+    }
+
+    #[test]
+    fn test_split_charwise_selection_into_cursors() {
+        let mut e = model("abc\ndef\nghi");
+        e.goto(0, 1);
+        e.start_visual(VisualKind::Char);
+        e.goto(2, 1);
+        assert!(e.split_selection_into_cursors());
+        assert_eq!((e.cursor_y, e.cursor_x), (2, 0));
+        assert_eq!(e.cursors_on_line(0), vec![1]);
+        assert_eq!(e.cursors_on_line(1), vec![0]);
+        e.clear_cursors();
+        assert_eq!(e.cursor_count(), 1);
+    }
+
     #[test]
     fn test_jumplist_back_and_forward() {
         let mut e = model("l0\nl1\nl2\nl3");
@@ -1766,4 +5382,371 @@ mod tests {
         let fwd = e.jump_forward();
         assert_eq!(fwd, Some((Some("a.rs".to_string()), 2, 0)));
     }
+
+    // ---- Visual mode --------------------------------------------------------
+
+    #[test]
+    fn test_visual_charwise_delete_is_inclusive() {
+        let mut e = model("foo bar baz");
+        e.cursor_x = 4;
+        e.start_visual(VisualKind::Char);
+        e.goto(0, 6);
+        assert_eq!(e.selection(), Some(Selection::Chars(4, 7)));
+        assert!(!e.apply_operator_visual(Operator::Delete));
+        assert_eq!(e.buffer.line_text(0), "foo  baz");
+        assert_eq!(e.mode, EditorMode::Normal);
+        assert_eq!(e.register('"').unwrap().text, "bar");
+    }
+
+    #[test]
+    fn test_visual_charwise_backwards_and_across_lines() {
+        let mut e = model("abc\ndef");
+        e.goto(1, 1);
+        e.start_visual(VisualKind::Char);
+        e.goto(0, 2);
+        e.apply_operator_visual(Operator::Delete);
+        assert_eq!(e.buffer.to_lines(), vec!["abf".to_string()]);
+    }
+
+    #[test]
+    fn test_visual_linewise_yank() {
+        let mut e = model("a\nb\nc");
+        e.goto(1, 0);
+        e.start_visual(VisualKind::Line);
+        e.goto(2, 0);
+        e.apply_operator_visual(Operator::Yank);
+        assert_eq!(
+            e.register('"'),
+            Some(Register {
+                text: "b\nc\n".to_string(),
+                linewise: true,
+                blockwise: false,
+            })
+        );
+        assert_eq!((e.cursor_y, e.cursor_x), (1, 0));
+    }
+
+    #[test]
+    fn test_visual_block_uses_display_columns() {
+        // 'あ' is two columns wide: the block over columns 1..=2 covers "bc" on
+        // the first line but only "あ" (cols 0-1) and "い" (cols 2-3) below.
+        let mut e = model("abcd\nあいう");
+        e.goto(0, 1);
+        e.start_visual(VisualKind::Block);
+        e.goto(1, 1);
+        assert_eq!(
+            e.selection(),
+            Some(Selection::Block {
+                top: 0,
+                bottom: 1,
+                left: 1,
+                right: 3,
+            })
+        );
+        e.goto(0, 2);
+        e.goto(1, 0);
+        // Cursor on 'あ' (cols 0-1), anchor on 'b' (col 1): columns 0..=1.
+        assert_eq!(e.selection_span(0), Some((0, 2)));
+        assert_eq!(e.selection_span(1), Some((0, 1)));
+        e.apply_operator_visual(Operator::Delete);
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["cd".to_string(), "いう".to_string()]
+        );
+        let reg = e.register('"').unwrap();
+        assert!(reg.blockwise);
+        assert_eq!(reg.text, "ab\nあ");
+        // The whole block delete is one undo step.
+        e.undo();
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["abcd".to_string(), "あいう".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_blockwise_paste_pads_short_lines() {
+        let mut e = model("abc\nd\nefg");
+        e.registers.set(
+            '"',
+            Register {
+                text: "X\nY\nZ".to_string(),
+                linewise: false,
+                blockwise: true,
+            },
+        );
+        e.goto(0, 1);
+        e.paste(None, true, 1);
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["abXc".to_string(), "d Y".to_string(), "efZg".to_string()]
+        );
+        e.undo();
+        assert_eq!(e.buffer.line_text(1), "d");
+        // `.` pastes the block again, from the new cursor.
+        e.goto(0, 0);
+        e.repeat_last_change();
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["aXbc".to_string(), "dY".to_string(), "eZfg".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_block_insert_replicates_on_every_line_as_one_undo_step() {
+        let mut e = model("abc\nd\nefg");
+        e.goto(0, 1);
+        e.start_visual(VisualKind::Block);
+        e.goto(2, 1);
+        assert!(e.start_block_insert(false));
+        e.set_mode(EditorMode::Insert);
+        e.insert_char('-');
+        e.insert_char('-');
+        e.set_mode(EditorMode::Normal);
+        // "d" is shorter than the block column and is skipped by `I`.
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["a--bc".to_string(), "d".to_string(), "e--fg".to_string()]
+        );
+        e.undo();
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["abc".to_string(), "d".to_string(), "efg".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_block_append_pads_short_lines() {
+        let mut e = model("abc\nd");
+        e.goto(0, 1);
+        e.start_visual(VisualKind::Block);
+        e.goto(1, 0);
+        assert!(e.start_block_insert(true));
+        e.set_mode(EditorMode::Insert);
+        e.insert_char('|');
+        e.set_mode(EditorMode::Normal);
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["ab|c".to_string(), "d |".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_block_change_replaces_block() {
+        let mut e = model("abc\nabc");
+        e.goto(0, 1);
+        e.start_visual(VisualKind::Block);
+        e.goto(1, 1);
+        assert!(e.apply_operator_visual(Operator::Change));
+        e.set_mode(EditorMode::Insert);
+        e.insert_char('X');
+        e.set_mode(EditorMode::Normal);
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["aXc".to_string(), "aXc".to_string()]
+        );
+        e.undo();
+        assert_eq!(
+            e.buffer.to_lines(),
+            vec!["abc".to_string(), "abc".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_visual_paste_replaces_selection_and_swaps_register() {
+        let mut e = model("foo bar");
+        e.registers.set(
+            '"',
+            Register {
+                text: "baz".to_string(),
+                linewise: false,
+                blockwise: false,
+            },
+        );
+        e.goto(0, 4);
+        e.start_visual(VisualKind::Char);
+        e.goto(0, 6);
+        e.paste_visual(None, true);
+        assert_eq!(e.buffer.line_text(0), "foo baz");
+        assert_eq!(e.register('"').unwrap().text, "bar");
+        e.undo();
+        assert_eq!(e.buffer.line_text(0), "foo bar");
+    }
+
+    #[test]
+    fn test_visual_paste_linewise_over_lines() {
+        let mut e = model("a\nb\nc\nd\ne");
+        e.registers.set(
+            '"',
+            Register {
+                text: "x\n".to_string(),
+                linewise: true,
+                blockwise: false,
+            },
+        );
+        e.goto(1, 0);
+        e.start_visual(VisualKind::Line);
+        e.goto(2, 0);
+        e.paste_visual(None, false);
+        assert_eq!(e.buffer.to_lines(), ["a", "x", "d", "e"]);
+        assert_eq!(e.register('"').unwrap().text, "x\n");
+        // `.` puts it over as many lines again.
+        e.goto(2, 0);
+        e.repeat_last_change();
+        assert_eq!(e.buffer.to_lines(), ["a", "x", "x"]);
+        assert_eq!(e.mode, EditorMode::Normal);
+    }
+
+    #[test]
+    fn test_selection_span_marks_selected_line_break() {
+        let mut e = model("ab\ncd");
+        e.goto(0, 1);
+        e.start_visual(VisualKind::Char);
+        e.goto(1, 0);
+        assert_eq!(e.selection_span(0), Some((1, 3)));
+        assert_eq!(e.selection_span(1), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_marks_follow_edits() {
+        let mut e = model("one\ntwo\nthree");
+        e.goto(2, 1);
+        e.set_mark('a');
+        e.goto(0, 0);
+        e.operate_current_lines(Operator::Delete, 1);
+        assert_eq!(e.mark('a'), Some((1, 1)));
+        e.insert_char('X');
+        assert_eq!(e.mark('a'), Some((1, 1)));
+        assert_eq!(e.mark('.'), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_yank_and_insert_set_automatic_marks() {
+        let mut e = model("foo bar baz");
+        e.goto(0, 4);
+        e.apply_operator(Operator::Yank, Motion::WordEnd { big: false }, 1);
+        assert_eq!(e.mark('['), Some((0, 4)));
+        assert_eq!(e.mark(']'), Some((0, 6)));
+        e.set_mode(EditorMode::Insert);
+        e.insert_char('x');
+        e.set_mode(EditorMode::Normal);
+        assert_eq!(e.mark('^'), Some((0, 5)));
+    }
+
+    #[test]
+    fn test_global_mark_only_resolves_in_its_file() {
+        let mut e = model("abc");
+        e.set_filepath("a.rs".to_string());
+        e.goto(0, 2);
+        e.set_mark('A');
+        assert_eq!(e.mark('A'), Some((0, 2)));
+        e.set_filepath("b.rs".to_string());
+        assert_eq!(e.mark('A'), None);
+        assert_eq!(e.mark_file('A'), Some("a.rs".to_string()));
+    }
+
+    #[test]
+    fn test_undo_tree_keeps_branches_reachable() {
+        let mut e = model("");
+        e.insert_char('a');
+        e.set_mode(EditorMode::Normal); // end the coalescing run
+        e.insert_char('b'); // state 2: "ab"
+        e.undo();
+        e.insert_char('c'); // state 3: "ac", a new branch
+        assert_eq!(e.buffer.line_text(0), "ac");
+        // g- walks back in time onto the other branch.
+        e.undo_chronological(1, false);
+        assert_eq!(e.buffer.line_text(0), "ab");
+        e.undo_chronological(1, false);
+        assert_eq!(e.buffer.line_text(0), "a");
+        e.undo_chronological(2, true);
+        assert_eq!(e.buffer.line_text(0), "ac");
+        assert_eq!(e.undo_state(), (3, 3));
+    }
+
+    fn sub(arg: &str) -> Substitution {
+        substitute::parse(arg).unwrap()
+    }
+
+    #[test]
+    fn test_substitute_range_flags_and_single_undo() {
+        let mut e = model("a a\na a\na a");
+        let msg = e.substitute(0, 1, sub("/a/b/")).unwrap();
+        assert_eq!(msg, "2 substitutions on 2 lines");
+        assert_eq!(e.buffer.to_lines(), vec!["b a", "b a", "a a"]);
+        assert_eq!(e.cursor_y, 1);
+        e.substitute(0, 2, sub("/a/c/g")).unwrap();
+        assert_eq!(e.buffer.to_lines(), vec!["b c", "b c", "c c"]);
+        e.undo();
+        assert_eq!(e.buffer.to_lines(), vec!["b a", "b a", "a a"]);
+        assert_eq!(
+            e.substitute(0, 0, sub("/x/y/")),
+            Err("Pattern not found: x".to_string())
+        );
+        assert_eq!(e.substitute(0, 0, sub("/x/y/e")), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_substitute_captures_case_and_line_breaks() {
+        let mut e = model("foo_bar = 1;\nbaz_qux = 2;");
+        e.substitute(0, 1, sub(r"/(\w+)_(\w+)/\u\1\U\2/")).unwrap();
+        assert_eq!(e.buffer.to_lines(), vec!["FooBAR = 1;", "BazQUX = 2;"]);
+        e.substitute(0, 1, sub(r"/ = /\r/")).unwrap();
+        assert_eq!(e.buffer.to_lines(), vec!["FooBAR", "1;", "BazQUX", "2;"]);
+        // An empty pattern is the last search; `\n` joins lines.
+        e.search_for(";", false);
+        e.substitute(0, 3, sub("//!/")).unwrap();
+        e.substitute(0, 3, sub(r"/!\n/ /")).unwrap();
+        assert_eq!(e.buffer.to_lines(), vec!["FooBAR", "1 BazQUX", "2!"]);
+    }
+
+    #[test]
+    fn test_substitute_confirm_and_repeat() {
+        let mut e = model("x x x\nx");
+        let prompt = e.substitute(0, 1, sub("/x/y/gc")).unwrap();
+        assert_eq!(prompt, "replace with y (y/n/a/q/l)?");
+        assert_eq!(e.mode, EditorMode::Confirm);
+        e.confirm_substitute('y');
+        e.confirm_substitute('n');
+        assert_eq!((e.cursor_y, e.cursor_x), (0, 4));
+        let msg = e.confirm_substitute('a');
+        assert_eq!(msg, "3 substitutions on 2 lines");
+        assert_eq!(e.mode, EditorMode::Normal);
+        assert_eq!(e.buffer.to_lines(), vec!["y x y", "y"]);
+        e.undo();
+        assert_eq!(e.buffer.to_lines(), vec!["x x x", "x"]);
+        e.repeat_substitute(0, 1, true).unwrap();
+        assert_eq!(e.mode, EditorMode::Confirm);
+        e.confirm_substitute('q');
+        assert_eq!(e.mode, EditorMode::Normal);
+        assert_eq!(e.buffer.to_lines(), vec!["x x x", "x"]);
+        // `&` drops the flags: first match on the line only, no prompt.
+        e.repeat_substitute(0, 0, false).unwrap();
+        assert_eq!(e.buffer.to_lines(), vec!["y x x", "x"]);
+    }
+
+    #[test]
+    fn test_resolve_ex_ranges() {
+        let mut e = model("fn a\nlet x\nfn b\nlet y\nfn c");
+        e.goto(1, 0);
+        let resolve = |e: &EditorModel, s: &str| {
+            let (range, _) = ex_range::parse(s).unwrap();
+            e.resolve_range(&range.unwrap())
+        };
+        let lines = |start, end| Ok(LineRange { start, end });
+        assert_eq!(resolve(&e, "%"), lines(0, 4));
+        assert_eq!(resolve(&e, ".,$-1"), lines(1, 3));
+        assert_eq!(resolve(&e, "/fn/"), lines(2, 2));
+        assert_eq!(resolve(&e, "?fn?"), lines(0, 0));
+        // `;` searches on from the first address; `,` from the cursor.
+        assert_eq!(resolve(&e, "/fn/;/fn/"), lines(2, 4));
+        assert_eq!(resolve(&e, "/fn/,/fn/"), lines(2, 2));
+        assert_eq!(resolve(&e, "+2"), lines(3, 3));
+        e.set_mark('a');
+        e.goto(3, 0);
+        assert_eq!(resolve(&e, "'a,."), lines(1, 3));
+        assert_eq!(resolve(&e, "3,1"), Err("Backwards range".to_string()));
+        assert_eq!(resolve(&e, "$+1"), Err("Invalid range".to_string()));
+        assert_eq!(resolve(&e, "'b"), Err("Mark not set".to_string()));
+    }
 }
@@ -0,0 +1,198 @@
+use crate::domain::search;
+
+/// The lines an ex command applies to: 0-based and inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    /// The single line `y`.
+    pub fn line(y: usize) -> Self {
+        Self { start: y, end: y }
+    }
+}
+
+/// What an address counts from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// `.`, or an offset with nothing before it (`+2`).
+    Current,
+    /// `$`.
+    Last,
+    /// A line number as typed (1-based; `0` means before the first line).
+    Line(usize),
+    /// `'a`, `'<` …
+    Mark(char),
+    /// `/pat/` or `?pat?`: the next (previous) line matching, wrapping around.
+    /// An empty pattern is the last search pattern.
+    Search { pattern: String, backward: bool },
+}
+
+/// An address and the `+N` / `-N` offsets after it, summed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub base: Address,
+    pub offset: isize,
+}
+
+/// A parsed, unresolved range: one address, or two separated by `,` or by
+/// `;` (which makes the first the current line for resolving the second).
+/// `%` is `1,$`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Option<Position>,
+    pub relative_to_start: bool,
+}
+
+/// Split the range off the front of an ex command line. Returns `None` when
+/// the command has no range, and the rest of the line.
+pub fn parse(input: &str) -> Result<(Option<Range>, &str), String> {
+    let input = input.trim_start();
+    if let Some(rest) = input.strip_prefix('%') {
+        let range = Range {
+            start: Position {
+                base: Address::Line(1),
+                offset: 0,
+            },
+            end: Some(Position {
+                base: Address::Last,
+                offset: 0,
+            }),
+            relative_to_start: false,
+        };
+        return Ok((Some(range), rest));
+    }
+    let (start, rest) = parse_position(input)?;
+    let (relative_to_start, rest) = match rest.chars().next() {
+        Some(',') => (false, &rest[1..]),
+        Some(';') => (true, &rest[1..]),
+        _ => return Ok((start, rest)),
+    };
+    let (end, rest) = parse_position(rest)?;
+    let current = || Position {
+        base: Address::Current,
+        offset: 0,
+    };
+    let range = Range {
+        start: start.map_or_else(current, |r| r.start),
+        end: Some(end.map_or_else(current, |r| r.start)),
+        relative_to_start,
+    };
+    Ok((Some(range), rest))
+}
+
+/// One address with its offsets, as a one-address [`Range`] (or `None` when
+/// there is no address).
+fn parse_position(input: &str) -> Result<(Option<Range>, &str), String> {
+    let input = input.trim_start();
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let mut chars = input.chars();
+    let (base, mut rest) = match chars.next() {
+        Some('.') => (Some(Address::Current), chars.as_str()),
+        Some('$') => (Some(Address::Last), chars.as_str()),
+        Some('0'..='9') => {
+            let n = digits(input);
+            let line = input[..n].parse().map_err(|_| "Invalid range")?;
+            (Some(Address::Line(line)), &input[n..])
+        }
+        Some('\'') => match chars.next() {
+            Some(c) => (Some(Address::Mark(c)), chars.as_str()),
+            None => return Err("Invalid range".to_string()),
+        },
+        Some(delim @ ('/' | '?')) => {
+            let (pattern, rest) = search::split_offset(chars.as_str(), delim);
+            let address = Address::Search {
+                pattern,
+                backward: delim == '?',
+            };
+            (Some(address), rest)
+        }
+        _ => (None, input),
+    };
+    let mut offset: isize = 0;
+    let mut has_offset = false;
+    loop {
+        let trimmed = rest.trim_start();
+        let sign = match trimmed.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => break,
+        };
+        let after = &trimmed[1..];
+        let n = digits(after);
+        let count: isize = if n == 0 {
+            1
+        } else {
+            after[..n].parse().map_err(|_| "Invalid range")?
+        };
+        offset += sign * count;
+        has_offset = true;
+        rest = &after[n..];
+    }
+    let base = match (base, has_offset) {
+        (Some(base), _) => base,
+        (None, true) => Address::Current,
+        (None, false) => return Ok((None, input)),
+    };
+    let range = Range {
+        start: Position { base, offset },
+        end: None,
+        relative_to_start: false,
+    };
+    Ok((Some(range), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(base: Address, offset: isize) -> Position {
+        Position { base, offset }
+    }
+
+    #[test]
+    fn parses_addresses_offsets_and_separators() {
+        let (range, rest) = parse("%s/a/b/").unwrap();
+        let range = range.unwrap();
+        assert_eq!(range.start, pos(Address::Line(1), 0));
+        assert_eq!(range.end, Some(pos(Address::Last, 0)));
+        assert_eq!(rest, "s/a/b/");
+
+        let (range, rest) = parse(".+2;$-1d").unwrap();
+        let range = range.unwrap();
+        assert_eq!(range.start, pos(Address::Current, 2));
+        assert_eq!(range.end, Some(pos(Address::Last, -1)));
+        assert!(range.relative_to_start);
+        assert_eq!(rest, "d");
+
+        let (range, rest) = parse("'<,'>s/x/y/").unwrap();
+        let range = range.unwrap();
+        assert_eq!(range.start, pos(Address::Mark('<'), 0));
+        assert_eq!(range.end, Some(pos(Address::Mark('>'), 0)));
+        assert_eq!(rest, "s/x/y/");
+    }
+
+    #[test]
+    fn parses_search_addresses_and_defaults() {
+        let (range, rest) = parse(r"/a\/b/+1,?c?s").unwrap();
+        let range = range.unwrap();
+        let search = |pattern: &str, backward| Address::Search {
+            pattern: pattern.to_string(),
+            backward,
+        };
+        assert_eq!(range.start, pos(search("a/b", false), 1));
+        assert_eq!(range.end, Some(pos(search("c", true), 0)));
+        assert_eq!(rest, "s");
+
+        // A missing address is the current line; a bare offset counts from it.
+        let (range, _) = parse(",5").unwrap();
+        assert_eq!(range.unwrap().start, pos(Address::Current, 0));
+        let (range, _) = parse("--").unwrap();
+        assert_eq!(range.unwrap().start, pos(Address::Current, -2));
+        assert_eq!(parse("write").unwrap(), (None, "write"));
+        assert!(parse("'").is_err());
+    }
+}
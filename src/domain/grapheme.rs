@@ -1,4 +1,5 @@
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 /// Char offsets of every grapheme-cluster boundary in `line`, including 0 and
 /// the end. Horizontal cursor motion steps between these so a combining mark or
@@ -56,6 +57,56 @@ pub fn prev_n(line: &str, char_idx: usize, count: usize) -> usize {
     x
 }
 
+/// Terminal display width of `line` (wide CJK = 2, combining = 0).
+pub fn width(line: &str) -> usize {
+    UnicodeWidthStr::width(line)
+}
+
+/// Display column at which char offset `char_idx` of `line` starts.
+pub fn display_col(line: &str, char_idx: usize) -> usize {
+    let prefix: String = line.chars().take(char_idx).collect();
+    width(&prefix)
+}
+
+/// Char offset of the first grapheme starting at or after display column `col`
+/// (the line length when the line is narrower than `col`).
+pub fn col_to_char(line: &str, col: usize) -> usize {
+    let mut c = 0;
+    let mut chars = 0;
+    for g in line.graphemes(true) {
+        if c >= col {
+            return chars;
+        }
+        c += width(g);
+        chars += g.chars().count();
+    }
+    chars
+}
+
+/// Half-open char range of the graphemes overlapping the inclusive display
+/// columns `left..=right`. A wide character straddling either edge is taken
+/// whole. `None` when the line ends before `left`.
+pub fn col_span(line: &str, left: usize, right: usize) -> Option<(usize, usize)> {
+    let mut col = 0;
+    let mut chars = 0;
+    let mut start = None;
+    let mut end = 0;
+    for g in line.graphemes(true) {
+        let w = width(g).max(1);
+        let n = g.chars().count();
+        if col > right {
+            break;
+        }
+        if col + w > left {
+            start.get_or_insert(chars);
+            end = chars + n;
+        }
+        col += w;
+        chars += n;
+    }
+    start.map(|s| (s, end))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,4 +125,22 @@ mod tests {
         assert_eq!(next_boundary("a\u{310}b", 0), 2);
         assert_eq!(prev_boundary("a\u{310}b", 2), 0);
     }
+
+    #[test]
+    fn display_columns_count_wide_chars_twice() {
+        assert_eq!(display_col("aあb", 2), 3);
+        assert_eq!(col_to_char("aあb", 1), 1);
+        // Column 2 is the right half of 'あ'; the next grapheme starts at 3.
+        assert_eq!(col_to_char("aあb", 2), 2);
+        assert_eq!(col_to_char("ab", 5), 2);
+    }
+
+    #[test]
+    fn col_span_takes_straddling_wide_chars_whole() {
+        assert_eq!(col_span("abcdef", 1, 3), Some((1, 4)));
+        // Columns 1..=1 cut through the right half of 'あ' (cols 0-1).
+        assert_eq!(col_span("あいう", 1, 1), Some((0, 1)));
+        assert_eq!(col_span("あいう", 1, 2), Some((0, 2)));
+        assert_eq!(col_span("ab", 3, 4), None);
+    }
 }
@@ -0,0 +1,172 @@
+/// How a number is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Optionally signed with a `-` right before it.
+    Decimal,
+    /// `0x1f` / `0X1F`.
+    Hex,
+    /// `0b101` / `0B101`.
+    Binary,
+    /// `0755`: a leading zero and only octal digits.
+    Octal,
+}
+
+/// A number in a line, by char index: `[start, end)` spans the sign or
+/// prefix too (`-`, `0x`, `0b`, or the leading `0` of an octal number);
+/// `digits` starts past it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Number {
+    start: usize,
+    digits: usize,
+    end: usize,
+    format: Format,
+}
+
+/// The numbers of a line, left to right.
+fn numbers(chars: &[char]) -> Vec<Number> {
+    let run = |from: usize, f: fn(&char) -> bool| {
+        from + chars[from..].iter().take_while(|c| f(c)).count()
+    };
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let prefix = chars.get(i + 1).map(char::to_ascii_lowercase);
+        let after = chars.get(i + 2);
+        let number = match (chars[i], prefix, after) {
+            ('0', Some('x'), Some(c)) if c.is_ascii_hexdigit() => Number {
+                start: i,
+                digits: i + 2,
+                end: run(i + 2, char::is_ascii_hexdigit),
+                format: Format::Hex,
+            },
+            ('0', Some('b'), Some('0' | '1')) => Number {
+                start: i,
+                digits: i + 2,
+                end: run(i + 2, |c| matches!(c, '0' | '1')),
+                format: Format::Binary,
+            },
+            _ => {
+                let end = run(i, char::is_ascii_digit);
+                let octal = chars[i] == '0'
+                    && end - i > 1
+                    && chars[i..end].iter().all(|c| ('0'..='7').contains(c));
+                let negative = !octal && i > 0 && chars[i - 1] == '-';
+                Number {
+                    start: if negative { i - 1 } else { i },
+                    digits: if octal { i + 1 } else { i },
+                    end,
+                    format: if octal {
+                        Format::Octal
+                    } else {
+                        Format::Decimal
+                    },
+                }
+            }
+        };
+        i = number.end;
+        found.push(number);
+    }
+    found
+}
+
+/// Add `delta` to the number under or after char `from` of `line`, as
+/// `Ctrl-a` / `Ctrl-x` do. Returns the chars `[start, end)` to replace and
+/// the new text.
+///
+/// Hex, binary and octal numbers are unsigned and wrap around; they keep
+/// their width (padding with zeros) and hex numbers the case of their last
+/// letter. A decimal number is negative after a `-`, and keeps leading zeros
+/// it had.
+pub fn increment(line: &str, from: usize, delta: i128) -> Option<(usize, usize, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let number = numbers(&chars).into_iter().find(|n| n.end > from)?;
+    let digits: String = chars[number.digits..number.end].iter().collect();
+    let width = digits.len();
+    let text = match number.format {
+        Format::Decimal => {
+            let magnitude = digits.parse::<i128>().unwrap_or(i128::MAX);
+            let value = if number.start < number.digits {
+                -magnitude
+            } else {
+                magnitude
+            };
+            let new = value.saturating_add(delta);
+            let pad = if digits.starts_with('0') { width } else { 0 };
+            let sign = if new < 0 { "-" } else { "" };
+            format!("{}{:0pad$}", sign, new.unsigned_abs(), pad = pad)
+        }
+        format => {
+            let radix = match format {
+                Format::Hex => 16,
+                Format::Binary => 2,
+                _ => 8,
+            };
+            let value = u64::from_str_radix(&digits, radix).unwrap_or(u64::MAX);
+            let new = (value as i128 + delta).rem_euclid(1 << 64) as u64;
+            let prefix: String = chars[number.start..number.digits].iter().collect();
+            let upper = digits
+                .chars()
+                .rev()
+                .find(|c| c.is_ascii_alphabetic())
+                .is_some_and(|c| c.is_ascii_uppercase());
+            let body = match format {
+                Format::Hex if upper => format!("{:0w$X}", new, w = width),
+                Format::Hex => format!("{:0w$x}", new, w = width),
+                Format::Binary => format!("{:0w$b}", new, w = width),
+                _ => format!("{:0w$o}", new, w = width),
+            };
+            prefix + &body
+        }
+    };
+    Some((number.start, number.end, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inc(line: &str, from: usize, delta: i128) -> Option<String> {
+        increment(line, from, delta).map(|(s, e, text)| {
+            let chars: Vec<char> = line.chars().collect();
+            let before: String = chars[..s].iter().collect();
+            let after: String = chars[e..].iter().collect();
+            before + &text + &after
+        })
+    }
+
+    #[test]
+    fn finds_the_number_under_or_after_the_cursor() {
+        assert_eq!(inc("x = 9;", 0, 1).as_deref(), Some("x = 10;"));
+        assert_eq!(inc("a1 b2", 2, 1).as_deref(), Some("a1 b3"));
+        assert_eq!(inc("a12", 2, 5).as_deref(), Some("a17"));
+        assert_eq!(inc("abc", 0, 1), None);
+        assert_eq!(inc("1 abc", 2, 1), None);
+    }
+
+    #[test]
+    fn decimal_signs_and_widths() {
+        assert_eq!(inc("-1", 0, 3).as_deref(), Some("2"));
+        assert_eq!(inc("x-1", 0, -2).as_deref(), Some("x-3"));
+        assert_eq!(inc("0", 0, -1).as_deref(), Some("-1"));
+        assert_eq!(inc("009", 0, 1).as_deref(), Some("010"));
+        assert_eq!(inc("99", 0, 1).as_deref(), Some("100"));
+    }
+
+    #[test]
+    fn hex_binary_and_octal_keep_width_and_case() {
+        assert_eq!(inc("0x0f", 0, 1).as_deref(), Some("0x10"));
+        assert_eq!(inc("0X00FE", 3, 1).as_deref(), Some("0X00FF"));
+        assert_eq!(inc("0xaF", 0, 1).as_deref(), Some("0xB0"));
+        assert_eq!(inc("0x00", 0, -1).as_deref(), Some("0xffffffffffffffff"));
+        assert_eq!(inc("0b0111", 0, 1).as_deref(), Some("0b1000"));
+        assert_eq!(inc("0755", 0, 1).as_deref(), Some("0756"));
+        assert_eq!(inc("077", 0, 1).as_deref(), Some("0100"));
+        // Not octal with an 8 or 9; octal is unsigned.
+        assert_eq!(inc("089", 0, 1).as_deref(), Some("090"));
+        assert_eq!(inc("-07", 0, 1).as_deref(), Some("-010"));
+    }
+}
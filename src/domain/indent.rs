@@ -0,0 +1,66 @@
+use crate::domain::transaction::ByteEdit;
+use ropey::Rope;
+use std::ops::Range;
+
+/// Works out from the syntax how deep lines should be indented (smart
+/// indent). The editor model holds one when the file's language has a
+/// parser; see `infrastructure::syntax_worker::SyntaxIndenter`.
+pub trait Indenter {
+    /// The indent level (in shiftwidths) of each line in `lines` of `text`;
+    /// `None` where the syntax does not say (inside a string or comment).
+    /// `revision` is the model's edit revision of `text`.
+    fn indent_levels(
+        &mut self,
+        text: &Rope,
+        revision: u64,
+        lines: Range<usize>,
+    ) -> Vec<Option<usize>>;
+
+    /// `edit` took the text to `revision`.
+    fn edit(&mut self, edit: &ByteEdit, revision: u64);
+}
+
+pub fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Chars of leading blanks in `line`.
+pub fn leading_blanks(line: &str) -> usize {
+    line.chars().take_while(|&c| is_blank(c)).count()
+}
+
+/// Columns the leading blanks of `line` take, with tab stops every
+/// `tabstop`.
+pub fn indent_width(line: &str, tabstop: usize) -> usize {
+    line.chars()
+        .take_while(|&c| is_blank(c))
+        .fold(0, |col, c| match c {
+            '\t' => (col / tabstop + 1) * tabstop,
+            _ => col + 1,
+        })
+}
+
+/// Blanks making an indent `width` columns wide: spaces with `expandtab`,
+/// else as many tabs as fit, padded with spaces.
+pub fn indent_text(width: usize, tabstop: usize, expandtab: bool) -> String {
+    if expandtab {
+        " ".repeat(width)
+    } else {
+        "\t".repeat(width / tabstop) + &" ".repeat(width % tabstop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths_count_tabs_to_the_next_stop() {
+        assert_eq!(indent_width("  \tx", 4), 4);
+        assert_eq!(indent_width("\t  x", 8), 10);
+        assert_eq!(indent_width("x  ", 8), 0);
+        assert_eq!(leading_blanks(" \t x"), 3);
+        assert_eq!(indent_text(10, 8, false), "\t  ");
+        assert_eq!(indent_text(3, 8, true), "   ");
+    }
+}
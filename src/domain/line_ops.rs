@@ -0,0 +1,209 @@
+use crate::domain::grapheme;
+use crate::domain::search;
+use regex::Regex;
+
+/// The options of `:sort[!] [i][n][u][r] [/pattern/]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortOptions {
+    /// `!`: reverse the order.
+    pub reverse: bool,
+    /// `i`: ignore case.
+    pub ignore_case: bool,
+    /// `n`: sort on the first decimal number in the line.
+    pub numeric: bool,
+    /// `u`: keep only the first of a run of lines that compare equal.
+    pub unique: bool,
+    /// `r`: sort on the text the pattern matches instead of what follows it.
+    pub use_match: bool,
+    pub pattern: Option<String>,
+}
+
+/// Parse the argument of `:sort` (the `!` already taken off): flag letters
+/// and an optional `/pattern/` in any order.
+pub fn parse_sort_args(arg: &str, reverse: bool) -> Result<SortOptions, String> {
+    let mut opts = SortOptions {
+        reverse,
+        ..SortOptions::default()
+    };
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' => {}
+            'i' => opts.ignore_case = true,
+            'n' => opts.numeric = true,
+            'u' => opts.unique = true,
+            'r' => opts.use_match = true,
+            c if !(c.is_alphanumeric() || c == '\\' || c == '"') => {
+                let (pattern, rest) = search::split_offset(chars.as_str(), c);
+                opts.pattern = Some(pattern);
+                chars = rest.chars();
+            }
+            c => return Err(format!("Invalid argument: {}", c)),
+        }
+    }
+    Ok(opts)
+}
+
+/// What a line is sorted on: nothing (a line the pattern, or with `n` a
+/// number, is missing from sorts first and keeps its place), a number or
+/// text.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    None,
+    Number(i128),
+    Text(String),
+}
+
+fn sort_key(line: &str, opts: &SortOptions, pattern: Option<&Regex>, number: &Regex) -> Key {
+    let text = match pattern {
+        Some(re) => match re.find(line) {
+            Some(m) if opts.use_match => m.as_str(),
+            Some(m) => &line[m.end()..],
+            None => return Key::None,
+        },
+        None => line,
+    };
+    if opts.numeric {
+        return match number.find(text).and_then(|m| m.as_str().parse().ok()) {
+            Some(n) => Key::Number(n),
+            None => Key::None,
+        };
+    }
+    if opts.ignore_case {
+        Key::Text(text.to_lowercase())
+    } else {
+        Key::Text(text.to_string())
+    }
+}
+
+/// Sort `lines` as `:sort` does. The sort is stable; `!` reverses the
+/// result, so lines without a key end up last, in reverse order.
+pub fn sort_lines(lines: &[String], opts: &SortOptions, pattern: Option<&Regex>) -> Vec<String> {
+    let number = Regex::new(r"-?\d+").expect("valid regex");
+    let mut keyed: Vec<(Key, &String)> = lines
+        .iter()
+        .map(|l| (sort_key(l, opts, pattern, &number), l))
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    if opts.reverse {
+        keyed.reverse();
+    }
+    if opts.unique {
+        keyed.dedup_by(|b, a| a.0 == b.0);
+    }
+    keyed.into_iter().map(|(_, l)| l.clone()).collect()
+}
+
+/// What goes between two lines being joined: the text of the line so far
+/// (ending in `last`) and the next one with its indent removed. A space,
+/// unless the line so far is empty or ends in whitespace, or the next one is
+/// empty or starts with `)`.
+pub fn join_separator(last: Option<char>, next: &str) -> &'static str {
+    match (last, next.chars().next()) {
+        (None, _) | (_, None) | (_, Some(')')) => "",
+        (Some(c), _) if c.is_whitespace() => "",
+        _ => " ",
+    }
+}
+
+/// `:retab` one line: rewrite each run of blanks that holds a tab (with
+/// `all`, every run of two or more blanks) for tab stops every `new_ts`
+/// columns, as spaces (`expandtab`) or as tabs padded with spaces. Columns
+/// are measured with tab stops every `old_ts`.
+pub fn retab_line(line: &str, old_ts: usize, new_ts: usize, expandtab: bool, all: bool) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ' ' && c != '\t' {
+            out.push(c);
+            col += grapheme::width(c.encode_utf8(&mut [0; 4]));
+            continue;
+        }
+        let start = col;
+        let mut run = String::new();
+        let mut next = Some(c);
+        while let Some(b) = next {
+            run.push(b);
+            col = if b == '\t' {
+                (col / old_ts + 1) * old_ts
+            } else {
+                col + 1
+            };
+            next = chars.next_if(|&b| b == ' ' || b == '\t');
+        }
+        let rewrite = run.contains('\t') || (all && run.chars().count() > 1);
+        if !rewrite {
+            out.push_str(&run);
+            continue;
+        }
+        if expandtab {
+            out.extend(std::iter::repeat_n(' ', col - start));
+        } else {
+            let mut at = start;
+            while (at / new_ts + 1) * new_ts <= col {
+                out.push('\t');
+                at = (at / new_ts + 1) * new_ts;
+            }
+            out.extend(std::iter::repeat_n(' ', col - at));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn sorted(v: &[&str], arg: &str, reverse: bool) -> Vec<String> {
+        let opts = parse_sort_args(arg, reverse).unwrap();
+        let re = opts.pattern.as_ref().map(|p| Regex::new(p).unwrap());
+        sort_lines(&lines(v), &opts, re.as_ref())
+    }
+
+    #[test]
+    fn sorts_by_text_number_and_pattern() {
+        assert_eq!(sorted(&["b", "B", "a"], "", false), lines(&["B", "a", "b"]));
+        assert_eq!(sorted(&["b", "B", "a"], "i", true), lines(&["B", "b", "a"]));
+        assert_eq!(
+            sorted(&["x10", "none", "x9", "x-1"], "n", false),
+            lines(&["none", "x-1", "x9", "x10"])
+        );
+        assert_eq!(
+            sorted(&["a", "A", "b", "a"], "ui", false),
+            lines(&["a", "b"])
+        );
+        // Sort on what follows the match; `r` on the match itself.
+        assert_eq!(
+            sorted(&["2:b", "1:a", "-"], "/\\d:/", false),
+            lines(&["-", "1:a", "2:b"])
+        );
+        assert_eq!(
+            sorted(&["b3", "a1", "c2"], r"r /\d/", false),
+            lines(&["a1", "c2", "b3"])
+        );
+        assert!(parse_sort_args("z", false).is_err());
+    }
+
+    #[test]
+    fn join_separators() {
+        assert_eq!(join_separator(Some('a'), "b"), " ");
+        assert_eq!(join_separator(Some(' '), "b"), "");
+        assert_eq!(join_separator(None, "b"), "");
+        assert_eq!(join_separator(Some('a'), ")"), "");
+        assert_eq!(join_separator(Some('a'), ""), "");
+    }
+
+    #[test]
+    fn retabs_blank_runs() {
+        assert_eq!(retab_line("\tx\ty", 8, 4, true, false), "        x       y");
+        assert_eq!(retab_line("\t  x", 4, 4, false, false), "\t  x");
+        assert_eq!(retab_line("        x", 8, 4, false, true), "\t\tx");
+        assert_eq!(retab_line("      x", 8, 4, false, false), "      x");
+        assert_eq!(retab_line("  \tx", 4, 8, false, false), "    x");
+    }
+}
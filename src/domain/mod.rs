@@ -1,6 +1,19 @@
+pub mod comment;
 pub mod editor_model;
+pub mod ex_range;
 pub mod grapheme;
+pub mod increment;
+pub mod indent;
+pub mod line_ops;
 pub mod motion;
+pub mod options;
+pub mod pairs;
+pub mod reflow;
+pub mod register;
+pub mod search;
+pub mod snippet;
+pub mod substitute;
 pub mod text_buffer;
 pub mod text_object;
 pub mod transaction;
+pub mod undo_tree;
@@ -66,6 +66,31 @@ pub enum Motion {
     },
     /// `%` — jump to the bracket matching the one at/after the cursor.
     MatchPair,
+    /// `'x` (linewise, to the first non-blank) / `` `x `` (charwise, exact) —
+    /// jump to a mark. Marks live in the editor model, which resolves this
+    /// motion itself; [`compute`] leaves the cursor in place.
+    Mark {
+        name: char,
+        linewise: bool,
+    },
+}
+
+impl Motion {
+    /// Whether ending where it started means the motion failed (`h` at column
+    /// 0, `fx` with no `x`), as opposed to absolute motions like `0` or `G`
+    /// that may legitimately stay put. A failed motion aborts a running macro.
+    pub fn fails_in_place(self) -> bool {
+        !matches!(
+            self,
+            Motion::LineStart
+                | Motion::FirstNonBlank
+                | Motion::LineEnd
+                | Motion::FileStart
+                | Motion::FileEnd
+                | Motion::GotoLine(_)
+                | Motion::Mark { .. }
+        )
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -338,6 +363,12 @@ pub fn compute(buf: &TextBuffer, y: usize, x: usize, motion: Motion, count: usiz
             forward,
         } => find_char(buf, y, x, target, till, forward, count),
         Motion::MatchPair => match_pair(buf, y, x),
+        Motion::Mark { .. } => Target {
+            y,
+            x,
+            kind: MotionKind::Charwise,
+            inclusive: false,
+        },
     }
 }
 
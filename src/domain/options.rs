@@ -0,0 +1,183 @@
+/// Editor options, changed with `:set`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// `ts`: columns between tab stops.
+    pub tabstop: usize,
+    /// `sw`: columns one level of indent takes (`>>`, `<<`, smart indent);
+    /// 0 means `tabstop`.
+    pub shiftwidth: usize,
+    /// `et`: indent (and `:retab`) with spaces rather than tabs.
+    pub expandtab: bool,
+    /// `ai`: a new line takes the indent of the line above.
+    pub autoindent: bool,
+    /// `si`: a new line, a closing bracket typed first on its line and `=`
+    /// take the indent the syntax calls for, when it is known.
+    pub smartindent: bool,
+    /// `ap`: typing an opening bracket or quote in Insert mode adds its
+    /// closing one, outside strings and comments.
+    pub autopairs: bool,
+    /// `tw`: the width `gq` fills lines to; past it, typing in Insert mode
+    /// breaks the line. 0 turns the breaking off (`gq` then uses 79).
+    pub textwidth: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tabstop: 8,
+            shiftwidth: 4,
+            expandtab: true,
+            autoindent: true,
+            smartindent: true,
+            autopairs: false,
+            textwidth: 0,
+        }
+    }
+}
+
+/// An option's long and short names.
+const NAMES: &[(&str, &str)] = &[
+    ("tabstop", "ts"),
+    ("shiftwidth", "sw"),
+    ("expandtab", "et"),
+    ("autoindent", "ai"),
+    ("smartindent", "si"),
+    ("autopairs", "ap"),
+    ("textwidth", "tw"),
+];
+
+impl Options {
+    /// The columns of one level of indent.
+    pub fn shift_width(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+
+    /// The width `gq` fills lines to.
+    pub fn format_width(&self) -> usize {
+        if self.textwidth == 0 {
+            79
+        } else {
+            self.textwidth
+        }
+    }
+
+    /// Apply the arguments of `:set`, each `name`, `noname`, `invname`,
+    /// `name!`, `name=value` or `name?`. Returns what to show: the values
+    /// asked for (all of them for no arguments), or nothing.
+    pub fn set(&mut self, args: &str) -> Result<Option<String>, String> {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        if args.is_empty() {
+            let all: Vec<String> = NAMES.iter().map(|(name, _)| self.show(name)).collect();
+            return Ok(Some(all.join("  ")));
+        }
+        let mut shown = Vec::new();
+        for arg in args {
+            if let Some((name, value)) = arg.split_once('=') {
+                let value: usize = value
+                    .parse()
+                    .map_err(|_| format!("Invalid argument: {}", arg))?;
+                match long_name(name) {
+                    Some("tabstop") if value > 0 => self.tabstop = value,
+                    Some("shiftwidth") => self.shiftwidth = value,
+                    Some("textwidth") => self.textwidth = value,
+                    Some(_) => return Err(format!("Invalid argument: {}", arg)),
+                    None => return Err(format!("Unknown option: {}", name)),
+                }
+                continue;
+            }
+            if let Some(name) = arg.strip_suffix('?') {
+                let name = long_name(name).ok_or_else(|| format!("Unknown option: {}", name))?;
+                shown.push(self.show(name));
+                continue;
+            }
+            let (name, value) = if let Some(name) = arg.strip_suffix('!') {
+                (name, None)
+            } else if let Some(name) = arg.strip_prefix("inv") {
+                (name, None)
+            } else if let Some(name) = arg.strip_prefix("no").filter(|n| long_name(n).is_some()) {
+                (name, Some(false))
+            } else {
+                (arg, Some(true))
+            };
+            let flag = match long_name(name) {
+                Some("expandtab") => &mut self.expandtab,
+                Some("autoindent") => &mut self.autoindent,
+                Some("smartindent") => &mut self.smartindent,
+                Some("autopairs") => &mut self.autopairs,
+                // A number option named alone shows its value.
+                Some(name) if value == Some(true) => {
+                    shown.push(self.show(name));
+                    continue;
+                }
+                Some(_) => return Err(format!("Invalid argument: {}", arg)),
+                None => return Err(format!("Unknown option: {}", name)),
+            };
+            *flag = value.unwrap_or(!*flag);
+        }
+        Ok((!shown.is_empty()).then(|| shown.join("  ")))
+    }
+
+    /// `name=value`, or `name` / `noname` for a flag.
+    fn show(&self, name: &str) -> String {
+        let flag = |name: &str, on: bool| format!("{}{}", if on { "" } else { "no" }, name);
+        match name {
+            "tabstop" => format!("tabstop={}", self.tabstop),
+            "shiftwidth" => format!("shiftwidth={}", self.shiftwidth),
+            "textwidth" => format!("textwidth={}", self.textwidth),
+            "expandtab" => flag(name, self.expandtab),
+            "autoindent" => flag(name, self.autoindent),
+            "autopairs" => flag(name, self.autopairs),
+            _ => flag(name, self.smartindent),
+        }
+    }
+}
+
+fn long_name(name: &str) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(long, short)| name == *long || name == *short)
+        .map(|(long, _)| *long)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_numbers_and_flags() {
+        let mut o = Options::default();
+        assert_eq!(o.set("sw=2 ts=4 noet"), Ok(None));
+        assert_eq!((o.shiftwidth, o.tabstop, o.expandtab), (2, 4, false));
+        assert_eq!(o.set("et! noai"), Ok(None));
+        assert!(o.expandtab && !o.autoindent);
+        assert_eq!(o.set("invai si"), Ok(None));
+        assert!(o.autoindent && o.smartindent);
+        assert_eq!(o.set("ap"), Ok(None));
+        assert!(o.autopairs);
+        o.set("shiftwidth=0").unwrap();
+        assert_eq!(o.shift_width(), 4);
+        assert_eq!(o.format_width(), 79);
+        o.set("tw=60").unwrap();
+        assert_eq!(o.format_width(), 60);
+    }
+
+    #[test]
+    fn shows_values_and_rejects_bad_arguments() {
+        let mut o = Options::default();
+        assert_eq!(o.set("sw?").unwrap().as_deref(), Some("shiftwidth=4"));
+        assert_eq!(o.set("et?").unwrap().as_deref(), Some("expandtab"));
+        assert_eq!(o.set("ts").unwrap().as_deref(), Some("tabstop=8"));
+        assert!(o
+            .set("")
+            .unwrap()
+            .unwrap()
+            .starts_with("tabstop=8  shiftwidth=4"));
+        assert_eq!(o.set("ts=0"), Err("Invalid argument: ts=0".to_string()));
+        assert_eq!(o.set("et=1"), Err("Invalid argument: et=1".to_string()));
+        assert_eq!(o.set("bogus"), Err("Unknown option: bogus".to_string()));
+    }
+}
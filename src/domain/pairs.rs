@@ -0,0 +1,101 @@
+use crate::domain::text_object::TextObject;
+use crate::domain::transaction::ByteEdit;
+use ropey::Rope;
+
+/// Tells whether a place in the text is inside a string, character literal
+/// or comment, where auto-pairs leave typing alone. The editor model holds
+/// one when the file's language has a parser; see
+/// `infrastructure::syntax_worker::SyntaxLiterals`.
+pub trait Literals {
+    /// Whether byte `at` of `text` (a cursor before that byte) is inside a
+    /// literal or comment. `revision` is the model's edit revision of `text`.
+    fn in_literal(&mut self, text: &Rope, revision: u64, at: usize) -> bool;
+
+    /// `edit` took the text to `revision`.
+    fn edit(&mut self, edit: &ByteEdit, revision: u64);
+}
+
+/// The brackets and quotes auto-pairs closes, as `(open, close)`.
+const PAIRS: &[(char, char)] = &[
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('"', '"'),
+    ('\'', '\''),
+    ('`', '`'),
+];
+
+/// The closing half of an opening bracket or quote `c`.
+pub fn closer(c: char) -> Option<char> {
+    PAIRS
+        .iter()
+        .find(|(open, _)| *open == c)
+        .map(|(_, close)| *close)
+}
+
+/// Whether `c` closes a pair (a quote closes itself).
+pub fn is_closer(c: char) -> bool {
+    PAIRS.iter().any(|(_, close)| *close == c)
+}
+
+pub fn is_quote(c: char) -> bool {
+    matches!(c, '"' | '\'' | '`')
+}
+
+/// The text object whose delimiters `ds` / `cs` take for `c`: a bracket
+/// pair for either bracket (or the aliases `b`, `B`, `r`, `a`), else a quote.
+pub fn surround_object(c: char) -> Option<TextObject> {
+    match c {
+        '(' | ')' | 'b' => Some(TextObject::Pair('(', ')')),
+        '{' | '}' | 'B' => Some(TextObject::Pair('{', '}')),
+        '[' | ']' | 'r' => Some(TextObject::Pair('[', ']')),
+        '<' | '>' | 'a' => Some(TextObject::Pair('<', '>')),
+        c if is_quote(c) => Some(TextObject::Quoted(c)),
+        _ => None,
+    }
+}
+
+/// Whether `c` is an opening bracket, which `ys` / `cs` pad with a space
+/// inside and `ds` / `cs` trim the blanks inside of.
+pub fn pads(c: char) -> bool {
+    matches!(c, '(' | '{' | '[' | '<')
+}
+
+/// The delimiters `ys` / `cs` put around text for `c`: a bracket pair (with a
+/// space inside for an opening bracket), or `c` on both sides for any other
+/// punctuation.
+pub fn surround_delimiters(c: char) -> Option<(String, String)> {
+    let (open, close) = match c {
+        '(' | ')' | 'b' => ('(', ')'),
+        '{' | '}' | 'B' => ('{', '}'),
+        '[' | ']' | 'r' => ('[', ']'),
+        '<' | '>' | 'a' => ('<', '>'),
+        c if c.is_ascii_punctuation() => (c, c),
+        _ => return None,
+    };
+    Some(if pads(c) {
+        (format!("{} ", open), format!(" {}", close))
+    } else {
+        (open.to_string(), close.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimiters_for_surround_chars() {
+        let pair = |c| surround_delimiters(c).map(|(o, e)| o + "|" + &e);
+        assert_eq!(pair(')').as_deref(), Some("(|)"));
+        assert_eq!(pair('(').as_deref(), Some("( | )"));
+        assert_eq!(pair('B').as_deref(), Some("{|}"));
+        assert_eq!(pair('"').as_deref(), Some("\"|\""));
+        assert_eq!(pair('*').as_deref(), Some("*|*"));
+        assert_eq!(pair('x'), None);
+        assert_eq!(surround_object('r'), Some(TextObject::Pair('[', ']')));
+        assert_eq!(surround_object('\''), Some(TextObject::Quoted('\'')));
+        assert_eq!(closer('{'), Some('}'));
+        assert!(is_closer('"') && !is_closer('('));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::domain::{grapheme, indent};
+
+/// Comment leaders kept at the start of every line of a reflowed paragraph,
+/// longest first.
+const LEADERS: &[&str] = &["//!", "///", "//"];
+
+/// A line taken apart: its head (indent, comment leader and the blanks after
+/// it), the leader alone, and the text after the head.
+struct Line<'a> {
+    head: &'a str,
+    leader: &'a str,
+    body: &'a str,
+}
+
+fn split(line: &str) -> Line<'_> {
+    let indent = line.len() - line.trim_start_matches(indent::is_blank).len();
+    let rest = &line[indent..];
+    let leader = LEADERS
+        .iter()
+        .find(|leader| rest.starts_with(**leader))
+        .copied()
+        .unwrap_or("");
+    let after = &rest[leader.len()..];
+    let head = line.len() - after.trim_start_matches(indent::is_blank).len();
+    Line {
+        head: &line[..head],
+        leader,
+        body: line[head..].trim_end(),
+    }
+}
+
+/// Chars of the list-item marker `body` starts with (`- `, `* `, `+ `, `1. `,
+/// `2) `), with the blanks after it.
+fn list_marker(body: &str) -> Option<usize> {
+    let digits = body.chars().take_while(char::is_ascii_digit).count();
+    let marker = match body.chars().nth(digits)? {
+        '.' | ')' if digits > 0 => digits + 1,
+        '-' | '*' | '+' if digits == 0 => 1,
+        _ => return None,
+    };
+    let blanks = body
+        .chars()
+        .skip(marker)
+        .take_while(|c| indent::is_blank(*c))
+        .count();
+    (blanks > 0).then_some(marker + blanks)
+}
+
+/// Display columns `text` takes from the start of a line.
+fn width(text: &str, tabstop: usize) -> usize {
+    indent::indent_width(text, tabstop) + grapheme::width(text.trim_start_matches(indent::is_blank))
+}
+
+/// The head a line continuing `line`'s paragraph starts with: the same
+/// indent and comment leader, and below a list item, blanks lining up with
+/// its text.
+pub fn continuation(line: &str) -> String {
+    let line = split(line);
+    let marker = list_marker(line.body).map_or(0, |n| line.body.chars().take(n).count());
+    format!("{}{}", line.head, " ".repeat(marker))
+}
+
+/// Reflow `lines` as `gq` does, filling each paragraph's lines with as many
+/// words as fit in `textwidth` display columns. A paragraph ends at a blank
+/// line (or one with only a comment leader), where the comment leader
+/// changes, and before a list item; its lines after the first take the head
+/// of [`continuation`]. A word wider than the width gets a line to itself.
+pub fn reflow(lines: &[String], textwidth: usize, tabstop: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    // The paragraph being filled: its leader and continuation head, and
+    // the line being built with its width.
+    let mut para: Option<(&str, String)> = None;
+    let mut current = String::new();
+    let mut current_width = 0;
+    let mut has_words = false;
+    for text in lines {
+        let line = split(text);
+        let continues = matches!(&para, Some((leader, _)) if *leader == line.leader)
+            && !line.body.is_empty()
+            && list_marker(line.body).is_none();
+        if !continues {
+            if para.take().is_some() {
+                out.push(std::mem::take(&mut current));
+            }
+            if line.body.is_empty() {
+                out.push(text.trim_end().to_string());
+                continue;
+            }
+            para = Some((line.leader, continuation(text)));
+            current = line.head.to_string();
+            current_width = width(line.head, tabstop);
+            has_words = false;
+        }
+        let Some((_, head)) = &para else {
+            continue;
+        };
+        for word in line.body.split_whitespace() {
+            let word_width = grapheme::width(word);
+            if has_words && current_width + 1 + word_width > textwidth {
+                out.push(std::mem::replace(&mut current, head.clone()));
+                current_width = width(head, tabstop);
+                has_words = false;
+            }
+            if has_words {
+                current.push(' ');
+                current_width += 1;
+            }
+            current.push_str(word);
+            current_width += word_width;
+            has_words = true;
+        }
+    }
+    if para.is_some() {
+        out.push(current);
+    }
+    out
+}
+
+/// Where to break `line` when typing at char `x` took it past `textwidth`:
+/// the chars `[start, end)` of the last run of blanks before `x`, after the
+/// head, that leaves the text before it within the width, or failing that
+/// the first run (a word too long for the width is left whole).
+pub fn wrap_point(
+    line: &str,
+    x: usize,
+    textwidth: usize,
+    tabstop: usize,
+) -> Option<(usize, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let x = x.min(chars.len());
+    let typed: String = chars[..x].iter().collect();
+    if width(&typed, tabstop) <= textwidth {
+        return None;
+    }
+    let head = split(line).head.chars().count();
+    let mut first = None;
+    let mut end = x;
+    while end > head {
+        if !indent::is_blank(chars[end - 1]) {
+            end -= 1;
+            continue;
+        }
+        let mut start = end;
+        while start > head && indent::is_blank(chars[start - 1]) {
+            start -= 1;
+        }
+        let before: String = chars[..start].iter().collect();
+        if start > head && width(&before, tabstop) <= textwidth {
+            return Some((start, end));
+        }
+        if start > head {
+            first = Some((start, end));
+        }
+        end = start;
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gq(text: &str, textwidth: usize) -> Vec<String> {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        reflow(&lines, textwidth, 8)
+    }
+
+    #[test]
+    fn fills_paragraphs_to_the_width() {
+        assert_eq!(gq("a b c d e f", 5), vec!["a b c", "d e f"]);
+        assert_eq!(gq("a\nb\n\nc   d", 10), vec!["a b", "", "c d"]);
+        assert_eq!(gq("  aaa bbb ccc", 9), vec!["  aaa bbb", "  ccc"]);
+        assert_eq!(gq("toolongword a", 4), vec!["toolongword", "a"]);
+        // Wide characters take two columns.
+        assert_eq!(gq("日本 語", 5), vec!["日本", "語"]);
+    }
+
+    #[test]
+    fn keeps_comment_leaders_and_list_indents() {
+        assert_eq!(
+            gq("    /// one two\n    /// three four", 18),
+            vec!["    /// one two", "    /// three four"]
+        );
+        assert_eq!(
+            gq("//! a b c\n//!\n// d\n// e", 20),
+            vec!["//! a b c", "//!", "// d e"]
+        );
+        assert_eq!(
+            gq("- one two three\n- four\n12. five six", 9),
+            vec!["- one two", "  three", "- four", "12. five", "    six"]
+        );
+        assert_eq!(gq("// * a b", 6), vec!["// * a", "//   b"]);
+    }
+
+    #[test]
+    fn wraps_at_the_last_blank_that_fits() {
+        assert_eq!(wrap_point("aa bb cc", 8, 6, 8), Some((5, 6)));
+        assert_eq!(wrap_point("aa bb  c", 8, 6, 8), Some((5, 7)));
+        assert_eq!(wrap_point("aa bb", 5, 6, 8), None);
+        assert_eq!(wrap_point("   abcdefgh", 11, 6, 8), None);
+        assert_eq!(wrap_point("abcdefgh ij", 11, 5, 8), Some((8, 9)));
+        assert_eq!(wrap_point("abcdefgh ijklmn o", 17, 5, 8), Some((8, 9)));
+        assert_eq!(continuation("  // - item"), "  //   ");
+    }
+}
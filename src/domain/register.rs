@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+/// Text captured by a delete/change/yank, plus whether it was linewise (pasted
+/// onto new lines) or charwise (pasted inline). A blockwise register holds one
+/// `\n`-separated row per line of a Visual block and pastes back as a
+/// rectangle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+    pub blockwise: bool,
+}
+
+impl Register {
+    /// A charwise register holding `text`.
+    pub fn charwise(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            linewise: false,
+            blockwise: false,
+        }
+    }
+}
+
+/// The writable register file, following Vim's rules:
+///
+/// - `""` (unnamed) always holds the last yank/delete, whichever register it
+///   was written to.
+/// - `"a`–`"z` are written with a `"x` prefix; `"A`–`"Z` append to them.
+/// - `"0` holds the last unnamed yank. `"1`–`"9` are a ring of unnamed deletes
+///   that are linewise or span lines; smaller deletes go to `"-` instead.
+/// - `"_` (black hole) discards what is written to it.
+/// - `":` (the last command line) is read-only from the user's side. `"%` is
+///   resolved by the editor model, which owns the file path.
+#[derive(Clone, Default)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    named: HashMap<char, Register>,
+    numbered: [Option<Register>; 10],
+    small_delete: Option<Register>,
+    last_command: Option<String>,
+}
+
+impl Registers {
+    /// Whether `name` is a register a `"x` prefix may select.
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || "\"-_%:".contains(name)
+    }
+
+    /// Contents of register `name` (`"` for the unnamed register). Uppercase
+    /// names read their lowercase register.
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            '"' => self.unnamed.as_ref(),
+            'a'..='z' | 'A'..='Z' => self.named.get(&name.to_ascii_lowercase()),
+            '0'..='9' => self.numbered[name as usize - '0' as usize].as_ref(),
+            '-' => self.small_delete.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The last command line executed, for `":`.
+    pub fn last_command(&self) -> Option<&str> {
+        self.last_command.as_deref()
+    }
+
+    pub fn set_last_command(&mut self, command: &str) {
+        self.last_command = Some(command.to_string());
+    }
+
+    /// Record yanked text. Without a name it also becomes `"0`.
+    pub fn yank(&mut self, name: Option<char>, reg: Register) {
+        if name.is_none() || name == Some('"') {
+            self.numbered[0] = Some(reg.clone());
+        }
+        self.write(name, reg);
+    }
+
+    /// Record deleted (or changed) text. Without a name, multi-line deletes
+    /// shift the `"1`–`"9` ring and small ones go to `"-`.
+    pub fn delete(&mut self, name: Option<char>, reg: Register) {
+        if name.is_none() || name == Some('"') {
+            if reg.linewise || reg.text.contains('\n') {
+                self.numbered[1..].rotate_right(1);
+                self.numbered[1] = Some(reg.clone());
+            } else {
+                self.small_delete = Some(reg.clone());
+            }
+        }
+        self.write(name, reg);
+    }
+
+    /// Write `reg` directly into register `name` (uppercase appends). Used for
+    /// recorded macros and by the yank/delete paths. Read-only and unknown
+    /// names are ignored.
+    pub fn set(&mut self, name: char, reg: Register) {
+        self.write(Some(name), reg);
+    }
+
+    fn write(&mut self, name: Option<char>, reg: Register) {
+        let stored = match name {
+            None | Some('"') => reg,
+            Some('_') => return,
+            Some(c @ 'a'..='z') => {
+                self.named.insert(c, reg.clone());
+                reg
+            }
+            Some(c @ 'A'..='Z') => {
+                let lower = c.to_ascii_lowercase();
+                let joined = match self.named.remove(&lower) {
+                    Some(old) => append(old, reg),
+                    None => reg,
+                };
+                self.named.insert(lower, joined.clone());
+                joined
+            }
+            Some(c @ '0'..='9') => {
+                self.numbered[c as usize - '0' as usize] = Some(reg.clone());
+                reg
+            }
+            Some('-') => {
+                self.small_delete = Some(reg.clone());
+                reg
+            }
+            Some(_) => return,
+        };
+        self.unnamed = Some(stored);
+    }
+}
+
+/// Vim's append rule: if either side is linewise the result is linewise, with
+/// the new text starting on its own line.
+fn append(old: Register, new: Register) -> Register {
+    if old.linewise || new.linewise {
+        let mut text = old.text;
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&new.text);
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Register {
+            text,
+            linewise: true,
+            blockwise: false,
+        }
+    } else {
+        Register {
+            text: old.text + &new.text,
+            linewise: false,
+            blockwise: old.blockwise,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Register {
+        Register {
+            text: text.to_string(),
+            linewise: true,
+            blockwise: false,
+        }
+    }
+
+    #[test]
+    fn named_write_also_fills_unnamed() {
+        let mut r = Registers::default();
+        r.yank(Some('a'), Register::charwise("foo"));
+        assert_eq!(r.get('a').unwrap().text, "foo");
+        assert_eq!(r.get('"').unwrap().text, "foo");
+        // A named yank leaves "0 alone.
+        assert!(r.get('0').is_none());
+    }
+
+    #[test]
+    fn uppercase_appends() {
+        let mut r = Registers::default();
+        r.set('a', Register::charwise("foo"));
+        r.set('A', Register::charwise("bar"));
+        assert_eq!(r.get('a'), Some(&Register::charwise("foobar")));
+        // Appending linewise text makes the register linewise.
+        r.set('A', line("baz\n"));
+        assert_eq!(r.get('a'), Some(&line("foobar\nbaz\n")));
+    }
+
+    #[test]
+    fn deletes_shift_the_numbered_ring() {
+        let mut r = Registers::default();
+        r.delete(None, line("one\n"));
+        r.delete(None, line("two\n"));
+        r.delete(None, Register::charwise("x"));
+        assert_eq!(r.get('1').unwrap().text, "two\n");
+        assert_eq!(r.get('2').unwrap().text, "one\n");
+        assert_eq!(r.get('-').unwrap().text, "x");
+        assert_eq!(r.get('"').unwrap().text, "x");
+        r.yank(None, Register::charwise("y"));
+        assert_eq!(r.get('0').unwrap().text, "y");
+        assert_eq!(r.get('1').unwrap().text, "two\n");
+    }
+
+    #[test]
+    fn black_hole_discards() {
+        let mut r = Registers::default();
+        r.yank(None, Register::charwise("keep"));
+        r.delete(Some('_'), line("gone\n"));
+        assert_eq!(r.get('"').unwrap().text, "keep");
+        assert!(r.get('1').is_none());
+    }
+}
@@ -0,0 +1,159 @@
+use regex::{Regex, RegexBuilder};
+
+/// Where a search leaves the cursor relative to the match (`/foo/e+1`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchOffset {
+    /// `s[+-n]` / `b[+-n]` (and no offset): `n` chars from the match start.
+    Start(isize),
+    /// `e[+-n]`: `n` chars from the last char of the match.
+    End(isize),
+    /// `[+-]n`: `n` lines below (above) the match, in column 0.
+    Line(isize),
+}
+
+/// A compiled `/` or `?` search, kept for `n` / `N` and highlighting.
+pub struct Search {
+    pub pattern: String,
+    pub regex: Regex,
+    pub offset: SearchOffset,
+    pub backward: bool,
+}
+
+/// Split a search command line into the pattern and its offset at the first
+/// unescaped `delim` (`/` or `?`). An escaped delimiter in the pattern stands
+/// for itself.
+pub fn split_offset(input: &str, delim: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, d)) if d == delim => pattern.push(d),
+                Some((_, d)) => {
+                    pattern.push('\\');
+                    pattern.push(d);
+                }
+                None => pattern.push('\\'),
+            }
+        } else if c == delim {
+            return (pattern, &input[i + c.len_utf8()..]);
+        } else {
+            pattern.push(c);
+        }
+    }
+    (pattern, "")
+}
+
+/// Parse a search offset: `e`, `e+1`, `s-2`, `b`, `+3`, `-`, `2` … An empty
+/// string is no offset.
+pub fn parse_offset(s: &str) -> Option<SearchOffset> {
+    let number = |rest: &str| -> Option<isize> {
+        match rest {
+            "" => Some(0),
+            "+" => Some(1),
+            "-" => Some(-1),
+            _ => rest.strip_prefix('+').unwrap_or(rest).parse().ok(),
+        }
+    };
+    let mut chars = s.chars();
+    match chars.next() {
+        None => Some(SearchOffset::Start(0)),
+        Some('e') => number(chars.as_str()).map(SearchOffset::End),
+        Some('s' | 'b') => number(chars.as_str()).map(SearchOffset::Start),
+        Some('+' | '-' | '0'..='9') => {
+            let n = match s {
+                "+" => 1,
+                "-" => -1,
+                _ => number(s)?,
+            };
+            Some(SearchOffset::Line(n))
+        }
+        Some(_) => None,
+    }
+}
+
+/// Compile a search pattern: the `regex` crate's syntax plus Vim's `\<` / `\>`
+/// word boundaries and `\c` / `\C` to force case-insensitive / sensitive
+/// matching. Otherwise the search is smartcase: case-insensitive unless the
+/// pattern contains an uppercase letter. `^` and `$` match at line breaks and
+/// `\n` matches across lines.
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut translated = String::new();
+    let mut case: Option<bool> = None;
+    let mut upper = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            upper |= c.is_uppercase();
+            translated.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('c') => case = Some(false),
+            Some('C') => case = Some(true),
+            Some('<' | '>') => translated.push_str(r"\b"),
+            Some(d) => {
+                translated.push('\\');
+                translated.push(d);
+            }
+            None => translated.push('\\'),
+        }
+    }
+    RegexBuilder::new(&translated)
+        .case_insensitive(!case.unwrap_or(upper))
+        .multi_line(true)
+        .build()
+}
+
+/// Every match of `regex` in `text`, as half-open char ranges offset by
+/// `base`.
+pub fn find_all(regex: &Regex, text: &str, base: usize) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    let mut byte = 0;
+    let mut char_pos = base;
+    for m in regex.find_iter(text) {
+        char_pos += text[byte..m.start()].chars().count();
+        let len = m.as_str().chars().count();
+        out.push((char_pos, char_pos + len));
+        char_pos += len;
+        byte = m.end();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smartcase_and_case_flags() {
+        assert!(compile("foo").unwrap().is_match("FOO"));
+        assert!(!compile("Foo").unwrap().is_match("FOO"));
+        assert!(!compile(r"foo\C").unwrap().is_match("FOO"));
+        assert!(compile(r"\cFoo").unwrap().is_match("FOO"));
+        // Escapes like `\S` are not uppercase letters.
+        assert!(compile(r"a\Sc").unwrap().is_match("AbC"));
+    }
+
+    #[test]
+    fn word_boundaries_and_multiline() {
+        let re = compile(r"\<ab\>").unwrap();
+        assert_eq!(find_all(&re, "ab abc ab", 0), vec![(0, 2), (7, 9)]);
+        let re = compile(r"b\nc").unwrap();
+        assert_eq!(find_all(&re, "あb\nc", 10), vec![(11, 14)]);
+        assert_eq!(find_all(&compile("^x").unwrap(), "x\nx", 0).len(), 2);
+    }
+
+    #[test]
+    fn offsets_and_escaped_delimiters() {
+        assert_eq!(split_offset(r"a\/b/e+1", '/'), (r"a/b".to_string(), "e+1"));
+        assert_eq!(split_offset(r"a\d?", '?'), (r"a\d".to_string(), ""));
+        assert_eq!(parse_offset("e+1"), Some(SearchOffset::End(1)));
+        assert_eq!(parse_offset("b-2"), Some(SearchOffset::Start(-2)));
+        assert_eq!(parse_offset("e"), Some(SearchOffset::End(0)));
+        assert_eq!(parse_offset("-"), Some(SearchOffset::Line(-1)));
+        assert_eq!(parse_offset("3"), Some(SearchOffset::Line(3)));
+        assert_eq!(parse_offset(""), Some(SearchOffset::Start(0)));
+        assert_eq!(parse_offset("x"), None);
+    }
+}
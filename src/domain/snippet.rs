@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::domain::transaction::Change;
+
+/// A snippet ready to insert: its text, with every variable resolved and
+/// every placeholder filled in, and its tabstops in the order Tab visits
+/// them — `$1`, `$2`, … and the final `$0` last (at the end of the text when
+/// the snippet has none).
+#[derive(Debug, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    pub stops: Vec<Tabstop>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Tabstop {
+    /// Char ranges of the stop in the text. Typing goes into the first one,
+    /// the placeholder; the rest mirror it.
+    pub ranges: Vec<(usize, usize)>,
+    /// The values `${1|a,b|}` offers; the first is the one inserted.
+    pub choices: Vec<String>,
+}
+
+/// A piece of snippet source.
+enum Node {
+    Text(String),
+    /// `$1`, `${1:placeholder}` or `${1|a,b|}`; `content` is `None` for a
+    /// bare tabstop, which mirrors the placeholder given elsewhere.
+    Stop {
+        number: usize,
+        content: Option<Vec<Node>>,
+        choices: Vec<String>,
+    },
+    /// `$NAME` or `${NAME:default}`.
+    Variable {
+        name: String,
+        default: Option<Vec<Node>>,
+    },
+}
+
+/// Parse `source` in the LSP snippet syntax: tabstops (`$1`, `${1}`),
+/// placeholders (`${1:text}`, nesting), choices (`${1|a,b|}`), mirrors (a
+/// tabstop used again) and variables (`$NAME`, `${NAME:default}`), with `\`
+/// escaping `$`, `}` and `\`. `variable` gives the value of a variable;
+/// an unknown one is replaced by its default, else by its name. Transforms
+/// (`${1/re/fmt/}`) are read but not applied. Anything that does not parse
+/// is kept as text.
+pub fn parse(source: &str, variable: &dyn Fn(&str) -> Option<String>) -> Snippet {
+    let chars: Vec<char> = source.chars().collect();
+    let mut pos = 0;
+    let nodes = parse_nodes(&chars, &mut pos, false);
+    let mut placeholders = HashMap::new();
+    find_placeholders(&nodes, &mut placeholders);
+    let mut render = Render {
+        text: String::new(),
+        len: 0,
+        stops: HashMap::new(),
+        placeholders: &placeholders,
+        variable,
+        expanding: HashSet::new(),
+    };
+    render.nodes(&nodes, true);
+    let len = render.len;
+    let mut stops: Vec<(usize, Tabstop)> = render
+        .stops
+        .into_iter()
+        .map(|(number, (primary, mut stop))| {
+            let first = stop.ranges.remove(primary);
+            stop.ranges.insert(0, first);
+            (number, stop)
+        })
+        .collect();
+    stops.sort_by_key(|(number, _)| number.wrapping_sub(1));
+    if stops.last().is_none_or(|(number, _)| *number != 0) {
+        stops.push((
+            0,
+            Tabstop {
+                ranges: vec![(len, len)],
+                choices: Vec::new(),
+            },
+        ));
+    }
+    Snippet {
+        text: render.text,
+        stops: stops.into_iter().map(|(_, stop)| stop).collect(),
+    }
+}
+
+/// Parse nodes up to the end of the source, or up to an unescaped `}` when
+/// `nested` (the `}` is left for the caller).
+fn parse_nodes(chars: &[char], pos: &mut usize, nested: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    while let Some(&c) = chars.get(*pos) {
+        match c {
+            '\\' if matches!(chars.get(*pos + 1), Some('$' | '}' | '\\')) => {
+                text.push(chars[*pos + 1]);
+                *pos += 2;
+            }
+            '}' if nested => break,
+            '$' => match parse_dollar(chars, *pos) {
+                Some((node, end)) => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    nodes.push(node);
+                    *pos = end;
+                }
+                None => {
+                    text.push('$');
+                    *pos += 1;
+                }
+            },
+            c => {
+                text.push(c);
+                *pos += 1;
+            }
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    nodes
+}
+
+/// Parse the `$` construct at `start`, returning it and where it ends.
+fn parse_dollar(chars: &[char], start: usize) -> Option<(Node, usize)> {
+    let mut pos = start + 1;
+    let braced = chars.get(pos) == Some(&'{');
+    if braced {
+        pos += 1;
+    }
+    let digits = chars[pos..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let name_len = if digits > 0 {
+        digits
+    } else {
+        let first = chars.get(pos)?;
+        if !(first.is_ascii_alphabetic() || *first == '_') {
+            return None;
+        }
+        chars[pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .count()
+    };
+    let name: String = chars[pos..pos + name_len].iter().collect();
+    pos += name_len;
+    let make = |content, choices| match name.parse() {
+        Ok(number) => Node::Stop {
+            number,
+            content,
+            choices,
+        },
+        Err(_) => Node::Variable {
+            name: name.clone(),
+            default: content,
+        },
+    };
+    if !braced {
+        return Some((make(None, Vec::new()), pos));
+    }
+    match chars.get(pos)? {
+        '}' => Some((make(None, Vec::new()), pos + 1)),
+        ':' => {
+            pos += 1;
+            let content = parse_nodes(chars, &mut pos, true);
+            (chars.get(pos) == Some(&'}')).then(|| (make(Some(content), Vec::new()), pos + 1))
+        }
+        '|' if digits > 0 => {
+            let (choices, end) = parse_choices(chars, pos + 1)?;
+            let first = vec![Node::Text(choices[0].clone())];
+            Some((make(Some(first), choices), end))
+        }
+        '/' => {
+            // A transform: skip its regex, format and options.
+            let mut slashes = 0;
+            pos += 1;
+            while slashes < 2 {
+                match chars.get(pos)? {
+                    '\\' => pos += 1,
+                    '/' => slashes += 1,
+                    _ => {}
+                }
+                pos += 1;
+            }
+            while chars.get(pos)? != &'}' {
+                pos += 1;
+            }
+            Some((make(None, Vec::new()), pos + 1))
+        }
+        _ => None,
+    }
+}
+
+/// Parse the choices of `${1|a,b|}` from just after the first `|`, returning
+/// them and where the construct ends.
+fn parse_choices(chars: &[char], mut pos: usize) -> Option<(Vec<String>, usize)> {
+    let mut choices = Vec::new();
+    let mut choice = String::new();
+    loop {
+        match *chars.get(pos)? {
+            '\\' if matches!(chars.get(pos + 1), Some('$' | '}' | '\\' | ',' | '|')) => {
+                choice.push(chars[pos + 1]);
+                pos += 1;
+            }
+            ',' => choices.push(std::mem::take(&mut choice)),
+            '|' if chars.get(pos + 1) == Some(&'}') => {
+                choices.push(choice);
+                return Some((choices, pos + 2));
+            }
+            c => choice.push(c),
+        }
+        pos += 1;
+    }
+}
+
+/// Record the first placeholder given for each tabstop, which its mirrors
+/// repeat.
+fn find_placeholders<'a>(nodes: &'a [Node], found: &mut HashMap<usize, &'a [Node]>) {
+    for node in nodes {
+        match node {
+            Node::Stop {
+                number,
+                content: Some(content),
+                ..
+            } => {
+                found.entry(*number).or_insert(content);
+                find_placeholders(content, found);
+            }
+            Node::Variable {
+                default: Some(default),
+                ..
+            } => find_placeholders(default, found),
+            _ => {}
+        }
+    }
+}
+
+/// Renders nodes to text, noting where each tabstop lands.
+struct Render<'a> {
+    text: String,
+    /// Chars in `text`.
+    len: usize,
+    /// Per tabstop number: which of its ranges is the placeholder, and the
+    /// stop.
+    stops: HashMap<usize, (usize, Tabstop)>,
+    placeholders: &'a HashMap<usize, &'a [Node]>,
+    variable: &'a dyn Fn(&str) -> Option<String>,
+    /// Tabstops whose placeholder is being written out, so one that mirrors
+    /// itself ends.
+    expanding: HashSet<usize>,
+}
+
+impl Render<'_> {
+    /// Write out `nodes`, recording tabstops unless inside a mirror.
+    fn nodes(&mut self, nodes: &[Node], record: bool) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.push(text),
+                Node::Stop {
+                    number,
+                    content,
+                    choices,
+                } => {
+                    let start = self.len;
+                    match content {
+                        Some(content) => {
+                            let inside = self.expanding.insert(*number);
+                            self.nodes(content, record);
+                            if inside {
+                                self.expanding.remove(number);
+                            }
+                        }
+                        None => self.mirror(*number),
+                    }
+                    if record {
+                        // Typing goes into the placeholder the mirrors
+                        // repeat, else into the first range.
+                        let typed_into = content.as_deref().is_some_and(|content| {
+                            self.placeholders
+                                .get(number)
+                                .is_some_and(|p| std::ptr::eq(*p, content))
+                        });
+                        let stop = self.stops.entry(*number).or_default();
+                        if typed_into {
+                            stop.0 = stop.1.ranges.len();
+                            stop.1.choices = choices.clone();
+                        }
+                        stop.1.ranges.push((start, self.len));
+                    }
+                }
+                Node::Variable { name, default } => match (self.variable)(name) {
+                    Some(value) if !value.is_empty() || default.is_none() => self.push(&value),
+                    _ => match default {
+                        Some(default) => self.nodes(default, record),
+                        None => self.push(name),
+                    },
+                },
+            }
+        }
+    }
+
+    /// Write out the placeholder of tabstop `number`, as its mirror.
+    fn mirror(&mut self, number: usize) {
+        let Some(placeholder) = self.placeholders.get(&number).copied() else {
+            return;
+        };
+        if self.expanding.insert(number) {
+            self.nodes(placeholder, false);
+            self.expanding.remove(&number);
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.len += text.chars().count();
+    }
+}
+
+/// A snippet being filled in: its tabstops as whole-buffer char ranges, kept
+/// in place as the text changes, and the one the cursor is at.
+#[derive(Debug)]
+pub struct Session {
+    stops: Vec<Tabstop>,
+    current: usize,
+    /// Whether the current stop still holds its placeholder, which the
+    /// first key typed there replaces.
+    pub fresh: bool,
+}
+
+impl Session {
+    /// A session for `snippet` inserted at char `at`, at its first stop.
+    pub fn new(snippet: Snippet, at: usize) -> Self {
+        let mut stops = snippet.stops;
+        for stop in &mut stops {
+            for (s, e) in &mut stop.ranges {
+                *s += at;
+                *e += at;
+            }
+        }
+        Self {
+            stops,
+            current: 0,
+            fresh: true,
+        }
+    }
+
+    /// The current stop: its placeholder first, then its mirrors.
+    pub fn current(&self) -> &Tabstop {
+        &self.stops[self.current]
+    }
+
+    /// Whether the current stop is the final one, where the session ends.
+    pub fn at_final(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    /// Move to the next stop (or the previous one), with its placeholder
+    /// fresh again. Returns false when there is none.
+    pub fn step(&mut self, forward: bool) -> bool {
+        let next = if forward {
+            self.current + 1
+        } else {
+            self.current.wrapping_sub(1)
+        };
+        if next >= self.stops.len() {
+            return false;
+        }
+        self.current = next;
+        self.fresh = true;
+        true
+    }
+
+    /// Keep the ranges in place across `change`. Text typed at either end of
+    /// a range of the current stop goes into it; other stops do not grow at
+    /// their ends.
+    pub fn map(&mut self, change: &Change) {
+        let end = change.pos + change.removed.chars().count();
+        let inserted = change.inserted.chars().count();
+        for (i, stop) in self.stops.iter_mut().enumerate() {
+            let grow = i == self.current;
+            for (s, e) in &mut stop.ranges {
+                *s = if grow && *s <= change.pos {
+                    *s
+                } else {
+                    change.map_pos(*s)
+                };
+                *e = if grow && *e >= change.pos && *e <= end {
+                    change.pos + inserted
+                } else if !grow && *e <= end && *e >= change.pos {
+                    change.pos
+                } else {
+                    change.map_pos(*e)
+                };
+                *e = (*e).max(*s);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(source: &str) -> (String, Vec<Vec<(usize, usize)>>) {
+        let snippet = parse(source, &|name| {
+            (name == "TM_FILENAME").then(|| "main.rs".to_string())
+        });
+        let stops = snippet.stops.into_iter().map(|s| s.ranges).collect();
+        (snippet.text, stops)
+    }
+
+    #[test]
+    fn tabstops_placeholders_and_the_final_stop() {
+        assert_eq!(
+            expand("fn ${1:name}($2) {\n\t$0\n}"),
+            (
+                "fn name() {\n\t\n}".to_string(),
+                vec![vec![(3, 7)], vec![(8, 8)], vec![(13, 13)]]
+            )
+        );
+        // `$0` is implied at the end, and stops go in number order.
+        assert_eq!(
+            expand("${2:b} ${1}"),
+            (
+                "b ".to_string(),
+                vec![vec![(2, 2)], vec![(0, 1)], vec![(2, 2)]]
+            )
+        );
+        // Nested placeholders.
+        assert_eq!(
+            expand("${1:a ${2:b}}"),
+            (
+                "a b".to_string(),
+                vec![vec![(0, 3)], vec![(2, 3)], vec![(3, 3)]]
+            )
+        );
+    }
+
+    #[test]
+    fn mirrors_repeat_the_placeholder_wherever_it_is_given() {
+        assert_eq!(
+            expand("$1 = ${1:x}; $1"),
+            (
+                "x = x; x".to_string(),
+                vec![vec![(4, 5), (0, 1), (7, 8)], vec![(8, 8)]]
+            )
+        );
+        // A placeholder that mirrors itself stops there.
+        assert_eq!(expand("${1:a$1}").0, "a");
+    }
+
+    #[test]
+    fn choices_variables_and_escapes() {
+        let snippet = parse("${1|one,t\\,wo|}", &|_| None);
+        assert_eq!(snippet.text, "one");
+        assert_eq!(snippet.stops[0].choices, vec!["one", "t,wo"]);
+        assert_eq!(expand("$TM_FILENAME ${NOPE:x} $NOPE").0, "main.rs x NOPE");
+        assert_eq!(expand("\\$1 \\} $ ${ \\x").0, "$1 } $ ${ \\x");
+        assert_eq!(expand("${1/a/b/g} ${1:a}").0, "a a");
+    }
+
+    #[test]
+    fn sessions_grow_the_current_stop_and_step_between_stops() {
+        let mut session = Session::new(expand_snippet("${1:ab}-$2"), 10);
+        let typed = |pos, removed: &str, inserted: &str| Change {
+            pos,
+            removed: removed.to_string(),
+            inserted: inserted.to_string(),
+        };
+        session.map(&typed(10, "ab", ""));
+        session.map(&typed(10, "", "xyz"));
+        assert_eq!(session.current().ranges, vec![(10, 13)]);
+        assert!(session.step(true));
+        assert_eq!(session.current().ranges, vec![(14, 14)]);
+        session.map(&typed(14, "", "q"));
+        assert!(session.step(false));
+        assert_eq!(session.current().ranges, vec![(10, 13)]);
+        assert!(!session.step(false));
+        assert!(session.step(true) && session.step(true) && session.at_final());
+        assert_eq!(session.current().ranges, vec![(15, 15)]);
+    }
+
+    fn expand_snippet(source: &str) -> Snippet {
+        parse(source, &|_| None)
+    }
+}
@@ -0,0 +1,190 @@
+use regex::Captures;
+
+/// A parsed `:s/pattern/replacement/flags`, kept for `&` and `g&`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+    /// Search pattern (see [`crate::domain::search::compile`]); empty means
+    /// the last search pattern.
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: SubstituteFlags,
+}
+
+/// The `[gciIe]` flags of a substitution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubstituteFlags {
+    /// `g`: every match in a line, not just the first.
+    pub global: bool,
+    /// `c`: ask before each replacement.
+    pub confirm: bool,
+    /// `i` / `I`: ignore / match case, overriding smartcase.
+    pub ignore_case: Option<bool>,
+    /// `e`: no error when the pattern is not found.
+    pub quiet: bool,
+}
+
+impl Substitution {
+    /// The pattern with any `i`/`I` flag applied as `\c`/`\C`.
+    pub fn search_pattern(&self, pattern: &str) -> String {
+        match self.flags.ignore_case {
+            Some(true) => format!(r"{}\c", pattern),
+            Some(false) => format!(r"{}\C", pattern),
+            None => pattern.to_string(),
+        }
+    }
+}
+
+/// Parse the argument of `:s`: a delimiter (any char but a letter, digit,
+/// `\`, `"` or `|`), the pattern, the replacement and flags, as in
+/// `/old/new/g` or `#a/b#c/d#`. Trailing delimiters may be left out
+/// (`/old/new`, `/old`).
+pub fn parse(arg: &str) -> Result<Substitution, String> {
+    let mut chars = arg.chars();
+    let delim = match chars.next() {
+        Some(c) if !(c.is_alphanumeric() || matches!(c, '\\' | '"' | '|' | ' ')) => c,
+        _ => return Err("Invalid substitute delimiter".to_string()),
+    };
+    let rest = chars.as_str();
+    let (pattern, rest) = split_field(rest, delim);
+    let (replacement, rest) = split_field(rest, delim);
+    let mut flags = SubstituteFlags::default();
+    for c in rest.trim().chars() {
+        match c {
+            'g' => flags.global = true,
+            'c' => flags.confirm = true,
+            'i' => flags.ignore_case = Some(true),
+            'I' => flags.ignore_case = Some(false),
+            'e' => flags.quiet = true,
+            _ => return Err(format!("Invalid substitute flag: {}", c)),
+        }
+    }
+    Ok(Substitution {
+        pattern,
+        replacement,
+        flags,
+    })
+}
+
+/// Split at the first unescaped `delim`. An escaped delimiter stands for
+/// itself; other escapes are kept for the regex or the replacement expansion.
+fn split_field(s: &str, delim: char) -> (String, &str) {
+    let mut field = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, d)) if d == delim => field.push(d),
+                Some((_, d)) => {
+                    field.push('\\');
+                    field.push(d);
+                }
+                None => field.push('\\'),
+            }
+        } else if c == delim {
+            return (field, &s[i + c.len_utf8()..]);
+        } else {
+            field.push(c);
+        }
+    }
+    (field, "")
+}
+
+/// How the case of replacement text is being converted.
+#[derive(Clone, Copy, PartialEq)]
+enum Case {
+    Keep,
+    Upper,
+    Lower,
+}
+
+/// Expand a replacement for one match: `&` and `\0` are the whole match,
+/// `\1`–`\9` capture groups, `\r` / `\n` a line break, `\t` a tab and `\&`,
+/// `\\` literal characters. `\u` / `\l` change the case of the next
+/// character, `\U` / `\L` of everything up to `\E` / `\e`.
+pub fn expand(replacement: &str, caps: &Captures) -> String {
+    let mut out = String::new();
+    let mut case = Case::Keep;
+    let mut one: Option<Case> = None;
+    let push = |out: &mut String, text: &str, case: Case, one: &mut Option<Case>| {
+        for c in text.chars() {
+            let c_case = one.take().unwrap_or(case);
+            match c_case {
+                Case::Upper => out.extend(c.to_uppercase()),
+                Case::Lower => out.extend(c.to_lowercase()),
+                Case::Keep => out.push(c),
+            }
+        }
+    };
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        let text = match c {
+            '&' => caps.get(0).map_or("", |m| m.as_str()).to_string(),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => {
+                    let n = d.to_digit(10).unwrap() as usize;
+                    caps.get(n).map_or("", |m| m.as_str()).to_string()
+                }
+                Some('u') => {
+                    one = Some(Case::Upper);
+                    continue;
+                }
+                Some('l') => {
+                    one = Some(Case::Lower);
+                    continue;
+                }
+                Some('U') => {
+                    case = Case::Upper;
+                    continue;
+                }
+                Some('L') => {
+                    case = Case::Lower;
+                    continue;
+                }
+                Some('E' | 'e') => {
+                    case = Case::Keep;
+                    continue;
+                }
+                Some('r' | 'n') => "\n".to_string(),
+                Some('t') => "\t".to_string(),
+                Some(d) => d.to_string(),
+                None => "\\".to_string(),
+            },
+            c => c.to_string(),
+        };
+        push(&mut out, &text, case, &mut one);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn sub(replacement: &str, pattern: &str, text: &str) -> String {
+        let re = Regex::new(pattern).unwrap();
+        expand(replacement, &re.captures(text).unwrap())
+    }
+
+    #[test]
+    fn parses_fields_and_flags() {
+        let s = parse(r"/a\/b/c\/d/gi").unwrap();
+        assert_eq!((s.pattern.as_str(), s.replacement.as_str()), ("a/b", "c/d"));
+        assert!(s.flags.global && !s.flags.confirm);
+        assert_eq!(s.flags.ignore_case, Some(true));
+        let s = parse("#x#y").unwrap();
+        assert_eq!((s.pattern.as_str(), s.replacement.as_str()), ("x", "y"));
+        assert_eq!(parse("/x").unwrap().replacement, "");
+        assert!(parse("/x/y/z").is_err());
+        assert!(parse("ax").is_err());
+    }
+
+    #[test]
+    fn expands_groups_and_case_escapes() {
+        assert_eq!(sub(r"\2-\1", r"(\w+) (\w+)", "foo bar"), "bar-foo");
+        assert_eq!(sub(r"[&] \&", r"o+", "foo"), "[oo] &");
+        assert_eq!(sub(r"\u\1 \U\2\E!", r"(\w+) (\w+)", "foo bar"), "Foo BAR!");
+        assert_eq!(sub(r"\L\0", r"\w+", "MiXeD"), "mixed");
+        assert_eq!(sub(r"a\rb\\", "x", "x"), "a\nb\\");
+    }
+}
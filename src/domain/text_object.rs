@@ -369,7 +369,7 @@ mod tests {
     fn inner_paragraph_is_linewise() {
         let b = buf("a\nb\n\nc\nd");
         let r = range(&b, 0, 0, TextObject::Paragraph, true, 1).unwrap();
-        assert_eq!(r.2, true); // linewise
+        assert!(r.2); // linewise
         assert_eq!(text(&b, r), "a\nb\n"); // first paragraph block, incl. newlines
     }
 
@@ -29,19 +29,103 @@ impl Change {
             inserted: self.removed.clone(),
         }
     }
+
+    /// Where a char offset into the text before this change ends up after it:
+    /// offsets before `pos` stay, offsets after the removed text shift by the
+    /// size difference, and offsets inside it collapse to `pos`.
+    pub fn map_pos(&self, offset: usize) -> usize {
+        let removed = self.removed.chars().count();
+        if offset < self.pos {
+            offset
+        } else if offset >= self.pos + removed {
+            offset + self.inserted.chars().count() - removed
+        } else {
+            self.pos
+        }
+    }
+
+    /// This change in bytes and `(row, byte column)` points, as a syntax
+    /// tree is edited. `buf` is the text before the change.
+    pub fn byte_edit(&self, buf: &TextBuffer) -> ByteEdit {
+        let start_byte = buf.char_to_byte(self.pos);
+        let row = buf.char_to_line(self.pos);
+        let start = (row, start_byte - buf.line_to_byte(row));
+        let end = |text: &str| match text.rfind('\n') {
+            Some(i) => (start.0 + text.matches('\n').count(), text.len() - i - 1),
+            None => (start.0, start.1 + text.len()),
+        };
+        ByteEdit {
+            start_byte,
+            old_end_byte: start_byte + self.removed.len(),
+            new_end_byte: start_byte + self.inserted.len(),
+            start,
+            old_end: end(&self.removed),
+            new_end: end(&self.inserted),
+        }
+    }
+}
+
+/// A [`Change`] in the terms a syntax tree is edited in: byte offsets, and
+/// points as `(row, byte column)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteEdit {
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start: (usize, usize),
+    pub old_end: (usize, usize),
+    pub new_end: (usize, usize),
 }
 
-/// One undo step: a change plus the cursor positions to restore on undo
-/// (`cursor_before`) and redo (`cursor_after`). A single `Change` is enough for
-/// every current editor operation (even line merges are one `'\n'` removal);
-/// this can grow to `Vec<Change>` for multi-region edits without breaking the
-/// undo/redo protocol.
+/// One undo step: a list of changes plus the cursor positions to restore on
+/// undo (`cursor_before`) and redo (`cursor_after`).
+///
+/// The changes are in the order they were made, and each one's `pos` is an
+/// offset into the text as left by the changes before it, so applying them
+/// in order redoes the step and inverting them in reverse order undoes it.
+/// A multi-region edit (a block operation, an LSP rename) thus records only
+/// the text it actually touched, in buffer order (see
+/// [`sequential_changes`]); a step gathered from several edits (an Insert
+/// session, `:normal`) may revisit text an earlier change made.
 pub struct Transaction {
-    pub change: Change,
+    pub changes: Vec<Change>,
     pub cursor_before: (usize, usize),
     pub cursor_after: (usize, usize),
 }
 
+impl Transaction {
+    /// The changes that undo this transaction, in the order to apply them.
+    pub fn inverted(&self) -> Vec<Change> {
+        self.changes.iter().rev().map(Change::invert).collect()
+    }
+}
+
+/// Turn non-overlapping edits `(start, end, text)` with offsets into the
+/// current `buf` into sequential [`Change`]s, as a [`Transaction`] holds them.
+/// Empty edits are dropped.
+pub fn sequential_changes(buf: &TextBuffer, mut edits: Vec<(usize, usize, String)>) -> Vec<Change> {
+    edits.retain(|(s, e, t)| s != e || !t.is_empty());
+    edits.sort_by_key(|(s, _, _)| *s);
+    let len = buf.len_chars();
+    let mut changes = Vec::with_capacity(edits.len());
+    let mut delta: isize = 0;
+    let mut prev_end = 0;
+    for (s, e, text) in edits {
+        let s = s.clamp(prev_end, len);
+        let e = e.clamp(s, len);
+        let removed = buf.slice_text(s..e);
+        let pos = (s as isize + delta) as usize;
+        delta += text.chars().count() as isize - (e - s) as isize;
+        prev_end = e;
+        changes.push(Change {
+            pos,
+            removed,
+            inserted: text,
+        });
+    }
+    changes
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,4 +159,59 @@ mod tests {
         change.invert().apply(&mut buf);
         assert_eq!(buf.line_text(0), "hello");
     }
+
+    #[test]
+    fn byte_edit_counts_bytes_and_rows() {
+        let mut buf = TextBuffer::new();
+        buf.set_content("ab\nçd");
+        let change = Change {
+            pos: 4,
+            removed: "d".to_string(),
+            inserted: "x\nyé".to_string(),
+        };
+        assert_eq!(
+            change.byte_edit(&buf),
+            ByteEdit {
+                start_byte: 5,
+                old_end_byte: 6,
+                new_end_byte: 10,
+                start: (1, 2),
+                old_end: (1, 3),
+                new_end: (2, 3),
+            }
+        );
+    }
+
+    #[test]
+    fn map_pos_shifts_offsets_after_the_change() {
+        let change = Change {
+            pos: 2,
+            removed: "cd".to_string(),
+            inserted: "XYZ".to_string(),
+        };
+        assert_eq!(change.map_pos(1), 1);
+        assert_eq!(change.map_pos(3), 2);
+        assert_eq!(change.map_pos(4), 5);
+    }
+
+    #[test]
+    fn sequential_changes_apply_and_undo_in_order() {
+        let mut buf = TextBuffer::new();
+        buf.set_content("one two three");
+        let edits = vec![(8, 13, "3".to_string()), (0, 3, "1".to_string())];
+        let t = Transaction {
+            changes: sequential_changes(&buf, edits),
+            cursor_before: (0, 0),
+            cursor_after: (0, 0),
+        };
+        assert_eq!(t.changes[1].pos, 6);
+        for c in &t.changes {
+            c.apply(&mut buf);
+        }
+        assert_eq!(buf.line_text(0), "1 two 3");
+        for c in t.inverted() {
+            c.apply(&mut buf);
+        }
+        assert_eq!(buf.line_text(0), "one two three");
+    }
 }
@@ -0,0 +1,268 @@
+use crate::domain::transaction::Transaction;
+use std::time::{Duration, SystemTime};
+
+/// One state of the buffer: the transaction that produced it from its parent
+/// state, and when. Node `0` is the root (the text before any recorded edit)
+/// and carries an empty transaction.
+pub struct UndoNode {
+    pub parent: usize,
+    pub transaction: Transaction,
+    pub time: SystemTime,
+    /// The child `redo` follows: the one most recently created or visited.
+    pub redo_child: Option<usize>,
+}
+
+/// One step of a walk between two states: undo (invert) or redo (apply) the
+/// transaction of node `n`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UndoStep {
+    Undo(usize),
+    Redo(usize),
+}
+
+/// Undo history as a tree, so an edit made after undoing starts a new branch
+/// instead of discarding the undone changes. Nodes are numbered in creation
+/// order, which doubles as the chronological state number used by `g-`/`g+`
+/// and `:undolist`.
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![UndoNode {
+                parent: 0,
+                transaction: Transaction {
+                    changes: Vec::new(),
+                    cursor_before: (0, 0),
+                    cursor_after: (0, 0),
+                },
+                time: SystemTime::now(),
+                redo_child: None,
+            }],
+            current: 0,
+        }
+    }
+}
+
+impl UndoTree {
+    /// Number of the current state (`0` before any change).
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Number of the newest state.
+    pub fn last(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    pub fn node(&self, n: usize) -> &UndoNode {
+        &self.nodes[n]
+    }
+
+    /// Every state, root first, for persisting the tree.
+    pub fn nodes(&self) -> &[UndoNode] {
+        &self.nodes
+    }
+
+    /// Rebuild a tree from persisted states. Returns `None` unless the nodes
+    /// start with a root, every parent precedes its child and `current` exists.
+    pub fn from_nodes(nodes: Vec<UndoNode>, current: usize) -> Option<Self> {
+        let valid = !nodes.is_empty()
+            && current < nodes.len()
+            && nodes.iter().enumerate().skip(1).all(|(n, node)| {
+                node.parent < n && node.redo_child.is_none_or(|c| c < nodes.len())
+            });
+        valid.then_some(Self { nodes, current })
+    }
+
+    /// Record `transaction` as a new state below the current one.
+    pub fn push(&mut self, transaction: Transaction) {
+        let n = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: self.current,
+            transaction,
+            time: SystemTime::now(),
+            redo_child: None,
+        });
+        self.nodes[self.current].redo_child = Some(n);
+        self.current = n;
+    }
+
+    /// The transaction that produced the current state, if it is the newest
+    /// one (so typing can still be coalesced into it).
+    pub fn last_mut(&mut self) -> Option<&mut Transaction> {
+        if self.current == 0 || self.current != self.last() {
+            return None;
+        }
+        Some(&mut self.nodes[self.current].transaction)
+    }
+
+    /// Step to the parent state, returning the transaction to invert.
+    pub fn undo(&mut self) -> Option<&Transaction> {
+        if self.current == 0 {
+            return None;
+        }
+        let n = self.current;
+        self.current = self.nodes[n].parent;
+        self.nodes[self.current].redo_child = Some(n);
+        Some(&self.nodes[n].transaction)
+    }
+
+    /// Step to the most recent child state, returning the transaction to apply.
+    pub fn redo(&mut self) -> Option<&Transaction> {
+        let n = self.nodes[self.current].redo_child?;
+        self.current = n;
+        Some(&self.nodes[n].transaction)
+    }
+
+    /// The steps leading from the current state to state `target` (up to their
+    /// common ancestor, then down), making `target` current. Redo pointers
+    /// along the way are updated so `redo` follows the same branch later.
+    pub fn walk_to(&mut self, target: usize) -> Vec<UndoStep> {
+        let target = target.min(self.last());
+        let up = self.ancestors(self.current);
+        let down = self.ancestors(target);
+        let common = *up.iter().find(|n| down.contains(n)).unwrap_or(&0);
+        let mut steps: Vec<UndoStep> = up
+            .iter()
+            .take_while(|&&n| n != common)
+            .map(|&n| UndoStep::Undo(n))
+            .collect();
+        let mut path: Vec<usize> = down.into_iter().take_while(|&n| n != common).collect();
+        path.reverse();
+        for &n in &path {
+            let parent = self.nodes[n].parent;
+            self.nodes[parent].redo_child = Some(n);
+            steps.push(UndoStep::Redo(n));
+        }
+        self.current = target;
+        steps
+    }
+
+    /// The newest state created no later than `time` (the root if none).
+    pub fn state_at(&self, time: SystemTime) -> usize {
+        (1..self.nodes.len())
+            .rev()
+            .find(|&n| self.nodes[n].time <= time)
+            .unwrap_or(0)
+    }
+
+    /// Drop every state numbered `len` or above and make `current` current
+    /// again; used to fold a group of edits into one state. Returns the
+    /// dropped transactions, oldest first.
+    pub fn truncate(&mut self, len: usize, current: usize) -> Vec<Transaction> {
+        let dropped = self
+            .nodes
+            .drain(len.max(1).min(self.nodes.len())..)
+            .map(|node| node.transaction)
+            .collect();
+        for node in &mut self.nodes {
+            if node.redo_child.is_some_and(|c| c >= len) {
+                node.redo_child = None;
+            }
+        }
+        self.current = current.min(self.last());
+        dropped
+    }
+
+    /// `:undolist`: one line per leaf state (each branch's tip), oldest first,
+    /// with how many changes lead to it and how long ago it was made.
+    pub fn list(&self, now: SystemTime) -> Vec<String> {
+        let mut has_child = vec![false; self.nodes.len()];
+        for node in &self.nodes[1..] {
+            has_child[node.parent] = true;
+        }
+        let mut lines = vec!["number changes  when".to_string()];
+        for (n, _) in has_child.iter().enumerate().skip(1).filter(|(_, &c)| !c) {
+            let changes = self.ancestors(n).len() - 1;
+            let age = now
+                .duration_since(self.nodes[n].time)
+                .unwrap_or(Duration::ZERO);
+            let marker = if n == self.current { "  <" } else { "" };
+            lines.push(format!("{:>6} {:>7}  {}{}", n, changes, ago(age), marker));
+        }
+        lines
+    }
+
+    /// `n` and its ancestors up to and including the root, nearest first.
+    fn ancestors(&self, mut n: usize) -> Vec<usize> {
+        let mut out = vec![n];
+        while n != 0 {
+            n = self.nodes[n].parent;
+            out.push(n);
+        }
+        out
+    }
+}
+
+fn ago(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=99 => format!("{} seconds ago", secs),
+        100..=5999 => format!("{} minutes ago", secs / 60),
+        _ => format!("{} hours ago", secs / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::transaction::Change;
+
+    fn txn(pos: usize) -> Transaction {
+        Transaction {
+            changes: vec![Change {
+                pos,
+                removed: String::new(),
+                inserted: "x".to_string(),
+            }],
+            cursor_before: (0, 0),
+            cursor_after: (0, 0),
+        }
+    }
+
+    #[test]
+    fn edit_after_undo_keeps_the_old_branch() {
+        let mut t = UndoTree::default();
+        t.push(txn(0)); // 1
+        t.push(txn(1)); // 2
+        t.undo();
+        t.push(txn(5)); // 3, a sibling of 2
+        assert_eq!(t.node(3).parent, 1);
+        // Walking to state 2 undoes 3 and redoes 2.
+        assert_eq!(t.walk_to(2), vec![UndoStep::Undo(3), UndoStep::Redo(2)]);
+        assert_eq!(t.current(), 2);
+        // Redo from 1 now follows the branch to 2.
+        t.undo();
+        assert_eq!(t.redo().unwrap().changes[0].pos, 1);
+    }
+
+    #[test]
+    fn list_shows_each_branch_tip() {
+        let mut t = UndoTree::default();
+        t.push(txn(0));
+        t.push(txn(1));
+        t.undo();
+        t.push(txn(2));
+        let lines = t.list(SystemTime::now());
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("     2       2"));
+        assert!(lines[2].starts_with("     3       2") && lines[2].ends_with('<'));
+    }
+
+    #[test]
+    fn truncate_folds_recent_states() {
+        let mut t = UndoTree::default();
+        t.push(txn(0));
+        t.push(txn(1));
+        t.push(txn(2));
+        let dropped = t.truncate(2, 1);
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped[0].changes[0].pos, 1);
+        assert_eq!((t.current(), t.last()), (1, 1));
+        assert!(t.redo().is_none());
+    }
+}
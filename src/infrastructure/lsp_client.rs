@@ -17,11 +17,12 @@ use std::path::Path;
 use std::process::Stdio;
 
 use async_lsp::lsp_types::{
-    ClientCapabilities, CompletionResponse, ConfigurationParams, GeneralClientCapabilities,
-    GotoDefinitionResponse, Hover, InitializeParams, InitializeResult, LogMessageParams, OneOf,
-    PositionEncodingKind, ProgressParams, PublishDiagnosticsParams, RegistrationParams,
-    ShowMessageParams, TextEdit, Url, WindowClientCapabilities, WorkDoneProgressCreateParams,
-    WorkspaceEdit, WorkspaceFolder,
+    ClientCapabilities, CompletionClientCapabilities, CompletionItemCapability, CompletionResponse,
+    ConfigurationParams, GeneralClientCapabilities, GotoDefinitionResponse, Hover,
+    InitializeParams, InitializeResult, LogMessageParams, OneOf, PositionEncodingKind,
+    ProgressParams, PublishDiagnosticsParams, RegistrationParams, ShowMessageParams,
+    TextDocumentClientCapabilities, TextEdit, Url, WindowClientCapabilities,
+    WorkDoneProgressCreateParams, WorkspaceEdit, WorkspaceFolder,
 };
 use async_lsp::router::Router;
 use async_lsp::{LanguageClient, MainLoop, ResponseError, ServerSocket};
@@ -169,8 +170,8 @@ pub fn spawn(root_dir: &Path, tx: UnboundedSender<LspEvent>) -> std::io::Result<
 }
 
 /// Build the `initialize` request parameters: negotiate `utf-8` (preferred) then
-/// `utf-16` position encoding, advertise work-done progress, and root the server
-/// at `root_dir`.
+/// `utf-16` position encoding, advertise work-done progress and snippet
+/// completions, and root the server at `root_dir`.
 pub fn initialize_params(root_dir: &Path) -> InitializeParams {
     let root_uri = Url::from_file_path(root_dir).ok();
     #[allow(deprecated)] // `root_uri` is deprecated but still honored by servers
@@ -190,6 +191,16 @@ pub fn initialize_params(root_dir: &Path) -> InitializeParams {
                 ]),
                 ..GeneralClientCapabilities::default()
             }),
+            text_document: Some(TextDocumentClientCapabilities {
+                completion: Some(CompletionClientCapabilities {
+                    completion_item: Some(CompletionItemCapability {
+                        snippet_support: Some(true),
+                        ..CompletionItemCapability::default()
+                    }),
+                    ..CompletionClientCapabilities::default()
+                }),
+                ..TextDocumentClientCapabilities::default()
+            }),
             window: Some(WindowClientCapabilities {
                 work_done_progress: Some(true),
                 ..WindowClientCapabilities::default()
@@ -206,6 +217,17 @@ mod tests {
     use async_lsp::lsp_types::InitializedParams;
     use async_lsp::LanguageServer;
 
+    #[test]
+    fn initialize_params_advertise_snippet_completions() {
+        let params = initialize_params(Path::new("/tmp"));
+        let item = params
+            .capabilities
+            .text_document
+            .and_then(|t| t.completion)
+            .and_then(|c| c.completion_item);
+        assert_eq!(item.and_then(|i| i.snippet_support), Some(true));
+    }
+
     /// End-to-end handshake against a real `rust-analyzer`. Ignored by default
     /// (needs the binary + network-free Cargo project); run explicitly with
     /// `cargo test --  --ignored lsp_handshake`.
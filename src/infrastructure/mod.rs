@@ -1,4 +1,7 @@
 pub mod file_io;
 pub mod lsp_client;
+pub mod shell;
+pub mod snippet_file;
 pub mod syntax_worker;
 pub mod terminal_ui;
+pub mod undo_file;
@@ -0,0 +1,97 @@
+//! External commands.
+//!
+//! Runs a command line through the user's shell (`$SHELL -c`, else `sh`) as
+//! a `tokio::process` child, feeding it text on stdin and collecting what it
+//! prints. Awaiting [`run`] never blocks the runtime, so the editor keeps
+//! drawing while the command runs; dropping the future (the task running it
+//! is aborted on cancel) kills the child.
+
+use std::io::{self, ErrorKind};
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use tokio::time::Duration;
+
+/// What a finished command printed, and how it exited.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShellOutput {
+    /// The exit code; `None` when the command was killed by a signal.
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ShellOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Run `command` with `input` (if any) on its stdin. Fails with
+/// [`ErrorKind::TimedOut`] if it has not finished within `limit`, killing it.
+pub async fn run(command: &str, input: Option<String>, limit: Duration) -> io::Result<ShellOutput> {
+    let shell = std::env::var("SHELL")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "sh".to_string());
+    let mut child = tokio::process::Command::new(shell)
+        .arg("-c")
+        .arg(command)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdin = child.stdin.take();
+    // Write stdin while the output is read: a filter may fill its stdout pipe
+    // before it has read all of its input.
+    let write = async move {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            // A command that exits without reading its input is not an error.
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+    };
+    let finish = async {
+        let (_, output) = tokio::join!(write, child.wait_with_output());
+        output
+    };
+    let output = tokio::time::timeout(limit, finish)
+        .await
+        .map_err(|_| io::Error::new(ErrorKind::TimedOut, "Command timed out"))??;
+    Ok(ShellOutput {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn filters_stdin_and_reports_the_exit_code() {
+        let limit = Duration::from_secs(10);
+        let out = run("sort", Some("b\na\n".to_string()), limit)
+            .await
+            .unwrap();
+        assert!(out.success());
+        assert_eq!(out.stdout, "a\nb\n");
+
+        let out = run("echo oops >&2; exit 3", None, limit).await.unwrap();
+        assert_eq!(out.code, Some(3));
+        assert_eq!(out.stderr, "oops\n");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn times_out() {
+        let err = run("sleep 5", None, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,185 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A snippet the user defined: the word that expands it and its body, in
+/// the LSP snippet syntax.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserSnippet {
+    pub prefix: String,
+    pub body: String,
+}
+
+/// Where user snippets come from, per file type.
+pub trait SnippetStore {
+    /// The snippets defined for `filetype` (see [`filetype`]).
+    fn snippets(&mut self, filetype: &str) -> Vec<UserSnippet>;
+}
+
+/// File types known by another name than their extension.
+const FILETYPES: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+    ("py", "python"),
+    ("js", "javascript"),
+    ("ts", "typescript"),
+    ("sh", "shell"),
+    ("bash", "shell"),
+    ("yml", "yaml"),
+    ("h", "c"),
+    ("cc", "cpp"),
+    ("hpp", "cpp"),
+];
+
+/// The file type of the file at `path`, which names its snippet file: the
+/// language for a well-known extension (`rust` for `.rs`), else the
+/// extension itself (`toml`).
+pub fn filetype(path: &str) -> Option<&str> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    Some(
+        FILETYPES
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .map_or(extension, |(_, name)| name),
+    )
+}
+
+/// Snippet files in one directory, one per file type (`rust.json`), in the
+/// format VS Code uses: an object of named snippets, each with a `prefix`
+/// and a `body`, either of which may be an array (several prefixes; the
+/// lines of the body). A file is read again only when it has changed.
+pub struct LocalSnippetStore {
+    dir: PathBuf,
+    /// The snippets read per file type, with the file's modification time.
+    loaded: HashMap<String, (Option<SystemTime>, Vec<UserSnippet>)>,
+}
+
+impl LocalSnippetStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            loaded: HashMap::new(),
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/rim/snippets`, falling back to
+    /// `~/.config/rim/snippets`.
+    pub fn default_location() -> Option<Self> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))?;
+        Some(Self::new(base.join("rim").join("snippets")))
+    }
+}
+
+impl SnippetStore for LocalSnippetStore {
+    fn snippets(&mut self, filetype: &str) -> Vec<UserSnippet> {
+        let file = self.dir.join(format!("{}.json", filetype));
+        let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+        match self.loaded.get(filetype) {
+            Some((seen, snippets)) if *seen == modified => snippets.clone(),
+            _ => {
+                let snippets = read_snippets(&file);
+                self.loaded
+                    .insert(filetype.to_string(), (modified, snippets.clone()));
+                snippets
+            }
+        }
+    }
+}
+
+/// The snippets in `file`. A missing or malformed file has none; a malformed
+/// entry is skipped.
+fn read_snippets(file: &Path) -> Vec<UserSnippet> {
+    let Some(value) = fs::read_to_string(file)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+    else {
+        return Vec::new();
+    };
+    let Some(entries) = value.as_object() else {
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    for entry in entries.values() {
+        let (Some(prefixes), Some(body)) = (strings(&entry["prefix"]), strings(&entry["body"]))
+        else {
+            continue;
+        };
+        let body = body.join("\n");
+        out.extend(prefixes.into_iter().map(|prefix| UserSnippet {
+            prefix,
+            body: body.clone(),
+        }));
+    }
+    out
+}
+
+/// A string, or an array of them.
+fn strings(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::String(s) => Some(vec![s.clone()]),
+        Value::Array(items) => items.iter().map(|v| v.as_str().map(String::from)).collect(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_snippets_for_a_language() {
+        let dir = env::temp_dir().join(format!("rim-snippet-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("rust.json"),
+            r##"{
+                "test": { "prefix": ["test", "tst"], "body": ["#[test]", "fn $1() {}"] },
+                "print": { "prefix": "pr", "body": "println!($0);" },
+                "broken": { "prefix": 1, "body": "x" }
+            }"##,
+        )
+        .unwrap();
+        let mut store = LocalSnippetStore::new(dir.clone());
+        let mut snippets = store.snippets("rust");
+        snippets.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        // Until the file changes, the snippets read are kept.
+        let kept = store.snippets("rust").len();
+        fs::write(dir.join("rust.json"), "{}").unwrap();
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.join("rust.json"))
+            .unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(1))
+            .unwrap();
+        let reread = store.snippets("rust").len();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!((kept, reread), (3, 0));
+        let pairs: Vec<_> = snippets
+            .iter()
+            .map(|s| (s.prefix.as_str(), s.body.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("pr", "println!($0);"),
+                ("test", "#[test]\nfn $1() {}"),
+                ("tst", "#[test]\nfn $1() {}"),
+            ]
+        );
+        assert!(store.snippets("python").is_empty());
+    }
+
+    #[test]
+    fn snippet_files_are_named_for_the_file_type() {
+        assert_eq!(filetype("src/main.rs"), Some("rust"));
+        assert_eq!(filetype("README.md"), Some("markdown"));
+        assert_eq!(filetype("Cargo.toml"), Some("toml"));
+        assert_eq!(filetype("Makefile"), None);
+    }
+}
@@ -8,14 +8,31 @@
 //! reports [`Highlights`] back over a tokio channel. Wiring into the async
 //! event loop happens in the application/`main` layers.
 //!
+//! [`SyntaxIndenter`] is the smart-indent engine: it parses on demand, on
+//! the edit path, since the indent of a new line depends on text typed a
+//! moment ago. It keeps its parse edited along with the text, so that a
+//! keystroke re-parses only around itself; highlighting cannot. So do
+//! [`SyntaxLiterals`], which keeps auto-pairs out of strings and comments,
+//! and [`SyntaxComments`], which finds the language `gc` comments in. Each
+//! language's grammar, queries and comment strings are a [`LanguageDef`].
+//!
 //! Note (deliberate MS3 scope): `tree-sitter-highlight` re-parses the whole
 //! `source` on every call — its public API exposes no `old_tree` reuse — so we
 //! re-highlight the entire document per (debounced) change and clip spans to
 //! the viewport at render time. True incremental parsing is a later migration
 //! to the raw `Query` API.
 
+use crate::domain::comment::{CommentStyle, CommentSyntax};
+use crate::domain::indent::Indenter;
+use crate::domain::pairs::Literals;
+use crate::domain::transaction::ByteEdit;
 use crossterm::style::Color;
 use ropey::Rope;
+use std::collections::BTreeSet;
+use std::ops::Range;
+use tree_sitter::{
+    InputEdit, Language, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree,
+};
 use tree_sitter_highlight::{Highlight, HighlightConfiguration, HighlightEvent, Highlighter};
 
 /// A contiguous run of source bytes that should be drawn in one highlight
@@ -88,15 +105,72 @@ pub fn color_for(style: usize) -> Color {
     }
 }
 
+/// A language the editor can parse: its grammar and queries, and the
+/// per-language settings that go with them.
+pub struct LanguageDef {
+    /// The name injection queries use for it (`injection.language`).
+    pub name: &'static str,
+    /// Extensions of the files written in it.
+    pub extensions: &'static [&'static str],
+    pub grammar: fn() -> Language,
+    pub highlights: &'static str,
+    pub injections: &'static str,
+    /// What `gc` comments with.
+    pub comments: CommentStyle,
+    /// The query smart indent goes by (see [`SyntaxIndenter`]).
+    pub indents: &'static str,
+    /// Kinds of the string, character and block comment nodes, which hold
+    /// a cursor up to their end.
+    pub literals: &'static [&'static str],
+    /// Kinds of the line comment nodes, which hold a cursor up to the end
+    /// of their line.
+    pub line_comments: &'static [&'static str],
+}
+
+pub const RUST: LanguageDef = LanguageDef {
+    name: "rust",
+    extensions: &["rs"],
+    grammar: || tree_sitter_rust::LANGUAGE.into(),
+    highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+    injections: tree_sitter_rust::INJECTIONS_QUERY,
+    comments: CommentStyle {
+        line: Some("//"),
+        block: Some(("/*", "*/")),
+    },
+    indents: INDENTS_QUERY,
+    literals: &[
+        "string_literal",
+        "raw_string_literal",
+        "char_literal",
+        "block_comment",
+    ],
+    line_comments: &["line_comment"],
+};
+
+/// Every language the editor knows, by [`LanguageDef::name`].
+pub const LANGUAGES: &[LanguageDef] = &[RUST];
+
+pub fn language(name: &str) -> Option<&'static LanguageDef> {
+    LANGUAGES.iter().find(|def| def.name == name)
+}
+
+/// The language of the file at `path`, by its extension.
+pub fn language_for_path(path: &str) -> Option<&'static LanguageDef> {
+    let extension = std::path::Path::new(path).extension()?.to_str()?;
+    LANGUAGES
+        .iter()
+        .find(|def| def.extensions.contains(&extension))
+}
+
 /// Build the highlight configuration for Rust. Returns `Err` (rather than
 /// panicking) on an invalid grammar/query or an ABI mismatch, so the caller can
 /// degrade to plain, uncoloured rendering.
 pub fn build_config() -> Result<HighlightConfiguration, tree_sitter::QueryError> {
     let mut config = HighlightConfiguration::new(
-        tree_sitter_rust::LANGUAGE.into(),
-        "rust",
-        tree_sitter_rust::HIGHLIGHTS_QUERY,
-        tree_sitter_rust::INJECTIONS_QUERY,
+        (RUST.grammar)(),
+        RUST.name,
+        RUST.highlights,
+        RUST.injections,
         "", // tree-sitter-rust ships no locals query
     )?;
     config.configure(HIGHLIGHT_NAMES);
@@ -144,6 +218,346 @@ pub fn highlight_source(
     spans
 }
 
+/// Rust's nodes whose lines after the first are indented one level
+/// (`@indent`), and closing brackets that take a line starting with them
+/// back out (`@outdent`).
+pub const INDENTS_QUERY: &str = r#"
+[
+  (block)
+  (declaration_list)
+  (field_declaration_list)
+  (ordered_field_declaration_list)
+  (field_initializer_list)
+  (enum_variant_list)
+  (match_block)
+  (use_list)
+  (arguments)
+  (parameters)
+  (closure_parameters)
+  (type_arguments)
+  (type_parameters)
+  (where_clause)
+  (token_tree)
+  (array_expression)
+  (tuple_expression)
+  (tuple_type)
+  (tuple_pattern)
+  (slice_pattern)
+  (struct_pattern)
+] @indent
+
+["}" ")" "]"] @outdent
+
+(ERROR) @error
+"#;
+
+/// Smart indent from a language's [`LanguageDef::indents`] query. A line is
+/// indented one level for each line above it that starts an `@indent` node
+/// still open at the line's first non-blank (several starting on one line
+/// count once, so `foo(|| {` indents once), less one when it starts with an
+/// `@outdent`.
+///
+/// Code being typed often does not parse: a `{` with no `}` yet can leave
+/// the brackets loose in an `@error` node. An opening bracket there that no
+/// bracket after it in the node closes counts as open to the end.
+pub struct SyntaxIndenter {
+    doc: Document,
+    query: Query,
+    def: &'static LanguageDef,
+}
+
+impl SyntaxIndenter {
+    pub fn new(def: &'static LanguageDef) -> Result<Self, tree_sitter::QueryError> {
+        let query = Query::new(&(def.grammar)(), def.indents)?;
+        Ok(Self {
+            doc: Document::new(def),
+            query,
+            def,
+        })
+    }
+}
+
+impl Indenter for SyntaxIndenter {
+    fn indent_levels(
+        &mut self,
+        text: &Rope,
+        revision: u64,
+        lines: Range<usize>,
+    ) -> Vec<Option<usize>> {
+        let Some(tree) = self.doc.tree(text, revision) else {
+            return vec![None; lines.len()];
+        };
+        let len = text.len_bytes();
+        let line_start = |y: usize| text.line_to_byte(y.min(text.len_lines()));
+        let last = line_start(lines.end);
+
+        // (start row, end byte) of the `@indent` nodes and loose opening
+        // brackets, and where the `@outdent` brackets are.
+        let mut open = Vec::new();
+        let mut closers = BTreeSet::new();
+        let mut cursor = QueryCursor::new();
+        // Every node before the lines may be open at them.
+        cursor.set_byte_range(0..last);
+        let mut matches = cursor.matches(&self.query, tree.root_node(), node_text(text));
+        while let Some(m) = matches.next() {
+            for capture in m.captures {
+                let node = capture.node;
+                match self.query.capture_names()[capture.index as usize] {
+                    "indent" => open.push((node.start_position().row, node.end_byte())),
+                    "outdent" if !node.is_missing() => {
+                        closers.insert(node.start_byte());
+                    }
+                    "error" => {
+                        let mut loose = Vec::new();
+                        let mut walk = node.walk();
+                        for child in node.children(&mut walk) {
+                            match child.kind() {
+                                "{" | "(" | "[" => loose.push(child.start_position().row),
+                                "}" | ")" | "]" => {
+                                    loose.pop();
+                                }
+                                _ => {}
+                            }
+                        }
+                        open.extend(loose.into_iter().map(|row| (row, len)));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        lines
+            .map(|y| {
+                let start = line_start(y);
+                let end = line_start(y + 1);
+                let at = start
+                    + text
+                        .byte_slice(start..end)
+                        .bytes()
+                        .take_while(|b| *b == b' ' || b'\t' == *b)
+                        .count();
+                // Leave the inside of a string or comment alone.
+                let mut node = tree.root_node().descendant_for_byte_range(at, at);
+                while let Some(n) = node {
+                    if self.def.literals.contains(&n.kind()) && n.start_byte() < start {
+                        return None;
+                    }
+                    node = n.parent();
+                }
+                let rows: BTreeSet<usize> = open
+                    .iter()
+                    .filter(|&&(row, end)| row < y && (end > at || end == len))
+                    .map(|&(row, _)| row)
+                    .collect();
+                let outdent = closers.contains(&at) as usize;
+                Some(rows.len().saturating_sub(outdent))
+            })
+            .collect()
+    }
+
+    fn edit(&mut self, edit: &ByteEdit, revision: u64) {
+        self.doc.edit(edit, revision);
+    }
+}
+
+/// A parse of the editor's text kept in step with it: each edit is applied
+/// to the tree, and the next look re-parses from the edited tree, which
+/// only re-reads the text around the edits. An edit it was not told of (the
+/// revision moved on without it, as on loading a file) means a full parse.
+struct Document {
+    parser: Parser,
+    tree: Option<Tree>,
+    /// The edit revision of the text `tree` is (with its edits) for.
+    revision: u64,
+    /// Whether `tree` has edits not yet re-parsed.
+    edited: bool,
+}
+
+impl Document {
+    fn new(def: &LanguageDef) -> Self {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&(def.grammar)())
+            .expect("grammars match the linked tree-sitter ABI");
+        Self {
+            parser,
+            tree: None,
+            revision: 0,
+            edited: false,
+        }
+    }
+
+    fn edit(&mut self, edit: &ByteEdit, revision: u64) {
+        let point = |(row, column)| Point { row, column };
+        match self.tree.as_mut() {
+            Some(tree) if self.revision.wrapping_add(1) == revision => {
+                tree.edit(&InputEdit {
+                    start_byte: edit.start_byte,
+                    old_end_byte: edit.old_end_byte,
+                    new_end_byte: edit.new_end_byte,
+                    start_position: point(edit.start),
+                    old_end_position: point(edit.old_end),
+                    new_end_position: point(edit.new_end),
+                });
+                self.revision = revision;
+                self.edited = true;
+            }
+            _ => self.tree = None,
+        }
+    }
+
+    /// The tree of `text`, at edit revision `revision`.
+    fn tree(&mut self, text: &Rope, revision: u64) -> Option<&Tree> {
+        if self.revision != revision {
+            self.tree = None;
+        }
+        if self.tree.is_none() || self.edited {
+            // Hand the parser the rope's chunks rather than a copy of the text.
+            let mut read = |byte: usize, _: Point| -> &[u8] {
+                if byte >= text.len_bytes() {
+                    return &[];
+                }
+                let (chunk, start, _, _) = text.chunk_at_byte(byte);
+                &chunk.as_bytes()[byte - start..]
+            };
+            self.tree = self
+                .parser
+                .parse_with_options(&mut read, self.tree.as_ref(), None);
+            self.revision = revision;
+            self.edited = false;
+        }
+        self.tree.as_ref()
+    }
+}
+
+/// The text of a node in `text`, chunk by chunk, for queries over a rope.
+fn node_text<'a>(text: &'a Rope) -> impl FnMut(Node) -> NodeChunks<'a> {
+    move |node| {
+        text.byte_slice(node.byte_range())
+            .chunks()
+            .map(str::as_bytes as fn(&str) -> &[u8])
+    }
+}
+
+type NodeChunks<'a> = std::iter::Map<ropey::iter::Chunks<'a>, fn(&'a str) -> &'a [u8]>;
+
+/// Finds the strings, character literals and comments of a language for
+/// auto-pairs.
+pub struct SyntaxLiterals {
+    doc: Document,
+    def: &'static LanguageDef,
+}
+
+impl SyntaxLiterals {
+    pub fn new(def: &'static LanguageDef) -> Self {
+        Self {
+            doc: Document::new(def),
+            def,
+        }
+    }
+}
+
+impl Literals for SyntaxLiterals {
+    fn in_literal(&mut self, text: &Rope, revision: u64, at: usize) -> bool {
+        let def = self.def;
+        let Some(tree) = self.doc.tree(text, revision) else {
+            return false;
+        };
+        // The innermost node holding the byte before the cursor, and those
+        // around it. A string or char literal holds the cursor up to its
+        // closing quote; a comment up to the end of its line.
+        let mut node = tree
+            .root_node()
+            .descendant_for_byte_range(at.saturating_sub(1), at);
+        while let Some(n) = node {
+            let start = n.start_byte();
+            let inside = if def.literals.contains(&n.kind()) {
+                start < at && (at < n.end_byte() || n.has_error())
+            } else if def.line_comments.contains(&n.kind()) {
+                start < at && !text.byte_slice(start..at).chars().any(|c| c == '\n')
+            } else {
+                false
+            };
+            if inside {
+                return true;
+            }
+            node = n.parent();
+        }
+        false
+    }
+
+    fn edit(&mut self, edit: &ByteEdit, revision: u64) {
+        self.doc.edit(edit, revision);
+    }
+}
+
+/// Picks the comment style for `gc` from the language at a place in a file:
+/// that of the innermost injection around it the editor knows (as the
+/// file's [`LanguageDef::injections`] query places them), else the file's
+/// own.
+pub struct SyntaxComments {
+    doc: Document,
+    injections: Query,
+    def: &'static LanguageDef,
+}
+
+impl SyntaxComments {
+    pub fn new(def: &'static LanguageDef) -> Result<Self, tree_sitter::QueryError> {
+        let injections = Query::new(&(def.grammar)(), def.injections)?;
+        Ok(Self {
+            doc: Document::new(def),
+            injections,
+            def,
+        })
+    }
+}
+
+impl CommentSyntax for SyntaxComments {
+    fn comment_style(&mut self, text: &Rope, revision: u64, at: usize) -> CommentStyle {
+        let Some(tree) = self.doc.tree(text, revision) else {
+            return self.def.comments;
+        };
+        let names = self.injections.capture_names();
+        // (length, language) of the injections holding `at`.
+        let mut found: Option<(usize, &'static LanguageDef)> = None;
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(at..at + 1);
+        let mut matches = cursor.matches(&self.injections, tree.root_node(), node_text(text));
+        while let Some(m) = matches.next() {
+            // The language is set on the pattern, or captured from the text.
+            let mut name = self
+                .injections
+                .property_settings(m.pattern_index)
+                .iter()
+                .find(|p| &*p.key == "injection.language")
+                .and_then(|p| p.value.as_deref().map(str::to_string));
+            let mut content = None;
+            for capture in m.captures {
+                match names[capture.index as usize] {
+                    "injection.content" => content = Some(capture.node),
+                    "injection.language" => {
+                        name = Some(text.byte_slice(capture.node.byte_range()).to_string())
+                    }
+                    _ => {}
+                }
+            }
+            let (Some(node), Some(def)) = (content, name.as_deref().and_then(language)) else {
+                continue;
+            };
+            let len = node.byte_range().len();
+            let holds = node.start_byte() <= at && at < node.end_byte();
+            if holds && found.is_none_or(|(shortest, _)| len < shortest) {
+                found = Some((len, def));
+            }
+        }
+        found.map_or(self.def.comments, |(_, def)| def.comments)
+    }
+
+    fn edit(&mut self, edit: &ByteEdit, revision: u64) {
+        self.doc.edit(edit, revision);
+    }
+}
+
 /// Spawn the highlight worker on a dedicated OS thread. It owns the
 /// `Highlighter` + `HighlightConfiguration` (both `Send` but `!Sync`, so they
 /// must not be shared), receives [`ParseRequest`]s, coalesces to the newest
@@ -186,6 +600,8 @@ pub fn spawn(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::text_buffer::TextBuffer;
+    use crate::domain::transaction::Change;
 
     fn style_of(name: &str) -> usize {
         HIGHLIGHT_NAMES.iter().position(|n| *n == name).unwrap()
@@ -233,6 +649,105 @@ mod tests {
         );
     }
 
+    fn levels(src: &str) -> Vec<Option<usize>> {
+        let mut indenter = SyntaxIndenter::new(&RUST).unwrap();
+        let count = src.lines().count();
+        indenter.indent_levels(&Rope::from(src), 0, 0..count)
+    }
+
+    #[test]
+    fn indents_inside_brackets_and_outdents_closers() {
+        let src = "fn f(\n  a: u8,\n) {\nif x {\ny(|| {\nz\n})\n}\n}\n";
+        let got = levels(src);
+        let want = [0, 1, 0, 1, 2, 3, 2, 1, 0].map(Some);
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn unclosed_blocks_indent_the_lines_after_them() {
+        // As just typed: `{` then Enter, before the `}` exists.
+        assert_eq!(levels("fn f() {\n\n"), [Some(0), Some(1)]);
+        // The `}` closes `f`; the `impl` is still open.
+        assert_eq!(
+            levels("impl X {\n  fn f() {\n\n}\n"),
+            [0, 1, 2, 1].map(Some)
+        );
+        assert_eq!(levels("fn f() {\n  if x {\n\n}\n"), [0, 1, 2, 1].map(Some));
+    }
+
+    #[test]
+    fn comment_style_comes_from_the_language_at_the_cursor() {
+        // A file in a language commenting with `#`, holding Rust in its
+        // macros' token trees.
+        static HOST: LanguageDef = LanguageDef {
+            name: "host",
+            injections: "((macro_invocation (token_tree) @injection.content)
+                (#set! injection.language \"rust\"))",
+            comments: CommentStyle {
+                line: Some("#"),
+                block: None,
+            },
+            ..RUST
+        };
+        let mut comments = SyntaxComments::new(&HOST).unwrap();
+        let text = "fn f() { println!(\"{}\", x); }";
+        let inside = text.find("x)").unwrap();
+        let rope = Rope::from(text);
+        assert_eq!(comments.comment_style(&rope, 0, inside), RUST.comments);
+        assert_eq!(comments.comment_style(&rope, 0, 0), HOST.comments);
+        assert_eq!(language("rust").map(|def| def.name), Some("rust"));
+        assert!(language("markdown").is_none());
+    }
+
+    #[test]
+    fn finds_strings_and_comments_around_the_cursor() {
+        let mut literals = SyntaxLiterals::new(&RUST);
+        let mut revision = 0;
+        let mut inside = |text: &str| {
+            let at = text.find('|').unwrap();
+            revision += 1;
+            literals.in_literal(&Rope::from(text.replace('|', "")), revision, at)
+        };
+        assert!(inside("let s = \"a|b\";"));
+        assert!(inside("let s = \"|\";"));
+        assert!(!inside("let s = \"ab\"|;"));
+        assert!(!inside("let s = |\"ab\";"));
+        assert!(inside("let c = '|';"));
+        assert!(inside("x(); // a|"));
+        assert!(!inside("x(); // a\n|"));
+        assert!(inside("/* a| */ x();"));
+        assert!(!inside("fn f(|) {}"));
+    }
+
+    #[test]
+    fn literals_follow_edits_to_the_text() {
+        let mut buf = TextBuffer::new();
+        buf.set_content("let s = 1;\nx();");
+        let mut literals = SyntaxLiterals::new(&RUST);
+        assert!(!literals.in_literal(&buf.snapshot(), 0, 9));
+        let change = Change {
+            pos: 8,
+            removed: "1".to_string(),
+            inserted: "\"a\nb\"".to_string(),
+        };
+        literals.edit(&change.byte_edit(&buf), 1);
+        change.apply(&mut buf);
+        assert!(literals.in_literal(&buf.snapshot(), 1, 11));
+        assert!(!literals.in_literal(&buf.snapshot(), 1, 16));
+        // A change it is not told of is parsed afresh.
+        change.invert().apply(&mut buf);
+        assert!(!literals.in_literal(&buf.snapshot(), 2, 9));
+    }
+
+    #[test]
+    fn strings_and_comments_keep_their_indent() {
+        let src = "fn f() {\n  let s = \"a\n b\";\n  /* c\n d */\n}\n";
+        assert_eq!(
+            levels(src),
+            [Some(0), Some(1), None, Some(1), None, Some(0)]
+        );
+    }
+
     #[test]
     fn color_mapping_categories() {
         assert_eq!(color_for(style_of("keyword")), Color::Magenta);
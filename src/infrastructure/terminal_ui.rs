@@ -1,4 +1,4 @@
-use crate::domain::editor_model::{EditorMode, EditorModel};
+use crate::domain::editor_model::{EditorMode, EditorModel, VisualKind};
 use crate::infrastructure::syntax_worker::{color_for, HlSpan};
 use crossterm::{
     cursor, execute, queue,
@@ -62,13 +62,21 @@ fn diag_at(
 /// underlined (in the severity colour) where a diagnostic covers them.
 /// `line_start_byte` is the byte offset of the line within the document (for
 /// `spans` lookup); `line_diags` are this line's diagnostic ranges in char
-/// columns.
+/// columns. `selection` is the Visual selection on this line in char columns,
+/// drawn in reverse video; an end past the line also marks the line break.
+/// `cursors` are the char columns of secondary cursors, also reversed.
+/// `matches` are search matches on this line in char columns, drawn on a
+/// yellow background.
+#[allow(clippy::too_many_arguments)]
 fn draw_line_highlighted(
     stdout: &mut io::Stdout,
     line: &str,
     line_start_byte: usize,
     spans: &[HlSpan],
     line_diags: &[(usize, usize, DiagSeverity)],
+    selection: Option<(usize, usize)>,
+    cursors: &[usize],
+    matches: &[(usize, usize)],
     col_offset: usize,
     width: usize,
 ) -> io::Result<()> {
@@ -77,6 +85,8 @@ fn draw_line_highlighted(
     let mut char_in_line = 0usize;
     let mut current: Option<Color> = None;
     let mut underlined = false;
+    let mut reversed = false;
+    let mut matched = false;
     for g in line.graphemes(true) {
         let w = UnicodeWidthStr::width(g);
         let g_start = byte_in_line;
@@ -91,9 +101,22 @@ fn draw_line_highlighted(
             break;
         }
         let diag = diag_at(line_diags, char_start, char_in_line);
-        // Diagnostic severity colour overrides syntax colour on the offending
-        // text; otherwise use the syntax colour.
+        let want_match = matches
+            .iter()
+            .any(|&(s, e)| s <= char_start && char_start < e);
+        if want_match != matched {
+            let bg = if want_match {
+                Color::DarkYellow
+            } else {
+                Color::Reset
+            };
+            queue!(stdout, SetBackgroundColor(bg))?;
+            matched = want_match;
+        }
+        // Search matches are drawn black on yellow; otherwise a diagnostic's
+        // severity colour overrides the syntax colour on the offending text.
         let color = match diag {
+            _ if want_match => Color::Black,
             Some(sev) => severity_color(sev),
             None => style_at(spans, line_start_byte + g_start)
                 .map(color_for)
@@ -115,12 +138,42 @@ fn draw_line_highlighted(
             )?;
             underlined = want_underline;
         }
+        let want_reverse = selection.is_some_and(|(s, e)| s <= char_start && char_start < e)
+            || cursors.contains(&char_start);
+        if want_reverse != reversed {
+            queue!(
+                stdout,
+                SetAttribute(if want_reverse {
+                    Attribute::Reverse
+                } else {
+                    Attribute::NoReverse
+                })
+            )?;
+            reversed = want_reverse;
+        }
         stdout.write_all(g.as_bytes())?;
         col += w;
     }
     if underlined {
         queue!(stdout, SetAttribute(Attribute::NoUnderline))?;
     }
+    if matched {
+        queue!(stdout, SetBackgroundColor(Color::Reset))?;
+    }
+    // A selected line break (or a cursor on it) shows as one reversed cell
+    // after the text.
+    let eol_selected =
+        selection.is_some_and(|(_, e)| e > char_in_line) || cursors.contains(&char_in_line);
+    if eol_selected && col >= col_offset && col < col_offset + width {
+        if !reversed {
+            queue!(stdout, SetAttribute(Attribute::Reverse))?;
+            reversed = true;
+        }
+        stdout.write_all(b" ")?;
+    }
+    if reversed {
+        queue!(stdout, SetAttribute(Attribute::NoReverse))?;
+    }
     if current.is_some() {
         queue!(stdout, SetForegroundColor(Color::Reset))?;
     }
@@ -179,9 +232,11 @@ pub fn gutter_width(line_count: usize) -> usize {
     gutter_digits(line_count) + 5
 }
 
-/// Draw a bordered popup box holding `lines`, anchored near the cursor at
-/// screen `(cursor_row, cursor_col)`. Placed below the cursor when it fits,
-/// otherwise above. Content is clipped to the box and the terminal width.
+/// Draw a bordered popup box holding up to `max_lines` of `lines`, anchored
+/// near the cursor at screen `(cursor_row, cursor_col)`. Placed below the
+/// cursor when it fits, otherwise above. Content is clipped to the box and the
+/// terminal width.
+#[allow(clippy::too_many_arguments)]
 fn draw_popup(
     stdout: &mut io::Stdout,
     lines: &[String],
@@ -189,12 +244,12 @@ fn draw_popup(
     cursor_col: u16,
     cols: u16,
     text_height: usize,
+    max_lines: usize,
 ) -> io::Result<()> {
     if lines.is_empty() || text_height == 0 {
         return Ok(());
     }
-    const MAX_LINES: usize = 8;
-    let shown = &lines[..lines.len().min(MAX_LINES)];
+    let shown = &lines[..lines.len().min(max_lines)];
     let content_w = shown
         .iter()
         .map(|l| UnicodeWidthStr::width(l.as_str()))
@@ -297,6 +352,7 @@ pub fn draw_editor(
     diagnostics: &[LineDiag],
     diagnostic_msg: &str,
     hover: &[String],
+    output: &[String],
     completion: Option<(&[String], usize)>,
 ) -> io::Result<()> {
     let (cols, rows) = size()?;
@@ -315,6 +371,8 @@ pub fn draw_editor(
     let gw = gutter_width(line_count);
     let text_width = (cols as usize).saturating_sub(gw);
     let text_height = (rows as usize).saturating_sub(2);
+    let search_highlights =
+        editor.search_highlights(editor.row_offset, editor.row_offset + text_height);
     for screen_row in 0..text_height {
         let line_idx = editor.row_offset + screen_row;
         if line_idx >= line_count {
@@ -341,7 +399,20 @@ pub fn draw_editor(
         queue!(stdout, SetForegroundColor(Color::Reset))?;
 
         let line = editor.buffer.line_text(line_idx);
-        if spans.is_empty() && line_diags.is_empty() {
+        let selection = editor.selection_span(line_idx);
+        let cursors = editor.cursors_on_line(line_idx);
+        let matches: Vec<(usize, usize)> = search_highlights
+            .iter()
+            .filter(|(y, _, _)| *y == line_idx)
+            .map(|&(_, s, e)| (s, e))
+            .chain(editor.placeholder_span(line_idx))
+            .collect();
+        if spans.is_empty()
+            && line_diags.is_empty()
+            && selection.is_none()
+            && cursors.is_empty()
+            && matches.is_empty()
+        {
             // Fast path: no highlights and no diagnostics on this line.
             let visible = visible_slice(&line, editor.col_offset, text_width);
             stdout.write_all(visible.as_bytes())?;
@@ -353,6 +424,9 @@ pub fn draw_editor(
                 line_start_byte,
                 spans,
                 &line_diags,
+                selection,
+                &cursors,
+                &matches,
                 editor.col_offset,
                 text_width,
             )?;
@@ -366,15 +440,31 @@ pub fn draw_editor(
         EditorMode::Insert => "INSERT",
         EditorMode::Command => "COMMAND",
         EditorMode::Search => "SEARCH",
+        EditorMode::Confirm => "CONFIRM",
+        EditorMode::Replace => "REPLACE",
+        EditorMode::Visual(VisualKind::Char) => "VISUAL",
+        EditorMode::Visual(VisualKind::Line) => "V-LINE",
+        EditorMode::Visual(VisualKind::Block) => "V-BLOCK",
+    };
+
+    let recording = editor
+        .recording
+        .map(|r| format!(" | recording @{}", r))
+        .unwrap_or_default();
+    let cursors = match editor.cursor_count() {
+        1 => String::new(),
+        n => format!(" | {} cursors", n),
     };
 
     let status_bar = format!(
-        " {}:{} | {} lines | {} | {}",
+        " {}:{} | {} lines | {} | {}{}{}",
         editor.cursor_y + 1,
         editor.cursor_x + 1,
         editor.buffer.line_count(),
         editor.filepath.as_deref().unwrap_or("[No Name]"),
-        mode_indicator
+        mode_indicator,
+        cursors,
+        recording
     );
 
     let status_message_line = if let EditorMode::Command = editor.mode {
@@ -409,7 +499,20 @@ pub fn draw_editor(
     let screen_y = (editor.cursor_y.saturating_sub(editor.row_offset)) as u16;
 
     // Hover popup / completion menu (drawn over the text, near the cursor).
-    draw_popup(stdout, hover, screen_y, screen_x, cols, text_height)?;
+    const HOVER_LINES: usize = 8;
+    draw_popup(
+        stdout,
+        hover,
+        screen_y,
+        screen_x,
+        cols,
+        text_height,
+        HOVER_LINES,
+    )?;
+    // Command output sits at the bottom-left, just above the status bar.
+    let bottom = text_height as u16;
+    let output_lines = text_height.saturating_sub(2);
+    draw_popup(stdout, output, bottom, 0, cols, text_height, output_lines)?;
     if let Some((items, selected)) = completion {
         draw_menu(
             stdout,
@@ -0,0 +1,220 @@
+use crate::domain::transaction::{Change, Transaction};
+use crate::domain::undo_tree::{UndoNode, UndoTree};
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Persistence for undo history, keyed by file path. A history is only
+/// handed back for the exact text it was saved with.
+pub trait UndoStore {
+    /// The history saved for `path`, if its recorded content hash matches
+    /// `content` (the text just read from disk).
+    fn load(&self, path: &str, content: &str) -> Option<UndoTree>;
+    /// Save `tree` as the history of `path`, whose text is now `content`.
+    fn save(&self, path: &str, content: &str, tree: &UndoTree) -> io::Result<()>;
+}
+
+/// The undo file format written. Version 1 files, with no `version` key,
+/// held one change per node as its `pos`, `removed` and `inserted`; they are
+/// read by taking that as the node's only change. Files of a later version
+/// are not read.
+const VERSION: u64 = 2;
+
+/// Undo files as JSON in one directory, named by a hash of the file's
+/// absolute path.
+pub struct LocalUndoStore {
+    dir: PathBuf,
+}
+
+impl LocalUndoStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `$XDG_STATE_HOME/rim/undo`, falling back to `~/.local/state/rim/undo`.
+    pub fn default_location() -> Option<Self> {
+        let base = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".local/state")))?;
+        Some(Self::new(base.join("rim").join("undo")))
+    }
+
+    fn undo_file(&self, path: &str) -> (String, PathBuf) {
+        let key = fs::canonicalize(path)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        let file = self
+            .dir
+            .join(format!("{:016x}.json", fnv1a(key.as_bytes())));
+        (key, file)
+    }
+}
+
+impl UndoStore for LocalUndoStore {
+    fn load(&self, path: &str, content: &str) -> Option<UndoTree> {
+        let (key, file) = self.undo_file(path);
+        let value: Value = serde_json::from_str(&fs::read_to_string(file).ok()?).ok()?;
+        if value["path"] != key.as_str() || value["hash"] != hash_hex(content).as_str() {
+            return None;
+        }
+        decode(&value)
+    }
+
+    fn save(&self, path: &str, content: &str, tree: &UndoTree) -> io::Result<()> {
+        let (key, file) = self.undo_file(path);
+        let value = json!({
+            "version": VERSION,
+            "path": key,
+            "hash": hash_hex(content),
+            "current": tree.current(),
+            "nodes": tree.nodes().iter().map(encode_node).collect::<Vec<_>>(),
+        });
+        fs::create_dir_all(&self.dir)?;
+        fs::write(file, value.to_string())
+    }
+}
+
+/// 64-bit FNV-1a: stable across runs and platforms, which `DefaultHasher` is
+/// not guaranteed to be.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hash_hex(content: &str) -> String {
+    format!("{:016x}", fnv1a(content.as_bytes()))
+}
+
+fn encode_node(node: &UndoNode) -> Value {
+    let t = &node.transaction;
+    let time = node.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    json!({
+        "parent": node.parent,
+        "redo": node.redo_child,
+        "time": time.as_secs(),
+        "changes": t.changes.iter().map(|c| json!({
+            "pos": c.pos,
+            "removed": c.removed,
+            "inserted": c.inserted,
+        })).collect::<Vec<_>>(),
+        "before": [t.cursor_before.0, t.cursor_before.1],
+        "after": [t.cursor_after.0, t.cursor_after.1],
+    })
+}
+
+fn decode(value: &Value) -> Option<UndoTree> {
+    let version = value.get("version").map_or(Some(1), Value::as_u64)?;
+    if version > VERSION {
+        return None;
+    }
+    let usize_of = |v: &Value| v.as_u64().map(|n| n as usize);
+    let cursor = |v: &Value| Some((usize_of(&v[0])?, usize_of(&v[1])?));
+    let change = |c: &Value| {
+        Some(Change {
+            pos: usize_of(&c["pos"])?,
+            removed: c["removed"].as_str()?.to_string(),
+            inserted: c["inserted"].as_str()?.to_string(),
+        })
+    };
+    let nodes = value["nodes"]
+        .as_array()?
+        .iter()
+        .map(|n| {
+            Some(UndoNode {
+                parent: usize_of(&n["parent"])?,
+                redo_child: usize_of(&n["redo"]),
+                time: UNIX_EPOCH + Duration::from_secs(n["time"].as_u64()?),
+                transaction: Transaction {
+                    changes: match version {
+                        // The root's change was an empty one.
+                        1 => Some(change(n)?)
+                            .filter(|c| !(c.removed.is_empty() && c.inserted.is_empty()))
+                            .into_iter()
+                            .collect(),
+                        _ => n["changes"]
+                            .as_array()?
+                            .iter()
+                            .map(change)
+                            .collect::<Option<Vec<_>>>()?,
+                    },
+                    cursor_before: cursor(&n["before"])?,
+                    cursor_after: cursor(&n["after"])?,
+                },
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    UndoTree::from_nodes(nodes, usize_of(&value["current"])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> LocalUndoStore {
+        let dir = env::temp_dir().join(format!("rim-undo-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        LocalUndoStore::new(dir)
+    }
+
+    fn tree() -> UndoTree {
+        let mut tree = UndoTree::default();
+        tree.push(Transaction {
+            changes: vec![Change {
+                pos: 0,
+                removed: String::new(),
+                inserted: "hi".to_string(),
+            }],
+            cursor_before: (0, 0),
+            cursor_after: (0, 2),
+        });
+        tree
+    }
+
+    #[test]
+    fn round_trips_when_content_matches() {
+        let s = store("match");
+        s.save("/tmp/x.txt", "hi\n", &tree()).unwrap();
+        let t = s.load("/tmp/x.txt", "hi\n").unwrap();
+        assert_eq!((t.current(), t.last()), (1, 1));
+        assert_eq!(t.node(1).transaction.changes[0].inserted, "hi");
+        assert_eq!(t.node(1).transaction.cursor_after, (0, 2));
+    }
+
+    #[test]
+    fn discards_history_for_changed_content() {
+        let s = store("stale");
+        s.save("/tmp/y.txt", "hi\n", &tree()).unwrap();
+        assert!(s.load("/tmp/y.txt", "edited elsewhere\n").is_none());
+        assert!(s.load("/tmp/other.txt", "hi\n").is_none());
+    }
+
+    #[test]
+    fn migrates_version_1_files_and_rejects_newer_ones() {
+        let s = store("version");
+        let (key, file) = s.undo_file("/tmp/z.txt");
+        let mut value = json!({
+            "path": key,
+            "hash": hash_hex("hi\n"),
+            "current": 1,
+            "nodes": [
+                { "parent": 0, "redo": 1, "time": 0, "pos": 0, "removed": "",
+                  "inserted": "", "before": [0, 0], "after": [0, 0] },
+                { "parent": 0, "redo": null, "time": 0, "pos": 0, "removed": "",
+                  "inserted": "hi", "before": [0, 0], "after": [0, 2] },
+            ],
+        });
+        fs::create_dir_all(&s.dir).unwrap();
+        fs::write(&file, value.to_string()).unwrap();
+        let t = s.load("/tmp/z.txt", "hi\n").unwrap();
+        assert!(t.node(0).transaction.changes.is_empty());
+        assert_eq!(t.node(1).transaction.changes[0].inserted, "hi");
+
+        value["version"] = json!(VERSION + 1);
+        fs::write(&file, value.to_string()).unwrap();
+        assert!(s.load("/tmp/z.txt", "hi\n").is_none());
+    }
+}
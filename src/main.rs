@@ -2,22 +2,26 @@ mod application;
 mod domain;
 mod infrastructure;
 
-use application::editor_service::{EditorService, HandleCommandResult};
-use application::lsp::{ApplyOutcome, Lsp, LspRequest};
-use application::normal_mode::{NormalMode, NormalResult};
+use application::editor_service::EditorService;
+use application::input::handle_key;
+use application::lsp::{ApplyOutcome, Lsp};
+use application::normal_mode::NormalMode;
+use application::shell::Shell;
 use application::syntax::Syntax;
-use domain::editor_model::EditorMode;
 use infrastructure::file_io::LocalFileIO;
+use infrastructure::snippet_file::LocalSnippetStore;
 use infrastructure::terminal_ui;
+use infrastructure::undo_file::LocalUndoStore;
 
 use crossterm::{
-    event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers},
+    event::{Event, EventStream, KeyCode, KeyModifiers},
     execute,
     terminal::{
         disable_raw_mode, enable_raw_mode, size, EnterAlternateScreen, LeaveAlternateScreen,
     },
 };
 use futures::StreamExt;
+use std::collections::VecDeque;
 use std::env;
 use std::io;
 use tokio::time::{Duration, Instant};
@@ -52,153 +56,16 @@ async fn main() -> io::Result<()> {
     Ok(())
 }
 
-/// Plain insert-mode key handling (no completion popup active).
-fn insert_default_key(
-    event: KeyEvent,
-    editor_service: &mut EditorService<LocalFileIO>,
-    status_message: &mut String,
-) {
-    match event.code {
-        KeyCode::Esc => {
-            editor_service.set_mode(EditorMode::Normal);
-            status_message.clear();
-        }
-        KeyCode::Char(c) => {
-            editor_service.insert_char(c);
-            status_message.clear();
-        }
-        KeyCode::Enter => {
-            editor_service.editor_model.insert_newline();
-            status_message.clear();
-        }
-        KeyCode::Backspace => {
-            editor_service.delete_char();
-            status_message.clear();
-        }
-        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => {
-            editor_service.move_cursor(event.code);
-            status_message.clear();
-        }
-        _ => {}
-    }
-}
-
-/// Dispatch one key event to the editor. Returns `true` if the editor should
-/// quit. Pure synchronous CPU work — it never blocks the async runtime.
-fn handle_key(
-    event: KeyEvent,
-    editor_service: &mut EditorService<LocalFileIO>,
-    normal_mode: &mut NormalMode,
-    status_message: &mut String,
-    lsp: &mut Lsp,
-) -> bool {
-    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
-    match editor_service.editor_model.mode {
-        EditorMode::Normal => match normal_mode.feed(editor_service, &event, status_message) {
-            NormalResult::Quit => return true,
-            NormalResult::Continue => {}
-        },
-        EditorMode::Insert if lsp.completion_active() => match event.code {
-            KeyCode::Esc => lsp.close_completion(),
-            KeyCode::Char('n') if ctrl => lsp.completion_move(1),
-            KeyCode::Char('p') if ctrl => lsp.completion_move(-1),
-            KeyCode::Down => lsp.completion_move(1),
-            KeyCode::Up => lsp.completion_move(-1),
-            KeyCode::Enter | KeyCode::Tab => {
-                lsp.completion_accept(editor_service);
-            }
-            KeyCode::Char(c) if !ctrl && (c.is_alphanumeric() || c == '_') => {
-                editor_service.insert_char(c);
-                lsp.completion_refilter(&editor_service.editor_model);
-            }
-            KeyCode::Backspace => {
-                editor_service.delete_char();
-                lsp.completion_refilter(&editor_service.editor_model);
-            }
-            // Any other key dismisses the popup and is handled normally.
-            _ => {
-                lsp.close_completion();
-                insert_default_key(event, editor_service, status_message);
-            }
-        },
-        EditorMode::Insert => match event.code {
-            // Trigger completion: Ctrl-n or Ctrl-Space.
-            KeyCode::Char('n') if ctrl => {
-                let (y, x) = (
-                    editor_service.editor_model.cursor_y,
-                    editor_service.editor_model.cursor_x,
-                );
-                editor_service.request_lsp(LspRequest::Completion { y, x });
-            }
-            KeyCode::Char(' ') if ctrl => {
-                let (y, x) = (
-                    editor_service.editor_model.cursor_y,
-                    editor_service.editor_model.cursor_x,
-                );
-                editor_service.request_lsp(LspRequest::Completion { y, x });
-            }
-            _ => insert_default_key(event, editor_service, status_message),
-        },
-        EditorMode::Command => match event.code {
-            KeyCode::Esc => {
-                editor_service.set_mode(EditorMode::Normal);
-                editor_service.clear_command_buffer();
-                status_message.clear();
-            }
-            KeyCode::Char(c) => {
-                editor_service.push_command_char(c);
-                *status_message = format!(":{}", editor_service.editor_model.command_buffer);
-            }
-            KeyCode::Backspace => {
-                editor_service.pop_command_char();
-                *status_message = format!(":{}", editor_service.editor_model.command_buffer);
-            }
-            KeyCode::Enter => {
-                let command = editor_service.editor_model.command_buffer.clone();
-                editor_service.clear_command_buffer();
-                match editor_service.handle_command(&command) {
-                    Ok(HandleCommandResult::Quit) => return true,
-                    Ok(HandleCommandResult::Continue) => {
-                        *status_message = format!("Command executed: {}", command);
-                    }
-                    Err(e) => {
-                        *status_message = format!("Error: {}", e);
-                    }
-                }
-                editor_service.set_mode(EditorMode::Normal);
-            }
-            _ => {}
-        },
-        EditorMode::Search => match event.code {
-            KeyCode::Esc => {
-                editor_service.set_mode(EditorMode::Normal);
-                editor_service.clear_command_buffer();
-                status_message.clear();
-            }
-            KeyCode::Char(c) => {
-                editor_service.push_command_char(c);
-                *status_message = format!("/{}", editor_service.editor_model.command_buffer);
-            }
-            KeyCode::Backspace => {
-                editor_service.pop_command_char();
-                *status_message = format!("/{}", editor_service.editor_model.command_buffer);
-            }
-            KeyCode::Enter => {
-                let query = editor_service.editor_model.command_buffer.clone();
-                editor_service.search(&query);
-                editor_service.set_mode(EditorMode::Normal);
-                status_message.clear();
-            }
-            _ => {}
-        },
-    }
-    false
-}
-
 async fn run() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
     let file_io = LocalFileIO;
     let mut editor_service = EditorService::new(file_io);
+    if let Some(store) = LocalUndoStore::default_location() {
+        editor_service.set_undo_store(Box::new(store));
+    }
+    if let Some(store) = LocalSnippetStore::default_location() {
+        editor_service.set_snippet_store(Box::new(store));
+    }
     let mut status_message = String::new();
     let mut normal_mode = NormalMode::new();
 
@@ -225,14 +92,22 @@ async fn run() -> io::Result<()> {
     let mut lsp = Lsp::new(lsp_tx);
     lsp.on_open(&editor_service.editor_model);
 
+    // External commands (`:!`, `:r !`, filters) run as tokio tasks and report
+    // on `shell_rx`, a fifth branch of the select! below.
+    let (shell_tx, mut shell_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut shell = Shell::new(shell_tx);
+
     // Async terminal input via crossterm's EventStream (the "event-stream"
     // feature); awaiting it never blocks the runtime.
     let mut reader = EventStream::new();
     // When set, the instant at which a debounced re-highlight should fire.
     let mut deadline: Option<Instant> = None;
 
+    // Keys read but not yet handled.
+    let mut keys = VecDeque::new();
+
     let mut stdout = io::stdout();
-    loop {
+    'editor: loop {
         // Keep the cursor within the visible text area before rendering. The
         // line-number gutter narrows the horizontal text area.
         let (cols, rows) = size()?;
@@ -262,6 +137,7 @@ async fn run() -> io::Result<()> {
             &diagnostics,
             &diagnostic_msg,
             lsp.hover_lines(),
+            editor_service.output(),
             completion
                 .as_ref()
                 .map(|(items, sel)| (items.as_slice(), *sel)),
@@ -273,40 +149,17 @@ async fn run() -> io::Result<()> {
         tokio::select! {
             maybe_event = reader.next() => {
                 match maybe_event {
-                    Some(Ok(Event::Key(key))) => {
-                        // Any keypress dismisses the hover popup (completion has
-                        // its own lifecycle).
-                        lsp.clear_transient();
-                        let before = editor_service.editor_model.edit_revision();
-                        let before_path = editor_service.editor_model.filepath.clone();
-                        if handle_key(key, &mut editor_service, &mut normal_mode, &mut status_message, &mut lsp) {
-                            break;
-                        }
-                        let after = editor_service.editor_model.edit_revision();
-                        let after_path = editor_service.editor_model.filepath.clone();
-                        if after_path != before_path {
-                            // File switched (:e / cross-file gd): re-open in the
-                            // LSP and re-highlight the whole new buffer. Handled
-                            // before the didChange path so the `set_content`
-                            // revision bump is subsumed into the open, not sent
-                            // as a spurious change against the new document.
-                            lsp.on_open(&editor_service.editor_model);
-                            if editor_service.editor_model.buffer.line_count() > 0 {
-                                syntax.request_now(after, editor_service.editor_model.buffer.snapshot());
-                            }
-                            deadline = None;
-                        } else if after != before {
-                            // Text changed: (re-)arm the shared edit debounce for
-                            // both re-highlight and didChange.
-                            syntax.note_change(after, editor_service.editor_model.buffer.snapshot());
-                            lsp.note_change();
-                            deadline = Some(Instant::now() + HIGHLIGHT_DEBOUNCE);
-                        }
-                        // Fire any LSP feature request the keypress recorded.
-                        if let Some(req) = editor_service.take_pending_lsp() {
-                            lsp.dispatch_request(req, &editor_service.editor_model);
-                        }
+                    // Ctrl-C kills a running external command.
+                    Some(Ok(Event::Key(key)))
+                        if shell.is_running()
+                            && key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        shell.cancel();
+                        status_message = "Interrupted".to_string();
                     }
+                    // Handled below, once no external command runs.
+                    Some(Ok(Event::Key(key))) => keys.push_back(key),
                     Some(Ok(_)) => {}   // resize/mouse/paste: just re-render
                     Some(Err(_)) => {}  // transient read error: ignore
                     None => break,      // input stream closed
@@ -340,12 +193,76 @@ async fn run() -> io::Result<()> {
                     ApplyOutcome::Nothing => {}
                 }
             }
+            Some(result) = shell_rx.recv() => {
+                // A filter or `:r !` edited the buffer: re-highlight now and
+                // arm the debounced didChange.
+                if shell.apply(result, &mut editor_service, &mut status_message) {
+                    syntax.request_now(
+                        editor_service.editor_model.edit_revision(),
+                        editor_service.editor_model.buffer.snapshot(),
+                    );
+                    lsp.note_change();
+                    deadline = Some(Instant::now() + HIGHLIGHT_DEBOUNCE);
+                }
+            }
             _ = tokio::time::sleep_until(tick), if deadline.is_some() => {
                 syntax.dispatch();
                 lsp.dispatch_change(&editor_service.editor_model);
                 deadline = None;
             }
         }
+
+        // Handle the keys typed. While an external command runs they wait,
+        // and are handled in order once it is done or cancelled.
+        while !shell.is_running() {
+            let Some(key) = keys.pop_front() else {
+                break;
+            };
+            // Any keypress dismisses the hover popup (completion has
+            // its own lifecycle).
+            lsp.clear_transient();
+            editor_service.clear_output();
+            let before = editor_service.editor_model.edit_revision();
+            let before_path = editor_service.editor_model.filepath.clone();
+            if handle_key(
+                key,
+                &mut editor_service,
+                &mut normal_mode,
+                &mut status_message,
+                &mut lsp,
+            ) {
+                break 'editor;
+            }
+            let after = editor_service.editor_model.edit_revision();
+            let after_path = editor_service.editor_model.filepath.clone();
+            if after_path != before_path {
+                // File switched (:e / cross-file gd): re-open in the
+                // LSP and re-highlight the whole new buffer. Handled
+                // before the didChange path so the `set_content`
+                // revision bump is subsumed into the open, not sent
+                // as a spurious change against the new document.
+                lsp.on_open(&editor_service.editor_model);
+                if editor_service.editor_model.buffer.line_count() > 0 {
+                    syntax.request_now(after, editor_service.editor_model.buffer.snapshot());
+                }
+                deadline = None;
+            } else if after != before {
+                // Text changed: (re-)arm the shared edit debounce for
+                // both re-highlight and didChange.
+                syntax.note_change(after, editor_service.editor_model.buffer.snapshot());
+                lsp.note_change();
+                deadline = Some(Instant::now() + HIGHLIGHT_DEBOUNCE);
+            }
+            // Fire any LSP feature request the keypress recorded.
+            if let Some(req) = editor_service.take_pending_lsp() {
+                lsp.dispatch_request(req, &editor_service.editor_model);
+            }
+            // Start any external command the keypress ran.
+            if let Some(req) = editor_service.take_pending_shell() {
+                shell.dispatch(req, &editor_service.editor_model);
+                status_message = shell.status().unwrap_or_default();
+            }
+        }
     }
 
     // Best-effort graceful LSP shutdown (timeout-guarded) before the terminal